@@ -17,7 +17,7 @@ _aichat() {
 
     case "${cmd}" in
         aichat)
-            opts="-m -r -s -a -e -c -f -S -h -V --model --prompt --role --session --empty-session --save-session --agent --agent-variable --rag --rebuild-rag --serve --execute --code --file --no-stream --dry-run --info --list-models --list-roles --list-sessions --list-agents --list-rags --help --version"
+            opts="-m -r -t -s -a -e -c -o -f -S -h -V --model --models --prompt --role --template --var --session --empty-session --save-session --agent --agent-variable --rag --rebuild-rag --serve --repl --repl-stdin --execute --code --output --extract-code --file --no-stream --stream-raw --format --eval --batch --cron --sink --daemonize --jsonl --map --out-dir --concurrency --offline --init --platform -y --yes --dry-run --info --list-models --detail --sync-models --merge-local --export-profile --exclude-keys --import-profile --list-roles --dedupe-roles --cleanup --list-sessions --show-session --list-agents --list-rags --install-agent --update-agent --remove-agent --bug-report --check-tools --text --help --version"
             if [[ ${cur} == -* || ${cword} -eq 1 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -29,6 +29,48 @@ _aichat() {
                     __ltrim_colon_completions "$cur"
                     return 0
                     ;;
+                --models)
+                    return 0
+                    ;;
+                --platform)
+                    return 0
+                    ;;
+                --format)
+                    COMPREPLY=($(compgen -W "text json" -- "${cur}"))
+                    return 0
+                    ;;
+                --eval)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --export-profile|--import-profile)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --batch)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --map)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                -t|--template)
+                    COMPREPLY=()
+                    return 0
+                    ;;
+                --var)
+                    COMPREPLY=()
+                    return 0
+                    ;;
+                --out-dir)
+                    COMPREPLY=($(compgen -d "${cur}"))
+                    return 0
+                    ;;
+                --concurrency|--text|--cron)
+                    COMPREPLY=()
+                    return 0
+                    ;;
                 --prompt)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
@@ -38,12 +80,12 @@ _aichat() {
                     __ltrim_colon_completions "$cur"
                     return 0
                     ;;
-                -s|--session)
+                -s|--session|--show-session)
                     COMPREPLY=($(compgen -W "$("$1" --list-sessions)" -- "${cur}"))
                     __ltrim_colon_completions "$cur"
                     return 0
                     ;;
-                -a|--agent)
+                -a|--agent|--update-agent|--remove-agent)
                     COMPREPLY=($(compgen -W "$("$1" --list-agents)" -- "${cur}"))
                     __ltrim_colon_completions "$cur"
                     return 0
@@ -53,6 +95,14 @@ _aichat() {
                     __ltrim_colon_completions "$cur"
                     return 0
                     ;;
+                -o|--output)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --extract-code)
+                    COMPREPLY=()
+                    return 0
+                    ;;
                 -f|--file)
                     local oldifs
                     if [[ -v IFS ]]; then
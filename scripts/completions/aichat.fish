@@ -1,24 +1,61 @@
 complete -c aichat -s m -l model -x -a "(aichat --list-models)" -d 'Select a LLM model' -r
+complete -c aichat -l models -d 'Compare responses from multiple models, separated by commas' -r
 complete -c aichat -l prompt -d 'Use the system prompt'
 complete -c aichat -s r -l role -x -a "(aichat --list-roles)" -d 'Select a role' -r
 complete -c aichat -s s -l session -x  -a "(aichat --list-sessions)" -d 'Start or join a session' -r
 complete -c aichat -l empty-session -d 'Ensure the session is empty'
 complete -c aichat -l save-session -d 'Ensure the new conversation is saved to the session'
 complete -c aichat -s a -l agent -x  -a "(aichat --list-agents)" -d 'Start a agent' -r
+complete -c aichat -s t -l template -d 'Use a prompt template from the templates directory, or inline text with --map' -r
+complete -c aichat -l var -d 'Set template variables' -r
 complete -c aichat -l agent-variable -d 'Set agent variables'
 complete -c aichat -l rag -x  -a"(aichat --list-rags)" -d 'Start a RAG' -r
 complete -c aichat -l rebuild-rag -d 'Rebuild the RAG to sync document changes'
 complete -c aichat -l serve -d 'Serve the LLM API and WebAPP'
+complete -c aichat -l repl -d 'Open the REPL with the input text as the first message'
+complete -c aichat -l repl-stdin -d 'Run the REPL over stdin/stdout, one JSON request and one JSON reply per line'
 complete -c aichat -s e -l execute -d 'Execute commands in natural language'
 complete -c aichat -s c -l code -d 'Output code only'
+complete -c aichat -s o -l output -d 'Write the answer to a file' -r -F
+complete -c aichat -l extract-code -d 'Write only the first code block to --output, optionally the first one in LANG'
 complete -c aichat -s f -l file -d 'Include files, directories, or URLs' -r -F
 complete -c aichat -s S -l no-stream -d 'Turn off stream mode'
+complete -c aichat -l stream-raw -d 'Stream bare text deltas to stdout, or NDJSON frames with --stream-raw=ndjson'
+complete -c aichat -l format -x -a 'text json' -d 'Print the answer as text, or as a JSON object with tool calls, usage and timing; --list-models prints JSON too'
+complete -c aichat -l eval -d 'Run an eval suite and write a JSON report' -r -F
+complete -c aichat -l batch -d 'Submit the prompts of a JSONL file as an OpenAI or Claude batch job' -r -F
+complete -c aichat -l cron -d 'Run the prompt on a cron schedule, delivering each answer to --sink' -r
+complete -c aichat -l sink -d 'Where --cron answers go: file:<path>, webhook:<url>, command:<cmd>' -r
+complete -c aichat -l daemonize -d 'Keep --cron running in the background'
+complete -c aichat -l jsonl -d 'Answer JSONL requests from stdin with one JSON result per line'
+complete -c aichat -l map -d 'Apply --template to every file matching the glob' -r -F
+complete -c aichat -l out-dir -d 'Directory where --map writes the result of each file' -r -f -a '(__fish_complete_directories)'
+complete -c aichat -l concurrency -d 'Number of files --map processes at the same time' -r
+complete -c aichat -l offline -d 'Only use local models (e.g. ollama) and skip remote urls'
+complete -c aichat -l init -d 'Write a config file without prompts, API keys are read from env'
+complete -c aichat -l platform -x -d 'Platform of the config written by --init' -r
+complete -c aichat -s y -l yes -d 'Overwrite the existing config file with --init'
 complete -c aichat -l dry-run -d 'Display the message without sending it'
 complete -c aichat -l info -d 'Display information'
-complete -c aichat -l list-models -d 'List all available chat models'
+complete -c aichat -l list-models -d 'List all available chat models, optionally matching a pattern'
+complete -c aichat -l detail -d 'Show context window, max output, capabilities and prices with --list-models'
+complete -c aichat -l sync-models -d 'Download the latest model list into the config directory'
+complete -c aichat -l merge-local -d 'Fold the local models.yaml into the list downloaded by --sync-models'
+complete -c aichat -l export-profile -d 'Bundle the config, roles, templates, agents and chosen RAGs into an archive (e.g. profile.tar.gz)' -r -F
+complete -c aichat -l exclude-keys -d 'Leave API keys, credentials, webhooks and the .env file out of --export-profile'
+complete -c aichat -l import-profile -d 'Unpack an archive made by --export-profile into the config directory' -r -F
 complete -c aichat -l list-roles -d 'List all roles'
+complete -c aichat -l dedupe-roles -d 'Find roles with alike prompts, then merge or delete them'
+complete -c aichat -l cleanup -d 'Remove old temp sessions, logs and RAG caches'
 complete -c aichat -l list-sessions -d 'List all sessions'
+complete -c aichat -l show-session -x  -a "(aichat --list-sessions)" -d 'Show a session transcript without joining it' -r
 complete -c aichat -l list-agents -d 'List all agents'
 complete -c aichat -l list-rags -d 'List all RAGs'
+complete -c aichat -l install-agent -d 'Install an agent from a git repository (e.g. github:user/repo#tag)' -r
+complete -c aichat -l update-agent -x  -a"(aichat --list-agents)" -d 'Fetch an agent installed with --install-agent again' -r
+complete -c aichat -l remove-agent -x  -a"(aichat --list-agents)" -d 'Remove an agent installed with --install-agent' -r
+complete -c aichat -l bug-report -d 'Print the environment, a redacted config summary and the last logged error for a bug report'
+complete -c aichat -l check-tools -d 'Check that function calling is set up and every declared tool runs with --help'
+complete -c aichat -l text -d 'Input text taken as is, even when it starts with a dash' -r
 complete -c aichat -s h -l help -d 'Print help'
 complete -c aichat -s V -l version -d 'Print version'
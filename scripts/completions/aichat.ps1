@@ -22,9 +22,13 @@ Register-ArgumentCompleter -Native -CommandName 'aichat' -ScriptBlock {
         'aichat' {
             [CompletionResult]::new('-m', '-m', [CompletionResultType]::ParameterName, 'Select a LLM model')
             [CompletionResult]::new('--model', '--model', [CompletionResultType]::ParameterName, 'Select a LLM model')
+            [CompletionResult]::new('--models', '--models', [CompletionResultType]::ParameterName, 'Compare responses from multiple models, separated by commas')
             [CompletionResult]::new('--prompt', '--prompt', [CompletionResultType]::ParameterName, 'Use the system prompt')
             [CompletionResult]::new('-r', '-r', [CompletionResultType]::ParameterName, 'Select a role')
             [CompletionResult]::new('--role', '--role', [CompletionResultType]::ParameterName, 'Select a role')
+            [CompletionResult]::new('-t', '-t', [CompletionResultType]::ParameterName, 'Use a prompt template from the templates directory, or inline text with --map')
+            [CompletionResult]::new('--template', '--template', [CompletionResultType]::ParameterName, 'Use a prompt template from the templates directory, or inline text with --map')
+            [CompletionResult]::new('--var', '--var', [CompletionResultType]::ParameterName, 'Set template variables')
             [CompletionResult]::new('-s', '-s', [CompletionResultType]::ParameterName, 'Start or join a session')
             [CompletionResult]::new('--session', '--session', [CompletionResultType]::ParameterName, 'Start or join a session')
             [CompletionResult]::new('--empty-session', '--empty-session', [CompletionResultType]::ParameterName, 'Ensure the session is empty')
@@ -35,21 +39,57 @@ Register-ArgumentCompleter -Native -CommandName 'aichat' -ScriptBlock {
             [CompletionResult]::new('--rag', '--rag', [CompletionResultType]::ParameterName, 'Start a RAG')
             [CompletionResult]::new('--rebuild-rag', '--rebuild-rag', [CompletionResultType]::ParameterName, 'Rebuild the RAG to sync document changes')
             [CompletionResult]::new('--serve', '--serve', [CompletionResultType]::ParameterName, 'Serve the LLM API and WebAPP')
+            [CompletionResult]::new('--repl', '--repl', [CompletionResultType]::ParameterName, 'Open the REPL with the input text as the first message')
+            [CompletionResult]::new('--repl-stdin', '--repl-stdin', [CompletionResultType]::ParameterName, 'Run the REPL over stdin/stdout, one JSON request and one JSON reply per line')
             [CompletionResult]::new('-e', '-e', [CompletionResultType]::ParameterName, 'Execute commands in natural language')
             [CompletionResult]::new('--execute', '--execute', [CompletionResultType]::ParameterName, 'Execute commands in natural language')
             [CompletionResult]::new('-c', '-c', [CompletionResultType]::ParameterName, 'Output code only')
             [CompletionResult]::new('--code', '--code', [CompletionResultType]::ParameterName, 'Output code only')
+            [CompletionResult]::new('-o', '-o', [CompletionResultType]::ParameterName, 'Write the answer to a file')
+            [CompletionResult]::new('--output', '--output', [CompletionResultType]::ParameterName, 'Write the answer to a file')
+            [CompletionResult]::new('--extract-code', '--extract-code', [CompletionResultType]::ParameterName, 'Write only the first code block to --output, optionally the first one in LANG')
             [CompletionResult]::new('-f', '-f', [CompletionResultType]::ParameterName, 'Include files, directories, or URLs')
             [CompletionResult]::new('--file', '--file', [CompletionResultType]::ParameterName, 'Include files, directories, or URLs')
             [CompletionResult]::new('-S', '-S', [CompletionResultType]::ParameterName, 'Turn off stream mode')
             [CompletionResult]::new('--no-stream', '--no-stream', [CompletionResultType]::ParameterName, 'Turn off stream mode')
+            [CompletionResult]::new('--stream-raw', '--stream-raw', [CompletionResultType]::ParameterName, 'Stream bare text deltas to stdout, or NDJSON frames with --stream-raw=ndjson')
+            [CompletionResult]::new('--format', '--format', [CompletionResultType]::ParameterName, 'Print the answer as text, or as a JSON object with tool calls, usage and timing; --list-models prints JSON too')
+            [CompletionResult]::new('--eval', '--eval', [CompletionResultType]::ParameterName, 'Run an eval suite and write a JSON report')
+            [CompletionResult]::new('--batch', '--batch', [CompletionResultType]::ParameterName, 'Submit the prompts of a JSONL file as an OpenAI or Claude batch job')
+            [CompletionResult]::new('--cron', '--cron', [CompletionResultType]::ParameterName, 'Run the prompt on a cron schedule, delivering each answer to --sink')
+            [CompletionResult]::new('--sink', '--sink', [CompletionResultType]::ParameterName, 'Where --cron answers go: file:<path>, webhook:<url>, command:<cmd>')
+            [CompletionResult]::new('--daemonize', '--daemonize', [CompletionResultType]::ParameterName, 'Keep --cron running in the background')
+            [CompletionResult]::new('--jsonl', '--jsonl', [CompletionResultType]::ParameterName, 'Answer JSONL requests from stdin with one JSON result per line')
+            [CompletionResult]::new('--map', '--map', [CompletionResultType]::ParameterName, 'Apply --template to every file matching the glob')
+            [CompletionResult]::new('--out-dir', '--out-dir', [CompletionResultType]::ParameterName, 'Directory where --map writes the result of each file')
+            [CompletionResult]::new('--concurrency', '--concurrency', [CompletionResultType]::ParameterName, 'Number of files --map processes at the same time')
+            [CompletionResult]::new('--offline', '--offline', [CompletionResultType]::ParameterName, 'Only use local models (e.g. ollama) and skip remote urls')
+            [CompletionResult]::new('--init', '--init', [CompletionResultType]::ParameterName, 'Write a config file without prompts, API keys are read from env')
+            [CompletionResult]::new('--platform', '--platform', [CompletionResultType]::ParameterName, 'Platform of the config written by --init')
+            [CompletionResult]::new('-y', '-y', [CompletionResultType]::ParameterName, 'Overwrite the existing config file with --init')
+            [CompletionResult]::new('--yes', '--yes', [CompletionResultType]::ParameterName, 'Overwrite the existing config file with --init')
             [CompletionResult]::new('--dry-run', '--dry-run', [CompletionResultType]::ParameterName, 'Display the message without sending it')
             [CompletionResult]::new('--info', '--info', [CompletionResultType]::ParameterName, 'Display information')
-            [CompletionResult]::new('--list-models', '--list-models', [CompletionResultType]::ParameterName, 'List all available chat models')
+            [CompletionResult]::new('--list-models', '--list-models', [CompletionResultType]::ParameterName, 'List all available chat models, optionally matching a pattern')
+            [CompletionResult]::new('--detail', '--detail', [CompletionResultType]::ParameterName, 'Show context window, max output, capabilities and prices with --list-models')
+            [CompletionResult]::new('--sync-models', '--sync-models', [CompletionResultType]::ParameterName, 'Download the latest model list into the config directory')
+            [CompletionResult]::new('--merge-local', '--merge-local', [CompletionResultType]::ParameterName, 'Fold the local models.yaml into the list downloaded by --sync-models')
+            [CompletionResult]::new('--export-profile', '--export-profile', [CompletionResultType]::ParameterName, 'Bundle the config, roles, templates, agents and chosen RAGs into an archive (e.g. profile.tar.gz)')
+            [CompletionResult]::new('--exclude-keys', '--exclude-keys', [CompletionResultType]::ParameterName, 'Leave API keys, credentials, webhooks and the .env file out of --export-profile')
+            [CompletionResult]::new('--import-profile', '--import-profile', [CompletionResultType]::ParameterName, 'Unpack an archive made by --export-profile into the config directory')
             [CompletionResult]::new('--list-roles', '--list-roles', [CompletionResultType]::ParameterName, 'List all roles')
+            [CompletionResult]::new('--dedupe-roles', '--dedupe-roles', [CompletionResultType]::ParameterName, 'Find roles with alike prompts, then merge or delete them')
+            [CompletionResult]::new('--cleanup', '--cleanup', [CompletionResultType]::ParameterName, 'Remove old temp sessions, logs and RAG caches')
             [CompletionResult]::new('--list-sessions', '--list-sessions', [CompletionResultType]::ParameterName, 'List all sessions')
+            [CompletionResult]::new('--show-session', '--show-session', [CompletionResultType]::ParameterName, 'Show a session transcript without joining it')
             [CompletionResult]::new('--list-agents', '--list-agents', [CompletionResultType]::ParameterName, 'List all agents')
             [CompletionResult]::new('--list-rags', '--list-rags', [CompletionResultType]::ParameterName, 'List all RAGs')
+            [CompletionResult]::new('--install-agent', '--install-agent', [CompletionResultType]::ParameterName, 'Install an agent from a git repository (e.g. github:user/repo#tag)')
+            [CompletionResult]::new('--update-agent', '--update-agent', [CompletionResultType]::ParameterName, 'Fetch an agent installed with --install-agent again')
+            [CompletionResult]::new('--remove-agent', '--remove-agent', [CompletionResultType]::ParameterName, 'Remove an agent installed with --install-agent')
+            [CompletionResult]::new('--bug-report', '--bug-report', [CompletionResultType]::ParameterName, 'Print the environment, a redacted config summary and the last logged error for a bug report')
+            [CompletionResult]::new('--check-tools', '--check-tools', [CompletionResultType]::ParameterName, 'Check that function calling is set up and every declared tool runs with --help')
+            [CompletionResult]::new('--text', '--text', [CompletionResultType]::ParameterName, 'Input text taken as is, even when it starts with a dash')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('-V', '-V', [CompletionResultType]::ParameterName, 'Print version')
@@ -73,9 +113,9 @@ Register-ArgumentCompleter -Native -CommandName 'aichat' -ScriptBlock {
             $completions = Get-AichatValues "--list-models"
         } elseif ($flag -ceq "-r" -or $flag -eq "--role") {
             $completions = Get-AichatValues "--list-roles"
-        } elseif ($flag -ceq "-s" -or $flag -eq "--session") {
+        } elseif ($flag -ceq "-s" -or $flag -eq "--session" -or $flag -eq "--show-session") {
             $completions = Get-AichatValues "--list-sessions"
-        } elseif ($flag -ceq "-a" -or $flag -eq "--agent") {
+        } elseif ($flag -ceq "-a" -or $flag -eq "--agent" -or $flag -eq "--update-agent" -or $flag -eq "--remove-agent") {
             $completions = Get-AichatValues "--list-agents"
         } elseif ($flag -ceq "-R" -or $flag -eq "--rag") {
             $completions = Get-AichatValues "--list-rags"
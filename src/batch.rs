@@ -0,0 +1,388 @@
+use crate::client::{catch_error, Client, Model, RequestData};
+use crate::config::{GlobalConfig, Input};
+use crate::utils::*;
+
+use anyhow::{anyhow, bail, Context, Result};
+use indexmap::IndexMap;
+use reqwest::{multipart, Client as ReqwestClient, Method, RequestBuilder};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Provider {
+    OpenAI,
+    Claude,
+}
+
+/// Batch endpoints derived from the chat request of the model, so they share its api base and auth headers.
+#[derive(Debug)]
+struct BatchApi {
+    provider: Provider,
+    api_base: String,
+    endpoint: String,
+    headers: IndexMap<String, String>,
+}
+
+type JobResult = std::result::Result<String, String>;
+
+/// Submits the jobs as a provider batch, waits until it ends and writes the results next to the jobs file.
+///
+/// Each line of the jobs file is a prompt string or an object with `prompt` and an optional `custom_id`.
+pub async fn run(config: &GlobalConfig, jobs_path: &str, abort_signal: AbortSignal) -> Result<()> {
+    let jobs_path = Path::new(jobs_path);
+    let content = fs::read_to_string(jobs_path)
+        .with_context(|| format!("Failed to read batch jobs at '{}'", jobs_path.display()))?;
+    let jobs = parse_jobs(&content)
+        .with_context(|| format!("Invalid batch jobs at '{}'", jobs_path.display()))?;
+    if jobs.is_empty() {
+        bail!("No jobs in '{}'", jobs_path.display());
+    }
+
+    let mut client: Option<Box<dyn Client>> = None;
+    let mut requests = vec![];
+    for (custom_id, prompt) in &jobs {
+        let input = Input::from_str(config, prompt, None);
+        let client = match client.as_ref() {
+            Some(v) => v,
+            None => client.insert(input.create_client()?),
+        };
+        let http_client = client.build_client()?;
+        let data = input.prepare_completion_data(client.model(), false)?;
+        let mut request_data = client
+            .chat_completions_request_data(&http_client, data)
+            .await?;
+        client.patch_request_data(&mut request_data);
+        if let Some(body) = request_data.body.as_object_mut() {
+            body.remove("stream");
+        }
+        requests.push((custom_id.clone(), request_data));
+    }
+    let client = client.ok_or_else(|| anyhow!("No client"))?;
+    let api = BatchApi::new(client.model(), &requests[0].1)?;
+    if config.read().dry_run {
+        for entry in api.entries(&requests) {
+            println!("{entry}");
+        }
+        return Ok(());
+    }
+    let http_client = client.build_client()?;
+
+    let batch_id = api.submit(&http_client, &requests).await?;
+    println!(
+        "Submitted batch '{batch_id}' with {} jobs to {}",
+        jobs.len(),
+        client.model().id()
+    );
+    let (spinner, spinner_rx) = Spinner::create(&format!("Waiting for batch '{batch_id}'"));
+    let ret = abortable_run_with_spinner_rx(
+        api.wait(&http_client, &batch_id, &spinner),
+        spinner_rx,
+        abort_signal,
+    )
+    .await;
+    spinner.stop();
+    let mut results = ret.with_context(|| {
+        format!("The batch '{batch_id}' keeps running on the provider, check it there")
+    })?;
+
+    let output_path = jobs_path.with_extension("results.jsonl");
+    let mut failed = 0;
+    let mut lines = vec![];
+    for (custom_id, _) in &jobs {
+        let line = match results.remove(custom_id) {
+            Some(Ok(text)) => json!({ "custom_id": custom_id, "text": text }),
+            Some(Err(error)) => {
+                failed += 1;
+                json!({ "custom_id": custom_id, "error": error })
+            }
+            None => {
+                failed += 1;
+                json!({ "custom_id": custom_id, "error": "No result" })
+            }
+        };
+        lines.push(line.to_string());
+    }
+    fs::write(&output_path, format!("{}\n", lines.join("\n"))).with_context(|| {
+        format!(
+            "Failed to write batch results to '{}'",
+            output_path.display()
+        )
+    })?;
+    println!("✓ Saved batch results to '{}'.", output_path.display());
+    if failed > 0 {
+        bail!("{failed} of {} jobs failed", jobs.len());
+    }
+    Ok(())
+}
+
+fn parse_jobs(content: &str) -> Result<Vec<(String, String)>> {
+    let mut jobs = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(line)
+            .with_context(|| format!("Invalid JSON at line {}", i + 1))?;
+        let default_id = format!("job-{}", i + 1);
+        let job = match &value {
+            Value::String(prompt) => (default_id, prompt.clone()),
+            Value::Object(obj) => match obj.get("prompt").and_then(|v| v.as_str()) {
+                Some(prompt) => {
+                    let custom_id = match obj.get("custom_id") {
+                        Some(Value::String(v)) => v.clone(),
+                        Some(Value::Number(v)) => v.to_string(),
+                        _ => default_id,
+                    };
+                    (custom_id, prompt.to_string())
+                }
+                None => bail!("Missing 'prompt' at line {}", i + 1),
+            },
+            _ => bail!("Expected a string or an object at line {}", i + 1),
+        };
+        if jobs.iter().any(|(custom_id, _)| custom_id == &job.0) {
+            bail!("Duplicate custom_id '{}' at line {}", job.0, i + 1);
+        }
+        jobs.push(job);
+    }
+    Ok(jobs)
+}
+
+impl BatchApi {
+    fn new(model: &Model, request_data: &RequestData) -> Result<Self> {
+        let (provider, api_base) =
+            if let Some(v) = request_data.url.strip_suffix("/chat/completions") {
+                (Provider::OpenAI, v)
+            } else if let Some(v) = request_data.url.strip_suffix("/messages") {
+                (Provider::Claude, v)
+            } else {
+                bail!(
+                    "'{}' has no batch API, --batch works with OpenAI and Claude models",
+                    model.id()
+                )
+            };
+        let endpoint = reqwest::Url::parse(&request_data.url)
+            .map(|v| v.path().to_string())
+            .with_context(|| format!("Invalid url '{}'", request_data.url))?;
+        let headers = request_data
+            .headers
+            .iter()
+            .filter(|(k, _)| !k.eq_ignore_ascii_case("content-type"))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        Ok(Self {
+            provider,
+            api_base: api_base.to_string(),
+            endpoint,
+            headers,
+        })
+    }
+
+    fn request(&self, client: &ReqwestClient, method: Method, url: &str) -> RequestBuilder {
+        let mut builder = client.request(method, url);
+        for (key, value) in &self.headers {
+            builder = builder.header(key, value);
+        }
+        builder
+    }
+
+    /// The per-job entries of the batch in the provider's format.
+    fn entries(&self, requests: &[(String, RequestData)]) -> Vec<Value> {
+        requests
+            .iter()
+            .map(|(custom_id, request_data)| match self.provider {
+                Provider::OpenAI => json!({
+                    "custom_id": custom_id,
+                    "method": "POST",
+                    "url": self.endpoint,
+                    "body": request_data.body,
+                }),
+                Provider::Claude => json!({ "custom_id": custom_id, "params": request_data.body }),
+            })
+            .collect()
+    }
+
+    async fn submit(
+        &self,
+        client: &ReqwestClient,
+        requests: &[(String, RequestData)],
+    ) -> Result<String> {
+        let entries = self.entries(requests);
+        let data = match self.provider {
+            Provider::OpenAI => {
+                let lines: Vec<String> = entries.iter().map(|v| v.to_string()).collect();
+                let part =
+                    multipart::Part::bytes(lines.join("\n").into_bytes()).file_name("batch.jsonl");
+                let form = multipart::Form::new()
+                    .text("purpose", "batch")
+                    .part("file", part);
+                let url = format!("{}/files", self.api_base);
+                let file = send(self.request(client, Method::POST, &url).multipart(form)).await?;
+                let file_id = file["id"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Invalid response data: {file}"))?;
+                let url = format!("{}/batches", self.api_base);
+                let body = json!({
+                    "input_file_id": file_id,
+                    "endpoint": self.endpoint,
+                    "completion_window": "24h",
+                });
+                send(self.request(client, Method::POST, &url).json(&body)).await?
+            }
+            Provider::Claude => {
+                let url = format!("{}/messages/batches", self.api_base);
+                let body = json!({ "requests": entries });
+                send(self.request(client, Method::POST, &url).json(&body)).await?
+            }
+        };
+        data["id"]
+            .as_str()
+            .map(|v| v.to_string())
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))
+    }
+
+    async fn wait(
+        &self,
+        client: &ReqwestClient,
+        batch_id: &str,
+        spinner: &Spinner,
+    ) -> Result<HashMap<String, JobResult>> {
+        let url = match self.provider {
+            Provider::OpenAI => format!("{}/batches/{batch_id}", self.api_base),
+            Provider::Claude => format!("{}/messages/batches/{batch_id}", self.api_base),
+        };
+        let result_urls = loop {
+            let data = send(self.request(client, Method::GET, &url)).await?;
+            let (status, counts) = match self.provider {
+                Provider::OpenAI => (
+                    data["status"].as_str().unwrap_or_default(),
+                    &data["request_counts"],
+                ),
+                Provider::Claude => (
+                    data["processing_status"].as_str().unwrap_or_default(),
+                    &data["request_counts"],
+                ),
+            };
+            match (self.provider, status) {
+                (Provider::OpenAI, "completed") => {
+                    break ["output_file_id", "error_file_id"]
+                        .iter()
+                        .filter_map(|key| data[key].as_str())
+                        .map(|id| format!("{}/files/{id}/content", self.api_base))
+                        .collect::<Vec<_>>();
+                }
+                (Provider::OpenAI, "failed" | "expired" | "cancelled") => {
+                    bail!("The batch '{batch_id}' {status}: {}", data["errors"])
+                }
+                (Provider::Claude, "ended") => {
+                    break data["results_url"]
+                        .as_str()
+                        .map(|v| vec![v.to_string()])
+                        .unwrap_or_default();
+                }
+                _ => {}
+            }
+            spinner.set_message(format!("Waiting for batch '{batch_id}', {status} {counts}"))?;
+            tokio::time::sleep(POLL_INTERVAL).await;
+        };
+        let mut results = HashMap::new();
+        for url in result_urls {
+            let res = self.request(client, Method::GET, &url).send().await?;
+            let status = res.status();
+            if !status.is_success() {
+                let data: Value = res.json().await?;
+                catch_error(&data, status.as_u16())?;
+                continue;
+            }
+            let text = res.text().await?;
+            results.extend(
+                text.lines()
+                    .filter_map(|line| parse_result_line(self.provider, line)),
+            );
+        }
+        Ok(results)
+    }
+}
+
+async fn send(builder: RequestBuilder) -> Result<Value> {
+    let res = builder.send().await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    catch_error(&data, status.as_u16())?;
+    Ok(data)
+}
+
+fn parse_result_line(provider: Provider, line: &str) -> Option<(String, JobResult)> {
+    let data: Value = serde_json::from_str(line).ok()?;
+    let custom_id = data["custom_id"].as_str()?.to_string();
+    let result = match provider {
+        Provider::OpenAI => {
+            match data["response"]["body"]["choices"][0]["message"]["content"].as_str() {
+                Some(text) => Ok(text.to_string()),
+                None => Err(error_message(&data["error"])
+                    .or_else(|| error_message(&data["response"]["body"]["error"]))
+                    .unwrap_or_else(|| data.to_string())),
+            }
+        }
+        Provider::Claude => match data["result"]["type"].as_str() {
+            Some("succeeded") => Ok(data["result"]["message"]["content"]
+                .as_array()
+                .map(|list| {
+                    list.iter()
+                        .filter_map(|v| v["text"].as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n\n")
+                })
+                .unwrap_or_default()),
+            Some("errored") => Err(error_message(&data["result"]["error"]["error"])
+                .unwrap_or_else(|| data["result"].to_string())),
+            Some(kind) => Err(kind.to_string()),
+            None => Err(data.to_string()),
+        },
+    };
+    Some((custom_id, result))
+}
+
+fn error_message(data: &Value) -> Option<String> {
+    data["message"].as_str().map(|v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_jobs() {
+        let jobs = parse_jobs("\"hello\"\n\n{\"custom_id\": \"a\", \"prompt\": \"hi\"}\n").unwrap();
+        assert_eq!(
+            jobs,
+            vec![
+                ("job-1".to_string(), "hello".to_string()),
+                ("a".to_string(), "hi".to_string())
+            ]
+        );
+        assert!(parse_jobs("{\"custom_id\": \"a\"}").is_err());
+        assert!(parse_jobs("{\"custom_id\": \"job-2\", \"prompt\": \"hi\"}\n\"hello\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_result_line() {
+        let line = r#"{"custom_id":"a","response":{"status_code":200,"body":{"choices":[{"message":{"content":"hi"}}]}},"error":null}"#;
+        assert_eq!(
+            parse_result_line(Provider::OpenAI, line),
+            Some(("a".into(), Ok("hi".into())))
+        );
+        let line = r#"{"custom_id":"b","result":{"type":"succeeded","message":{"content":[{"type":"text","text":"hi"}]}}}"#;
+        assert_eq!(
+            parse_result_line(Provider::Claude, line),
+            Some(("b".into(), Ok("hi".into())))
+        );
+        let line = r#"{"custom_id":"c","result":{"type":"errored","error":{"type":"error","error":{"type":"invalid_request_error","message":"bad"}}}}"#;
+        assert_eq!(
+            parse_result_line(Provider::Claude, line),
+            Some(("c".into(), Err("bad".into())))
+        );
+    }
+}
@@ -6,12 +6,21 @@ pub struct Cli {
     /// Select a LLM model
     #[clap(short, long)]
     pub model: Option<String>,
+    /// Compare responses from multiple models, separated by commas
+    #[clap(long, value_delimiter = ',', value_name = "MODELS")]
+    pub models: Vec<String>,
     /// Use the system prompt
     #[clap(long)]
     pub prompt: Option<String>,
     /// Select a role
     #[clap(short, long)]
     pub role: Option<String>,
+    /// Use a prompt template from the templates directory, or inline text with --map
+    #[clap(short = 't', long)]
+    pub template: Option<String>,
+    /// Set template variables
+    #[clap(long, value_name = "NAME=VALUE")]
+    pub var: Vec<String>,
     /// Start or join a session
     #[clap(short = 's', long)]
     pub session: Option<Option<String>>,
@@ -36,50 +45,195 @@ pub struct Cli {
     /// Serve the LLM API and WebAPP
     #[clap(long, value_name = "ADDRESS")]
     pub serve: Option<Option<String>>,
+    /// Open the REPL with the input text as the first message
+    #[clap(long, conflicts_with_all = ["execute", "output", "format", "jsonl", "batch", "eval", "map"])]
+    pub repl: bool,
+    /// Run the REPL over stdin/stdout, one JSON request ({"text"}) and one JSON reply per line
+    #[clap(long, conflicts_with_all = ["repl", "execute", "output", "format", "jsonl", "batch", "eval", "map", "file"])]
+    pub repl_stdin: bool,
     /// Execute commands in natural language
     #[clap(short = 'e', long)]
     pub execute: bool,
     /// Output code only
     #[clap(short = 'c', long)]
     pub code: bool,
+    /// Write the answer to a file
+    #[clap(short = 'o', long, value_name = "PATH", conflicts_with = "execute")]
+    pub output: Option<String>,
+    /// Write only the first code block to --output, optionally the first one in LANG
+    #[clap(long, value_name = "LANG", num_args = 0..=1, require_equals = true, requires = "output")]
+    pub extract_code: Option<Option<String>>,
     /// Include files, directories, or URLs
     #[clap(short = 'f', long, value_name = "FILE")]
     pub file: Vec<String>,
     /// Turn off stream mode
     #[clap(short = 'S', long)]
     pub no_stream: bool,
-    /// Display the message without sending it
+    /// Stream bare text deltas to stdout, or NDJSON frames with `--stream-raw=ndjson`
+    #[clap(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, value_parser = ["text", "ndjson"], conflicts_with_all = ["no_stream", "format", "execute", "repl", "repl_stdin", "jsonl"])]
+    pub stream_raw: Option<Option<String>>,
+    /// Print the answer as text, or as a JSON object with tool calls, usage and timing; --list-models prints JSON too
+    #[clap(long, value_name = "FORMAT", value_parser = ["text", "json"], conflicts_with = "execute")]
+    pub format: Option<String>,
+    /// Run an eval suite and write a JSON report
+    #[clap(long, value_name = "SUITE")]
+    pub eval: Option<String>,
+    /// Submit the prompts of a JSONL file as an OpenAI or Claude batch job
+    #[clap(long, value_name = "JOBS")]
+    pub batch: Option<String>,
+    /// Run the prompt on a cron schedule (e.g. '0 8 * * *'), delivering each answer to --sink
+    #[clap(long, value_name = "EXPR", conflicts_with_all = ["repl", "repl_stdin", "execute", "output", "format", "jsonl", "batch", "eval", "map"])]
+    pub cron: Option<String>,
+    /// Where --cron answers go: file:<path>, webhook:<url>, command:<cmd> [default: stdout]
+    #[clap(long, value_name = "SINK", requires = "cron")]
+    pub sink: Option<String>,
+    /// Keep --cron running in the background
+    #[clap(long, requires = "cron")]
+    pub daemonize: bool,
+    /// Answer JSONL requests ({"text", "role", "vars"}) from stdin with one JSON result per line
+    #[clap(long, conflicts_with_all = ["batch", "map", "file"])]
+    pub jsonl: bool,
+    /// Apply --template to every file matching the glob (e.g. 'src/**/*.rs'), filling {{content}}, {{path}} and {{filename}}
+    #[clap(long, value_name = "GLOB", requires_all = ["template", "out_dir"])]
+    pub map: Option<String>,
+    /// Directory where --map writes the result of each file
+    #[clap(long, value_name = "DIR", requires = "map")]
+    pub out_dir: Option<String>,
+    /// Number of files --map processes at the same time [default: 4]
+    #[clap(long, value_name = "NUM", requires = "map")]
+    pub concurrency: Option<usize>,
+    /// Only use local models (e.g. ollama) and skip remote urls
+    #[clap(long, conflicts_with = "sync_models")]
+    pub offline: bool,
+    /// Write a config file without prompts, API keys are read from env
     #[clap(long)]
-    pub dry_run: bool,
+    pub init: bool,
+    /// Platform of the config written by --init
+    #[clap(long, value_name = "PLATFORM", requires = "init")]
+    pub platform: Option<String>,
+    /// Overwrite the existing config file with --init
+    #[clap(short = 'y', long, requires = "init")]
+    pub yes: bool,
+    /// Display the message without sending it, or the full request with `--dry-run=request`
+    #[clap(long, value_name = "MODE", num_args = 0..=1, require_equals = true, value_parser = ["request"])]
+    pub dry_run: Option<Option<String>>,
     /// Display information
     #[clap(long)]
     pub info: bool,
-    /// List all available chat models
+    /// List all available chat models, optionally matching a pattern (e.g. 'ollama:*')
+    #[clap(long, value_name = "PATTERN", num_args = 0..=1)]
+    pub list_models: Option<Option<String>>,
+    /// Show context window, max output, capabilities and prices with --list-models
+    #[clap(long, requires = "list_models")]
+    pub detail: bool,
+    /// Download the latest model list into the config directory
     #[clap(long)]
-    pub list_models: bool,
+    pub sync_models: bool,
+    /// Fold the local models.yaml into the list downloaded by --sync-models
+    #[clap(long, requires = "sync_models")]
+    pub merge_local: bool,
+    /// Bundle the config, roles, templates, agents and chosen RAGs into an archive (e.g. profile.tar.gz)
+    #[clap(long, value_name = "FILE")]
+    pub export_profile: Option<String>,
+    /// Leave API keys, credentials, webhooks and the .env file out of --export-profile
+    #[clap(long, requires = "export_profile")]
+    pub exclude_keys: bool,
+    /// Unpack an archive made by --export-profile into the config directory
+    #[clap(long, value_name = "FILE", conflicts_with = "export_profile")]
+    pub import_profile: Option<String>,
     /// List all roles
     #[clap(long)]
     pub list_roles: bool,
+    /// Find roles with alike prompts, then merge or delete them
+    #[clap(long)]
+    pub dedupe_roles: bool,
+    /// Remove old temp sessions, logs and RAG caches as `cleanup` says, 7d, 30d and 90d when unset
+    #[clap(long)]
+    pub cleanup: bool,
     /// List all sessions
     #[clap(long)]
     pub list_sessions: bool,
+    /// Show a session transcript without joining it
+    #[clap(long, value_name = "SESSION")]
+    pub show_session: Option<String>,
     /// List all agents
     #[clap(long)]
     pub list_agents: bool,
     /// List all RAGs
     #[clap(long)]
     pub list_rags: bool,
+    /// Install an agent from a git repository (e.g. github:user/repo#tag)
+    #[clap(long, value_name = "SOURCE")]
+    pub install_agent: Option<String>,
+    /// Fetch an agent installed with --install-agent again
+    #[clap(long, value_name = "AGENT", conflicts_with = "install_agent")]
+    pub update_agent: Option<String>,
+    /// Remove an agent installed with --install-agent
+    #[clap(long, value_name = "AGENT", conflicts_with_all = ["install_agent", "update_agent"])]
+    pub remove_agent: Option<String>,
+    /// Print the environment, a redacted config summary and the last logged error for a bug report
+    #[clap(long)]
+    pub bug_report: bool,
+    /// Check that function calling is set up and every declared tool runs with --help
+    #[clap(long)]
+    pub check_tools: bool,
+    /// Input text taken as is, even when it starts with a dash
+    #[clap(long = "text", value_name = "TEXT", allow_hyphen_values = true)]
+    text_arg: Option<String>,
     /// Input text
-    #[clap(trailing_var_arg = true)]
+    #[clap(trailing_var_arg = true, allow_negative_numbers = true)]
     text: Vec<String>,
 }
 
 impl Cli {
+    pub fn is_json_format(&self) -> bool {
+        self.format.as_deref() == Some("json")
+    }
+
     pub fn text(&self) -> Option<String> {
-        let text = self.text.to_vec().join(" ");
+        let text = self
+            .text_arg
+            .iter()
+            .chain(self.text.iter())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ");
         if text.is_empty() {
             return None;
         }
         Some(text)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_text(args: &[&str]) -> Option<String> {
+        let args = std::iter::once("aichat").chain(args.iter().copied());
+        Cli::try_parse_from(args).unwrap().text()
+    }
+
+    #[test]
+    fn test_leading_dash_text() {
+        assert_eq!(
+            parse_text(&["-5", "degrees"]).as_deref(),
+            Some("-5 degrees")
+        );
+        assert_eq!(
+            parse_text(&["--", "-r is an odd flag"]).as_deref(),
+            Some("-r is an odd flag")
+        );
+        assert_eq!(
+            parse_text(&["--text", "-r is an odd flag"]).as_deref(),
+            Some("-r is an odd flag")
+        );
+        assert_eq!(
+            parse_text(&["-r", "coder", "explain", "-x"]).as_deref(),
+            Some("explain -x")
+        );
+        assert!(Cli::try_parse_from(["aichat", "--lsit-roles", "--dry-run"]).is_err());
+        let cli = Cli::try_parse_from(["aichat", "-r", "coder", "hi"]).unwrap();
+        assert_eq!(cli.role.as_deref(), Some("coder"));
+    }
+}
@@ -37,7 +37,7 @@ impl_client_trait!(
     (
         prepare_chat_completions,
         openai_chat_completions,
-        openai_chat_completions_streaming
+        openai_lenient_chat_completions_streaming
     ),
     (prepare_embeddings, openai_embeddings),
     (noop_prepare_rerank, noop_rerank),
@@ -61,6 +61,9 @@ fn prepare_chat_completions(
     let mut request_data = RequestData::new(url, body);
 
     request_data.header("api-key", api_key);
+    if let Some(request_id) = current_request_id() {
+        request_data.header("x-ms-client-request-id", request_id);
+    }
 
     Ok(request_data)
 }
@@ -80,6 +83,9 @@ fn prepare_embeddings(self_: &AzureOpenAIClient, data: &EmbeddingsData) -> Resul
     let mut request_data = RequestData::new(url, body);
 
     request_data.header("api-key", api_key);
+    if let Some(request_id) = current_request_id() {
+        request_data.header("x-ms-client-request-id", request_id);
+    }
 
     Ok(request_data)
 }
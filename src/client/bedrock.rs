@@ -46,6 +46,28 @@ impl BedrockClient {
         ("region", "AWS Region", true, PromptKind::String),
     ];
 
+    fn chat_completions_uri(&self, data: &ChatCompletionsData) -> String {
+        let model_name = &self.model.name();
+        if data.stream {
+            format!("/model/{model_name}/converse-stream")
+        } else {
+            format!("/model/{model_name}/converse")
+        }
+    }
+
+    /// The request before it's signed, also what `--dry-run=request` prints.
+    fn prepare_chat_completions(&self, data: ChatCompletionsData) -> Result<RequestData> {
+        let region = self.get_region()?;
+        let uri = self.chat_completions_uri(&data);
+        let url = format!("https://bedrock-runtime.{region}.amazonaws.com{uri}");
+
+        let body = build_chat_completions_body(data, &self.model)?;
+
+        let mut request_data = RequestData::new(url, body);
+        self.patch_request_data(&mut request_data);
+        Ok(request_data)
+    }
+
     fn chat_completions_builder(
         &self,
         client: &ReqwestClient,
@@ -56,23 +78,13 @@ impl BedrockClient {
         let region = self.get_region()?;
         let host = format!("bedrock-runtime.{region}.amazonaws.com");
 
-        let model_name = &self.model.name();
-
-        let uri = if data.stream {
-            format!("/model/{model_name}/converse-stream")
-        } else {
-            format!("/model/{model_name}/converse")
-        };
-
-        let body = build_chat_completions_body(data, &self.model)?;
+        let uri = self.chat_completions_uri(&data);
 
-        let mut request_data = RequestData::new("", body);
-        self.patch_request_data(&mut request_data);
         let RequestData {
             url: _,
             headers,
             body,
-        } = request_data;
+        } = self.prepare_chat_completions(data)?;
 
         let builder = aws_fetch(
             client,
@@ -160,6 +172,14 @@ impl Client for BedrockClient {
         chat_completions(builder).await
     }
 
+    async fn chat_completions_request_data(
+        &self,
+        _client: &ReqwestClient,
+        data: ChatCompletionsData,
+    ) -> Result<RequestData> {
+        self.prepare_chat_completions(data)
+    }
+
     async fn chat_completions_streaming_inner(
         &self,
         client: &ReqwestClient,
@@ -182,6 +202,7 @@ impl Client for BedrockClient {
 
 async fn chat_completions(builder: RequestBuilder) -> Result<ChatCompletionsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
 
@@ -198,6 +219,7 @@ async fn chat_completions_streaming(
     handler: &mut SseHandler,
 ) -> Result<()> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     if !status.is_success() {
         let data: Value = res.json().await?;
@@ -254,17 +276,15 @@ async fn chat_completions_streaming(
                                 function_arguments.push_str(input);
                             }
                         }
-                        "contentBlockStop" => {
-                            if !function_name.is_empty() {
-                                let arguments: Value = function_arguments.parse().with_context(|| {
-                                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                })?;
-                                handler.tool_call(ToolCall::new(
-                                    function_name.clone(),
-                                    arguments,
-                                    Some(function_id.clone()),
-                                ))?;
-                            }
+                        "contentBlockStop" if !function_name.is_empty() => {
+                            let arguments: Value = function_arguments.parse().with_context(|| {
+                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
+                            })?;
+                            handler.tool_call(ToolCall::new(
+                                function_name.clone(),
+                                arguments,
+                                Some(function_id.clone()),
+                            ))?;
                         }
                         _ => {}
                     }
@@ -286,6 +306,7 @@ async fn chat_completions_streaming(
 
 async fn embeddings(builder: RequestBuilder) -> Result<EmbeddingsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
 
@@ -309,6 +330,8 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         temperature,
         top_p,
         functions,
+        native_tools: _,
+        json_mode: _,
         stream: _,
     } = data;
 
@@ -490,6 +513,7 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         id: None,
         input_tokens: data["usage"]["inputTokens"].as_u64(),
         output_tokens: data["usage"]["outputTokens"].as_u64(),
+        request_ids: None,
     };
     Ok(output)
 }
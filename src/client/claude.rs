@@ -64,6 +64,7 @@ pub async fn claude_chat_completions(
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
     if !status.is_success() {
@@ -118,21 +119,19 @@ pub async fn claude_chat_completions_streaming(
                         function_arguments.push_str(partial_json);
                     }
                 }
-                "content_block_stop" => {
-                    if !function_name.is_empty() {
-                        let arguments: Value = if function_arguments.is_empty() {
-                            json!({})
-                        } else {
-                            function_arguments.parse().with_context(|| {
-                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                            })?
-                        };
-                        handler.tool_call(ToolCall::new(
-                            function_name.clone(),
-                            arguments,
-                            Some(function_id.clone()),
-                        ))?;
-                    }
+                "content_block_stop" if !function_name.is_empty() => {
+                    let arguments: Value = if function_arguments.is_empty() {
+                        json!({})
+                    } else {
+                        function_arguments.parse().with_context(|| {
+                            format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
+                        })?
+                    };
+                    handler.tool_call(ToolCall::new(
+                        function_name.clone(),
+                        arguments,
+                        Some(function_id.clone()),
+                    ))?;
                 }
                 _ => {}
             }
@@ -152,6 +151,8 @@ pub fn claude_build_chat_completions_body(
         temperature,
         top_p,
         functions,
+        native_tools: _,
+        json_mode: _,
         stream,
     } = data;
 
@@ -322,6 +323,7 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["output_tokens"].as_u64(),
+        request_ids: None,
     };
     Ok(output)
 }
@@ -111,6 +111,7 @@ async fn chat_completions(
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
     if !status.is_success() {
@@ -191,6 +192,7 @@ async fn chat_completions_streaming(
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
     if !status.is_success() {
@@ -251,6 +253,7 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["billed_units"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["billed_units"]["output_tokens"].as_u64(),
+        request_ids: None,
     };
     Ok(output)
 }
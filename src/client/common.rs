@@ -1,26 +1,61 @@
 use super::*;
 
 use crate::{
-    config::{GlobalConfig, Input},
-    function::{eval_tool_calls, FunctionDeclaration, ToolCall, ToolResult},
-    render::render_stream,
+    config::{ensure_parent_exists, Config, GlobalConfig, Input, LastExchange},
+    function::{
+        diagnose_text_tool_calls, eval_tool_calls, parse_prompt_tool_calls, FunctionDeclaration,
+        ToolCall, ToolResult,
+    },
+    render::{print_reply_images, render_stream, write_ndjson_frame, write_raw_text},
+    schedule::notify_webhook,
+    telemetry::{provider_span, record_error},
     utils::*,
 };
 
 use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
 use indexmap::IndexMap;
-use reqwest::{Client as ReqwestClient, RequestBuilder};
+use reqwest::{header::HeaderMap, Client as ReqwestClient, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::{future::Future, time::Duration};
+use std::{
+    fs::{read_to_string, write},
+    future::Future,
+    path::Path,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::unbounded_channel;
+use tracing::Instrument;
+
+const DEFAULT_STREAM_IDLE_TIMEOUT: u64 = 300;
 
 const MODELS_YAML: &str = include_str!("../../models.yaml");
+const MODELS_YAML_URL: &str = "https://raw.githubusercontent.com/sigoden/aichat/main/models.yaml";
+
+/// Response headers in which providers return their own id of a request
+const PROVIDER_REQUEST_ID_HEADERS: [&str; 5] = [
+    "x-request-id",
+    "request-id",
+    "apim-request-id",
+    "x-ms-request-id",
+    "x-amzn-requestid",
+];
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
 
 lazy_static::lazy_static! {
-    pub static ref ALL_PREDEFINED_MODELS: Vec<PredefinedModels> = serde_yaml::from_str(MODELS_YAML).unwrap();
+    static ref HTTP_CLIENTS: parking_lot::Mutex<IndexMap<String, ReqwestClient>> =
+        Default::default();
+    static ref PROVIDER_REQUEST_IDS: parking_lot::Mutex<IndexMap<String, String>> =
+        Default::default();
+    pub static ref ALL_PREDEFINED_MODELS: Vec<PredefinedModels> = load_predefined_models();
     static ref ESCAPE_SLASH_RE: Regex = Regex::new(r"(?<!\\)/").unwrap();
+    static ref STREAM_UNSUPPORTED_RE: Regex = Regex::new(
+        r"(?i)stream(ing)?\b[^.]{0,40}\b(not|n't|un)\s*(be\s*)?(supported|allowed|enabled)|\b(not|n't|un)\s*support\w*\b[^.]{0,40}stream"
+    )
+    .unwrap();
 }
 
 #[async_trait::async_trait]
@@ -38,31 +73,107 @@ pub trait Client: Sync + Send {
     fn model_mut(&mut self) -> &mut Model;
 
     fn build_client(&self) -> Result<ReqwestClient> {
-        let mut builder = ReqwestClient::builder();
+        let read_timeout = self.extra_config().and_then(|v| v.read_timeout);
+        self.build_client_with_read_timeout(read_timeout)
+    }
+
+    /// Client for streaming requests, where the read timeout becomes the idle timeout between chunks.
+    fn build_stream_client(&self) -> Result<ReqwestClient> {
+        let idle_timeout = self
+            .extra_config()
+            .and_then(|v| v.stream_idle_timeout)
+            .unwrap_or(DEFAULT_STREAM_IDLE_TIMEOUT);
+        self.build_client_with_read_timeout(Some(idle_timeout).filter(|v| *v > 0))
+    }
+
+    /// Builds the HTTP client once per distinct setting, so later requests reuse its connections.
+    fn build_client_with_read_timeout(&self, read_timeout: Option<u64>) -> Result<ReqwestClient> {
         let extra = self.extra_config();
         let timeout = extra.and_then(|v| v.connect_timeout).unwrap_or(10);
         let proxy = extra.and_then(|v| v.proxy.clone());
+        let user_agent = self.global_config().read().user_agent.clone();
+        let key = format!(
+            "{}|{proxy:?}|{user_agent:?}|{timeout}|{read_timeout:?}",
+            self.model().client_name()
+        );
+        if let Some(client) = HTTP_CLIENTS.lock().get(&key) {
+            return Ok(client.clone());
+        }
+        let mut builder = ReqwestClient::builder();
         builder = set_proxy(builder, proxy.as_ref())?;
-        if let Some(user_agent) = self.global_config().read().user_agent.as_ref() {
+        if let Some(user_agent) = user_agent.as_ref() {
             builder = builder.user_agent(user_agent);
         }
+        if let Some(read_timeout) = read_timeout {
+            builder = builder.read_timeout(Duration::from_secs(read_timeout));
+        }
         let client = builder
             .connect_timeout(Duration::from_secs(timeout))
             .build()
             .with_context(|| "Failed to build client")?;
+        HTTP_CLIENTS.lock().insert(key, client.clone());
         Ok(client)
     }
 
     async fn chat_completions(&self, input: Input) -> Result<ChatCompletionsOutput> {
         if self.global_config().read().dry_run {
-            let content = input.echo_messages();
+            let content = if self.global_config().read().cli_dry_run_request {
+                self.echo_request(&input, false).await?
+            } else {
+                input.echo_messages()
+            };
             return Ok(ChatCompletionsOutput::new(&content));
         }
         let client = self.build_client()?;
         let data = input.prepare_completion_data(self.model(), false)?;
-        self.chat_completions_inner(&client, data)
-            .await
-            .with_context(|| "Failed to call chat-completions api")
+        let request_id = new_request_id();
+        let log = RequestLog::start(
+            self,
+            &request_id,
+            "chat-completions",
+            false,
+            &data.log_body(),
+        );
+        let span = provider_span(self.model(), &request_id, "chat", false);
+        let ret = REQUEST_ID
+            .scope(
+                request_id.clone(),
+                self.chat_completions_inner(&client, data),
+            )
+            .instrument(span.clone())
+            .await;
+        let request_ids = take_request_ids(&request_id);
+        let mut ret =
+            ret.with_context(|| format!("Failed to call chat-completions api ({request_ids})"));
+        if let Ok(output) = ret.as_mut() {
+            output.request_ids = Some(request_ids.clone());
+            if has_masked_secrets() {
+                output.text = restore_secrets(&output.text);
+                for call in output.tool_calls.iter_mut() {
+                    map_json_strings(&mut call.arguments, &restore_secrets);
+                }
+            }
+            if let Some(names) = input.prompt_tool_names(self.model()) {
+                output.tool_calls = parse_prompt_tool_calls(&output.text, &names);
+            }
+        }
+        match &ret {
+            Ok(v) => {
+                span.record("gen_ai.usage.input_tokens", v.input_tokens);
+                span.record("gen_ai.usage.output_tokens", v.output_tokens);
+            }
+            Err(err) => record_error(&span, err),
+        }
+        if let Some(log) = log {
+            log.finish(
+                request_ids.provider_id.as_deref(),
+                match &ret {
+                    Ok(v) => Ok((v.text.clone().into(), v.input_tokens, v.output_tokens)),
+                    Err(err) => Err(err),
+                },
+            );
+        }
+        ret
     }
 
     async fn chat_completions_streaming(
@@ -75,6 +186,11 @@ pub trait Client: Sync + Send {
         tokio::select! {
             ret = async {
                 if self.global_config().read().dry_run {
+                    if self.global_config().read().cli_dry_run_request {
+                        let content = self.echo_request(&input, true).await?;
+                        handler.text(&content)?;
+                        return Ok(());
+                    }
                     let content = input.echo_messages();
                     let tokens = split_content(&content);
                     for token in tokens {
@@ -83,12 +199,60 @@ pub trait Client: Sync + Send {
                     }
                     return Ok(());
                 }
-                let client = self.build_client()?;
+                let client = self.build_stream_client()?;
                 let data = input.prepare_completion_data(self.model(), true)?;
-                self.chat_completions_streaming_inner(&client, handler, data).await
+                handler.set_restore_secrets(has_masked_secrets());
+                let request_id = new_request_id();
+                let log = RequestLog::start(
+                    self,
+                    &request_id,
+                    "chat-completions",
+                    true,
+                    &data.log_body(),
+                );
+                let span = provider_span(self.model(), &request_id, "chat", true);
+                let mut ret = REQUEST_ID
+                    .scope(
+                        request_id.clone(),
+                        self.chat_completions_streaming_inner(&client, handler, data.clone()),
+                    )
+                    .instrument(span.clone())
+                    .await;
+                // A stall before anything was streamed is safe to retry once.
+                if ret.as_ref().is_err_and(is_stream_idle_timeout)
+                    && handler.buffer().is_empty()
+                    && handler.tool_calls().is_empty()
+                {
+                    debug!("retry the stalled stream of {request_id}");
+                    ret = REQUEST_ID
+                        .scope(
+                            request_id.clone(),
+                            self.chat_completions_streaming_inner(&client, handler, data),
+                        )
+                        .instrument(span.clone())
+                        .await;
+                }
+                let request_ids = take_request_ids(&request_id);
+                let ret = ret
+                    .with_context(|| format!("Failed to call chat-completions api ({request_ids})"));
+                if let Err(err) = &ret {
+                    record_error(&span, err);
+                } else if let Some(names) = input.prompt_tool_names(self.model()) {
+                    for call in parse_prompt_tool_calls(handler.buffer(), &names) {
+                        handler.tool_call(call)?;
+                    }
+                }
+                if let Some(log) = log {
+                    log.finish(request_ids.provider_id.as_deref(), match &ret {
+                        Ok(_) => Ok((handler.buffer().into(), None, None)),
+                        Err(err) => Err(err),
+                    });
+                }
+                handler.set_request_ids(request_ids);
+                ret
             } => {
                 handler.done();
-                ret.with_context(|| "Failed to call chat-completions api")
+                ret
             }
             _ = wait_abort_signal(&abort_signal) => {
                 handler.done();
@@ -97,18 +261,80 @@ pub trait Client: Sync + Send {
         }
     }
 
+    /// Renders the chat-completions request that would be sent, with secrets redacted.
+    async fn echo_request(&self, input: &Input, stream: bool) -> Result<String> {
+        let client = self.build_client()?;
+        let data = input.prepare_completion_data(self.model(), stream)?;
+        let mut request_data = self.chat_completions_request_data(&client, data).await?;
+        self.patch_request_data(&mut request_data);
+        Ok(request_data.echo())
+    }
+
     async fn embeddings(&self, data: &EmbeddingsData) -> Result<Vec<Vec<f32>>> {
         let client = self.build_client()?;
-        self.embeddings_inner(&client, data)
-            .await
-            .context("Failed to call embeddings api")
+        let request_id = new_request_id();
+        let log = RequestLog::start(
+            self,
+            &request_id,
+            "embeddings",
+            false,
+            &json!({ "texts": data.texts, "query": data.query }),
+        );
+        let span = provider_span(self.model(), &request_id, "embeddings", false);
+        let ret = REQUEST_ID
+            .scope(request_id.clone(), self.embeddings_inner(&client, data))
+            .instrument(span.clone())
+            .await;
+        let request_ids = take_request_ids(&request_id);
+        let ret = ret.with_context(|| format!("Failed to call embeddings api ({request_ids})"));
+        if let Err(err) = &ret {
+            record_error(&span, err);
+        }
+        if let Some(log) = log {
+            log.finish(
+                request_ids.provider_id.as_deref(),
+                match &ret {
+                    Ok(v) => Ok((json!({ "embeddings": v.len() }), None, None)),
+                    Err(err) => Err(err),
+                },
+            );
+        }
+        ret
     }
 
     async fn rerank(&self, data: &RerankData) -> Result<RerankOutput> {
         let client = self.build_client()?;
-        self.rerank_inner(&client, data)
-            .await
-            .context("Failed to call rerank api")
+        let request_id = new_request_id();
+        let log = RequestLog::start(
+            self,
+            &request_id,
+            "rerank",
+            false,
+            &json!({ "query": data.query, "documents": data.documents, "top_n": data.top_n }),
+        );
+        let span = provider_span(self.model(), &request_id, "rerank", false);
+        let ret = REQUEST_ID
+            .scope(request_id.clone(), self.rerank_inner(&client, data))
+            .instrument(span.clone())
+            .await;
+        let request_ids = take_request_ids(&request_id);
+        let ret = ret.with_context(|| format!("Failed to call rerank api ({request_ids})"));
+        if let Err(err) = &ret {
+            record_error(&span, err);
+        }
+        if let Some(log) = log {
+            log.finish(request_ids.provider_id.as_deref(), match &ret {
+                Ok(v) => Ok((
+                    v.iter()
+                        .map(|v| json!({ "index": v.index, "relevance_score": v.relevance_score }))
+                        .collect(),
+                    None,
+                    None,
+                )),
+                Err(err) => Err(err),
+            });
+        }
+        ret
     }
 
     async fn chat_completions_inner(
@@ -117,6 +343,14 @@ pub trait Client: Sync + Send {
         data: ChatCompletionsData,
     ) -> Result<ChatCompletionsOutput>;
 
+    async fn chat_completions_request_data(
+        &self,
+        _client: &ReqwestClient,
+        _data: ChatCompletionsData,
+    ) -> Result<RequestData> {
+        bail!("The client doesn't support printing the request")
+    }
+
     async fn chat_completions_streaming_inner(
         &self,
         client: &ReqwestClient,
@@ -189,6 +423,8 @@ impl Default for ClientConfig {
 pub struct ExtraConfig {
     pub proxy: Option<String>,
     pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub stream_idle_timeout: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -239,6 +475,15 @@ impl RequestData {
         debug!("Request {url} {body}");
 
         let mut builder = client.post(url);
+        if let Some(request_id) = current_request_id() {
+            // Clients that pass the id in their own header (e.g. Azure) already have one
+            let has_request_id = headers
+                .keys()
+                .any(|v| v.to_lowercase().ends_with("client-request-id"));
+            if !has_request_id {
+                builder = builder.header("X-Client-Request-Id", request_id);
+            }
+        }
         for (key, value) in headers {
             builder = builder.header(key, value);
         }
@@ -246,6 +491,32 @@ impl RequestData {
         builder
     }
 
+    pub fn echo(&self) -> String {
+        let headers: IndexMap<&str, String> = self
+            .headers
+            .iter()
+            .map(|(key, value)| {
+                let lower_key = key.to_lowercase();
+                let value = if ["auth", "key", "token", "secret"]
+                    .iter()
+                    .any(|v| lower_key.contains(v))
+                {
+                    "***".to_string()
+                } else {
+                    redact_secrets(value)
+                };
+                (key.as_str(), value)
+            })
+            .collect();
+        let data = json!({
+            "url": redact_secrets(&self.url),
+            "headers": headers,
+            "body": self.body,
+        });
+        let data = redact_secrets(&serde_json::to_string_pretty(&data).unwrap_or_default());
+        format!("```json\n{data}\n```")
+    }
+
     pub fn apply_patch(&mut self, patch: Value) {
         if let Some(patch_url) = patch["url"].as_str() {
             self.url = patch_url.into();
@@ -263,15 +534,31 @@ impl RequestData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChatCompletionsData {
     pub messages: Vec<Message>,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
     pub functions: Option<Vec<FunctionDeclaration>>,
+    pub native_tools: Vec<String>,
+    /// Ask for a JSON object as the answer, only set when the model supports it.
+    pub json_mode: bool,
     pub stream: bool,
 }
 
+impl ChatCompletionsData {
+    /// Provider-agnostic view of the request for `log_requests`.
+    pub fn log_body(&self) -> Value {
+        json!({
+            "messages": self.messages,
+            "temperature": self.temperature,
+            "top_p": self.top_p,
+            "functions": self.functions.as_ref().map(|v| v.iter().map(|v| v.name.clone()).collect::<Vec<_>>()),
+            "json_mode": self.json_mode,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ChatCompletionsOutput {
     pub text: String,
@@ -279,6 +566,7 @@ pub struct ChatCompletionsOutput {
     pub id: Option<String>,
     pub input_tokens: Option<u64>,
     pub output_tokens: Option<u64>,
+    pub request_ids: Option<RequestIds>,
 }
 
 impl ChatCompletionsOutput {
@@ -290,6 +578,32 @@ impl ChatCompletionsOutput {
     }
 }
 
+/// Our id of a provider call and the one the provider gave it, if it sent one back.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestIds {
+    pub id: String,
+    pub provider_id: Option<String>,
+}
+
+impl RequestIds {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "request_id": self.id,
+            "provider_request_id": self.provider_id,
+        })
+    }
+}
+
+impl std::fmt::Display for RequestIds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request_id: {}", self.id)?;
+        if let Some(provider_id) = &self.provider_id {
+            write!(f, ", provider_request_id: {provider_id}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct EmbeddingsData {
     pub texts: Vec<String>,
@@ -331,6 +645,192 @@ pub struct RerankResult {
 
 pub type PromptAction<'a> = (&'a str, &'a str, bool, PromptKind);
 
+/// Downloads the latest `models.yaml`, optionally folding the local overlay into it.
+pub async fn sync_models(merge_local: bool) -> Result<()> {
+    let (content, _) = fetch(&Default::default(), MODELS_YAML_URL, false)
+        .await
+        .with_context(|| format!("Failed to fetch '{MODELS_YAML_URL}'"))?;
+    let mut models: serde_yaml::Value =
+        serde_yaml::from_str(&content).context("Invalid upstream models.yaml")?;
+    serde_yaml::from_value::<Vec<PredefinedModels>>(models.clone())
+        .context("Invalid upstream models.yaml")?;
+    let content = match read_models_file(&Config::models_file()) {
+        Some(overlay) if merge_local => {
+            let (added, patched) = merge_models(&mut models, overlay);
+            println!("Merged the local models: {added} added, {patched} patched");
+            serde_yaml::to_string(&models)?
+        }
+        _ => content,
+    };
+    let path = Config::synced_models_file();
+    ensure_parent_exists(&path)?;
+    write(&path, content)
+        .with_context(|| format!("Failed to write models to '{}'", path.display()))?;
+    println!("✓ Synced models to '{}'", path.display());
+    Ok(())
+}
+
+/// Id of the provider call in flight, forwarded as a request header.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|v| v.clone()).ok()
+}
+
+/// Keeps the id the provider gave the call in flight, read from its response headers.
+pub fn note_provider_request_id(headers: &HeaderMap) {
+    let Some(request_id) = current_request_id() else {
+        return;
+    };
+    let provider_request_id = PROVIDER_REQUEST_ID_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name)?.to_str().ok());
+    if let Some(provider_request_id) = provider_request_id {
+        PROVIDER_REQUEST_IDS
+            .lock()
+            .insert(request_id, provider_request_id.to_string());
+    }
+}
+
+/// Whether the client serves models from this machine or the local network, so it works offline.
+pub fn is_local_client(config: &Config, client_name: &str) -> bool {
+    config.clients.iter().any(|v| match v {
+        // Without an api_base, ollama runs on localhost
+        ClientConfig::OllamaConfig(c) => {
+            OllamaClient::name(c) == client_name
+                && c.api_base
+                    .as_deref()
+                    .filter(|v| !v.is_empty())
+                    .is_none_or(is_local_url)
+        }
+        ClientConfig::OpenAICompatibleConfig(c) => {
+            OpenAICompatibleClient::name(c) == client_name
+                && c.api_base.as_deref().is_some_and(is_local_url)
+        }
+        ClientConfig::OpenAIConfig(c) => {
+            OpenAIClient::name(c) == client_name && c.api_base.as_deref().is_some_and(is_local_url)
+        }
+        _ => false,
+    })
+}
+
+pub fn is_stream_idle_timeout(err: &anyhow::Error) -> bool {
+    err.chain().any(|v| {
+        v.is::<StreamIdleTimeout>()
+            || v.downcast_ref::<reqwest::Error>()
+                .is_some_and(|v| v.is_timeout())
+    })
+}
+
+/// Whether the request may succeed when sent again, e.g. after a rate limit or an outage.
+pub fn is_transient_error(err: &anyhow::Error) -> bool {
+    is_stream_idle_timeout(err)
+        || err.chain().any(|v| {
+            if let Some(err) = v.downcast_ref::<ApiError>() {
+                matches!(err.status, 408 | 409 | 429) || err.status >= 500
+            } else {
+                v.downcast_ref::<reqwest::Error>()
+                    .is_some_and(|v| v.is_connect())
+            }
+        })
+}
+
+fn new_request_id() -> String {
+    format!("req_{}", uuid::Uuid::new_v4().simple())
+}
+
+fn take_request_ids(request_id: &str) -> RequestIds {
+    RequestIds {
+        id: request_id.to_string(),
+        provider_id: PROVIDER_REQUEST_IDS.lock().shift_remove(request_id),
+    }
+}
+
+/// Starts from the synced or bundled models and applies the local `models.yaml` on top.
+fn load_predefined_models() -> Vec<PredefinedModels> {
+    let bundled = || serde_yaml::from_str(MODELS_YAML).unwrap();
+    let mut models = read_models_file(&Config::synced_models_file()).unwrap_or_else(bundled);
+    if let Some(overlay) = read_models_file(&Config::models_file()) {
+        merge_models(&mut models, overlay);
+    }
+    match serde_yaml::from_value(models) {
+        Ok(models) => models,
+        Err(err) => {
+            warn!("Ignored the synced and local models, {err}");
+            serde_yaml::from_str(MODELS_YAML).unwrap()
+        }
+    }
+}
+
+fn read_models_file(path: &Path) -> Option<serde_yaml::Value> {
+    let content = read_to_string(path).ok()?;
+    match serde_yaml::from_str::<serde_yaml::Value>(&content) {
+        Ok(value) if value.is_sequence() => Some(value),
+        Ok(_) => {
+            warn!("Ignored '{}', expected a list of platforms", path.display());
+            None
+        }
+        Err(err) => {
+            warn!("Ignored '{}', {err}", path.display());
+            None
+        }
+    }
+}
+
+/// Adds unknown platforms and models, and overrides the given fields of known models.
+/// Returns the number of added and patched models.
+fn merge_models(base: &mut serde_yaml::Value, overlay: serde_yaml::Value) -> (usize, usize) {
+    let (mut added, mut patched) = (0, 0);
+    let (Some(platforms), serde_yaml::Value::Sequence(overlay)) = (base.as_sequence_mut(), overlay)
+    else {
+        return (added, patched);
+    };
+    for entry in overlay {
+        let Some(platform) = entry["platform"].as_str() else {
+            continue;
+        };
+        let index = match platforms
+            .iter()
+            .position(|v| v["platform"].as_str() == Some(platform))
+        {
+            Some(index) => index,
+            None => {
+                let mut value = serde_yaml::Mapping::new();
+                value.insert("platform".into(), platform.into());
+                platforms.push(value.into());
+                platforms.len() - 1
+            }
+        };
+        let target = &mut platforms[index]["models"];
+        if !target.is_sequence() {
+            *target = serde_yaml::Value::Sequence(vec![]);
+        }
+        let Some(target) = target.as_sequence_mut() else {
+            continue;
+        };
+        for model in entry["models"].as_sequence().cloned().unwrap_or_default() {
+            let Some(name) = model["name"].as_str() else {
+                continue;
+            };
+            match target.iter_mut().find(|v| v["name"].as_str() == Some(name)) {
+                Some(existing) => {
+                    if let (Some(existing), Some(fields)) =
+                        (existing.as_mapping_mut(), model.as_mapping())
+                    {
+                        for (key, value) in fields {
+                            existing.insert(key.clone(), value.clone());
+                        }
+                    }
+                    patched += 1;
+                }
+                None => {
+                    target.push(model);
+                    added += 1;
+                }
+            }
+        }
+    }
+    (added, patched)
+}
+
 pub fn create_config(prompts: &[PromptAction], client: &str) -> Result<(String, Value)> {
     let mut config = json!({
         "type": client,
@@ -384,29 +884,87 @@ pub async fn call_chat_completions(
     client: &dyn Client,
     abort_signal: AbortSignal,
 ) -> Result<(String, Vec<ToolResult>)> {
+    let started_at = Instant::now();
     let ret = abortable_run_with_spinner(
         client.chat_completions(input.clone()),
         "Generating",
-        abort_signal,
+        abort_signal.clone(),
     )
     .await;
 
+    let stream_raw = client.global_config().read().cli_stream_raw.clone();
     match ret {
         Ok(ret) => {
             let ChatCompletionsOutput {
                 mut text,
                 tool_calls,
+                input_tokens,
+                output_tokens,
+                request_ids,
                 ..
             } = ret;
+            record_exchange(
+                client,
+                input,
+                &text,
+                &tool_calls,
+                (input_tokens, output_tokens),
+                started_at.elapsed(),
+                Ok(request_ids.as_ref()),
+            );
             if !text.is_empty() {
-                if extract_code && text.trim_start().starts_with("```") {
-                    text = extract_block(&text);
+                if extract_code {
+                    text = strip_think_tag(&text).to_string();
+                    if text.trim_start().starts_with("```") {
+                        text = extract_block(&text);
+                    }
+                }
+                match &stream_raw {
+                    Some(format) => write_raw_text(&text, format == "ndjson")?,
+                    None => {
+                        Config::print_answer(client.global_config(), &text)?;
+                        print_reply_images(&text);
+                    }
+                }
+            }
+            if stream_raw.as_deref() == Some("ndjson") {
+                print_done_frame(
+                    client,
+                    input,
+                    &text,
+                    &tool_calls,
+                    (input_tokens, output_tokens),
+                    request_ids.as_ref(),
+                )?;
+            }
+            print_usage(client, input, &text, input_tokens, output_tokens)?;
+            print_text_tool_call_hints(client, input, &text, &tool_calls);
+            if tool_calls.is_empty() {
+                notify_webhook(client.global_config(), input, Ok(&text));
+            }
+            Ok((
+                text,
+                eval_tool_calls(client.global_config(), input, tool_calls).await?,
+            ))
+        }
+        Err(err) => {
+            record_exchange(
+                client,
+                input,
+                "",
+                &[],
+                (None, None),
+                started_at.elapsed(),
+                Err(&err),
+            );
+            if !abort_signal.aborted() {
+                if stream_raw.as_deref() == Some("ndjson") {
+                    print_error_frame(&err)?;
                 }
-                client.global_config().read().print_markdown(&text)?;
+                notify_webhook(client.global_config(), input, Err(&err));
             }
-            Ok((text, eval_tool_calls(client.global_config(), tool_calls)?))
+            Err(err)
         }
-        Err(err) => Err(err),
     }
 }
 
@@ -415,6 +973,7 @@ pub async fn call_chat_completions_streaming(
     client: &dyn Client,
     abort_signal: AbortSignal,
 ) -> Result<(String, Vec<ToolResult>)> {
+    let started_at = Instant::now();
     let (tx, rx) = unbounded_channel();
     let mut handler = SseHandler::new(tx, abort_signal.clone());
 
@@ -429,23 +988,301 @@ pub async fn call_chat_completions_streaming(
 
     render_ret?;
 
-    let (text, tool_calls) = handler.take();
+    let usage = handler.usage();
+    let (text, tool_calls, request_ids) = handler.take();
+    record_exchange(
+        client,
+        input,
+        &text,
+        &tool_calls,
+        usage,
+        started_at.elapsed(),
+        send_ret.as_ref().map(|_| request_ids.as_ref()),
+    );
+    let stream_raw = client.global_config().read().cli_stream_raw.clone();
     match send_ret {
         Ok(_) => {
-            if !text.is_empty() && !text.ends_with('\n') {
-                println!();
+            match stream_raw.as_deref() {
+                Some("ndjson") => print_done_frame(
+                    client,
+                    input,
+                    &text,
+                    &tool_calls,
+                    usage,
+                    request_ids.as_ref(),
+                )?,
+                Some(_) => {}
+                None => {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        println!();
+                    }
+                    print_reply_images(&text);
+                    Config::page_streamed_answer(client.global_config(), &text)?;
+                }
+            }
+            print_usage(client, input, &text, usage.0, usage.1)?;
+            print_text_tool_call_hints(client, input, &text, &tool_calls);
+            if tool_calls.is_empty() {
+                notify_webhook(client.global_config(), input, Ok(&text));
             }
-            Ok((text, eval_tool_calls(client.global_config(), tool_calls)?))
+            Ok((
+                text,
+                eval_tool_calls(client.global_config(), input, tool_calls).await?,
+            ))
         }
         Err(err) => {
-            if !text.is_empty() {
-                println!();
+            match stream_raw.as_deref() {
+                Some("ndjson") => print_error_frame(&err)?,
+                Some(_) => {}
+                None => {
+                    if !text.is_empty() {
+                        println!();
+                    }
+                }
+            }
+            if !is_stream_unsupported_error(&err) {
+                notify_webhook(client.global_config(), input, Err(&err));
             }
             Err(err)
         }
     }
 }
 
+/// Sends the same input to several models concurrently and prints the answers in labeled sections.
+pub async fn compare_models(
+    config: &GlobalConfig,
+    input: &Input,
+    model_ids: &[String],
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let mut clients = vec![];
+    for model_id in model_ids {
+        let model = Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?;
+        let mut input = input.clone();
+        input.set_model(model);
+        let client = input.create_client()?;
+        clients.push((input, client));
+    }
+    let tasks = clients.iter().map(|(input, client)| async move {
+        let started_at = Instant::now();
+        let ret = client.chat_completions(input.clone()).await;
+        (ret, started_at.elapsed())
+    });
+    let results = abortable_run_with_spinner(
+        async { Ok(futures_util::future::join_all(tasks).await) },
+        "Generating",
+        abort_signal,
+    )
+    .await?;
+    for ((input, client), (ret, elapsed)) in clients.iter().zip(results) {
+        let model_id = client.model().id();
+        println!(
+            "{}",
+            color_text(&format!("━━━ {model_id} ━━━"), nu_ansi_term::Color::Cyan)
+        );
+        match ret {
+            Ok(output) => {
+                if !output.text.is_empty() {
+                    config.read().print_markdown(&output.text)?;
+                }
+                if !output.tool_calls.is_empty() {
+                    println!(
+                        "{}",
+                        warning_text(&format!(
+                            "Skipped {} tool call(s) while comparing.",
+                            output.tool_calls.len()
+                        ))
+                    );
+                }
+                let usage = format_usage(
+                    client.model(),
+                    input,
+                    &output.text,
+                    output.input_tokens,
+                    output.output_tokens,
+                )?;
+                println!(
+                    "{}",
+                    dimmed_text(&format!("Latency: {:.2}s, {usage}", elapsed.as_secs_f64()))
+                );
+            }
+            Err(err) => println!("{}", error_text(&format!("{err:?}"))),
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn print_usage(
+    client: &dyn Client,
+    input: &Input,
+    text: &str,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+) -> Result<()> {
+    if !client.global_config().read().show_usage {
+        return Ok(());
+    }
+    let usage = format_usage(client.model(), input, text, input_tokens, output_tokens)?;
+    eprintln!("{}", dimmed_text(&usage));
+    Ok(())
+}
+
+/// Warns when the model wrote a tool call as text, which otherwise fails silently.
+fn print_text_tool_call_hints(
+    client: &dyn Client,
+    input: &Input,
+    text: &str,
+    tool_calls: &[ToolCall],
+) {
+    if !tool_calls.is_empty() {
+        return;
+    }
+    let hints = diagnose_text_tool_calls(&client.global_config().read(), input.role(), text);
+    if let Some(hints) = hints {
+        let mut message = String::from("The reply holds a tool call as text, it wasn't run:");
+        for hint in hints {
+            message.push_str(&format!("\n  - {hint}"));
+        }
+        eprintln!("{}", warning_text(&message));
+    }
+}
+
+/// Ends a reply of `--stream-raw=ndjson` with its model, finish reason, tool calls and usage.
+fn print_done_frame(
+    client: &dyn Client,
+    input: &Input,
+    text: &str,
+    tool_calls: &[ToolCall],
+    (input_tokens, output_tokens): (Option<u64>, Option<u64>),
+    request_ids: Option<&RequestIds>,
+) -> Result<()> {
+    let ((input_tokens, input_estimated), (output_tokens, output_estimated)) =
+        usage_tokens(client.model(), input, text, input_tokens, output_tokens)?;
+    let finish_reason = finish_reason(!tool_calls.is_empty());
+    let tool_calls: Vec<Value> = tool_calls
+        .iter()
+        .map(|v| json!({ "id": v.id, "name": v.name, "arguments": v.arguments }))
+        .collect();
+    write_ndjson_frame(&json!({
+        "type": "done",
+        "model": client.model().id(),
+        "request_id": request_ids.map(|v| &v.id),
+        "provider_request_id": request_ids.and_then(|v| v.provider_id.as_ref()),
+        "finish_reason": finish_reason,
+        "tool_calls": tool_calls,
+        "usage": {
+            "input_tokens": input_tokens,
+            "output_tokens": output_tokens,
+            "estimated": input_estimated || output_estimated,
+        },
+    }))
+}
+
+/// Keeps what happened in the round for `.info last`, the tokens are left unknown if counting them fails.
+pub fn record_exchange(
+    client: &dyn Client,
+    input: &Input,
+    text: &str,
+    tool_calls: &[ToolCall],
+    (input_tokens, output_tokens): (Option<u64>, Option<u64>),
+    latency: Duration,
+    ret: std::result::Result<Option<&RequestIds>, &anyhow::Error>,
+) {
+    let (input_tokens, output_tokens, estimated) =
+        match usage_tokens(client.model(), input, text, input_tokens, output_tokens) {
+            Ok(((input_tokens, input_estimated), (output_tokens, output_estimated))) => (
+                Some(input_tokens),
+                Some(output_tokens),
+                input_estimated || output_estimated,
+            ),
+            Err(err) => {
+                debug!("Failed to count the tokens of the exchange, {err:#}");
+                (None, None, false)
+            }
+        };
+    let finish_reason = match ret {
+        Err(_) => "error",
+        Ok(_) => finish_reason(!tool_calls.is_empty()),
+    };
+    let mut config = client.global_config().write();
+    // Only the searches of this input, the RAG may still hold the sources of an earlier one
+    let rag_sources = match input.rag_name() {
+        Some(_) => config.rag.as_ref().and_then(|v| v.get_last_sources()),
+        None => None,
+    };
+    let exchange = LastExchange {
+        model_id: client.model().id(),
+        finish_reason: finish_reason.into(),
+        input_tokens,
+        output_tokens,
+        estimated,
+        latency,
+        rounds: 1,
+        tools: tool_calls.iter().map(|v| v.name.clone()).collect(),
+        rag_sources,
+        history_sources: input.history_sources(),
+        request_ids: ret.ok().flatten().cloned(),
+        error: ret.err().map(|v| format!("{v:#}")),
+    };
+    config.record_exchange(exchange, input.tool_calls().is_some());
+}
+
+fn finish_reason(has_tool_calls: bool) -> &'static str {
+    if has_tool_calls {
+        "tool_calls"
+    } else {
+        "stop"
+    }
+}
+
+fn print_error_frame(err: &anyhow::Error) -> Result<()> {
+    write_ndjson_frame(&json!({ "type": "error", "error": format!("{err:#}") }))
+}
+
+/// The prompt and completion tokens, counted with the tokenizer when the API didn't report them.
+fn usage_tokens(
+    model: &Model,
+    input: &Input,
+    text: &str,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+) -> Result<((u64, bool), (u64, bool))> {
+    let input_tokens = match input_tokens {
+        Some(v) => (v, false),
+        None => (model.total_tokens(&input.build_messages()?) as u64, true),
+    };
+    let output_tokens = match output_tokens {
+        Some(v) => (v, false),
+        None => (model.tokenizer().count(text) as u64, true),
+    };
+    Ok((input_tokens, output_tokens))
+}
+
+fn format_usage(
+    model: &Model,
+    input: &Input,
+    text: &str,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+) -> Result<String> {
+    let ((input_tokens, input_estimated), (output_tokens, output_estimated)) =
+        usage_tokens(model, input, text, input_tokens, output_tokens)?;
+    let format_tokens = |tokens: u64, estimated: bool| match estimated {
+        true => format!("~{tokens}"),
+        false => tokens.to_string(),
+    };
+    let mut usage = format!(
+        "Tokens: {} prompt + {} completion",
+        format_tokens(input_tokens, input_estimated),
+        format_tokens(output_tokens, output_estimated)
+    );
+    if let Some(cost) = model.cost(input_tokens, output_tokens) {
+        usage.push_str(&format!(", Cost: ${cost:.6}"));
+    }
+    Ok(usage)
+}
+
 #[allow(unused)]
 pub async fn chat_completions_as_streaming<F, Fut>(
     builder: RequestBuilder,
@@ -484,41 +1321,68 @@ pub fn catch_error(data: &Value, status: u16) -> Result<()> {
         return Ok(());
     }
     debug!("Invalid response, status: {status}, data: {data}");
+    let message = error_message(data, status);
+    Err(ApiError { status, message }.into())
+}
+
+/// An error response from the api, keeping the http status for callers that retry.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: u16,
+    message: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+fn error_message(data: &Value, status: u16) -> String {
     if let Some(error) = data["error"].as_object() {
         if let (Some(typ), Some(message)) = (
             json_str_from_map(error, "type"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (type: {typ})");
+            return format!("{message} (type: {typ})");
         } else if let (Some(typ), Some(message)) = (
             json_str_from_map(error, "code"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (code: {typ})");
+            return format!("{message} (code: {typ})");
         }
     } else if let Some(error) = data["errors"][0].as_object() {
         if let (Some(code), Some(message)) = (
             error.get("code").and_then(|v| v.as_u64()),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (status: {code})")
+            return format!("{message} (status: {code})");
         }
     } else if let Some(error) = data[0]["error"].as_object() {
         if let (Some(status), Some(message)) = (
             json_str_from_map(error, "status"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (status: {status})")
+            return format!("{message} (status: {status})");
         }
     } else if let (Some(detail), Some(status)) = (data["detail"].as_str(), data["status"].as_i64())
     {
-        bail!("{detail} (status: {status})");
+        return format!("{detail} (status: {status})");
     } else if let Some(error) = data["error"].as_str() {
-        bail!("{error}");
+        return error.to_string();
     } else if let Some(message) = data["message"].as_str() {
-        bail!("{message}");
+        return message.to_string();
     }
-    bail!("Invalid response data: {data} (status: {status})");
+    format!("Invalid response data: {data} (status: {status})")
+}
+
+/// Whether the request failed because the model or endpoint can't stream.
+pub fn is_stream_unsupported_error(err: &anyhow::Error) -> bool {
+    let message = format!("{err:#}");
+    message.contains("Invalid response event-stream")
+        || STREAM_UNSUPPORTED_RE.is_match(&message).unwrap_or_default()
 }
 
 pub fn json_str_from_map<'a>(
@@ -621,3 +1485,80 @@ fn split_content(text: &str) -> Vec<&str> {
         unicode_segmentation::UnicodeSegmentation::graphemes(text, true).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_models() {
+        let mut base: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+- platform: openai
+  models:
+    - name: gpt-4o
+      max_input_tokens: 128000
+      supports_vision: true
+"#,
+        )
+        .unwrap();
+        let overlay: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+- platform: openai
+  models:
+    - name: gpt-4o
+      max_input_tokens: 64000
+    - name: gpt-next
+- platform: my-vllm
+  models:
+    - name: llama-3
+      supports_function_calling: true
+"#,
+        )
+        .unwrap();
+        assert_eq!(merge_models(&mut base, overlay), (2, 1));
+        let models: Vec<PredefinedModels> = serde_yaml::from_value(base).unwrap();
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].models[0].max_input_tokens, Some(64000));
+        assert!(models[0].models[0].supports_vision);
+        assert_eq!(models[0].models[1].name, "gpt-next");
+        assert_eq!(models[1].platform, "my-vllm");
+        assert!(models[1].models[0].supports_function_calling);
+    }
+
+    #[test]
+    fn test_is_stream_unsupported_error() {
+        let check = |message: &str| is_stream_unsupported_error(&anyhow::anyhow!("{message}"));
+        assert!(check("Streaming is not supported for this model"));
+        assert!(check(
+            "This model does not support streaming (type: invalid_request_error)"
+        ));
+        assert!(check(
+            "Unsupported value: 'stream' does not support true with this model."
+        ));
+        assert!(check(
+            "Invalid response event-stream. content-type: application/json, data: {}"
+        ));
+        assert!(!check("The model `gpt-5` does not exist"));
+        assert!(!check(
+            "Incorrect API key provided. Streaming requests are billed normally"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_provider_request_id() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-amzn-requestid", "prov_1".parse().unwrap());
+        note_provider_request_id(&headers);
+        REQUEST_ID
+            .scope("req_1".into(), async { note_provider_request_id(&headers) })
+            .await;
+        let request_ids = take_request_ids("req_1");
+        assert_eq!(request_ids.provider_id.as_deref(), Some("prov_1"));
+        assert_eq!(
+            request_ids.to_string(),
+            "request_id: req_1, provider_request_id: prov_1"
+        );
+        assert_eq!(take_request_ids("req_1").to_string(), "request_id: req_1");
+    }
+}
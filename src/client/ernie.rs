@@ -45,6 +45,15 @@ impl Client for ErnieClient {
         chat_completions(builder, &self.model).await
     }
 
+    async fn chat_completions_request_data(
+        &self,
+        client: &ReqwestClient,
+        data: ChatCompletionsData,
+    ) -> Result<RequestData> {
+        prepare_access_token(self, client).await?;
+        prepare_chat_completions(self, data)
+    }
+
     async fn chat_completions_streaming_inner(
         &self,
         client: &ReqwestClient,
@@ -155,7 +164,9 @@ async fn chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let data: Value = builder.send().await?.json().await?;
+    let res = builder.send().await?;
+    note_provider_request_id(res.headers());
+    let data: Value = res.json().await?;
     maybe_catch_error(&data)?;
     debug!("non-stream-data: {data}");
     extract_chat_completions_text(&data)
@@ -189,7 +200,9 @@ async fn chat_completions_streaming(
 }
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
-    let data: Value = builder.send().await?.json().await?;
+    let res = builder.send().await?;
+    note_provider_request_id(res.headers());
+    let data: Value = res.json().await?;
     maybe_catch_error(&data)?;
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
@@ -208,7 +221,9 @@ struct EmbeddingsResBodyEmbedding {
 }
 
 async fn rerank(builder: RequestBuilder, _model: &Model) -> Result<RerankOutput> {
-    let data: Value = builder.send().await?.json().await?;
+    let res = builder.send().await?;
+    note_provider_request_id(res.headers());
+    let data: Value = res.json().await?;
     maybe_catch_error(&data)?;
     let res_body: GenericRerankResBody =
         serde_json::from_value(data).context("Invalid rerank data")?;
@@ -221,6 +236,8 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Valu
         temperature,
         top_p,
         functions,
+        native_tools: _,
+        json_mode: _,
         stream,
     } = data;
 
@@ -307,6 +324,7 @@ fn extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsOutput>
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["prompt_tokens"].as_u64(),
         output_tokens: data["usage"]["completion_tokens"].as_u64(),
+        request_ids: None,
     };
     Ok(output)
 }
@@ -110,6 +110,7 @@ fn prepare_embeddings(self_: &GeminiClient, data: &EmbeddingsData) -> Result<Req
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
     if !status.is_success() {
@@ -74,6 +74,9 @@ macro_rules! register_client {
 
         pub fn init_client(config: &$crate::config::GlobalConfig, model: Option<$crate::client::Model>) -> anyhow::Result<Box<dyn Client>> {
             let model = model.unwrap_or_else(|| config.read().model.clone());
+            if $crate::utils::is_offline() && !is_local_client(&config.read(), model.client_name()) {
+                anyhow::bail!("Unable to use the remote model '{}' in offline mode", model.id());
+            }
             None
             $(.or_else(|| $client::init(config, &model)))+
             .ok_or_else(|| {
@@ -118,19 +121,20 @@ macro_rules! register_client {
         static ALL_MODELS: std::sync::OnceLock<Vec<$crate::client::Model>> = std::sync::OnceLock::new();
 
         pub fn list_all_models(config: &$crate::config::Config) -> Vec<&'static $crate::client::Model> {
-            let models = ALL_MODELS.get_or_init(|| {
-                config
-                    .clients
-                    .iter()
-                    .flat_map(|v| match v {
-                        $(ClientConfig::$config(c) => $client::list_models(c),)+
-                        ClientConfig::Unknown => vec![],
-                    })
-                    .collect()
-            });
+            let models = ALL_MODELS.get_or_init(|| list_client_models(&config.clients));
             models.iter().collect()
         }
 
+        pub fn list_client_models(clients: &[ClientConfig]) -> Vec<$crate::client::Model> {
+            clients
+                .iter()
+                .flat_map(|v| match v {
+                    $(ClientConfig::$config(c) => $client::list_models(c),)+
+                    ClientConfig::Unknown => vec![],
+                })
+                .collect()
+        }
+
         pub fn list_models(config: &$crate::config::Config, model_type: $crate::client::ModelType) -> Vec<&'static $crate::client::Model> {
             list_all_models(config).into_iter().filter(|v| v.model_type() == model_type).collect()
         }
@@ -188,6 +192,14 @@ macro_rules! impl_client_trait {
                 $chat_completions(builder, self.model()).await
             }
 
+            async fn chat_completions_request_data(
+                &self,
+                _client: &reqwest::Client,
+                data: $crate::client::ChatCompletionsData,
+            ) -> anyhow::Result<$crate::client::RequestData> {
+                $prepare_chat_completions(self, data)
+            }
+
             async fn chat_completions_streaming_inner(
                 &self,
                 client: &reqwest::Client,
@@ -1,4 +1,7 @@
-use crate::{function::ToolResult, utils::dimmed_text};
+use crate::{
+    function::ToolResult,
+    utils::{dimmed_text, map_json_strings},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -140,6 +143,29 @@ impl MessageContent {
         }
     }
 
+    /// Applies `f` to every text in the content, tool call arguments and results included.
+    pub fn map_text(&mut self, f: impl Fn(&str) -> String) {
+        match self {
+            MessageContent::Text(text) => *text = f(text),
+            MessageContent::Array(list) => {
+                for item in list {
+                    if let MessageContentPart::Text { text } = item {
+                        *text = f(text)
+                    }
+                }
+            }
+            MessageContent::ToolCalls(MessageContentToolCalls {
+                tool_results, text, ..
+            }) => {
+                *text = f(text);
+                for tool_result in tool_results {
+                    map_json_strings(&mut tool_result.call.arguments, &f);
+                    map_json_strings(&mut tool_result.output, &f);
+                }
+            }
+        }
+    }
+
     pub fn to_text(&self) -> String {
         match self {
             MessageContent::Text(text) => text.to_string(),
@@ -203,6 +229,42 @@ pub fn patch_system_message(messages: &mut Vec<Message>) {
     }
 }
 
+/// Asks for a JSON object in the last user message, for models without a JSON mode.
+pub fn append_json_instruction(messages: &mut [Message]) {
+    const INSTRUCTION: &str = "Answer with a valid JSON object only.";
+    let Some(message) = messages.iter_mut().rev().find(|v| v.role.is_user()) else {
+        return;
+    };
+    match &mut message.content {
+        MessageContent::Text(text) => {
+            text.push_str("\n\n");
+            text.push_str(INSTRUCTION);
+        }
+        MessageContent::Array(list) => list.push(MessageContentPart::Text {
+            text: INSTRUCTION.into(),
+        }),
+        _ => {}
+    }
+}
+
+/// Replaces image parts with a text placeholder, returns how many were dropped.
+pub fn strip_image_parts(messages: &mut [Message]) -> usize {
+    let mut count = 0;
+    for message in messages {
+        if let MessageContent::Array(parts) = &mut message.content {
+            for part in parts.iter_mut() {
+                if let MessageContentPart::ImageUrl { .. } = part {
+                    *part = MessageContentPart::Text {
+                        text: "[image omitted]".into(),
+                    };
+                    count += 1;
+                }
+            }
+        }
+    }
+    count
+}
+
 pub fn extract_system_message(messages: &mut Vec<Message>) -> Option<String> {
     if messages[0].role.is_system() {
         let system_message = messages.remove(0);
@@ -210,3 +272,48 @@ pub fn extract_system_message(messages: &mut Vec<Message>) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_image_parts() {
+        let mut messages = vec![Message::new(
+            MessageRole::User,
+            MessageContent::Array(vec![
+                MessageContentPart::Text {
+                    text: "describe it".into(),
+                },
+                MessageContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: "data:image/png;base64,AAAA".into(),
+                    },
+                },
+            ]),
+        )];
+        assert_eq!(strip_image_parts(&mut messages), 1);
+        assert_eq!(
+            messages[0].content.to_text(),
+            "describe it\n\n[image omitted]"
+        );
+        assert_eq!(strip_image_parts(&mut messages), 0);
+    }
+
+    #[test]
+    fn test_append_json_instruction() {
+        let mut messages = vec![
+            Message::new(MessageRole::System, MessageContent::Text("be brief".into())),
+            Message::new(
+                MessageRole::User,
+                MessageContent::Text("list colors".into()),
+            ),
+        ];
+        append_json_instruction(&mut messages);
+        assert_eq!(messages[0].content.to_text(), "be brief");
+        assert_eq!(
+            messages[1].content.to_text(),
+            "list colors\n\nAnswer with a valid JSON object only."
+        );
+    }
+}
@@ -4,14 +4,18 @@ mod message;
 #[macro_use]
 mod macros;
 mod model;
+mod request_log;
 mod stream;
+mod tokenizer;
 
 pub use crate::function::ToolCall;
 pub use crate::utils::PromptKind;
 pub use common::*;
 pub use message::*;
 pub use model::*;
+pub use request_log::RequestLog;
 pub use stream::*;
+pub use tokenizer::Tokenizer;
 
 register_client!(
     (openai, "openai", OpenAIConfig, OpenAIClient),
@@ -24,6 +28,7 @@ register_client!(
     (gemini, "gemini", GeminiConfig, GeminiClient),
     (claude, "claude", ClaudeConfig, ClaudeClient),
     (cohere, "cohere", CohereConfig, CohereClient),
+    (ollama, "ollama", OllamaConfig, OllamaClient),
     (
         azure_openai,
         "azure-openai",
@@ -35,7 +40,7 @@ register_client!(
     (ernie, "ernie", ErnieConfig, ErnieClient),
 );
 
-pub const OPENAI_COMPATIBLE_PLATFORMS: [(&str, &str); 21] = [
+pub const OPENAI_COMPATIBLE_PLATFORMS: [(&str, &str); 20] = [
     ("ai21", "https://api.ai21.com/studio/v1"),
     ("cloudflare", ""),
     ("deepinfra", "https://api.deepinfra.com/v1/openai"),
@@ -48,7 +53,6 @@ pub const OPENAI_COMPATIBLE_PLATFORMS: [(&str, &str); 21] = [
     ("mistral", "https://api.mistral.ai/v1"),
     ("moonshot", "https://api.moonshot.cn/v1"),
     ("openrouter", "https://openrouter.ai/api/v1"),
-    ("ollama", ""),
     ("perplexity", "https://api.perplexity.ai"),
     (
         "qianwen",
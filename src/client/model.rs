@@ -1,18 +1,20 @@
 use super::{
     list_all_models, list_client_names,
     message::{Message, MessageContent, MessageContentPart},
-    ApiPatch, MessageContentToolCalls, RequestPatch,
+    ApiPatch, MessageContentToolCalls, RequestPatch, Tokenizer,
 };
 
 use crate::config::Config;
-use crate::utils::{estimate_token_length, format_option_value};
+use crate::utils::{color_text, dimmed_text, edit_distance, format_option_value};
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::fmt::Display;
 
 const PER_MESSAGES_TOKENS: usize = 5;
 const BASIS_TOKENS: usize = 2;
+const MAX_MODEL_SUGGESTIONS: usize = 3;
 
 #[derive(Debug, Clone)]
 pub struct Model {
@@ -44,7 +46,12 @@ impl Model {
             .collect()
     }
 
+    /// Looks up a model by its `<client>:<name>` id.
+    ///
+    /// Ids are matched ignoring case, and the client prefix may be left out when a single
+    /// client has a model of that name. Unknown ids fail with the closest known ones.
     pub fn retrieve_model(config: &Config, model_id: &str, model_type: ModelType) -> Result<Self> {
+        let model_id = model_id.trim();
         let models = list_all_models(config);
         let (client_name, model_name) = match model_id.split_once(':') {
             Some((client_name, model_name)) => {
@@ -65,6 +72,9 @@ impl Model {
                         bail!("Model '{model_id}' is not a {model_type} model")
                     }
                 }
+                if let Some(model) = find_normalized_model(&models, model_id, model_type)? {
+                    return Ok(model);
+                }
                 if list_client_names(config)
                     .into_iter()
                     .any(|v| *v == client_name)
@@ -82,9 +92,19 @@ impl Model {
                 {
                     return Ok((*found).clone());
                 }
+                if let Some(model) = find_normalized_model(&models, model_id, model_type)? {
+                    return Ok(model);
+                }
             }
         };
-        bail!("Unknown {model_type} model '{model_id}'")
+        let suggestions = suggest_model_ids(&models, model_id, model_type);
+        if suggestions.is_empty() {
+            bail!("Unknown {model_type} model '{model_id}'")
+        }
+        bail!(
+            "Unknown {model_type} model '{model_id}', did you mean {}?",
+            quote_ids(&suggestions, " or ")
+        )
     }
 
     pub fn id(&self) -> String {
@@ -178,10 +198,6 @@ impl Model {
         self.data.max_output_tokens
     }
 
-    pub fn supports_vision(&self) -> bool {
-        self.data.supports_vision
-    }
-
     pub fn no_stream(&self) -> bool {
         self.data.no_stream
     }
@@ -190,6 +206,34 @@ impl Model {
         self.data.no_system_message
     }
 
+    /// The temperature and top_p to send, after the policies of the model.
+    pub fn apply_param_policies(
+        &self,
+        temperature: Option<f64>,
+        top_p: Option<f64>,
+    ) -> (Option<f64>, Option<f64>) {
+        let id = self.id();
+        let apply = |policy: &Option<ParamPolicy>, name: &str, value: Option<f64>| match policy {
+            Some(policy) => policy.apply(&id, name, value),
+            None => value,
+        };
+        (
+            apply(&self.data.temperature, "temperature", temperature),
+            apply(&self.data.top_p, "top_p", top_p),
+        )
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            vision: self.data.supports_vision,
+            tools: self.data.supports_function_calling,
+            max_tools: self.data.max_tools,
+            streaming: !self.data.no_stream,
+            system_message: !self.data.no_system_message,
+            json_mode: self.data.supports_json_mode,
+        }
+    }
+
     pub fn max_tokens_per_chunk(&self) -> Option<usize> {
         self.data.max_tokens_per_chunk
     }
@@ -202,6 +246,15 @@ impl Model {
         self.data.max_batch_size
     }
 
+    /// Estimated cost in USD, prices in models.yaml are per million tokens.
+    pub fn cost(&self, input_tokens: u64, output_tokens: u64) -> Option<f64> {
+        let input_price = self.data.input_price?;
+        let output_price = self.data.output_price.unwrap_or_default();
+        Some(
+            (input_tokens as f64 * input_price + output_tokens as f64 * output_price) / 1_000_000.0,
+        )
+    }
+
     pub fn max_tokens_param(&self) -> Option<isize> {
         if self.data.require_max_tokens {
             self.data.max_output_tokens
@@ -223,27 +276,32 @@ impl Model {
         self
     }
 
+    pub fn tokenizer(&self) -> Tokenizer {
+        Tokenizer::new(self.data.tokenizer.as_deref(), &self.data.name)
+    }
+
     pub fn messages_tokens(&self, messages: &[Message]) -> usize {
+        let tokenizer = self.tokenizer();
         messages
             .iter()
             .map(|v| match &v.content {
-                MessageContent::Text(text) => estimate_token_length(text),
+                MessageContent::Text(text) => tokenizer.count(text),
                 MessageContent::Array(list) => list
                     .iter()
                     .map(|v| match v {
-                        MessageContentPart::Text { text } => estimate_token_length(text),
+                        MessageContentPart::Text { text } => tokenizer.count(text),
                         MessageContentPart::ImageUrl { .. } => 0,
                     })
                     .sum(),
                 MessageContent::ToolCalls(MessageContentToolCalls {
                     tool_results, text, ..
                 }) => {
-                    estimate_token_length(text)
+                    tokenizer.count(text)
                         + tool_results
                             .iter()
                             .map(|v| {
                                 serde_json::to_string(v)
-                                    .map(|v| estimate_token_length(&v))
+                                    .map(|v| tokenizer.count(&v))
                                     .unwrap_or_default()
                             })
                             .sum::<usize>()
@@ -276,6 +334,141 @@ impl Model {
     }
 }
 
+/// Renders chat models as an aligned table for `--list-models --detail`.
+pub fn render_models_table(models: &[&Model]) -> String {
+    let width = models
+        .iter()
+        .map(|v| v.id().len())
+        .max()
+        .unwrap_or_default()
+        .max(5);
+    let header = format!(
+        "{:<width$}  {:>9}  {:>10}  {:^6}  {:^5}  {:>8}  {:>8}",
+        "MODEL", "CONTEXT", "MAX OUTPUT", "VISION", "TOOLS", "INPUT $", "OUTPUT $"
+    );
+    let mut output = vec![color_text(&header, nu_ansi_term::Color::Cyan)];
+    let optional = |value: String, width: usize| {
+        if value == "-" {
+            dimmed_text(&format!("{value:>width$}"))
+        } else {
+            format!("{value:>width$}")
+        }
+    };
+    let flag = |value: bool, width: usize| {
+        if value {
+            color_text(&format!("{:^width$}", "✓"), nu_ansi_term::Color::Green)
+        } else {
+            dimmed_text(&format!("{:^width$}", "-"))
+        }
+    };
+    for model in models {
+        let data = model.data();
+        output.push(format!(
+            "{:<width$}  {}  {}  {}  {}  {}  {}",
+            model.id(),
+            optional(format_option_value(&data.max_input_tokens), 9),
+            optional(format_option_value(&data.max_output_tokens), 10),
+            flag(data.supports_vision, 6),
+            flag(data.supports_function_calling, 5),
+            optional(format_option_value(&data.input_price), 8),
+            optional(format_option_value(&data.output_price), 8),
+        ));
+    }
+    output.push(dimmed_text("Prices are in USD per 1M tokens."));
+    output.join("\n")
+}
+
+/// Chat models as JSON for `--list-models --json`.
+pub fn models_json(models: &[&Model]) -> Value {
+    models
+        .iter()
+        .map(|model| {
+            let data = model.data();
+            json!({
+                "id": model.id(),
+                "client": model.client_name(),
+                "name": model.name(),
+                "max_input_tokens": data.max_input_tokens,
+                "max_output_tokens": data.max_output_tokens,
+                "supports_vision": data.supports_vision,
+                "supports_function_calling": data.supports_function_calling,
+                "supports_json_mode": data.supports_json_mode,
+                "max_tools": data.max_tools,
+                "input_price": data.input_price,
+                "output_price": data.output_price,
+            })
+        })
+        .collect()
+}
+
+/// Matches `model_id` ignoring case, then as a model name without its client prefix.
+fn find_normalized_model(
+    models: &[&Model],
+    model_id: &str,
+    model_type: ModelType,
+) -> Result<Option<Model>> {
+    let models: Vec<&Model> = models
+        .iter()
+        .filter(|v| v.model_type() == model_type)
+        .copied()
+        .collect();
+    if let Some(model) = models
+        .iter()
+        .find(|v| v.id().eq_ignore_ascii_case(model_id))
+    {
+        return Ok(Some((*model).clone()));
+    }
+    let named: Vec<&Model> = models
+        .into_iter()
+        .filter(|v| v.name().eq_ignore_ascii_case(model_id))
+        .collect();
+    match named.as_slice() {
+        [] => Ok(None),
+        [model] => Ok(Some((*model).clone())),
+        _ => {
+            let ids: Vec<String> = named.iter().map(|v| v.id()).collect();
+            bail!(
+                "Ambiguous {model_type} model '{model_id}', use one of {}",
+                quote_ids(&ids, ", ")
+            )
+        }
+    }
+}
+
+/// The known ids closest to a mistyped one, ignoring case and punctuation.
+fn suggest_model_ids(models: &[&Model], model_id: &str, model_type: ModelType) -> Vec<String> {
+    let compact = |v: &str| -> String {
+        v.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let target = compact(model_id);
+    let max_distance = (target.chars().count() / 4).max(1);
+    let mut scored: Vec<(usize, String)> = models
+        .iter()
+        .filter(|v| v.model_type() == model_type)
+        .filter_map(|v| {
+            let distance = edit_distance(&target, &compact(&v.id()))
+                .min(edit_distance(&target, &compact(v.name())));
+            (distance <= max_distance).then(|| (distance, v.id()))
+        })
+        .collect();
+    scored.sort();
+    scored
+        .into_iter()
+        .take(MAX_MODEL_SUGGESTIONS)
+        .map(|(_, id)| id)
+        .collect()
+}
+
+fn quote_ids(ids: &[String], separator: &str) -> String {
+    ids.iter()
+        .map(|v| format!("'{v}'"))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModelData {
     pub name: String,
@@ -284,6 +477,7 @@ pub struct ModelData {
     pub max_input_tokens: Option<usize>,
     pub input_price: Option<f64>,
     pub output_price: Option<f64>,
+    pub tokenizer: Option<String>,
 
     // chat-only properties
     pub max_output_tokens: Option<isize>,
@@ -294,9 +488,16 @@ pub struct ModelData {
     #[serde(default)]
     pub supports_function_calling: bool,
     #[serde(default)]
+    pub supports_json_mode: bool,
+    #[serde(default)]
     no_stream: bool,
     #[serde(default)]
     no_system_message: bool,
+    pub max_tools: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<ParamPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<ParamPolicy>,
 
     // embedding-only properties
     pub max_tokens_per_chunk: Option<usize>,
@@ -314,6 +515,71 @@ impl ModelData {
     }
 }
 
+/// How a model treats a sampling parameter, e.g. `temperature: { policy: clamp, max: 1 }`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParamPolicy {
+    #[serde(default)]
+    pub policy: ParamPolicyKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Sent when neither the role nor the config sets the parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamPolicyKind {
+    /// Sent as set
+    #[default]
+    Allow,
+    /// Kept within `min` and `max`
+    Clamp,
+    /// Rejected by the API, so never sent; a value that was set is dropped with a warning
+    Forbid,
+    /// Accepted but without effect, so never sent
+    Ignore,
+}
+
+impl ParamPolicy {
+    fn apply(&self, model_id: &str, name: &str, value: Option<f64>) -> Option<f64> {
+        match self.policy {
+            ParamPolicyKind::Forbid => {
+                if let Some(value) = value {
+                    warn!("'{model_id}' rejects {name}, dropped {name}={value}");
+                }
+                None
+            }
+            ParamPolicyKind::Ignore => None,
+            ParamPolicyKind::Allow => value.or(self.default),
+            ParamPolicyKind::Clamp => value.or(self.default).map(|value| {
+                let clamped = value
+                    .max(self.min.unwrap_or(f64::MIN))
+                    .min(self.max.unwrap_or(f64::MAX));
+                if clamped != value {
+                    warn!(
+                        "'{model_id}' takes {name} within its range, changed {value} to {clamped}"
+                    );
+                }
+                clamped
+            }),
+        }
+    }
+}
+
+/// What a chat model accepts, used to degrade requests instead of failing on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub vision: bool,
+    pub tools: bool,
+    pub max_tools: Option<usize>,
+    pub streaming: bool,
+    pub system_message: bool,
+    pub json_mode: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct PredefinedModels {
     pub platform: String,
@@ -366,3 +632,32 @@ impl ModelType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_param_policies() {
+        let mut model = Model::new("openai", "o1");
+        assert_eq!(
+            model.apply_param_policies(Some(0.7), None),
+            (Some(0.7), None)
+        );
+        model.data_mut().temperature = Some(ParamPolicy {
+            policy: ParamPolicyKind::Forbid,
+            ..Default::default()
+        });
+        model.data_mut().top_p = Some(ParamPolicy {
+            policy: ParamPolicyKind::Clamp,
+            max: Some(1.0),
+            default: Some(0.9),
+            ..Default::default()
+        });
+        assert_eq!(
+            model.apply_param_policies(Some(0.7), Some(1.5)),
+            (None, Some(1.0))
+        );
+        assert_eq!(model.apply_param_policies(None, None), (None, Some(0.9)));
+    }
+}
@@ -0,0 +1,416 @@
+use super::*;
+
+use anyhow::{bail, Context, Result};
+use is_terminal::IsTerminal;
+use reqwest::{header::HeaderMap, Client as ReqwestClient, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+const API_BASE: &str = "http://localhost:11434";
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct OllamaConfig {
+    pub name: Option<String>,
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    pub keep_alive: Option<Value>,
+    pub options: Option<Map<String, Value>>,
+    #[serde(default)]
+    pub models: Vec<ModelData>,
+    pub patch: Option<RequestPatch>,
+    pub extra: Option<ExtraConfig>,
+}
+
+impl OllamaClient {
+    config_get_fn!(api_base, get_api_base);
+    config_get_fn!(api_key, get_api_key);
+
+    pub const PROMPTS: [PromptAction<'static>; 1] =
+        [("api_base", "API Base:", false, PromptKind::String)];
+
+    fn api_base(&self) -> String {
+        self.get_api_base()
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| API_BASE.to_string())
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    fn request_data(&self, path: &str, mut body: Value) -> RequestData {
+        if let Some(keep_alive) = &self.config.keep_alive {
+            body["keep_alive"] = keep_alive.clone();
+        }
+        let mut request_data = RequestData::new(format!("{}{path}", self.api_base()), body);
+        if let Ok(api_key) = self.get_api_key() {
+            request_data.bearer_auth(api_key);
+        }
+        request_data
+    }
+}
+
+impl_client_trait!(
+    OllamaClient,
+    (
+        prepare_chat_completions,
+        chat_completions,
+        chat_completions_streaming
+    ),
+    (prepare_embeddings, embeddings),
+    (noop_prepare_rerank, noop_rerank),
+);
+
+fn prepare_chat_completions(
+    self_: &OllamaClient,
+    data: ChatCompletionsData,
+) -> Result<RequestData> {
+    let body = build_chat_completions_body(data, &self_.model, self_.config.options.as_ref())?;
+    Ok(self_.request_data("/api/chat", body))
+}
+
+fn prepare_embeddings(self_: &OllamaClient, data: &EmbeddingsData) -> Result<RequestData> {
+    let mut body = json!({
+        "model": self_.model.name(),
+        "input": data.texts,
+    });
+    if let Some(options) = &self_.config.options {
+        body["options"] = options.clone().into();
+    }
+    Ok(self_.request_data("/api/embed", body))
+}
+
+async fn chat_completions(builder: RequestBuilder, model: &Model) -> Result<ChatCompletionsOutput> {
+    let res = send_or_pull(builder, model).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if !status.is_success() {
+        catch_error(&data, status.as_u16())?;
+    }
+    debug!("non-stream-data: {data}");
+    extract_chat_completions(&data)
+}
+
+async fn chat_completions_streaming(
+    builder: RequestBuilder,
+    handler: &mut SseHandler,
+    model: &Model,
+) -> Result<()> {
+    let res = send_or_pull(builder, model).await?;
+    let status = res.status();
+    if !status.is_success() {
+        let data: Value = res.json().await?;
+        catch_error(&data, status.as_u16())?;
+    } else {
+        let handle = |value: &str| -> Result<()> {
+            let data: Value = serde_json::from_str(value)?;
+            debug!("stream-data: {data}");
+            if let Some(error) = data["error"].as_str() {
+                bail!("{error}");
+            }
+            if let Some(text) = data["message"]["content"].as_str() {
+                if !text.is_empty() {
+                    handler.text(text)?;
+                }
+            }
+            for call in extract_tool_calls(&data) {
+                handler.tool_call(call)?;
+            }
+            if data["done"].as_bool() == Some(true) {
+                handler.set_usage(
+                    data["prompt_eval_count"].as_u64(),
+                    data["eval_count"].as_u64(),
+                );
+            }
+            Ok(())
+        };
+        json_stream(res.bytes_stream(), handle).await?;
+    }
+    Ok(())
+}
+
+async fn embeddings(builder: RequestBuilder, model: &Model) -> Result<EmbeddingsOutput> {
+    let res = send_or_pull(builder, model).await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if !status.is_success() {
+        catch_error(&data, status.as_u16())?;
+    }
+    let res_body: EmbeddingsResBody =
+        serde_json::from_value(data).context("Invalid embeddings data")?;
+    Ok(res_body.embeddings)
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResBody {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// Sends the request, pulling the model first when the Ollama server doesn't have it yet.
+async fn send_or_pull(builder: RequestBuilder, model: &Model) -> Result<Response> {
+    let (client, request) = builder.build_split();
+    let request = request?;
+    let retry = request.try_clone();
+    let res = client.execute(request).await?;
+    let Some(retry) = retry.filter(|_| res.status() == StatusCode::NOT_FOUND) else {
+        return Ok(res);
+    };
+    let data: Value = res.json().await?;
+    if !data["error"]
+        .as_str()
+        .is_some_and(|v| v.contains("not found"))
+    {
+        catch_error(&data, StatusCode::NOT_FOUND.as_u16())?;
+    }
+    let mut url = retry.url().clone();
+    let prefix = url
+        .path()
+        .rsplit_once("/api/")
+        .map(|(prefix, _)| prefix.to_string())
+        .unwrap_or_default();
+    url.set_path(&format!("{prefix}/api/pull"));
+    pull_model(&client, url.as_str(), retry.headers().clone(), model.name()).await?;
+    Ok(client.execute(retry).await?)
+}
+
+async fn pull_model(
+    client: &ReqwestClient,
+    url: &str,
+    headers: HeaderMap,
+    name: &str,
+) -> Result<()> {
+    eprintln!("Pulling model '{name}'");
+    let res = client
+        .post(url)
+        .headers(headers)
+        .json(&json!({ "model": name, "stream": true }))
+        .send()
+        .await?;
+    let status = res.status();
+    if !status.is_success() {
+        let data: Value = res.json().await?;
+        return catch_error(&data, status.as_u16())
+            .with_context(|| format!("Failed to pull model '{name}'"));
+    }
+    let is_terminal = std::io::stderr().is_terminal();
+    let mut last_line = String::new();
+    let handle = |value: &str| -> Result<()> {
+        let data: Value = serde_json::from_str(value)?;
+        if let Some(error) = data["error"].as_str() {
+            bail!("Failed to pull model '{name}', {error}");
+        }
+        let status = data["status"].as_str().unwrap_or_default();
+        let line = match (data["completed"].as_u64(), data["total"].as_u64()) {
+            (Some(completed), Some(total)) if total > 0 && is_terminal => {
+                format!("{status} {}%", completed * 100 / total)
+            }
+            _ => status.to_string(),
+        };
+        if line != last_line {
+            if is_terminal {
+                eprint!("\r\x1b[2K{line}");
+            } else {
+                eprintln!("{line}");
+            }
+            last_line = line;
+        }
+        Ok(())
+    };
+    let ret = json_stream(res.bytes_stream(), handle).await;
+    if is_terminal {
+        eprintln!();
+    }
+    ret
+}
+
+fn build_chat_completions_body(
+    data: ChatCompletionsData,
+    model: &Model,
+    options: Option<&Map<String, Value>>,
+) -> Result<Value> {
+    let ChatCompletionsData {
+        messages,
+        temperature,
+        top_p,
+        functions,
+        native_tools: _,
+        json_mode,
+        stream,
+    } = data;
+
+    let mut network_image_urls = vec![];
+
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .flat_map(|message| {
+            let Message { role, content } = message;
+            match content {
+                MessageContent::Text(text) => vec![json!({ "role": role, "content": text })],
+                MessageContent::Array(list) => {
+                    let mut texts = vec![];
+                    let mut images = vec![];
+                    for item in list {
+                        match item {
+                            MessageContentPart::Text { text } => texts.push(text),
+                            MessageContentPart::ImageUrl {
+                                image_url: ImageUrl { url },
+                            } => match url
+                                .strip_prefix("data:")
+                                .and_then(|v| v.split_once(";base64,"))
+                            {
+                                Some((_, data)) => images.push(data.to_string()),
+                                None => network_image_urls.push(url),
+                            },
+                        }
+                    }
+                    vec![json!({ "role": role, "content": texts.join("\n\n"), "images": images })]
+                }
+                MessageContent::ToolCalls(MessageContentToolCalls {
+                    tool_results, text, ..
+                }) => {
+                    let tool_calls: Vec<_> = tool_results
+                        .iter()
+                        .map(|tool_result| {
+                            json!({
+                                "function": {
+                                    "name": tool_result.call.name,
+                                    "arguments": tool_result.call.arguments,
+                                },
+                            })
+                        })
+                        .collect();
+                    let mut messages = vec![json!({
+                        "role": MessageRole::Assistant,
+                        "content": text,
+                        "tool_calls": tool_calls,
+                    })];
+                    for tool_result in tool_results {
+                        messages.push(json!({
+                            "role": "tool",
+                            "content": tool_result.output.to_string(),
+                        }));
+                    }
+                    messages
+                }
+            }
+        })
+        .collect();
+
+    if !network_image_urls.is_empty() {
+        bail!(
+            "The model does not support network images: {:?}",
+            network_image_urls
+        );
+    }
+
+    let mut body = json!({
+        "model": model.name(),
+        "messages": messages,
+        "stream": stream,
+    });
+
+    let mut options = options.cloned().unwrap_or_default();
+    if let Some(v) = model.max_tokens_param() {
+        options.insert("num_predict".into(), v.into());
+    }
+    if let Some(v) = temperature {
+        options.insert("temperature".into(), v.into());
+    }
+    if let Some(v) = top_p {
+        options.insert("top_p".into(), v.into());
+    }
+    if !options.is_empty() {
+        body["options"] = options.into();
+    }
+    if json_mode {
+        body["format"] = "json".into();
+    }
+    if let Some(functions) = functions {
+        body["tools"] = functions
+            .iter()
+            .map(|v| {
+                json!({
+                    "type": "function",
+                    "function": v,
+                })
+            })
+            .collect();
+    }
+    Ok(body)
+}
+
+fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
+    let text = data["message"]["content"].as_str().unwrap_or_default();
+    let tool_calls = extract_tool_calls(data);
+    if text.is_empty() && tool_calls.is_empty() {
+        bail!("Invalid response data: {data}");
+    }
+    let output = ChatCompletionsOutput {
+        text: text.to_string(),
+        tool_calls,
+        id: None,
+        input_tokens: data["prompt_eval_count"].as_u64(),
+        output_tokens: data["eval_count"].as_u64(),
+        request_ids: None,
+    };
+    Ok(output)
+}
+
+fn extract_tool_calls(data: &Value) -> Vec<ToolCall> {
+    let Some(calls) = data["message"]["tool_calls"].as_array() else {
+        return vec![];
+    };
+    calls
+        .iter()
+        .filter_map(|call| {
+            let name = call["function"]["name"].as_str()?;
+            Some(ToolCall::new(
+                name.to_string(),
+                call["function"]["arguments"].clone(),
+                None,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_chat_completions_body() {
+        let data = ChatCompletionsData {
+            messages: vec![Message::new(
+                MessageRole::User,
+                MessageContent::Array(vec![
+                    MessageContentPart::Text {
+                        text: "describe it".into(),
+                    },
+                    MessageContentPart::ImageUrl {
+                        image_url: ImageUrl {
+                            url: "data:image/png;base64,AAAA".into(),
+                        },
+                    },
+                ]),
+            )],
+            temperature: Some(0.2),
+            top_p: None,
+            functions: None,
+            native_tools: vec![],
+            json_mode: false,
+            stream: false,
+        };
+        let options: Map<String, Value> = serde_json::from_value(json!({ "num_ctx": 8192 })).unwrap();
+        let body =
+            build_chat_completions_body(data, &Model::new("ollama", "llava"), Some(&options))
+                .unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "model": "llava",
+                "messages": [{ "role": "user", "content": "describe it", "images": ["AAAA"] }],
+                "stream": false,
+                "options": { "num_ctx": 8192, "temperature": 0.2 },
+            })
+        );
+    }
+}
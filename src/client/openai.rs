@@ -86,6 +86,7 @@ pub async fn openai_chat_completions(
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
     if !status.is_success() {
@@ -97,9 +98,29 @@ pub async fn openai_chat_completions(
 }
 
 pub async fn openai_chat_completions_streaming(
+    builder: RequestBuilder,
+    handler: &mut SseHandler,
+    model: &Model,
+) -> Result<()> {
+    openai_chat_completions_streaming_with(builder, handler, model, SseDecoder::Strict).await
+}
+
+/// For Azure and OpenAI-compatible APIs that are often served behind a gateway.
+pub async fn openai_lenient_chat_completions_streaming(
+    builder: RequestBuilder,
+    handler: &mut SseHandler,
+    model: &Model,
+) -> Result<()> {
+    openai_chat_completions_streaming_with(builder, handler, model, SseDecoder::Lenient).await
+}
+
+/// Gateways such as Azure APIM may send filter-only chunks, keep-alive payloads or drop
+/// the content-type, `SseDecoder::Lenient` skips what isn't a completion chunk.
+pub async fn openai_chat_completions_streaming_with(
     builder: RequestBuilder,
     handler: &mut SseHandler,
     _model: &Model,
+    decoder: SseDecoder,
 ) -> Result<()> {
     let mut call_id = String::new();
     let mut function_name = String::new();
@@ -119,8 +140,18 @@ pub async fn openai_chat_completions_streaming(
             }
             return Ok(true);
         }
-        let data: Value = serde_json::from_str(&message.data)?;
+        let data: Value = match serde_json::from_str(&message.data) {
+            Ok(data) => data,
+            Err(err) if decoder == SseDecoder::Lenient => {
+                debug!("Skip invalid stream-data: {}, {err}", message.data);
+                return Ok(false);
+            }
+            Err(err) => return Err(err.into()),
+        };
         debug!("stream-data: {data}");
+        if decoder == SseDecoder::Lenient && data["error"].is_object() {
+            catch_error(&data, 500)?;
+        }
         if let Some(text) = data["choices"][0]["delta"]["content"]
             .as_str()
             .filter(|v| !v.is_empty())
@@ -167,7 +198,7 @@ pub async fn openai_chat_completions_streaming(
         Ok(false)
     };
 
-    sse_stream(builder, handle).await
+    sse_stream_with(builder, decoder, handle).await
 }
 
 pub async fn openai_embeddings(
@@ -175,6 +206,7 @@ pub async fn openai_embeddings(
     _model: &Model,
 ) -> Result<EmbeddingsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
     if !status.is_success() {
@@ -202,6 +234,8 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
         temperature,
         top_p,
         functions,
+        native_tools: _,
+        json_mode,
         stream,
     } = data;
 
@@ -289,6 +323,9 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
     if stream {
         body["stream"] = true.into();
     }
+    if json_mode {
+        body["response_format"] = json!({ "type": "json_object" });
+    }
     if let Some(functions) = functions {
         body["tools"] = functions
             .iter()
@@ -344,6 +381,7 @@ pub fn openai_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["prompt_tokens"].as_u64(),
         output_tokens: data["usage"]["completion_tokens"].as_u64(),
+        request_ids: None,
     };
     Ok(output)
 }
@@ -40,7 +40,7 @@ impl_client_trait!(
     (
         prepare_chat_completions,
         openai_chat_completions,
-        openai_chat_completions_streaming
+        openai_lenient_chat_completions_streaming
     ),
     (prepare_embeddings, openai_embeddings),
     (prepare_rerank, generic_rerank),
@@ -126,6 +126,7 @@ fn get_api_base_ext(self_: &OpenAICompatibleClient) -> Result<String> {
 
 pub async fn generic_rerank(builder: RequestBuilder, _model: &Model) -> Result<RerankOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let mut data: Value = res.json().await?;
     if !status.is_success() {
@@ -0,0 +1,108 @@
+use super::Client;
+
+use crate::config::ensure_parent_exists;
+use crate::utils::{now, redact_secrets};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::{fs::OpenOptions, io::Write, path::PathBuf, time::Instant};
+
+const MAX_BODY_LENGTH: usize = 4000;
+
+/// A provider call being recorded to the `log_requests` JSONL file.
+pub struct RequestLog {
+    path: PathBuf,
+    started_at: Instant,
+    record: Value,
+}
+
+impl RequestLog {
+    /// Returns `None` when request logging is disabled.
+    pub fn start<C: Client + ?Sized>(
+        client: &C,
+        request_id: &str,
+        api: &str,
+        stream: bool,
+        request: &Value,
+    ) -> Option<Self> {
+        let path = client.global_config().read().log_requests.clone()?;
+        let model = client.model();
+        Some(Self {
+            path: PathBuf::from(path),
+            started_at: Instant::now(),
+            record: json!({
+                "timestamp": now(),
+                "request_id": request_id,
+                "client": model.client_name(),
+                "model": model.id(),
+                "api": api,
+                "stream": stream,
+                "request": truncate_body(request),
+            }),
+        })
+    }
+
+    pub fn finish(
+        mut self,
+        provider_request_id: Option<&str>,
+        ret: std::result::Result<(Value, Option<u64>, Option<u64>), &anyhow::Error>,
+    ) {
+        self.record["latency_ms"] = (self.started_at.elapsed().as_millis() as u64).into();
+        if let Some(provider_request_id) = provider_request_id {
+            self.record["provider_request_id"] = provider_request_id.into();
+        }
+        match ret {
+            Ok((response, input_tokens, output_tokens)) => {
+                self.record["input_tokens"] = input_tokens.into();
+                self.record["output_tokens"] = output_tokens.into();
+                self.record["response"] = truncate_body(&response).into();
+            }
+            Err(err) => {
+                self.record["error"] = redact_secrets(&format!("{err:#}")).into();
+            }
+        }
+        if let Err(err) = self.write() {
+            warn!("Failed to log request, {err}");
+        }
+    }
+
+    fn write(&self) -> Result<()> {
+        ensure_parent_exists(&self.path)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open '{}'", self.path.display()))?;
+        writeln!(file, "{}", self.record)?;
+        Ok(())
+    }
+}
+
+fn truncate_body(body: &Value) -> String {
+    let body = match body {
+        Value::String(v) => v.clone(),
+        _ => body.to_string(),
+    };
+    let body = redact_secrets(&body);
+    match body.char_indices().nth(MAX_BODY_LENGTH) {
+        Some((index, _)) => format!("{}…(truncated)", &body[..index]),
+        None => body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_body() {
+        assert_eq!(truncate_body(&json!("hello")), "hello");
+        assert_eq!(truncate_body(&json!({"a": 1})), r#"{"a":1}"#);
+        let body = truncate_body(&json!("é".repeat(MAX_BODY_LENGTH + 10)));
+        assert_eq!(
+            body.chars().count(),
+            MAX_BODY_LENGTH + "…(truncated)".chars().count()
+        );
+        assert!(body.ends_with("…(truncated)"));
+    }
+}
@@ -1,7 +1,8 @@
-use super::{catch_error, ToolCall};
-use crate::utils::AbortSignal;
+use super::{catch_error, note_provider_request_id, RequestIds, ToolCall};
+use crate::utils::{map_json_strings, restore_secrets, take_restorable, AbortSignal};
 
 use anyhow::{anyhow, bail, Context, Result};
+use eventsource_stream::Eventsource;
 use futures_util::{Stream, StreamExt};
 use reqwest::RequestBuilder;
 use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
@@ -13,6 +14,10 @@ pub struct SseHandler {
     abort_signal: AbortSignal,
     buffer: String,
     tool_calls: Vec<ToolCall>,
+    restore_secrets: bool,
+    pending: String,
+    request_ids: Option<RequestIds>,
+    usage: (Option<u64>, Option<u64>),
 }
 
 impl SseHandler {
@@ -22,18 +27,40 @@ impl SseHandler {
             abort_signal,
             buffer: String::new(),
             tool_calls: Vec::new(),
+            restore_secrets: false,
+            pending: String::new(),
+            request_ids: None,
+            usage: (None, None),
         }
     }
 
+    /// Puts back the secrets masked in the request as their placeholders stream in.
+    pub fn set_restore_secrets(&mut self, value: bool) {
+        self.restore_secrets = value;
+    }
+
+    pub fn set_request_ids(&mut self, request_ids: RequestIds) {
+        self.request_ids = Some(request_ids);
+    }
+
     pub fn text(&mut self, text: &str) -> Result<()> {
         // debug!("HandleText: {}", text);
         if text.is_empty() {
             return Ok(());
         }
-        self.buffer.push_str(text);
+        let text = if self.restore_secrets {
+            self.pending.push_str(text);
+            take_restorable(&mut self.pending)
+        } else {
+            text.to_string()
+        };
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.buffer.push_str(&text);
         let ret = self
             .sender
-            .send(SseEvent::Text(text.to_string()))
+            .send(SseEvent::Text(text))
             .with_context(|| "Failed to send SseEvent:Text");
         if let Err(err) = ret {
             if self.abort_signal.aborted() {
@@ -46,6 +73,11 @@ impl SseHandler {
 
     pub fn done(&mut self) {
         // debug!("HandleDone");
+        if !self.pending.is_empty() {
+            let text = std::mem::take(&mut self.pending);
+            self.buffer.push_str(&text);
+            let _ = self.sender.send(SseEvent::Text(text));
+        }
         let ret = self.sender.send(SseEvent::Done);
         if ret.is_err() {
             if self.abort_signal.aborted() {
@@ -57,10 +89,23 @@ impl SseHandler {
 
     pub fn tool_call(&mut self, call: ToolCall) -> Result<()> {
         // debug!("HandleCall: {:?}", call);
+        let mut call = call;
+        if self.restore_secrets {
+            map_json_strings(&mut call.arguments, &restore_secrets);
+        }
         self.tool_calls.push(call);
         Ok(())
     }
 
+    /// The prompt and completion tokens, for providers that report them at the end of the stream.
+    pub fn set_usage(&mut self, input_tokens: Option<u64>, output_tokens: Option<u64>) {
+        self.usage = (input_tokens, output_tokens);
+    }
+
+    pub fn usage(&self) -> (Option<u64>, Option<u64>) {
+        self.usage
+    }
+
     pub fn abort(&self) -> AbortSignal {
         self.abort_signal.clone()
     }
@@ -69,11 +114,18 @@ impl SseHandler {
         &self.tool_calls
     }
 
-    pub fn take(self) -> (String, Vec<ToolCall>) {
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    pub fn take(self) -> (String, Vec<ToolCall>, Option<RequestIds>) {
         let Self {
-            buffer, tool_calls, ..
+            buffer,
+            tool_calls,
+            request_ids,
+            ..
         } = self;
-        (buffer, tool_calls)
+        (buffer, tool_calls, request_ids)
     }
 }
 
@@ -83,6 +135,32 @@ pub enum SseEvent {
     Done,
 }
 
+/// No bytes arrived within the client's `stream_idle_timeout`; the request can be retried.
+#[derive(Debug)]
+pub struct StreamIdleTimeout;
+
+impl std::fmt::Display for StreamIdleTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The stream stalled, no data received within the idle timeout"
+        )
+    }
+}
+
+impl std::error::Error for StreamIdleTimeout {}
+
+fn read_error<E: std::error::Error + 'static>(err: E, context: &str) -> anyhow::Error {
+    let is_timeout = (&err as &dyn std::error::Error)
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|v| v.is_timeout());
+    if is_timeout {
+        StreamIdleTimeout.into()
+    } else {
+        anyhow!("{context}, {err}")
+    }
+}
+
 #[derive(Debug)]
 pub struct SseMmessage {
     #[allow(unused)]
@@ -90,7 +168,26 @@ pub struct SseMmessage {
     pub data: String,
 }
 
-pub async fn sse_stream<F>(builder: RequestBuilder, mut handle: F) -> Result<()>
+/// How `sse_stream` treats responses that gateways (e.g. Azure APIM) may have rewritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SseDecoder {
+    Strict,
+    /// Decodes the body as an event stream whatever its content-type and skips empty events
+    Lenient,
+}
+
+pub async fn sse_stream<F>(builder: RequestBuilder, handle: F) -> Result<()>
+where
+    F: FnMut(SseMmessage) -> Result<bool>,
+{
+    sse_stream_with(builder, SseDecoder::Strict, handle).await
+}
+
+pub async fn sse_stream_with<F>(
+    builder: RequestBuilder,
+    decoder: SseDecoder,
+    mut handle: F,
+) -> Result<()>
 where
     F: FnMut(SseMmessage) -> Result<bool>,
 {
@@ -99,6 +196,9 @@ where
         match event {
             Ok(Event::Open) => {}
             Ok(Event::Message(message)) => {
+                if decoder == SseDecoder::Lenient && message.data.trim().is_empty() {
+                    continue;
+                }
                 let message = SseMmessage {
                     event: message.event,
                     data: message.data,
@@ -111,6 +211,7 @@ where
                 match err {
                     EventSourceError::StreamEnded => {}
                     EventSourceError::InvalidStatusCode(status, res) => {
+                        note_provider_request_id(res.headers());
                         let text = res.text().await?;
                         let data: Value = match text.parse() {
                             Ok(data) => data,
@@ -124,12 +225,21 @@ where
                         catch_error(&data, status.as_u16())?;
                     }
                     EventSourceError::InvalidContentType(header_value, res) => {
+                        note_provider_request_id(res.headers());
+                        let content_type = header_value.to_str().unwrap_or_default();
+                        if decoder == SseDecoder::Lenient && !content_type.contains("json") {
+                            debug!("Decode event-stream with content-type '{content_type}'");
+                            es.close();
+                            return lenient_sse_stream(res.bytes_stream(), handle).await;
+                        }
                         let text = res.text().await?;
                         bail!(
-                            "Invalid response event-stream. content-type: {}, data: {text}",
-                            header_value.to_str().unwrap_or_default()
+                            "Invalid response event-stream. content-type: {content_type}, data: {text}",
                         );
                     }
+                    EventSourceError::Transport(err) if err.is_timeout() => {
+                        return Err(StreamIdleTimeout.into());
+                    }
                     _ => {
                         bail!("{}", err);
                     }
@@ -141,17 +251,50 @@ where
     Ok(())
 }
 
+async fn lenient_sse_stream<S, F, E>(stream: S, mut handle: F) -> Result<()>
+where
+    S: Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+    F: FnMut(SseMmessage) -> Result<bool>,
+    E: std::error::Error + 'static,
+{
+    let mut stream = stream.eventsource();
+    let mut has_event = false;
+    while let Some(event) = stream.next().await {
+        let event = event.map_err(|err| match err {
+            eventsource_stream::EventStreamError::Transport(err) => {
+                read_error(err, "Failed to read event stream")
+            }
+            err => anyhow!("Failed to read event stream, {err}"),
+        })?;
+        if event.data.trim().is_empty() {
+            continue;
+        }
+        has_event = true;
+        let message = SseMmessage {
+            event: event.event,
+            data: event.data,
+        };
+        if handle(message)? {
+            break;
+        }
+    }
+    if !has_event {
+        bail!("Invalid response event-stream, no events in the response");
+    }
+    Ok(())
+}
+
 pub async fn json_stream<S, F, E>(mut stream: S, mut handle: F) -> Result<()>
 where
     S: Stream<Item = Result<bytes::Bytes, E>> + Unpin,
     F: FnMut(&str) -> Result<()>,
-    E: std::error::Error,
+    E: std::error::Error + 'static,
 {
     let mut parser = JsonStreamParser::default();
     let mut unparsed_bytes = vec![];
     while let Some(chunk_bytes) = stream.next().await {
         let chunk_bytes =
-            chunk_bytes.map_err(|err| anyhow!("Failed to read json stream, {err}"))?;
+            chunk_bytes.map_err(|err| read_error(err, "Failed to read json stream"))?;
         unparsed_bytes.extend(chunk_bytes);
         match std::str::from_utf8(&unparsed_bytes) {
             Ok(text) => {
@@ -212,10 +355,8 @@ impl JsonStreamParser {
                     }
                     self.balances.push(ch);
                 }
-                '[' => {
-                    if self.start.is_some() {
-                        self.balances.push(ch);
-                    }
+                '[' if self.start.is_some() => {
+                    self.balances.push(ch);
                 }
                 '}' => {
                     self.balances.pop();
@@ -245,6 +386,7 @@ mod tests {
     use futures_util::stream;
     use rand::{thread_rng, Rng};
 
+    #[allow(clippy::sliced_string_as_bytes)]
     fn split_chunks(text: &str) -> Vec<Vec<u8>> {
         let mut rng = thread_rng();
         let len = text.len();
@@ -274,6 +416,33 @@ mod tests {
         };
     }
 
+    #[tokio::test]
+    async fn test_lenient_sse_stream() {
+        let input = "data: {\"choices\":[],\"prompt_filter_results\":[]}\n\ndata:\n\n: ping\n\ndata: {\"a\":1}\r\n\r\ndata: [DONE]\n\n";
+        let chunks: Vec<_> = split_chunks(input)
+            .into_iter()
+            .map(|chunk| Ok::<_, std::convert::Infallible>(Bytes::from(chunk)))
+            .collect();
+        let mut output = vec![];
+        let ret = lenient_sse_stream(stream::iter(chunks), |message| {
+            output.push(message.data);
+            Ok(false)
+        })
+        .await;
+        assert!(ret.is_ok());
+        assert_eq!(
+            output,
+            [
+                r#"{"choices":[],"prompt_filter_results":[]}"#,
+                r#"{"a":1}"#,
+                "[DONE]"
+            ]
+        );
+        let chunks = vec![Ok::<_, std::convert::Infallible>(Bytes::from("{}"))];
+        let ret = lenient_sse_stream(stream::iter(chunks), |_| Ok(false)).await;
+        assert!(ret.is_err());
+    }
+
     #[tokio::test]
     async fn test_json_stream_ndjson() {
         let data = r#"{"key": "value"}
@@ -0,0 +1,124 @@
+use crate::utils::estimate_token_length;
+
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton, CoreBPE};
+
+const HF_TOKENIZER_PREFIX: &str = "hf:";
+
+lazy_static::lazy_static! {
+    static ref HF_TOKENIZERS: Mutex<HashMap<String, Option<Arc<tokenizers::Tokenizer>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Counts tokens the way the model does, falls back to a heuristic estimation
+/// when the model family has no known tokenizer.
+#[derive(Clone)]
+pub enum Tokenizer {
+    Estimate,
+    Tiktoken(Arc<Mutex<CoreBPE>>),
+    HuggingFace(Arc<tokenizers::Tokenizer>),
+}
+
+impl Tokenizer {
+    /// Resolves the tokenizer by name (`o200k_base`, `cl100k_base`, `hf:<tokenizer.json>`
+    /// or `estimate`), or infers it from the model name if no name is given.
+    /// Only OpenAI families are inferred, a HF tokenizer needs an explicit `hf:<path>`.
+    pub fn new(name: Option<&str>, model_name: &str) -> Self {
+        let name = match name {
+            Some(v) => v,
+            None => infer_tokenizer_name(model_name),
+        };
+        match name {
+            "o200k_base" => Self::Tiktoken(o200k_base_singleton()),
+            "cl100k_base" => Self::Tiktoken(cl100k_base_singleton()),
+            _ => match name.strip_prefix(HF_TOKENIZER_PREFIX) {
+                Some(path) => match load_hf_tokenizer(path) {
+                    Some(tokenizer) => Self::HuggingFace(tokenizer),
+                    None => Self::Estimate,
+                },
+                None => Self::Estimate,
+            },
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+        match self {
+            Self::Estimate => estimate_token_length(text),
+            Self::Tiktoken(bpe) => bpe.lock().encode_ordinary(text).len(),
+            Self::HuggingFace(tokenizer) => match tokenizer.encode(text, false) {
+                Ok(encoding) => encoding.len(),
+                Err(_) => estimate_token_length(text),
+            },
+        }
+    }
+}
+
+fn infer_tokenizer_name(model_name: &str) -> &'static str {
+    let name = model_name.to_lowercase();
+    let name = name.rsplit('/').next().unwrap_or_default();
+    if [
+        "gpt-4o",
+        "chatgpt-4o",
+        "gpt-4.1",
+        "gpt-4.5",
+        "o1",
+        "o3",
+        "o4",
+    ]
+    .iter()
+    .any(|v| name.starts_with(v))
+    {
+        "o200k_base"
+    } else if ["gpt-4", "gpt-3.5", "text-embedding-3", "text-embedding-ada"]
+        .iter()
+        .any(|v| name.starts_with(v))
+    {
+        "cl100k_base"
+    } else {
+        "estimate"
+    }
+}
+
+fn load_hf_tokenizer(path: &str) -> Option<Arc<tokenizers::Tokenizer>> {
+    HF_TOKENIZERS
+        .lock()
+        .entry(path.to_string())
+        .or_insert_with(|| match tokenizers::Tokenizer::from_file(path) {
+            Ok(v) => Some(Arc::new(v)),
+            Err(err) => {
+                warn!("Failed to load tokenizer at '{path}', {err}");
+                None
+            }
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_tokenizer_name() {
+        assert_eq!(infer_tokenizer_name("gpt-4o-mini"), "o200k_base");
+        assert_eq!(infer_tokenizer_name("openai/o3-mini"), "o200k_base");
+        assert_eq!(infer_tokenizer_name("gpt-4-turbo"), "cl100k_base");
+        assert_eq!(
+            infer_tokenizer_name("text-embedding-3-small"),
+            "cl100k_base"
+        );
+        assert_eq!(infer_tokenizer_name("claude-3-5-sonnet"), "estimate");
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        let tokenizer = Tokenizer::new(None, "gpt-4");
+        assert_eq!(tokenizer.count("hello world"), 2);
+        assert_eq!(tokenizer.count(""), 0);
+        let tokenizer = Tokenizer::new(Some("hf:/not/exist/tokenizer.json"), "llama3");
+        assert!(matches!(tokenizer, Tokenizer::Estimate));
+    }
+}
@@ -3,6 +3,9 @@ use super::claude::*;
 use super::openai::*;
 use super::*;
 
+use crate::render::save_temp_image;
+use crate::utils::base64_decode;
+
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::{Duration, Utc};
 use reqwest::{Client as ReqwestClient, RequestBuilder};
@@ -53,6 +56,16 @@ impl Client for VertexAIClient {
         }
     }
 
+    async fn chat_completions_request_data(
+        &self,
+        client: &ReqwestClient,
+        data: ChatCompletionsData,
+    ) -> Result<RequestData> {
+        prepare_gcloud_access_token(client, self.name(), &self.config.adc_file).await?;
+        let model_category = ModelCategory::from_str(self.model().name())?;
+        prepare_chat_completions(self, data, &model_category)
+    }
+
     async fn chat_completions_streaming_inner(
         &self,
         client: &ReqwestClient,
@@ -174,6 +187,7 @@ pub async fn gemini_chat_completions(
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
     if !status.is_success() {
@@ -189,6 +203,7 @@ pub async fn gemini_chat_completions_streaming(
     _model: &Model,
 ) -> Result<()> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     if !status.is_success() {
         let data: Value = res.json().await?;
@@ -199,11 +214,11 @@ pub async fn gemini_chat_completions_streaming(
             debug!("stream-data: {data}");
             if let Some(parts) = data["candidates"][0]["content"]["parts"].as_array() {
                 for (i, part) in parts.iter().enumerate() {
-                    if let Some(text) = part["text"].as_str() {
+                    if let Some(text) = gemini_render_part(part) {
                         if i > 0 {
                             handler.text("\n\n")?;
                         }
-                        handler.text(text)?;
+                        handler.text(&text)?;
                     } else if let (Some(name), Some(args)) = (
                         part["functionCall"]["name"].as_str(),
                         part["functionCall"]["args"].as_object(),
@@ -217,6 +232,9 @@ pub async fn gemini_chat_completions_streaming(
             {
                 bail!("Blocked due to safety")
             }
+            if let Some(sources) = gemini_render_sources(&data["candidates"][0]) {
+                handler.text(&format!("\n\n{sources}"))?;
+            }
 
             Ok(())
         };
@@ -227,6 +245,7 @@ pub async fn gemini_chat_completions_streaming(
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
     let res = builder.send().await?;
+    note_provider_request_id(res.headers());
     let status = res.status();
     let data: Value = res.json().await?;
     if !status.is_success() {
@@ -262,7 +281,7 @@ fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsO
     let mut tool_calls = vec![];
     if let Some(parts) = data["candidates"][0]["content"]["parts"].as_array() {
         for part in parts {
-            if let Some(text) = part["text"].as_str() {
+            if let Some(text) = gemini_render_part(part) {
                 text_parts.push(text);
             }
             if let (Some(name), Some(args)) = (
@@ -274,6 +293,7 @@ fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsO
         }
     }
 
+    text_parts.extend(gemini_render_sources(&data["candidates"][0]));
     let text = text_parts.join("\n\n");
     if text.is_empty() && tool_calls.is_empty() {
         if let Some("SAFETY") = data["promptFeedback"]["blockReason"]
@@ -291,10 +311,55 @@ fn gemini_extract_chat_completions_text(data: &Value) -> Result<ChatCompletionsO
         id: None,
         input_tokens: data["usageMetadata"]["promptTokenCount"].as_u64(),
         output_tokens: data["usageMetadata"]["candidatesTokenCount"].as_u64(),
+        request_ids: None,
     };
     Ok(output)
 }
 
+/// Renders text parts along with the code and its output produced by the `code_execution` tool.
+///
+/// Generated images are saved to temporary files and referenced as markdown images.
+fn gemini_render_part(part: &Value) -> Option<String> {
+    if let Some(text) = part["text"].as_str() {
+        return Some(text.to_string());
+    }
+    if let Some(code) = part["executableCode"]["code"].as_str() {
+        let lang = part["executableCode"]["language"]
+            .as_str()
+            .unwrap_or_default()
+            .to_lowercase();
+        return Some(format!("```{lang}\n{}\n```", code.trim_end()));
+    }
+    if let Some(output) = part["codeExecutionResult"]["output"].as_str() {
+        return Some(format!("```output\n{}\n```", output.trim_end()));
+    }
+    if let Some(data) = part["inlineData"]["data"].as_str() {
+        let mime_type = part["inlineData"]["mimeType"].as_str().unwrap_or_default();
+        let data = base64_decode(data).ok()?;
+        let path = save_temp_image(&data, mime_type).ok()?;
+        return Some(format!("![image]({path})"));
+    }
+    None
+}
+
+/// Lists the web pages that grounded the answer when `google_search` is enabled.
+fn gemini_render_sources(candidate: &Value) -> Option<String> {
+    let chunks = candidate["groundingMetadata"]["groundingChunks"].as_array()?;
+    let sources: Vec<String> = chunks
+        .iter()
+        .filter_map(|chunk| {
+            let uri = chunk["web"]["uri"].as_str()?;
+            Some((chunk["web"]["title"].as_str().unwrap_or(uri), uri))
+        })
+        .enumerate()
+        .map(|(i, (title, uri))| format!("{}. [{title}]({uri})", i + 1))
+        .collect();
+    if sources.is_empty() {
+        return None;
+    }
+    Some(format!("Sources:\n{}", sources.join("\n")))
+}
+
 pub fn gemini_build_chat_completions_body(
     data: ChatCompletionsData,
     model: &Model,
@@ -304,6 +369,8 @@ pub fn gemini_build_chat_completions_body(
         temperature,
         top_p,
         functions,
+        native_tools,
+        json_mode,
         stream: _,
     } = data;
 
@@ -391,6 +458,9 @@ pub fn gemini_build_chat_completions_body(
     if let Some(v) = top_p {
         body["generationConfig"]["topP"] = v.into();
     }
+    if json_mode {
+        body["generationConfig"]["responseMimeType"] = "application/json".into();
+    }
 
     if let Some(functions) = functions {
         // Gemini doesn't support functions with parameters that have empty properties, so we need to patch it.
@@ -410,6 +480,21 @@ pub fn gemini_build_chat_completions_body(
         body["tools"] = json!([{ "functionDeclarations": function_declarations }]);
     }
 
+    for name in native_tools {
+        let tool = match name.as_str() {
+            "google_search" if model.name().starts_with("gemini-1") => {
+                json!({ "googleSearchRetrieval": {} })
+            }
+            "google_search" => json!({ "googleSearch": {} }),
+            "code_execution" => json!({ "codeExecution": {} }),
+            _ => bail!("Unknown native tool '{name}', expected google_search or code_execution"),
+        };
+        match body["tools"].as_array_mut() {
+            Some(tools) => tools.push(tool),
+            None => body["tools"] = json!([tool]),
+        }
+    }
+
     Ok(body)
 }
 
@@ -524,3 +609,56 @@ fn strip_model_version(name: &str) -> &str {
         None => name,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gemini_native_tools() {
+        let body = |model_name: &str| {
+            let data = ChatCompletionsData {
+                messages: vec![Message::new(
+                    MessageRole::User,
+                    MessageContent::Text("hi".into()),
+                )],
+                temperature: None,
+                top_p: None,
+                functions: None,
+                native_tools: vec!["google_search".into(), "code_execution".into()],
+                json_mode: false,
+                stream: false,
+            };
+            gemini_build_chat_completions_body(data, &Model::new("gemini", model_name)).unwrap()
+        };
+        assert_eq!(
+            body("gemini-2.0-flash")["tools"],
+            json!([{ "googleSearch": {} }, { "codeExecution": {} }])
+        );
+        assert_eq!(
+            body("gemini-1.5-pro")["tools"][0],
+            json!({ "googleSearchRetrieval": {} })
+        );
+    }
+
+    #[test]
+    fn test_gemini_extract_grounded_text() {
+        let data = json!({
+            "candidates": [{
+                "content": { "parts": [
+                    { "text": "Answer" },
+                    { "executableCode": { "language": "PYTHON", "code": "print(1)\n" } },
+                    { "codeExecutionResult": { "outcome": "OUTCOME_OK", "output": "1\n" } },
+                ] },
+                "groundingMetadata": { "groundingChunks": [
+                    { "web": { "uri": "https://a.example", "title": "a.example" } },
+                ] },
+            }],
+        });
+        let output = gemini_extract_chat_completions_text(&data).unwrap();
+        assert_eq!(
+            output.text,
+            "Answer\n\n```python\nprint(1)\n```\n\n```output\n1\n```\n\nSources:\n1. [a.example](https://a.example)"
+        );
+    }
+}
@@ -6,8 +6,12 @@ use crate::{
 };
 
 use anyhow::{Context, Result};
-use inquire::{validator::Validation, Text};
-use std::{fs::read_to_string, path::Path};
+use inquire::{validator::Validation, Select, Text};
+use path_absolutize::Absolutize;
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -65,27 +69,19 @@ impl Agent {
                 None => config.current_model().clone(),
             }
         };
+        check_allowed_model(name, &agent_config.allowed_models, &model)?;
 
         let rag = if rag_path.exists() {
             Some(Arc::new(Rag::load(config, DEFAULT_AGENT_NAME, &rag_path)?))
         } else if !definition.documents.is_empty() && !config.read().cli_info_flag {
             let mut ans = false;
             if *IS_STDOUT_TERMINAL {
-                ans = Confirm::new("The agent has the documents, init RAG?")
+                ans = Confirm::new(&tr("The agent has the documents, init RAG?"))
                     .with_default(true)
                     .prompt()?;
             }
             if ans {
-                let mut document_paths = vec![];
-                for path in &definition.documents {
-                    if is_url(path) {
-                        document_paths.push(path.to_string());
-                    } else {
-                        let new_path = safe_join_path(&functions_dir, path)
-                            .ok_or_else(|| anyhow!("Invalid document path: '{path}'"))?;
-                        document_paths.push(new_path.display().to_string())
-                    }
-                }
+                let document_paths = resolve_documents(&functions_dir, &definition.documents)?;
                 let rag =
                     Rag::init(config, "rag", &rag_path, &document_paths, abort_signal).await?;
                 Some(Arc::new(rag))
@@ -123,13 +119,29 @@ impl Agent {
         let mut unset_variables = vec![];
         for agent_variable in agent_variables {
             let key = agent_variable.name.clone();
-            match variables.get(&key) {
+            let env_value = agent_variable
+                .from_env
+                .as_ref()
+                .and_then(|v| env::var(v).ok())
+                .filter(|v| !v.is_empty());
+            match variables.get(&key).cloned().or(env_value) {
                 Some(value) => {
-                    output.insert(key, value.clone());
+                    output.insert(key, agent_variable.validate(&value)?);
                 }
                 None => {
+                    if let Some(cmd) = &agent_variable.from_cmd {
+                        match run_variable_cmd(cmd) {
+                            Ok(value) => {
+                                output.insert(key, agent_variable.validate(&value)?);
+                                continue;
+                            }
+                            Err(err) => {
+                                warn!("Failed to compute agent variable '{key}', {err}")
+                            }
+                        }
+                    }
                     if let Some(value) = agent_variable.default.clone() {
-                        output.insert(key, value);
+                        output.insert(key, agent_variable.validate(&value)?);
                         continue;
                     }
                     if no_interaction {
@@ -140,18 +152,27 @@ impl Agent {
                             println!("⚙ Init agent variables...");
                             printed = true;
                         }
-                        let value = Text::new(&format!(
-                            "{} ({}):",
-                            agent_variable.name, agent_variable.description
-                        ))
-                        .with_validator(|input: &str| {
-                            if input.trim().is_empty() {
-                                Ok(Validation::Invalid("This field is required".into()))
-                            } else {
-                                Ok(Validation::Valid)
-                            }
-                        })
-                        .prompt()?;
+                        let message =
+                            format!("{} ({}):", agent_variable.name, agent_variable.description);
+                        let value = if agent_variable.kind == Some(AgentVariableType::Enum) {
+                            Select::new(&message, agent_variable.values.clone()).prompt()?
+                        } else {
+                            let validated = agent_variable.clone();
+                            let value = Text::new(&message)
+                                .with_validator(move |input: &str| {
+                                    if input.trim().is_empty() {
+                                        return Ok(Validation::Invalid(
+                                            "This field is required".into(),
+                                        ));
+                                    }
+                                    match validated.validate(input) {
+                                        Ok(_) => Ok(Validation::Valid),
+                                        Err(err) => Ok(Validation::Invalid(err.to_string().into())),
+                                    }
+                                })
+                                .prompt()?;
+                            agent_variable.validate(&value)?
+                        };
                         output.insert(key, value);
                     } else {
                         unset_variables.push(agent_variable)
@@ -207,6 +228,14 @@ impl Agent {
         &self.name
     }
 
+    pub fn pipeline(&self) -> &[PipelineStage] {
+        &self.definition.pipeline
+    }
+
+    pub fn check_model(&self, model: &Model) -> Result<()> {
+        check_allowed_model(&self.name, &self.config.allowed_models, model)
+    }
+
     pub fn functions(&self) -> &Functions {
         &self.functions
     }
@@ -215,6 +244,50 @@ impl Agent {
         self.rag.clone()
     }
 
+    pub fn set_rag(&mut self, rag: Option<Arc<Rag>>) {
+        self.rag = rag;
+    }
+
+    pub fn bundled_documents(&self) -> Result<Vec<String>> {
+        let functions_dir = Config::agent_functions_dir(&self.name);
+        resolve_documents(&functions_dir, &self.definition.documents)
+    }
+
+    pub fn guard_document_paths(&self, document_paths: &[String]) -> Result<()> {
+        let bundled_documents = self.bundled_documents()?;
+        let mut document_roots = vec![Config::agent_functions_dir(&self.name)
+            .display()
+            .to_string()];
+        document_roots.extend(self.config.document_roots.iter().map(|v| {
+            if is_url(v) {
+                v.to_string()
+            } else {
+                absolute_path(v).display().to_string()
+            }
+        }));
+        let denied_paths: Vec<&String> = document_paths
+            .iter()
+            .filter(|path| {
+                !bundled_documents.contains(path)
+                    && !document_roots
+                        .iter()
+                        .any(|root| is_document_in_root(path, root))
+            })
+            .collect();
+        if !denied_paths.is_empty() {
+            bail!(
+                "The following documents are outside the document roots of agent '{}':\n{}",
+                self.name,
+                denied_paths
+                    .iter()
+                    .map(|v| format!("  - {v}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )
+        }
+        Ok(())
+    }
+
     pub fn conversation_staters(&self) -> &[String] {
         &self.definition.conversation_starters
     }
@@ -233,10 +306,18 @@ impl Agent {
         output
     }
 
+    pub fn memory(&self) -> Option<AgentMemory> {
+        self.definition.memory.then(|| AgentMemory::new(&self.name))
+    }
+
     pub fn agent_prelude(&self) -> Option<&str> {
         self.config.agent_prelude.as_deref()
     }
 
+    pub fn native_tools(&self) -> &[String] {
+        &self.config.native_tools
+    }
+
     pub fn variables(&self) -> &AgentVariables {
         match &self.session_variables {
             Some(variables) => variables,
@@ -280,6 +361,11 @@ impl Agent {
         let Some(old_value) = variables.get(key) else {
             bail!("Unknown variable '{key}'")
         };
+        let value = match self.definition.variables.iter().find(|v| v.name == key) {
+            Some(agent_variable) => agent_variable.validate(value)?,
+            None => value.to_string(),
+        };
+        let value = value.as_str();
         if old_value == value {
             return Ok(());
         }
@@ -324,6 +410,27 @@ impl Agent {
         Ok(())
     }
 
+    /// Runs the function declared for the hook with the agent variables, a failure is only a warning.
+    pub fn run_hook(&self, hook: AgentHook) {
+        let hooks = &self.definition.hooks;
+        let function = match hook {
+            AgentHook::Start => &hooks.on_start,
+            AgentHook::Exit => &hooks.on_exit,
+            AgentHook::SessionNew => &hooks.on_session_new,
+        };
+        let Some(function) = function else {
+            return;
+        };
+        if let Err(err) = run_llm_function(
+            self.name().to_string(),
+            vec![function.clone(), "{}".into()],
+            self.variable_envs(),
+        ) {
+            let message = format!("The {hook} hook of agent '{}' failed: {err}", self.name());
+            eprintln!("{}", warning_text(&message));
+        }
+    }
+
     fn run_instructions_fn(&self) -> Result<String> {
         let value = run_llm_function(
             self.name().to_string(),
@@ -342,6 +449,7 @@ impl RoleLike for Agent {
         let prompt = self.interpolated_instructions();
         let mut role = Role::new("", &prompt);
         role.sync(self);
+        role.set_native_tools(self.config.native_tools.clone());
         role
     }
 
@@ -393,12 +501,18 @@ pub struct AgentConfig {
     pub top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub use_tools: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub native_tools: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub agent_prelude: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub instructions: Option<String>,
     #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
     pub variables: AgentVariables,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub document_roots: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_models: Vec<String>,
 }
 
 impl AgentConfig {
@@ -444,6 +558,21 @@ impl AgentConfig {
                 self.variables = v;
             }
         }
+        if let Ok(v) = env::var(with_prefix("native_tools")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.native_tools = v;
+            }
+        }
+        if let Ok(v) = env::var(with_prefix("document_roots")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.document_roots = v;
+            }
+        }
+        if let Ok(v) = env::var(with_prefix("allowed_models")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.allowed_models = v;
+            }
+        }
     }
 }
 
@@ -464,6 +593,46 @@ pub struct AgentDefinition {
     pub conversation_starters: Vec<String>,
     #[serde(default)]
     pub documents: Vec<String>,
+    #[serde(default)]
+    pub hooks: AgentHooks,
+    /// Keep a memory across sessions, with the `memory_save`/`memory_search` tools
+    #[serde(default)]
+    pub memory: bool,
+    /// Shell command that `--install-agent` runs in the agent directory, e.g. to build functions.json
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// Stages run on every message in place of a single chat, making this a pipeline agent
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipeline: Vec<PipelineStage>,
+}
+
+/// Agent functions run when the agent starts, exits or begins a new session.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AgentHooks {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_exit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_session_new: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentHook {
+    Start,
+    Exit,
+    SessionNew,
+}
+
+impl std::fmt::Display for AgentHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AgentHook::Start => "on_start",
+            AgentHook::Exit => "on_exit",
+            AgentHook::SessionNew => "on_session_new",
+        };
+        write!(f, "{name}")
+    }
 }
 
 impl AgentDefinition {
@@ -472,6 +641,17 @@ impl AgentDefinition {
             .with_context(|| format!("Failed to read agent index file at '{}'", path.display()))?;
         let definition: Self = serde_yaml::from_str(&contents)
             .with_context(|| format!("Failed to load agent index at '{}'", path.display()))?;
+        for variable in &definition.variables {
+            if variable.kind == Some(AgentVariableType::Enum) && variable.values.is_empty() {
+                bail!(
+                    "Agent variable '{}' is an enum without values in '{}'",
+                    variable.name,
+                    path.display()
+                );
+            }
+        }
+        validate_pipeline(&definition.pipeline)
+            .with_context(|| format!("Invalid pipeline in '{}'", path.display()))?;
         Ok(definition)
     }
 
@@ -531,10 +711,124 @@ pub struct AgentVariable {
     pub description: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_cmd: Option<String>,
+    /// Environment variable to take the value from when it isn't set explicitly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_env: Option<String>,
+    /// Without a type, any non-empty string is accepted
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<AgentVariableType>,
+    /// The allowed values of an enum variable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
     #[serde(skip_deserializing, default)]
     pub value: String,
 }
 
+impl AgentVariable {
+    /// Checks a value against the declared type, returning it normalized.
+    pub fn validate(&self, value: &str) -> Result<String> {
+        let Some(kind) = self.kind else {
+            return Ok(value.to_string());
+        };
+        let value = value.trim();
+        let normalized = match kind {
+            AgentVariableType::Enum => self
+                .values
+                .iter()
+                .any(|v| v == value)
+                .then(|| value.to_string()),
+            AgentVariableType::Int => value.parse::<i64>().ok().map(|v| v.to_string()),
+            AgentVariableType::Bool => match value.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Some("true".to_string()),
+                "false" | "no" | "off" | "0" => Some("false".to_string()),
+                _ => None,
+            },
+            AgentVariableType::Path => Path::new(value).exists().then(|| value.to_string()),
+        };
+        normalized.ok_or_else(|| {
+            let expected = match kind {
+                AgentVariableType::Enum => format!("one of {}", self.values.join(", ")),
+                AgentVariableType::Int => "an integer".into(),
+                AgentVariableType::Bool => "true or false".into(),
+                AgentVariableType::Path => "an existing path".into(),
+            };
+            anyhow!(
+                "Invalid value '{value}' for agent variable '{}', expected {expected}",
+                self.name
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentVariableType {
+    Enum,
+    Int,
+    Bool,
+    Path,
+}
+
+fn resolve_documents(functions_dir: &Path, documents: &[String]) -> Result<Vec<String>> {
+    let mut document_paths = vec![];
+    for path in documents {
+        if is_url(path) {
+            document_paths.push(path.to_string());
+        } else {
+            let new_path = safe_join_path(functions_dir, path)
+                .ok_or_else(|| anyhow!("Invalid document path: '{path}'"))?;
+            document_paths.push(new_path.display().to_string())
+        }
+    }
+    Ok(document_paths)
+}
+
+fn absolute_path(path: &str) -> PathBuf {
+    match Path::new(path).absolutize() {
+        Ok(v) => v.to_path_buf(),
+        Err(_) => PathBuf::from(path),
+    }
+}
+
+/// Fails when `allowed_models` is set and none of its patterns match the model.
+fn check_allowed_model(agent_name: &str, allowed_models: &[String], model: &Model) -> Result<()> {
+    let model_id = model.id();
+    if allowed_models.is_empty()
+        || allowed_models
+            .iter()
+            .any(|v| is_model_allowed(&model_id, v))
+    {
+        return Ok(());
+    }
+    bail!(
+        "The agent '{agent_name}' can't run on '{model_id}', allowed_models: {}",
+        allowed_models.join(", ")
+    )
+}
+
+/// Matches the full id (`client:name`) or just the name, `*` standing for anything.
+fn is_model_allowed(model_id: &str, pattern: &str) -> bool {
+    let name = model_id.split_once(':').map(|(_, v)| v).unwrap_or(model_id);
+    wildcard_match(model_id, pattern) || wildcard_match(name, pattern)
+}
+
+fn is_document_in_root(path: &str, root: &str) -> bool {
+    if is_url(path) {
+        is_url(root) && path.starts_with(root)
+    } else {
+        !is_url(root) && absolute_path(path).starts_with(root)
+    }
+}
+
+/// The description from the agent's index file, if it can be read.
+pub fn agent_description(name: &str) -> Option<String> {
+    let path = Config::agent_functions_dir(name).join("index.yaml");
+    let definition = AgentDefinition::load(&path).ok()?;
+    Some(definition.description)
+}
+
 pub fn list_agents() -> Vec<String> {
     let agents_file = Config::functions_dir().join("agents.txt");
     let contents = match read_to_string(agents_file) {
@@ -553,3 +847,59 @@ pub fn list_agents() -> Vec<String> {
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_agent_variable() {
+        let variable = |kind, values: &[&str]| AgentVariable {
+            name: "v".into(),
+            kind: Some(kind),
+            values: values.iter().map(|v| v.to_string()).collect(),
+            ..Default::default()
+        };
+        let level = variable(AgentVariableType::Enum, &["low", "high"]);
+        assert_eq!(level.validate("high").unwrap(), "high");
+        assert!(level.validate("medium").is_err());
+        let count = variable(AgentVariableType::Int, &[]);
+        assert_eq!(count.validate(" 42 ").unwrap(), "42");
+        assert!(count.validate("4.2").is_err());
+        let flag = variable(AgentVariableType::Bool, &[]);
+        assert_eq!(flag.validate("Yes").unwrap(), "true");
+        assert!(flag.validate("maybe").is_err());
+        let dir = variable(AgentVariableType::Path, &[]);
+        assert!(dir.validate(".").is_ok());
+        assert!(dir.validate("/no/such/path").is_err());
+        let any = AgentVariable::default();
+        assert_eq!(any.validate(" any ").unwrap(), " any ");
+    }
+
+    #[test]
+    fn test_is_model_allowed() {
+        assert!(is_model_allowed("claude:claude-3-5-sonnet", "claude-*"));
+        assert!(is_model_allowed("openai:gpt-4o-mini", "gpt-4o*"));
+        assert!(is_model_allowed("openai:gpt-4o", "openai:*"));
+        assert!(is_model_allowed("openai:gpt-4o", "gpt-4o"));
+        assert!(!is_model_allowed("openai:gpt-4o-mini", "gpt-4o"));
+        assert!(!is_model_allowed("ollama:llama3.2", "claude-*"));
+    }
+
+    #[test]
+    fn test_is_document_in_root() {
+        assert!(is_document_in_root("/data/docs/a.md", "/data/docs"));
+        assert!(is_document_in_root("/data/docs/**/*.md", "/data"));
+        assert!(!is_document_in_root("/data/docs2/a.md", "/data/docs"));
+        assert!(!is_document_in_root("/data/docs/../secret", "/data/docs"));
+        assert!(is_document_in_root(
+            "https://example.com/docs/**",
+            "https://example.com/docs/"
+        ));
+        assert!(!is_document_in_root("https://example.com/docs", "/data"));
+        assert!(!is_document_in_root(
+            "/data/docs/a.md",
+            "https://example.com"
+        ));
+    }
+}
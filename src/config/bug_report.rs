@@ -0,0 +1,132 @@
+use super::*;
+
+use crate::client::list_client_names;
+
+use std::fs::read_to_string;
+
+/// Environment variables that tell the terminal apart
+const TERMINAL_ENV_NAMES: [&str; 4] = ["TERM", "TERM_PROGRAM", "TERM_PROGRAM_VERSION", "COLORTERM"];
+
+/// Settings whose URL path, query or command line may carry a token
+const ENDPOINT_KEYS: [&str; 3] = ["otel_endpoint", "on_complete", "notify_webhook"];
+
+/// Gathers what a bug report needs as markdown, ready to paste into an issue.
+///
+/// Runs before the logger is set up, so the log still holds the previous run.
+pub fn bug_report() -> String {
+    let mut terminal: Vec<String> = TERMINAL_ENV_NAMES
+        .iter()
+        .filter_map(|name| env::var(name).ok().map(|v| format!("{name}={v}")))
+        .collect();
+    if !*IS_STDOUT_TERMINAL {
+        terminal.push("stdout is not a terminal".into());
+    }
+    let (model, config_summary) = config_summary();
+    let report = format!(
+        r#"### Environment
+
+- version: {} {}
+- os: {} ({})
+- terminal: {}
+- shell: {}
+- model: {model}
+
+<details><summary>Config</summary>
+
+```
+{}
+```
+
+</details>
+
+### Last error
+
+```
+{}
+```
+"#,
+        env!("CARGO_CRATE_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        os_info::get(),
+        env::consts::ARCH,
+        terminal.join(", "),
+        SHELL.name,
+        config_summary.trim_end(),
+        last_logged_error(),
+    );
+    redact_secrets(&report)
+}
+
+fn config_summary() -> (String, String) {
+    if !Config::config_file().exists() && env::var(get_env_name("platform")).is_err() {
+        return ("-".into(), "No config file".into());
+    }
+    match Config::init(WorkingMode::Cmd) {
+        Ok(config) => {
+            let clients = list_client_names(&config)
+                .into_iter()
+                .map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let summary = match config.sysinfo() {
+                Ok(v) => format!("{:<24}{clients}\n{}", "clients", scrub_endpoints(&v)),
+                Err(err) => format!("Failed to get config info, {err}"),
+            };
+            (config.current_model().id(), summary)
+        }
+        Err(err) => ("-".into(), format!("Failed to load the config, {err:#}")),
+    }
+}
+
+fn scrub_endpoints(sysinfo: &str) -> String {
+    sysinfo
+        .lines()
+        .map(|line| match line.split_once(' ') {
+            Some((key, value)) if ENDPOINT_KEYS.contains(&key) => {
+                let width = line.len() - value.trim_start().len();
+                format!("{key:<width$}{}", scrub_endpoint(value.trim_start()))
+            }
+            _ => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Keeps the scheme and host of a URL, e.g. in `webhook:<url>`, and hides the rest.
+fn scrub_endpoint(value: &str) -> String {
+    if value == "-" {
+        return value.to_string();
+    }
+    let (kind, rest) = match value.split_once(':') {
+        Some((kind @ ("webhook" | "command" | "file"), rest)) => (format!("{kind}:"), rest),
+        _ => (String::new(), value),
+    };
+    match reqwest::Url::parse(rest) {
+        Ok(url) if url.has_host() => {
+            let origin = url.origin().ascii_serialization();
+            match url.path() == "/" && url.query().is_none() {
+                true => format!("{kind}{origin}"),
+                false => format!("{kind}{origin}/***"),
+            }
+        }
+        _ => format!("{kind}***"),
+    }
+}
+
+fn last_logged_error() -> String {
+    let log_path = match env::var(get_env_name("log_path")) {
+        Ok(v) => PathBuf::from(v),
+        Err(_) => Config::local_path(&format!("{}.log", env!("CARGO_CRATE_NAME"))),
+    };
+    let hint = format!(
+        "rerun the failing command with {}=debug first",
+        get_env_name("log_level")
+    );
+    let Ok(contents) = read_to_string(&log_path) else {
+        return format!("No log at '{}', {hint}", log_path.display());
+    };
+    match contents.lines().rev().find(|v| v.contains("[ERROR]")) {
+        Some(line) => line.to_string(),
+        None => format!("No error in '{}', {hint}", log_path.display()),
+    }
+}
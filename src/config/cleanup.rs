@@ -0,0 +1,218 @@
+use super::*;
+
+use std::time::{Duration, SystemTime};
+
+const DEFAULT_TEMP_SESSIONS_RETENTION: &str = "7d";
+const DEFAULT_LOGS_RETENTION: &str = "30d";
+const DEFAULT_RAG_CACHE_RETENTION: &str = "90d";
+
+/// How long the files aichat leaves behind are kept, e.g. `7d`; unset keeps them forever.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CleanupConfig {
+    /// The autonamed sessions under `sessions/_`
+    pub temp_sessions: Option<String>,
+    /// The log files, the `log_requests` records and the full results of shortened tool results
+    pub logs: Option<String>,
+    /// The `history_rag` stores and the `temp` RAGs built by `.rag` without a name, they are rebuilt when needed
+    pub rag_cache: Option<String>,
+}
+
+impl CleanupConfig {
+    pub fn is_empty(&self) -> bool {
+        self.temp_sessions.is_none() && self.logs.is_none() && self.rag_cache.is_none()
+    }
+
+    /// Checks the retentions when the config is loaded rather than on the first cleanup.
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("temp_sessions", &self.temp_sessions),
+            ("logs", &self.logs),
+            ("rag_cache", &self.rag_cache),
+        ] {
+            if let Some(value) = value {
+                parse_retention(value).with_context(|| format!("Invalid cleanup.{name}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills the unset retentions with the defaults of `--cleanup`.
+    pub fn or_defaults(&self) -> Self {
+        let or = |v: &Option<String>, default: &str| Some(v.clone().unwrap_or(default.into()));
+        Self {
+            temp_sessions: or(&self.temp_sessions, DEFAULT_TEMP_SESSIONS_RETENTION),
+            logs: or(&self.logs, DEFAULT_LOGS_RETENTION),
+            rag_cache: or(&self.rag_cache, DEFAULT_RAG_CACHE_RETENTION),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    pub temp_sessions: usize,
+    pub logs: usize,
+    pub log_records: usize,
+    pub rag_cache: usize,
+}
+
+impl std::fmt::Display for CleanupReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Removed {} temp session(s), {} log file(s), {} request log record(s) and {} RAG cache(s)",
+            self.temp_sessions, self.logs, self.log_records, self.rag_cache
+        )
+    }
+}
+
+/// Removes what is older than the retentions in `cleanup`.
+pub fn run_cleanup(config: &Config, cleanup: &CleanupConfig) -> Result<CleanupReport> {
+    let mut report = CleanupReport::default();
+    let agent_dirs: Vec<PathBuf> = match read_dir(Config::agents_data_dir()) {
+        Ok(rd) => rd.flatten().map(|v| v.path()).collect(),
+        Err(_) => vec![],
+    };
+    if let Some(retention) = &cleanup.temp_sessions {
+        let cutoff = parse_cutoff(retention)?;
+        let dirs = [config.sessions_dir()]
+            .into_iter()
+            .chain(agent_dirs.iter().map(|v| v.join(SESSIONS_DIR_NAME)));
+        for dir in dirs {
+            report.temp_sessions += remove_files_before(&dir.join("_"), cutoff)?;
+        }
+    }
+    if let Some(retention) = &cleanup.logs {
+        let cutoff = parse_cutoff(retention)?;
+        for name in [
+            format!("{}.log", env!("CARGO_CRATE_NAME")),
+            "schedule.log".into(),
+        ] {
+            report.logs += remove_file_before(&Config::local_path(&name), cutoff)?;
+        }
+        report.logs += remove_files_before(&Config::tool_results_dir(), cutoff)?;
+        if let Some(path) = &config.log_requests {
+            report.log_records += trim_request_log(Path::new(path), cutoff)?;
+        }
+    }
+    if let Some(retention) = &cleanup.rag_cache {
+        let cutoff = parse_cutoff(retention)?;
+        let paths = [
+            Config::local_path(HISTORY_RAG_FILE_NAME),
+            Config::rags_dir().join(format!("{TEMP_RAG_NAME}.yaml")),
+        ]
+        .into_iter()
+        .chain(agent_dirs.iter().map(|v| v.join(HISTORY_RAG_FILE_NAME)));
+        for path in paths {
+            report.rag_cache += remove_file_before(&path, cutoff)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Parses a retention like `30m`, `12h`, `7d` or `2w`.
+pub fn parse_retention(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let unit = value.chars().last().unwrap_or_default();
+    let secs = match unit {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => bail!("Invalid retention '{value}', expected a number with m, h, d or w (e.g. 7d)"),
+    };
+    let count: u64 = value[..value.len() - 1]
+        .parse()
+        .with_context(|| format!("Invalid retention '{value}'"))?;
+    match count.checked_mul(secs) {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => bail!("Invalid retention '{value}', it is too long"),
+    }
+}
+
+fn parse_cutoff(retention: &str) -> Result<SystemTime> {
+    let retention = parse_retention(retention)?;
+    Ok(SystemTime::now()
+        .checked_sub(retention)
+        .unwrap_or(SystemTime::UNIX_EPOCH))
+}
+
+fn remove_files_before(dir: &Path, cutoff: SystemTime) -> Result<usize> {
+    let Ok(rd) = read_dir(dir) else {
+        return Ok(0);
+    };
+    let mut count = 0;
+    for entry in rd.flatten() {
+        let path = entry.path();
+        if path.is_file() {
+            count += remove_file_before(&path, cutoff)?;
+        }
+    }
+    Ok(count)
+}
+
+fn remove_file_before(path: &Path, cutoff: SystemTime) -> Result<usize> {
+    let Ok(modified) = path.metadata().and_then(|v| v.modified()) else {
+        return Ok(0);
+    };
+    if modified >= cutoff {
+        return Ok(0);
+    }
+    remove_file(path).with_context(|| format!("Failed to remove '{}'", path.display()))?;
+    debug!("cleanup removed '{}'", path.display());
+    Ok(1)
+}
+
+/// Drops the `log_requests` records logged before the cutoff, keeping the ones without a readable timestamp.
+fn trim_request_log(path: &Path, cutoff: SystemTime) -> Result<usize> {
+    let Ok(content) = read_to_string(path) else {
+        return Ok(0);
+    };
+    let cutoff: chrono::DateTime<chrono::Utc> = cutoff.into();
+    let mut removed = 0;
+    let mut kept = String::new();
+    for line in content.lines() {
+        let timestamp = serde_json::from_str::<serde_json::Value>(line)
+            .ok()
+            .and_then(|v| v["timestamp"].as_str().map(|v| v.to_string()))
+            .and_then(|v| chrono::DateTime::parse_from_rfc3339(&v).ok());
+        if timestamp.is_some_and(|v| v < cutoff) {
+            removed += 1;
+        } else {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    if removed > 0 {
+        // Written aside then renamed, an interrupted cleanup can't leave the log half written
+        let temp_path = path.with_extension("cleanup.tmp");
+        std::fs::write(&temp_path, kept)
+            .and_then(|_| std::fs::rename(&temp_path, path))
+            .with_context(|| format!("Failed to write '{}'", path.display()))?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retention() {
+        assert_eq!(
+            parse_retention("7d").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert_eq!(
+            parse_retention("12h").unwrap(),
+            Duration::from_secs(12 * 3600)
+        );
+        assert_eq!(
+            parse_retention("2w").unwrap(),
+            Duration::from_secs(14 * 86400)
+        );
+        assert!(parse_retention("7").is_err());
+        assert!(parse_retention("xd").is_err());
+        assert!(parse_retention("").is_err());
+        assert!(parse_retention(&format!("{}w", u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use super::Config;
+use crate::utils::is_url;
+
+use anyhow::{anyhow, bail, Context, Result};
+use indexmap::IndexMap;
+use path_absolutize::Absolutize;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{read_to_string, write},
+    path::Path,
+};
+
+/// Attaches a context set wherever a file is accepted (e.g. `.file use:api-docs`).
+pub const CONTEXT_SET_PREFIX: &str = "use:";
+
+/// Named bundles of files, directories, URLs and commands, kept in `contexts.yaml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ContextSets(IndexMap<String, Vec<String>>);
+
+impl ContextSets {
+    pub fn load() -> Result<Self> {
+        let path = Config::contexts_file();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(&path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        serde_yaml::from_str(&content).with_context(|| format!("Invalid '{}'", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Config::contexts_file();
+        let content = serde_yaml::to_string(self)?;
+        write(&path, content).with_context(|| format!("Failed to write '{}'", path.display()))
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = (&String, &Vec<String>)> {
+        self.0.iter()
+    }
+
+    /// Adds or replaces the set, with local paths made absolute so it works from any directory.
+    pub fn create(&mut self, name: &str, paths: &[String]) -> Result<()> {
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ':') {
+            bail!("Invalid context set name '{name}'");
+        }
+        if paths.is_empty() {
+            bail!("No files for the context set '{name}'");
+        }
+        let paths = paths
+            .iter()
+            .map(|path| {
+                if path.starts_with(CONTEXT_SET_PREFIX) {
+                    bail!("Context sets can't include other sets ('{path}')");
+                }
+                if is_url(path)
+                    || path.starts_with('%')
+                    || path.starts_with('`')
+                    || path.starts_with("~/")
+                {
+                    return Ok(path.clone());
+                }
+                let absolute_path = Path::new(path)
+                    .absolutize()
+                    .with_context(|| format!("Invalid path '{path}'"))?;
+                Ok(absolute_path.display().to_string())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.0.insert(name.to_string(), paths);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        self.0
+            .shift_remove(name)
+            .ok_or_else(|| anyhow!("Unknown context set '{name}'"))?;
+        Ok(())
+    }
+}
+
+/// Replaces every `use:<name>` with the paths of that context set.
+pub fn expand_context_sets(paths: Vec<String>) -> Result<Vec<String>> {
+    if !paths.iter().any(|v| v.starts_with(CONTEXT_SET_PREFIX)) {
+        return Ok(paths);
+    }
+    let sets = ContextSets::load()?;
+    let mut output = vec![];
+    for path in paths {
+        match path.strip_prefix(CONTEXT_SET_PREFIX) {
+            Some(name) => {
+                let paths = sets
+                    .0
+                    .get(name)
+                    .ok_or_else(|| anyhow!("Unknown context set '{name}'"))?;
+                output.extend(paths.iter().cloned());
+            }
+            None => output.push(path),
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_context_set() {
+        let mut sets = ContextSets::default();
+        let paths = vec![
+            "docs/api.md".to_string(),
+            "https://example.com/spec.json".into(),
+            "`git diff`".into(),
+        ];
+        sets.create("api-docs", &paths).unwrap();
+        let saved = &sets.0["api-docs"];
+        assert!(Path::new(&saved[0]).is_absolute());
+        assert_eq!(saved[1..], paths[1..]);
+        assert!(sets.create("api docs", &paths).is_err());
+        assert!(sets.create("nested", &["use:api-docs".into()]).is_err());
+        assert!(sets.delete("api-docs").is_ok());
+        assert!(sets.delete("api-docs").is_err());
+    }
+}
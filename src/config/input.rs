@@ -1,11 +1,18 @@
 use super::*;
 
 use crate::client::{
-    init_client, patch_system_message, ChatCompletionsData, Client, ImageUrl, Message,
-    MessageContent, MessageContentPart, MessageContentToolCalls, MessageRole, Model,
+    append_json_instruction, init_client, patch_system_message, strip_image_parts,
+    ChatCompletionsData, Client, ImageUrl, Message, MessageContent, MessageContentPart,
+    MessageContentToolCalls, MessageRole, Model,
+};
+use crate::function::{
+    flatten_tool_call_messages, inject_tools_prompt, summarize_large_file, ToolResult,
+};
+use crate::rag::search_history;
+use crate::utils::{
+    base64_encode, detect_language, estimate_token_length, get_clipboard_content, list_secrets,
+    mask_secrets, sha256, unallowed_secrets, warning_text, AbortSignal, ClipboardContent,
 };
-use crate::function::ToolResult;
-use crate::utils::{base64_encode, sha256, AbortSignal};
 
 use anyhow::{bail, Context, Result};
 use path_absolutize::Absolutize;
@@ -14,6 +21,9 @@ use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const IMAGE_EXTS: [&str; 5] = ["png", "jpeg", "jpg", "webp", "gif"];
 const SUMMARY_MAX_WIDTH: usize = 80;
+const MEMORY_RECALL_LIMIT: usize = 5;
+/// Pseudo-path that includes the clipboard text or image.
+const CLIPBOARD_PATH: &str = "%clip%";
 
 #[derive(Debug, Clone)]
 pub struct Input {
@@ -21,13 +31,18 @@ pub struct Input {
     text: String,
     raw: (String, Vec<String>),
     patched_text: Option<String>,
+    enforced_language: Option<&'static str>,
     last_reply: Option<String>,
     continue_output: Option<String>,
     regenerate: bool,
     medias: Vec<String>,
     data_urls: HashMap<String, String>,
+    /// The files attached as a summary, by path, for `read_section`
+    large_files: IndexMap<String, String>,
     tool_calls: Option<MessageContentToolCalls>,
     rag_name: Option<String>,
+    /// The past Q&As added by `history_rag`, with where they came from
+    history_rag_context: Vec<(String, String)>,
     role: Role,
     with_session: bool,
     with_agent: bool,
@@ -41,13 +56,16 @@ impl Input {
             text: text.to_string(),
             raw: (text.to_string(), vec![]),
             patched_text: None,
+            enforced_language: None,
             last_reply: None,
             continue_output: None,
             regenerate: false,
             medias: Default::default(),
             data_urls: Default::default(),
+            large_files: Default::default(),
             tool_calls: None,
             rag_name: None,
+            history_rag_context: vec![],
             role,
             with_session,
             with_agent,
@@ -66,12 +84,16 @@ impl Input {
         let mut remote_urls = vec![];
         let mut last_reply = None;
         let mut with_last_reply = false;
-        for path in paths {
+        let mut with_clipboard = false;
+        for path in expand_context_sets(paths)? {
             match resolve_local_path(&path) {
                 Some(v) => {
                     if v == "%%" {
                         with_last_reply = true;
                         raw_paths.push(v);
+                    } else if v == CLIPBOARD_PATH {
+                        with_clipboard = true;
+                        raw_paths.push(v);
                     } else if v.len() > 2 && v.starts_with('`') && v.ends_with('`') {
                         external_cmds.push(v[1..v.len() - 1].to_string());
                         raw_paths.push(v);
@@ -88,10 +110,22 @@ impl Input {
                 }
             }
         }
-        let (files, medias, data_urls) =
+        let (mut files, mut medias, mut data_urls) =
             load_documents(config, external_cmds, local_paths, remote_urls)
                 .await
                 .context("Failed to load files")?;
+        if with_clipboard {
+            match get_clipboard_content()? {
+                ClipboardContent::Text(text) => {
+                    files.push(("CLIPBOARD", CLIPBOARD_PATH.into(), text))
+                }
+                ClipboardContent::Image(data) => {
+                    let contents = format!("data:image/png;base64,{}", base64_encode(data));
+                    data_urls.insert(sha256(&contents), CLIPBOARD_PATH.into());
+                    medias.push(contents);
+                }
+            }
+        }
         let mut texts = vec![];
         if !raw_text.is_empty() {
             texts.push(raw_text.to_string());
@@ -111,24 +145,39 @@ impl Input {
                 bail!("No last reply found");
             }
         }
-        for (kind, path, contents) in files {
+        let (role, with_session, with_agent) = resolve_role(&config.read(), role);
+        let large_file_threshold = {
+            let config = config.read();
+            match config.function_calling && role.model().capabilities().tools {
+                true => config.large_file_threshold,
+                false => 0,
+            }
+        };
+        let mut large_files = IndexMap::new();
+        for (kind, path, mut contents) in files {
+            if large_file_threshold > 0 && estimate_token_length(&contents) > large_file_threshold {
+                let summary = summarize_large_file(&path, &contents);
+                large_files.insert(path.clone(), std::mem::replace(&mut contents, summary));
+            }
             texts.push(format!(
                 "\n============ {kind}: {path} ============\n{contents}"
             ));
         }
-        let (role, with_session, with_agent) = resolve_role(&config.read(), role);
         Ok(Self {
             config: config.clone(),
             text: texts.join("\n"),
             raw: (raw_text.to_string(), raw_paths),
             patched_text: None,
+            enforced_language: None,
             last_reply,
             continue_output: None,
             regenerate: false,
             medias,
             data_urls,
+            large_files,
             tool_calls: Default::default(),
             rag_name: None,
+            history_rag_context: vec![],
             role,
             with_session,
             with_agent,
@@ -158,6 +207,38 @@ impl Input {
         self.data_urls.clone()
     }
 
+    pub fn own_large_files(&self) -> IndexMap<String, String> {
+        self.large_files.clone()
+    }
+
+    /// The large files `read_section` can serve, the ones attached earlier in the session too.
+    pub fn large_files(&self) -> IndexMap<String, String> {
+        let mut large_files = self
+            .session(&self.config.read().session)
+            .map(|v| v.large_files().clone())
+            .unwrap_or_default();
+        large_files.extend(self.own_large_files());
+        large_files
+    }
+
+    /// The tools selected for the role, with `read_section` when large files are attached.
+    pub fn select_functions(&self) -> Option<Vec<FunctionDeclaration>> {
+        let large_files = self.large_files();
+        let config = self.config.read();
+        let mut functions = config.select_functions(self.role()).unwrap_or_default();
+        if config.function_calling
+            && !large_files.is_empty()
+            && !functions.iter().any(|v| v.name == READ_SECTION_FUNCTION)
+        {
+            functions.push(read_section_declaration(&large_files));
+        }
+        if functions.is_empty() {
+            None
+        } else {
+            Some(functions)
+        }
+    }
+
     pub fn tool_calls(&self) -> &Option<MessageContentToolCalls> {
         &self.tool_calls
     }
@@ -177,8 +258,29 @@ impl Input {
         self.text = text;
     }
 
+    /// The language of the query when `enforce_query_language` is on and the output is in another one.
+    pub fn mismatched_language(&self, output: &str) -> Option<&'static str> {
+        if !self.config.read().enforce_query_language || self.enforced_language.is_some() {
+            return None;
+        }
+        let expected = detect_language(&self.raw.0)?;
+        let actual = detect_language(output)?;
+        (expected != actual).then_some(expected)
+    }
+
+    pub fn enforce_language(&mut self, language: &'static str) {
+        let instruction = format!("\n\nAnswer in {language}.");
+        self.text.push_str(&instruction);
+        if let Some(text) = self.patched_text.as_mut() {
+            text.push_str(&instruction);
+        }
+        self.enforced_language = Some(language);
+    }
+
     pub fn stream(&self) -> bool {
-        self.config.read().stream && !self.role().model().no_stream()
+        let model = self.role().model();
+        let config = self.config.read();
+        config.stream && !model.no_stream() && !config.no_stream_models.contains(&model.id())
     }
 
     pub fn continue_output(&self) -> Option<&str> {
@@ -213,11 +315,73 @@ impl Input {
             let rag = self.config.read().rag.clone();
             if let Some(rag) = rag {
                 let result =
-                    Config::search_rag(&self.config, &rag, &self.text, abort_signal).await?;
+                    Config::search_rag(&self.config, &rag, &self.text, abort_signal.clone())
+                        .await?;
                 self.patched_text = Some(result);
                 self.rag_name = Some(rag.name().to_string());
             }
         }
+        self.recall_history(abort_signal).await?;
+        self.recall_memories()?;
+        Ok(())
+    }
+
+    /// Puts the past conversations that relate to the text in front of it, with `history_rag`.
+    async fn recall_history(&mut self, abort_signal: AbortSignal) -> Result<()> {
+        if !self.config.read().history_rag {
+            return Ok(());
+        }
+        let context = match search_history(&self.config, &self.text, abort_signal).await {
+            Ok(v) if v.is_empty() => return Ok(()),
+            Ok(v) => v,
+            Err(err) => {
+                eprintln!("{}", warning_text(&format!("Skipped history_rag: {err:#}")));
+                return Ok(());
+            }
+        };
+        let exchanges: Vec<&str> = context.iter().map(|(_, v)| v.as_str()).collect();
+        self.patched_text = Some(format!(
+            "Relevant exchanges from past conversations, use them if they help and cite where they came from:\n<history>\n{}\n</history>\n\n{}",
+            exchanges.join("\n\n"),
+            self.text()
+        ));
+        self.history_rag_context = context;
+        Ok(())
+    }
+
+    /// Where the past Q&As added by `history_rag` came from.
+    pub fn history_sources(&self) -> Vec<String> {
+        let mut sources: Vec<String> = vec![];
+        for (source, _) in &self.history_rag_context {
+            if !sources.contains(source) {
+                sources.push(source.clone());
+            }
+        }
+        sources
+    }
+
+    /// Puts the memories of the agent that relate to the text in front of it.
+    fn recall_memories(&mut self) -> Result<()> {
+        if !self.with_agent {
+            return Ok(());
+        }
+        let memory = self.config.read().agent.as_ref().and_then(|v| v.memory());
+        let Some(memory) = memory else {
+            return Ok(());
+        };
+        let memories = memory.search(&self.text, MEMORY_RECALL_LIMIT)?;
+        if memories.is_empty() {
+            return Ok(());
+        }
+        let memories: Vec<String> = memories
+            .iter()
+            .map(|v| format!("- {} ({})", v.content, v.time))
+            .collect();
+        self.patched_text = Some(format!(
+            "Facts remembered from earlier sessions, use them if they help:\n<memories>\n{}\n</memories>\n\n{}",
+            memories.join("\n"),
+            self.text()
+        ));
         Ok(())
     }
 
@@ -235,6 +399,10 @@ impl Input {
         self
     }
 
+    pub fn set_model(&mut self, model: Model) {
+        self.role.set_model(&model);
+    }
+
     pub fn create_client(&self) -> Result<Box<dyn Client>> {
         init_client(&self.config, Some(self.role().model().clone()))
     }
@@ -244,26 +412,121 @@ impl Input {
         model: &Model,
         stream: bool,
     ) -> Result<ChatCompletionsData> {
-        if !self.medias.is_empty() && !model.supports_vision() {
-            bail!("The current model does not support vision. Is the model configured with `supports_vision: true`?");
-        }
+        let capabilities = model.capabilities();
         let mut messages = self.build_messages()?;
-        if model.no_system_message() {
+        match self.config.read().redact.as_str() {
+            "mask" => {
+                for message in messages.iter_mut() {
+                    message.content.map_text(mask_secrets);
+                }
+            }
+            // Tool results and other inputs not seen by `confirm_preview` end up here too.
+            "warn" => {
+                let texts = std::cell::RefCell::new(vec![]);
+                for message in &messages {
+                    message.content.clone().map_text(|v| {
+                        texts.borrow_mut().push(v.to_string());
+                        v.to_string()
+                    });
+                }
+                let secrets = unallowed_secrets(&texts.into_inner().join("\n"));
+                if !secrets.is_empty() {
+                    bail!(
+                        "The input contains secrets:\n{}\nSet `redact` to 'mask' to send placeholders instead, or 'off' to send them as they are",
+                        list_secrets(&secrets)
+                    );
+                }
+            }
+            _ => {}
+        }
+        if !capabilities.vision {
+            let count = strip_image_parts(&mut messages);
+            if count > 0 {
+                warn!(
+                    "'{}' does not support vision, dropped {count} image(s). Configure the model with `supports_vision: true` if it does.",
+                    model.id()
+                );
+            }
+        }
+        let mut functions = self.select_functions();
+        if let Some(list) = functions.as_ref().filter(|_| self.use_prompt_tools(model)) {
+            messages = flatten_tool_call_messages(messages);
+            inject_tools_prompt(&mut messages, list);
+            functions = None;
+        }
+        if !capabilities.system_message && messages[0].role.is_system() {
+            debug!(
+                "'{}' takes no system message, merging it into the first user message",
+                model.id()
+            );
             patch_system_message(&mut messages);
         }
         model.guard_max_input_tokens(&messages)?;
-        let temperature = self.role().temperature();
-        let top_p = self.role().top_p();
-        let functions = self.config.read().select_functions(self.role());
+        let (temperature, top_p) =
+            model.apply_param_policies(self.role().temperature(), self.role().top_p());
+        if let Some(list) = functions.as_mut() {
+            if !capabilities.tools {
+                warn!(
+                    "'{}' does not support function calling, sending the request without tools. Configure the model with `supports_function_calling: true` if it does.",
+                    model.id()
+                );
+                functions = None;
+            } else if let Some(max_tools) = capabilities.max_tools.filter(|v| list.len() > *v) {
+                warn!(
+                    "'{}' accepts at most {max_tools} tools, dropped {} of them",
+                    model.id(),
+                    list.len() - max_tools
+                );
+                list.truncate(max_tools);
+            }
+        }
+        let mut native_tools = self.role().native_tools().to_vec();
+        if !native_tools.is_empty() && !model.name().starts_with("gemini") {
+            warn!(
+                "'{}' has no native tools, ignored {}",
+                model.id(),
+                native_tools.join(", ")
+            );
+            native_tools.clear();
+        }
+        let mut json_mode = self.role().json_mode();
+        if json_mode && !capabilities.json_mode {
+            debug!(
+                "'{}' has no JSON mode, asking for JSON in the prompt instead",
+                model.id()
+            );
+            append_json_instruction(&mut messages);
+            json_mode = false;
+        }
         Ok(ChatCompletionsData {
             messages,
             temperature,
             top_p,
             functions,
+            native_tools,
+            json_mode,
             stream,
         })
     }
 
+    /// Whether the tools go in the prompt, as `function_calling_mode: prompt` does for models
+    /// without native function calling.
+    pub fn use_prompt_tools(&self, model: &Model) -> bool {
+        let config = self.config.read();
+        config.function_calling
+            && config.function_calling_mode == "prompt"
+            && !model.capabilities().tools
+    }
+
+    /// The tools put in the prompt by `prepare_completion_data`, the only ones a reply may call.
+    pub fn prompt_tool_names(&self, model: &Model) -> Option<Vec<String>> {
+        if !self.use_prompt_tools(model) {
+            return None;
+        }
+        let functions = self.select_functions()?;
+        Some(functions.into_iter().map(|v| v.name).collect())
+    }
+
     pub fn build_messages(&self) -> Result<Vec<Message>> {
         let mut messages = if let Some(session) = self.session(&self.config.read().session) {
             session.build_messages(self)
@@ -312,27 +575,58 @@ impl Input {
     }
 
     pub fn summary(&self) -> String {
-        let text: String = self
-            .text
-            .trim()
-            .chars()
-            .map(|c| if c.is_control() { ' ' } else { c })
-            .collect();
-        if text.width_cjk() > SUMMARY_MAX_WIDTH {
-            let mut sum_width = 0;
-            let mut chars = vec![];
-            for c in text.chars() {
-                sum_width += c.width_cjk().unwrap_or(1);
-                if sum_width > SUMMARY_MAX_WIDTH - 3 {
-                    chars.extend(['.', '.', '.']);
-                    break;
+        summarize_text(&self.text, SUMMARY_MAX_WIDTH)
+    }
+
+    /// What is about to be sent: every message with its token count, the RAG and the attachments.
+    pub fn preview(&self) -> Result<String> {
+        let messages = self.build_messages()?;
+        let model = self.role().model();
+        let tokenizer = model.tokenizer();
+        let mut lines = vec![format!(
+            "Preview for {}, ~{} tokens:",
+            model.id(),
+            model.total_tokens(&messages)
+        )];
+        for message in &messages {
+            let role = match message.role {
+                MessageRole::System => "system",
+                MessageRole::Assistant => "assistant",
+                MessageRole::User => "user",
+                MessageRole::Tool => "tool",
+            };
+            let (role, tokens, text) = match &message.content {
+                MessageContent::ToolCalls(tool_calls) => {
+                    let outputs: Vec<String> = tool_calls
+                        .tool_results
+                        .iter()
+                        .map(|v| v.output.to_string())
+                        .collect();
+                    let names: Vec<&str> = tool_calls
+                        .tool_results
+                        .iter()
+                        .map(|v| v.call.name.as_str())
+                        .collect();
+                    let text = format!("{} results", names.join(", "));
+                    ("tool", tokenizer.count(&outputs.join("\n")), text)
                 }
-                chars.push(c);
-            }
-            chars.into_iter().collect()
-        } else {
-            text
+                content => {
+                    let text = content.to_text();
+                    (role, tokenizer.count(&text), summarize_text(&text, 60))
+                }
+            };
+            lines.push(format!("  {role:<9} {tokens:>6} tokens  {text}"));
+        }
+        if let Some(rag_name) = &self.rag_name {
+            lines.push(format!(
+                "  RAG context from '{rag_name}' is in the last message"
+            ));
         }
+        let files = &self.raw.1;
+        if !files.is_empty() {
+            lines.push(format!("  Attachments: {}", files.join(", ")));
+        }
+        Ok(lines.join("\n"))
     }
 
     pub fn raw(&self) -> String {
@@ -453,6 +747,30 @@ async fn load_documents(
     Ok((files, medias, data_urls))
 }
 
+/// The text on one line, cut to `max_width` columns with an ellipsis.
+fn summarize_text(text: &str, max_width: usize) -> String {
+    let text: String = text
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if text.width_cjk() > max_width {
+        let mut sum_width = 0;
+        let mut chars = vec![];
+        for c in text.chars() {
+            sum_width += c.width_cjk().unwrap_or(1);
+            if sum_width > max_width - 3 {
+                chars.extend(['.', '.', '.']);
+                break;
+            }
+            chars.push(c);
+        }
+        chars.into_iter().collect()
+    } else {
+        text
+    }
+}
+
 pub fn resolve_data_url(data_urls: &HashMap<String, String>, data_url: String) -> String {
     if data_url.starts_with("data:") {
         let hash = sha256(&data_url);
@@ -0,0 +1,254 @@
+use super::*;
+
+use anyhow::{bail, Context, Result};
+use inquire::Confirm;
+use std::fs::{read_to_string, remove_dir_all, rename, write};
+use std::path::Path;
+use std::process::Command;
+
+/// Remembers where an installed agent came from, for `--update-agent`
+const SOURCE_FILE_NAME: &str = ".aichat-source";
+
+/// A git repository holding one agent, with an optional tag or branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AgentSource {
+    url: String,
+    reference: Option<String>,
+    name: String,
+}
+
+impl AgentSource {
+    /// Accepts `github:user/repo`, `gitlab:user/repo` or a git URL, each with an optional `#ref`.
+    fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let (location, reference) = match value.rsplit_once('#') {
+            Some((location, reference)) if !reference.is_empty() => {
+                (location, Some(reference.to_string()))
+            }
+            _ => (value, None),
+        };
+        let url = if let Some(path) = location.strip_prefix("github:") {
+            format!("https://github.com/{path}.git")
+        } else if let Some(path) = location.strip_prefix("gitlab:") {
+            format!("https://gitlab.com/{path}.git")
+        } else if is_url(location)
+            || location.starts_with("git@")
+            || location.starts_with("ssh://")
+            || location.starts_with("file://")
+        {
+            location.to_string()
+        } else {
+            bail!(
+                "Invalid agent source '{value}', expected github:<user>/<repo>[#tag] or a git URL"
+            )
+        };
+        let name = url
+            .trim_end_matches('/')
+            .trim_end_matches(".git")
+            .rsplit(['/', ':'])
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Invalid agent source '{value}', unable to name the agent");
+        }
+        Ok(Self {
+            url,
+            reference,
+            name,
+        })
+    }
+}
+
+/// Clones an agent from a git repository into the functions directory.
+pub fn install_agent(source_value: &str) -> Result<()> {
+    let source = AgentSource::parse(source_value)?;
+    let agent_dir = Config::agent_functions_dir(&source.name);
+    if agent_dir.exists() {
+        bail!(
+            "Agent '{}' already exists at '{}', use --update-agent to update it",
+            source.name,
+            agent_dir.display()
+        );
+    }
+    fetch_agent(&source, source_value, &agent_dir)?;
+    add_to_agents_list(&source.name)?;
+    println!(
+        "✓ Installed agent '{}' into '{}'",
+        source.name,
+        agent_dir.display()
+    );
+    Ok(())
+}
+
+/// Fetches an installed agent again from where it came from.
+pub fn update_agent(name: &str) -> Result<()> {
+    let agent_dir = Config::agent_functions_dir(name);
+    let source_value = read_source(name, &agent_dir)?;
+    let source = AgentSource::parse(&source_value)?;
+    fetch_agent(&source, &source_value, &agent_dir)?;
+    println!("✓ Updated agent '{name}' from '{source_value}'");
+    Ok(())
+}
+
+/// Deletes an installed agent, keeping its data (config, sessions and RAG).
+pub fn remove_agent(name: &str) -> Result<()> {
+    let agent_dir = Config::agent_functions_dir(name);
+    read_source(name, &agent_dir)?;
+    remove_dir_all(&agent_dir)
+        .with_context(|| format!("Failed to remove '{}'", agent_dir.display()))?;
+    let agents_file = Config::functions_dir().join("agents.txt");
+    if let Ok(contents) = read_to_string(&agents_file) {
+        let contents: String = contents
+            .lines()
+            .filter(|v| v.trim() != name)
+            .map(|v| format!("{v}\n"))
+            .collect();
+        write(&agents_file, contents)?;
+    }
+    println!("✓ Removed agent '{name}'");
+    Ok(())
+}
+
+/// Clones into a staging directory next to `agent_dir`, verifies and builds it, then moves it in place.
+fn fetch_agent(source: &AgentSource, source_value: &str, agent_dir: &Path) -> Result<()> {
+    if is_offline() && !source.url.starts_with("file://") && !is_local_url(&source.url) {
+        bail!("Unable to clone '{}' in offline mode", source.url);
+    }
+    let staging_dir = agent_dir.with_file_name(format!(".{}.installing", source.name));
+    if staging_dir.exists() {
+        remove_dir_all(&staging_dir)?;
+    }
+    ensure_parent_exists(&staging_dir)?;
+    let ret = prepare_agent(source, source_value, &staging_dir);
+    if let Err(err) = ret {
+        let _ = remove_dir_all(&staging_dir);
+        return Err(err);
+    }
+    if agent_dir.exists() {
+        remove_dir_all(agent_dir)
+            .with_context(|| format!("Failed to replace '{}'", agent_dir.display()))?;
+    }
+    rename(&staging_dir, agent_dir)
+        .with_context(|| format!("Failed to move the agent to '{}'", agent_dir.display()))?;
+    Ok(())
+}
+
+fn prepare_agent(source: &AgentSource, source_value: &str, dir: &Path) -> Result<()> {
+    let dir_path = dir.display().to_string();
+    let mut args = vec!["clone", "--depth", "1"];
+    if let Some(reference) = &source.reference {
+        args.extend(["--branch", reference]);
+    }
+    args.extend([source.url.as_str(), dir_path.as_str()]);
+    let (success, _, stderr) =
+        run_command_with_output("git", &args, None).with_context(|| "Failed to run `git`")?;
+    if !success {
+        bail!("Failed to clone '{}': {}", source.url, stderr.trim());
+    }
+    let definition = AgentDefinition::load(&dir.join("index.yaml"))
+        .with_context(|| format!("No valid agent in '{source_value}'"))?;
+    if let Some(build) = &definition.build {
+        run_build(build, dir)?;
+    }
+    let functions_file = dir.join("functions.json");
+    if functions_file.exists() {
+        Functions::init(&functions_file)?;
+    } else if definition.build.is_none() && definition.instructions.contains("{{__tools__}}") {
+        bail!(
+            "The agent '{}' lists tools but has no functions.json",
+            source.name
+        );
+    }
+    write(dir.join(SOURCE_FILE_NAME), source_value)?;
+    Ok(())
+}
+
+/// Runs the build step declared by the agent once confirmed.
+fn run_build(build: &str, dir: &Path) -> Result<()> {
+    println!("The agent declares a build step: `{build}`");
+    let ans = *IS_STDOUT_TERMINAL
+        && Confirm::new(&tr("Run the build step?"))
+            .with_default(false)
+            .prompt()?;
+    if !ans {
+        println!(
+            "{}",
+            warning_text("Skipped the build step, the agent may miss its tools")
+        );
+        return Ok(());
+    }
+    let status = Command::new(&SHELL.cmd)
+        .args([&SHELL.arg, build])
+        .current_dir(dir)
+        .status()
+        .with_context(|| format!("Failed to run `{build}`"))?;
+    if !status.success() {
+        bail!("The build step `{build}` exited with {status}");
+    }
+    Ok(())
+}
+
+fn read_source(name: &str, agent_dir: &Path) -> Result<String> {
+    let source = read_to_string(agent_dir.join(SOURCE_FILE_NAME))
+        .map(|v| v.trim().to_string())
+        .unwrap_or_default();
+    if source.is_empty() {
+        bail!("Agent '{name}' wasn't installed with --install-agent");
+    }
+    Ok(source)
+}
+
+fn add_to_agents_list(name: &str) -> Result<()> {
+    if list_agents().iter().any(|v| v == name) {
+        return Ok(());
+    }
+    let agents_file = Config::functions_dir().join("agents.txt");
+    let mut contents = read_to_string(&agents_file).unwrap_or_default();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(name);
+    contents.push('\n');
+    ensure_parent_exists(&agents_file)?;
+    write(&agents_file, contents)
+        .with_context(|| format!("Failed to write '{}'", agents_file.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_agent_source() {
+        assert_eq!(
+            AgentSource::parse("github:sigoden/todo-agent#v1.0").unwrap(),
+            AgentSource {
+                url: "https://github.com/sigoden/todo-agent.git".into(),
+                reference: Some("v1.0".into()),
+                name: "todo-agent".into(),
+            }
+        );
+        assert_eq!(
+            AgentSource::parse("git@example.com:me/coder.git").unwrap(),
+            AgentSource {
+                url: "git@example.com:me/coder.git".into(),
+                reference: None,
+                name: "coder".into(),
+            }
+        );
+        assert_eq!(
+            AgentSource::parse("https://gitlab.com/me/my_agent/")
+                .unwrap()
+                .name,
+            "my_agent"
+        );
+        assert!(AgentSource::parse("sigoden/todo-agent").is_err());
+        assert!(AgentSource::parse("github:sigoden/../..").is_err());
+    }
+}
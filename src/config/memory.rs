@@ -0,0 +1,144 @@
+use super::*;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+const MEMORY_FILE_NAME: &str = "memory.jsonl";
+const STOP_WORDS: [&str; 24] = [
+    "the", "and", "for", "with", "that", "this", "are", "was", "were", "you", "your", "what",
+    "how", "why", "when", "where", "which", "who", "can", "does", "have", "has", "not", "but",
+];
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MemoryEntry {
+    pub time: String,
+    pub content: String,
+}
+
+/// The facts an agent keeps across sessions, one JSON entry per line in its data dir.
+#[derive(Debug, Clone)]
+pub struct AgentMemory {
+    path: PathBuf,
+}
+
+impl AgentMemory {
+    pub fn new(agent_name: &str) -> Self {
+        Self {
+            path: Config::agent_data_dir(agent_name).join(MEMORY_FILE_NAME),
+        }
+    }
+
+    pub fn save(&self, content: &str) -> Result<()> {
+        let content = content.trim();
+        if content.is_empty() {
+            bail!("Nothing to remember");
+        }
+        ensure_parent_exists(&self.path)?;
+        let entry = MemoryEntry {
+            time: now(),
+            content: content.to_string(),
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open '{}'", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    /// The entries sharing the most words with `query`, newer ones first on ties.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<MemoryEntry>> {
+        let terms = memory_terms(query);
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut scored: Vec<(usize, usize, MemoryEntry)> = self
+            .load()?
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                let words = memory_terms(&entry.content);
+                let score = terms.iter().filter(|v| words.contains(*v)).count();
+                (score > 0).then_some((score, index, entry))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+        Ok(scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, _, entry)| entry)
+            .collect())
+    }
+
+    fn load(&self) -> Result<Vec<MemoryEntry>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let contents = read_to_string(&self.path)
+            .with_context(|| format!("Failed to read '{}'", self.path.display()))?;
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+/// Lowercased words of at least 3 characters, with CJK characters taken one by one.
+fn memory_terms(text: &str) -> HashSet<String> {
+    let mut terms = HashSet::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if word.chars().any(is_cjk) {
+            terms.extend(word.chars().filter(|c| is_cjk(*c)).map(String::from));
+        } else if word.chars().count() >= 3 {
+            let word = word.to_lowercase();
+            if !STOP_WORDS.contains(&word.as_str()) {
+                terms.insert(word);
+            }
+        }
+    }
+    terms
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{ac00}'..='\u{d7af}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_search() {
+        let path = temp_file("-memory-", ".jsonl");
+        let memory = AgentMemory { path: path.clone() };
+        memory.save("The user prefers Rust over Go").unwrap();
+        memory.save("The deploy target is a Raspberry Pi").unwrap();
+        memory.save("Rust builds for the Pi use cross").unwrap();
+        let contents = |query| -> Vec<String> {
+            memory
+                .search(query, 2)
+                .unwrap()
+                .into_iter()
+                .map(|v| v.content)
+                .collect()
+        };
+        assert_eq!(
+            contents("how to build rust for the pi?"),
+            [
+                "Rust builds for the Pi use cross",
+                "The user prefers Rust over Go"
+            ]
+        );
+        assert_eq!(
+            contents("raspberry"),
+            ["The deploy target is a Raspberry Pi"]
+        );
+        assert!(contents("python").is_empty());
+        assert!(memory.save("  ").is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{bail, Context, Result};
+use serde_yaml::Value;
+use std::{fs, path::Path};
+
+pub const CONFIG_VERSION: u64 = 1;
+pub const SESSION_VERSION: u64 = 1;
+pub const RAG_VERSION: u64 = 1;
+
+const VERSION_FIELD: &str = "version";
+
+/// Upgrades the document in place by one version, returns whether the content changed.
+pub type Migration = fn(&mut Value) -> Result<bool>;
+
+/// The migration at index `i` upgrades a file from version `i` to version `i + 1`.
+pub const CONFIG_MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [stamp_version];
+pub const SESSION_MIGRATIONS: [Migration; SESSION_VERSION as usize] = [stamp_version];
+pub const RAG_MIGRATIONS: [Migration; RAG_VERSION as usize] = [stamp_version];
+
+/// Brings the YAML `content` read from `path` up to `current_version`.
+///
+/// Files written by a newer aichat are rejected. When a migration changes the content and
+/// `persist` is set, the original file is backed up to `<path>.v<version>.bak` and the migrated
+/// content is written back to `path`, otherwise it is only returned.
+pub fn migrate_file(
+    kind: &str,
+    path: &Path,
+    content: String,
+    current_version: u64,
+    migrations: &[Migration],
+    persist: bool,
+) -> Result<String> {
+    // Most files are current, they are recognized without parsing the whole document
+    if peek_version(&content) == Some(current_version) {
+        return Ok(content);
+    }
+    let mut value: Value = match serde_yaml::from_str(&content) {
+        Ok(Value::Mapping(v)) => Value::Mapping(v),
+        _ => return Ok(content),
+    };
+    let version = get_version(&value)?;
+    if version > current_version {
+        bail!(
+            "The {kind} file at '{}' has version {version}, but this aichat only supports up to version {current_version}. Please upgrade aichat.",
+            path.display()
+        );
+    }
+    if version == current_version {
+        return Ok(content);
+    }
+    let mut changed = false;
+    for (index, migrate) in migrations
+        .iter()
+        .enumerate()
+        .take(current_version as usize)
+        .skip(version as usize)
+    {
+        changed |= migrate(&mut value).with_context(|| {
+            format!(
+                "Failed to migrate the {kind} file at '{}' from version {index} to {}",
+                path.display(),
+                index + 1
+            )
+        })?;
+    }
+    if !changed {
+        return Ok(content);
+    }
+    value[VERSION_FIELD] = current_version.into();
+    let new_content = serde_yaml::to_string(&value)
+        .with_context(|| format!("Failed to serialize the migrated {kind} file"))?;
+    if !persist {
+        return Ok(new_content);
+    }
+    let backup_path = format!("{}.v{version}.bak", path.display());
+    fs::copy(path, &backup_path).with_context(|| {
+        format!(
+            "Failed to back up the {kind} file at '{}' to '{backup_path}'",
+            path.display()
+        )
+    })?;
+    fs::write(path, &new_content).with_context(|| {
+        format!(
+            "Failed to write the migrated {kind} file to '{}'",
+            path.display()
+        )
+    })?;
+    eprintln!(
+        "✓ Migrated {kind} '{}' to version {current_version}, backup saved to '{backup_path}'.",
+        path.display()
+    );
+    Ok(new_content)
+}
+
+/// The version from a top-level `version: <n>` line, if there is one.
+fn peek_version(content: &str) -> Option<u64> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("version:"))
+        .and_then(|v| v.split('#').next())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+fn get_version(value: &Value) -> Result<u64> {
+    match value.get(VERSION_FIELD) {
+        None | Some(Value::Null) => Ok(0),
+        Some(v) => match v.as_u64() {
+            Some(v) => Ok(v),
+            None => bail!(
+                "Invalid {VERSION_FIELD} '{}'",
+                serde_yaml::to_string(v)?.trim()
+            ),
+        },
+    }
+}
+
+/// Unversioned files share the layout of version 1, so only the version number is added.
+fn stamp_version(_value: &mut Value) -> Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename_foo(value: &mut Value) -> Result<bool> {
+        match value.as_mapping_mut().and_then(|v| v.remove("foo")) {
+            Some(foo) => {
+                value["bar"] = foo;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    #[test]
+    fn test_migrate_file() {
+        let dir = std::env::temp_dir().join(format!("aichat-migration-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.yaml");
+        let migrations: [Migration; 2] = [stamp_version, rename_foo];
+
+        let content = "foo: 1\n".to_string();
+        fs::write(&path, &content).unwrap();
+        let output = migrate_file("test", &path, content, 2, &migrations, true).unwrap();
+        let value: Value = serde_yaml::from_str(&output).unwrap();
+        assert_eq!(value["bar"], Value::from(1));
+        assert_eq!(value["version"], Value::from(2));
+        assert_eq!(fs::read_to_string(&path).unwrap(), output);
+        assert_eq!(
+            fs::read_to_string(dir.join("data.yaml.v0.bak")).unwrap(),
+            "foo: 1\n"
+        );
+
+        let content = "version: 1\nbar: 1\n".to_string();
+        let output = migrate_file("test", &path, content.clone(), 2, &migrations, true).unwrap();
+        assert_eq!(output, content);
+
+        assert_eq!(peek_version("model: a\nversion: 2 # current\n"), Some(2));
+        assert_eq!(peek_version("agent:\n  version: 2\n"), None);
+
+        fs::write(&path, "foo: 2\n").unwrap();
+        let output = migrate_file("test", &path, "foo: 2\n".into(), 2, &migrations, false).unwrap();
+        assert!(output.contains("bar: 2"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "foo: 2\n");
+
+        let content = "version: 3\n".to_string();
+        assert!(migrate_file("test", &path, content, 2, &migrations, true).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,22 +1,54 @@
 mod agent;
+mod bug_report;
+mod cleanup;
+mod context_set;
 mod input;
+mod install;
+mod memory;
+mod migration;
+mod pipeline;
+mod profile;
 mod role;
 mod session;
+mod state;
+mod template;
 
+use self::agent::{agent_description, AgentDefinition, AgentHook, AgentVariableType};
 pub use self::agent::{list_agents, Agent, AgentVariables};
+pub use self::bug_report::bug_report;
+pub use self::cleanup::{run_cleanup, CleanupConfig};
+pub use self::context_set::{expand_context_sets, ContextSets, CONTEXT_SET_PREFIX};
 pub use self::input::Input;
+pub use self::install::{install_agent, remove_agent, update_agent};
+pub use self::memory::AgentMemory;
+pub use self::migration::{migrate_file, RAG_MIGRATIONS, RAG_VERSION};
+use self::migration::{CONFIG_MIGRATIONS, CONFIG_VERSION};
+pub use self::pipeline::run_agent_pipeline;
+use self::pipeline::{validate_pipeline, PipelineStage};
+pub use self::profile::{export_profile, import_profile};
+use self::role::{find_duplicate_roles, merge_role_prompt, ROLE_DUPLICATE_RATIO};
 pub use self::role::{
     Role, RoleLike, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE,
 };
 use self::session::Session;
+pub use self::state::State;
+pub use self::template::{parse_template_variables, PromptTemplate};
 
 use crate::client::{
-    create_client_config, list_client_types, list_models, ClientConfig, MessageContentToolCalls,
-    Model, ModelType, OPENAI_COMPATIBLE_PLATFORMS,
+    create_client_config, init_client, list_client_models, list_client_types, list_models,
+    ClientConfig, Message, MessageContentToolCalls, Model, ModelType, RequestIds,
+    OPENAI_COMPATIBLE_PLATFORMS,
+};
+use crate::function::{
+    guard_injection, memory_declarations, read_section_declaration, FunctionDeclaration, Functions,
+    ToolResult, READ_SECTION_FUNCTION,
 };
-use crate::function::{FunctionDeclaration, Functions, ToolResult};
 use crate::rag::Rag;
-use crate::render::{MarkdownRender, RenderOptions};
+use crate::render::{
+    need_pager, render_diff, render_messages_diff, run_pager, DiffTheme, MarkdownRender,
+    RenderOptions, PAGER_MODES,
+};
+use crate::schedule::{Schedule, Sink};
 use crate::utils::*;
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -36,8 +68,10 @@ use std::{
     path::{Path, PathBuf},
     process,
     sync::{Arc, OnceLock},
+    time::{Duration, SystemTime},
 };
 use syntect::highlighting::ThemeSet;
+use textwrap::core::display_width;
 
 pub const TEMP_ROLE_NAME: &str = "%%";
 pub const TEMP_RAG_NAME: &str = "temp";
@@ -49,14 +83,22 @@ const LIGHT_THEME: &[u8] = include_bytes!("../../assets/monokai-extended-light.t
 
 const CONFIG_FILE_NAME: &str = "config.yaml";
 const ROLES_DIR_NAME: &str = "roles";
+const TEMPLATES_DIR_NAME: &str = "templates";
 const ENV_FILE_NAME: &str = ".env";
 const MESSAGES_FILE_NAME: &str = "messages.md";
+const HISTORY_RAG_FILE_NAME: &str = "history_rag.yaml";
+const STATE_FILE_NAME: &str = "state.yaml";
+const CONTEXTS_FILE_NAME: &str = "contexts.yaml";
+const MODELS_FILE_NAME: &str = "models.yaml";
+const SYNCED_MODELS_FILE_NAME: &str = "models-synced.yaml";
 const SESSIONS_DIR_NAME: &str = "sessions";
 const RAGS_DIR_NAME: &str = "rags";
 const FUNCTIONS_DIR_NAME: &str = "functions";
 const FUNCTIONS_FILE_NAME: &str = "functions.json";
 const FUNCTIONS_BIN_DIR_NAME: &str = "bin";
 const AGENTS_DIR_NAME: &str = "agents";
+const HISTORY_DIR_NAME: &str = "history";
+const TOOL_RESULTS_DIR_NAME: &str = "tool_results";
 
 const CLIENTS_FIELD: &str = "clients";
 
@@ -65,6 +107,163 @@ const SERVE_ADDR: &str = "127.0.0.1:8000";
 const SUMMARIZE_PROMPT: &str =
     "Summarize the discussion briefly in 200 words or less to use as a prompt for future context.";
 const SUMMARY_PROMPT: &str = "This is a summary of the chat history as a recap: ";
+const FOLLOWUPS_PROMPT: &str = r#"Suggest 3 short follow-up questions the user may ask next, based on the question and answer below. Write them in the language of the question, one per line, without numbering or any other text.
+
+<question>
+__QUESTION__
+</question>
+
+<answer>
+__ANSWER__
+</answer>"#;
+const MAX_FOLLOWUPS: usize = 3;
+
+const COMPRESS_STRATEGIES: [&str; 2] = ["rolling", "single"];
+const FUNCTION_CALLING_MODES: [&str; 2] = ["native", "prompt"];
+const INJECTION_GUARD_MODES: [&str; 3] = ["off", "warn", "block"];
+const REDACT_MODES: [&str; 3] = ["off", "mask", "warn"];
+const CODE_STYLES: [&str; 4] = ["plain", "numbers", "header", "full"];
+const COMPRESS_WINDOW_SIZE: usize = 20;
+
+/// Keys accepted by `.set`, with a short description.
+const SET_KEYS: [(&str, &str); 46] = [
+    (
+        "max_output_tokens",
+        "Max output tokens of the current model",
+    ),
+    ("temperature", "Temperature parameter (0, 1)"),
+    (
+        "top_p",
+        "Top-p parameter, (0, 1) or (0, 2) depending on the model",
+    ),
+    ("dry_run", "Print the messages instead of calling the API"),
+    ("stream", "Use the stream-style API"),
+    (
+        "stream_render_interval",
+        "Repaint streamed output at most every N ms, 0 for every chunk",
+    ),
+    ("show_usage", "Show token usage and cost under each answer"),
+    (
+        "preview",
+        "Show the assembled messages and confirm before sending",
+    ),
+    ("followups", "Suggest follow-up questions after each answer"),
+    (
+        "followup_model",
+        "Model that suggests the follow-up questions, the current one by default",
+    ),
+    (
+        "pager",
+        "Show completed answers in $PAGER (auto, always, never)",
+    ),
+    ("save", "Persist the messages to messages.md"),
+    (
+        "history_hints",
+        "Suggest the rest of the input from the REPL history",
+    ),
+    ("function_calling", "Enable function calling"),
+    (
+        "function_calling_mode",
+        "How tools reach models without native support (native, prompt)",
+    ),
+    ("use_tools", "Tools to use (e.g. 'fs,web_search', 'all')"),
+    (
+        "tool_judge_model",
+        "Model that screens tool results before they reach the LLM",
+    ),
+    (
+        "tool_judge_tools",
+        "Tools whose results are screened (e.g. 'execute_command', 'all')",
+    ),
+    (
+        "large_file_threshold",
+        "Attach a summary of files with more tokens than this, 0 to disable",
+    ),
+    (
+        "tool_timeout_secs",
+        "Kill a tool running longer than this, 0 to disable",
+    ),
+    (
+        "tool_max_output_bytes",
+        "Truncate tool outputs larger than this, 0 to disable",
+    ),
+    (
+        "tool_allowed_paths",
+        "Directories the builtin fs tools can access (e.g. '~/notes,/tmp')",
+    ),
+    (
+        "tool_result_max_tokens",
+        "Shorten tool results with more tokens than this, 0 to disable",
+    ),
+    (
+        "tool_result_summarize",
+        "Tools whose long results are summarized rather than cut (e.g. 'web_search', 'all')",
+    ),
+    (
+        "security.injection_guard",
+        "Scan tool calls, tool results and RAG content for prompt injections (off, warn, block)",
+    ),
+    (
+        "redact",
+        "Mask secrets before sending them or ask first (off, mask, warn)",
+    ),
+    ("auto_max_tool_calls", "Tool calls allowed in one .auto run"),
+    ("auto_max_tokens", "Tokens allowed in one .auto run"),
+    ("auto_max_seconds", "Seconds allowed for one .auto run"),
+    (
+        "auto_checkpoint",
+        "Ask before continuing .auto every N tool calls, 0 to never ask",
+    ),
+    ("save_session", "Save the session on exit, null to ask"),
+    (
+        "project_sessions",
+        "Use a session named after the git repo when none is given",
+    ),
+    (
+        "compress_threshold",
+        "Compress the session when tokens reach this count, 0 to disable",
+    ),
+    (
+        "compress_strategy",
+        "How to compress the session (rolling, single)",
+    ),
+    (
+        "compress_keep_recent",
+        "Recent turns kept verbatim when compressing",
+    ),
+    (
+        "rag_reranker_model",
+        "Reranker model for sorting retrieved documents",
+    ),
+    ("rag_top_k", "Number of documents to retrieve"),
+    (
+        "history_rag",
+        "Add relevant past conversations to new prompts",
+    ),
+    (
+        "enforce_query_language",
+        "Ask again when the answer isn't in the language of the query",
+    ),
+    ("highlight", "Syntax highlighting"),
+    (
+        "code_style",
+        "Decorate code blocks (plain, numbers, header, full)",
+    ),
+    (
+        "log_requests",
+        "JSONL file that every provider call is appended to",
+    ),
+    ("clipboard", "How to copy text (auto, osc52, command:<cmd>)"),
+    ("locale", "Language of the UI (auto, en, de, ja, zh-CN)"),
+    (
+        "on_complete",
+        "Hook fired after a non-interactive run (webhook:<url>, command:<cmd>)",
+    ),
+    (
+        "notify_webhook",
+        "URL that every finished reply or error is posted to",
+    ),
+];
 
 const RAG_TEMPLATE: &str = r#"Answer the query based on the context while respecting the rules. (user query, some textual context and rules, all inside xml tags)
 
@@ -89,6 +288,7 @@ const LEFT_PROMPT: &str = "{color.green}{?session {?agent {agent}>}{session}{?ro
 const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
 
 static EDITOR: OnceLock<Option<String>> = OnceLock::new();
+static PROJECT_NAME: OnceLock<Option<String>> = OnceLock::new();
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
@@ -98,46 +298,90 @@ pub struct Config {
     pub model_id: String,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    pub favorite_models: Vec<String>,
 
     pub dry_run: bool,
     pub stream: bool,
+    pub stream_render_interval: u64,
+    pub show_usage: bool,
+    pub preview: bool,
+    pub followups: bool,
+    pub followup_model: Option<String>,
     pub save: bool,
     pub keybindings: String,
+    pub keybindings_custom: IndexMap<String, String>,
+    pub history_hints: bool,
     pub editor: Option<String>,
     pub wrap: Option<String>,
     pub wrap_code: bool,
+    pub code_style: String,
+    pub pager: String,
+    pub locale: Option<String>,
 
     pub function_calling: bool,
+    pub function_calling_mode: String,
     pub mapping_tools: IndexMap<String, String>,
     pub use_tools: Option<String>,
+    pub tool_judge_model: Option<String>,
+    pub tool_judge_tools: Option<String>,
+    pub large_file_threshold: usize,
+    pub tool_timeout_secs: u64,
+    pub tool_max_output_bytes: usize,
+    pub tool_allowed_paths: Vec<String>,
+    pub tool_result_max_tokens: usize,
+    pub tool_result_summarize: Option<String>,
+    pub security: SecurityConfig,
+    pub redact: String,
+    pub cleanup: CleanupConfig,
+    pub auto_max_tool_calls: usize,
+    pub auto_max_tokens: usize,
+    pub auto_max_seconds: u64,
+    pub auto_checkpoint: usize,
 
     pub prelude: Option<String>,
     pub repl_prelude: Option<String>,
     pub agent_prelude: Option<String>,
 
     pub save_session: Option<bool>,
+    pub project_sessions: bool,
     pub compress_threshold: usize,
+    pub compress_strategy: String,
+    pub compress_keep_recent: usize,
     pub summarize_prompt: Option<String>,
     pub summary_prompt: Option<String>,
 
     pub rag_embedding_model: Option<String>,
     pub rag_reranker_model: Option<String>,
     pub rag_top_k: usize,
+    pub history_rag: bool,
     pub rag_chunk_size: Option<usize>,
     pub rag_chunk_overlap: Option<usize>,
     pub rag_template: Option<String>,
+    pub enforce_query_language: bool,
 
     #[serde(default)]
     pub document_loaders: HashMap<String, String>,
+    pub code_interpreters: HashMap<String, String>,
 
     pub highlight: bool,
     pub light_theme: bool,
+    pub theme: Option<String>,
+    pub syntaxes_dir: Option<String>,
     pub left_prompt: Option<String>,
     pub right_prompt: Option<String>,
 
     pub serve_addr: Option<String>,
     pub user_agent: Option<String>,
     pub save_shell_history: bool,
+    pub save_repl_history: bool,
+    pub timestamp_format: Option<String>,
+    pub timestamp_utc: bool,
+    pub log_requests: Option<String>,
+    pub otel_endpoint: Option<String>,
+    pub clipboard: Option<String>,
+    pub on_complete: Option<String>,
+    pub notify_webhook: Option<String>,
+    pub schedules: Vec<Schedule>,
 
     pub clients: Vec<ClientConfig>,
 
@@ -147,6 +391,9 @@ pub struct Config {
     pub session: Option<Session>,
     #[serde(skip)]
     pub rag: Option<Arc<Rag>>,
+    /// The `history_rag` store kept between searches, with the path and modified time it was loaded at
+    #[serde(skip)]
+    pub history_rag_cache: Option<(PathBuf, Option<SystemTime>, Arc<Rag>)>,
     #[serde(skip)]
     pub agent: Option<Agent>,
     #[serde(skip)]
@@ -157,10 +404,30 @@ pub struct Config {
     pub working_mode: WorkingMode,
     #[serde(skip)]
     pub last_message: Option<LastMessage>,
+    /// What happened in the last exchange with the model, for `.info last`
+    #[serde(skip)]
+    pub last_exchange: Option<LastExchange>,
+    /// The reply before the last one, kept for `.diff`
+    #[serde(skip)]
+    pub previous_output: Option<String>,
+    /// Follow-up questions suggested for the last answer, asked with `.f <n>`
+    #[serde(skip)]
+    pub last_followups: Vec<String>,
+    /// Models found to be unable to stream, requests to them don't stream for the rest of the run
+    #[serde(skip)]
+    pub no_stream_models: HashSet<String>,
+    /// The config dir can't be written, sessions, history and messages are only kept in memory
+    #[serde(skip)]
+    pub read_only: bool,
 
     #[serde(skip)]
     pub cli_info_flag: bool,
     #[serde(skip)]
+    pub cli_dry_run_request: bool,
+    /// The format of `--stream-raw`, text or ndjson
+    #[serde(skip)]
+    pub cli_stream_raw: Option<String>,
+    #[serde(skip)]
     pub cli_agent_variables: Option<AgentVariables>,
 }
 
@@ -170,58 +437,110 @@ impl Default for Config {
             model_id: Default::default(),
             temperature: None,
             top_p: None,
+            favorite_models: vec![],
 
             dry_run: false,
             stream: true,
+            stream_render_interval: 50,
+            show_usage: false,
+            preview: false,
+            followups: false,
+            followup_model: None,
             save: false,
             keybindings: "emacs".into(),
+            keybindings_custom: Default::default(),
+            history_hints: true,
             editor: None,
             wrap: None,
             wrap_code: false,
+            code_style: "plain".into(),
+            pager: "never".into(),
+            locale: None,
 
             function_calling: true,
+            function_calling_mode: "native".into(),
             mapping_tools: Default::default(),
             use_tools: None,
+            tool_judge_model: None,
+            tool_judge_tools: None,
+            large_file_threshold: 32000,
+            tool_timeout_secs: 0,
+            tool_max_output_bytes: 100000,
+            tool_allowed_paths: vec![],
+            tool_result_max_tokens: 0,
+            tool_result_summarize: None,
+            security: Default::default(),
+            redact: "off".into(),
+            cleanup: Default::default(),
+            auto_max_tool_calls: 20,
+            auto_max_tokens: 100000,
+            auto_max_seconds: 600,
+            auto_checkpoint: 5,
 
             prelude: None,
             repl_prelude: None,
             agent_prelude: None,
 
             save_session: None,
+            project_sessions: false,
             compress_threshold: 4000,
+            compress_strategy: "rolling".into(),
+            compress_keep_recent: 2,
             summarize_prompt: None,
             summary_prompt: None,
 
             rag_embedding_model: None,
             rag_reranker_model: None,
             rag_top_k: 5,
+            history_rag: false,
             rag_chunk_size: None,
             rag_chunk_overlap: None,
             rag_template: None,
+            enforce_query_language: false,
 
             document_loaders: Default::default(),
+            code_interpreters: Default::default(),
 
             highlight: true,
             light_theme: false,
+            theme: None,
+            syntaxes_dir: None,
             left_prompt: None,
             right_prompt: None,
 
             serve_addr: None,
             user_agent: None,
             save_shell_history: true,
+            save_repl_history: true,
+            timestamp_format: None,
+            timestamp_utc: false,
+            log_requests: None,
+            otel_endpoint: None,
+            clipboard: None,
+            on_complete: None,
+            notify_webhook: None,
+            schedules: vec![],
 
             clients: vec![],
 
             role: None,
             session: None,
             rag: None,
+            history_rag_cache: None,
             agent: None,
             model: Default::default(),
             functions: Default::default(),
             working_mode: WorkingMode::Cmd,
             last_message: None,
+            last_exchange: None,
+            previous_output: None,
+            last_followups: vec![],
+            no_stream_models: Default::default(),
+            read_only: false,
 
             cli_info_flag: false,
+            cli_dry_run_request: false,
+            cli_stream_raw: None,
             cli_agent_variables: None,
         }
     }
@@ -232,6 +551,7 @@ pub type GlobalConfig = Arc<RwLock<Config>>;
 impl Config {
     pub fn init(working_mode: WorkingMode) -> Result<Self> {
         let config_path = Self::config_file();
+        let writable = is_writable_dir(&Self::config_dir());
         let mut config = if !config_path.exists() {
             match env::var(get_env_name("platform")) {
                 Ok(v) => Self::load_dynamic(&v)?,
@@ -239,16 +559,22 @@ impl Config {
                     if *IS_STDOUT_TERMINAL {
                         create_config_file(&config_path)?;
                     }
-                    Self::load_from_file(&config_path)?
+                    Self::load_from_file(&config_path, writable)?
                 }
             }
         } else {
-            Self::load_from_file(&config_path)?
+            Self::load_from_file(&config_path, writable)?
         };
 
         config.working_mode = working_mode;
 
         config.load_envs();
+        config.cleanup.validate()?;
+        config.validate_modes()?;
+
+        if !writable {
+            config.use_read_only_storage();
+        }
 
         if let Some(wrap) = config.wrap.clone() {
             config.set_wrap(&wrap)?;
@@ -258,7 +584,11 @@ impl Config {
 
         config.setup_model()?;
         config.setup_document_loaders();
+        config.setup_code_interpreters();
         config.setup_user_agent();
+        set_timestamp_options(config.timestamp_format.clone(), config.timestamp_utc)?;
+        set_clipboard_method(config.clipboard.as_deref())?;
+        set_locale(config.locale.as_deref())?;
 
         Ok(config)
     }
@@ -296,6 +626,21 @@ impl Config {
         Self::roles_dir().join(format!("{name}.md"))
     }
 
+    pub fn templates_dir() -> PathBuf {
+        match env::var(get_env_name("templates_dir")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(TEMPLATES_DIR_NAME),
+        }
+    }
+
+    pub fn template_file(name: &str) -> PathBuf {
+        Self::templates_dir().join(format!("{name}.md"))
+    }
+
+    pub fn contexts_file() -> PathBuf {
+        Self::local_path(CONTEXTS_FILE_NAME)
+    }
+
     pub fn env_file() -> PathBuf {
         match env::var(get_env_name("env_file")) {
             Ok(value) => PathBuf::from(value),
@@ -303,6 +648,19 @@ impl Config {
         }
     }
 
+    pub fn state_file() -> PathBuf {
+        Self::local_path(STATE_FILE_NAME)
+    }
+
+    /// Local overlay that adds or patches entries of the predefined models.
+    pub fn models_file() -> PathBuf {
+        Self::local_path(MODELS_FILE_NAME)
+    }
+
+    pub fn synced_models_file() -> PathBuf {
+        Self::local_path(SYNCED_MODELS_FILE_NAME)
+    }
+
     pub fn messages_file(&self) -> PathBuf {
         match &self.agent {
             None => match env::var(get_env_name("messages_file")) {
@@ -313,6 +671,14 @@ impl Config {
         }
     }
 
+    /// The embeddings of past conversations, for `history_rag`.
+    pub fn history_rag_file(&self) -> PathBuf {
+        match &self.agent {
+            None => Self::local_path(HISTORY_RAG_FILE_NAME),
+            Some(agent) => Self::agent_data_dir(agent.name()).join(HISTORY_RAG_FILE_NAME),
+        }
+    }
+
     pub fn sessions_dir(&self) -> PathBuf {
         match &self.agent {
             None => match env::var(get_env_name("sessions_dir")) {
@@ -323,6 +689,11 @@ impl Config {
         }
     }
 
+    /// Where the full results of shortened tool results are kept.
+    pub fn tool_results_dir() -> PathBuf {
+        Self::local_path(TOOL_RESULTS_DIR_NAME)
+    }
+
     pub fn rags_dir() -> PathBuf {
         match env::var(get_env_name("rags_dir")) {
             Ok(value) => PathBuf::from(value),
@@ -359,6 +730,22 @@ impl Config {
         }
     }
 
+    /// The REPL input history, kept apart for each agent and each named session.
+    pub fn repl_history_file(&self) -> Option<PathBuf> {
+        if !self.save_repl_history {
+            return None;
+        }
+        let dir = match &self.agent {
+            Some(agent) => Self::agent_data_dir(agent.name()).join(HISTORY_DIR_NAME),
+            None => Self::local_path(HISTORY_DIR_NAME),
+        };
+        let path = match self.session.as_ref().map(|v| v.name()) {
+            None | Some(TEMP_SESSION_NAME) => dir.join("repl.txt"),
+            Some(name) => dir.join(SESSIONS_DIR_NAME).join(format!("{name}.txt")),
+        };
+        Some(path)
+    }
+
     pub fn agents_data_dir() -> PathBuf {
         Self::local_path(AGENTS_DIR_NAME)
     }
@@ -525,6 +912,16 @@ impl Config {
     }
 
     pub fn sysinfo(&self) -> Result<String> {
+        let output = self
+            .sysinfo_items()
+            .iter()
+            .map(|(name, value)| format!("{name:<24}{value}\n"))
+            .collect::<Vec<String>>()
+            .join("");
+        Ok(output)
+    }
+
+    fn sysinfo_items(&self) -> Vec<(&'static str, String)> {
         let display_path = |path: &Path| path.display().to_string();
         let wrap = self
             .wrap
@@ -548,24 +945,97 @@ impl Config {
             ("top_p", format_option_value(&role.top_p())),
             ("dry_run", self.dry_run.to_string()),
             ("stream", self.stream.to_string()),
+            (
+                "stream_render_interval",
+                self.stream_render_interval.to_string(),
+            ),
+            ("show_usage", self.show_usage.to_string()),
+            ("preview", self.preview.to_string()),
+            ("followups", self.followups.to_string()),
+            ("followup_model", format_option_value(&self.followup_model)),
             ("save", self.save.to_string()),
             ("keybindings", self.keybindings.clone()),
+            ("history_hints", self.history_hints.to_string()),
             ("wrap", wrap),
             ("wrap_code", self.wrap_code.to_string()),
+            ("pager", self.pager.clone()),
             ("function_calling", self.function_calling.to_string()),
+            ("function_calling_mode", self.function_calling_mode.clone()),
             ("use_tools", format_option_value(&role.use_tools())),
+            (
+                "tool_judge_model",
+                format_option_value(&self.tool_judge_model),
+            ),
+            (
+                "tool_judge_tools",
+                format_option_value(&self.tool_judge_tools),
+            ),
+            (
+                "large_file_threshold",
+                self.large_file_threshold.to_string(),
+            ),
+            ("tool_timeout_secs", self.tool_timeout_secs.to_string()),
+            (
+                "tool_max_output_bytes",
+                self.tool_max_output_bytes.to_string(),
+            ),
+            (
+                "tool_allowed_paths",
+                match self.tool_allowed_paths.is_empty() {
+                    true => "-".into(),
+                    false => self.tool_allowed_paths.join(","),
+                },
+            ),
+            (
+                "tool_result_max_tokens",
+                self.tool_result_max_tokens.to_string(),
+            ),
+            (
+                "tool_result_summarize",
+                format_option_value(&self.tool_result_summarize),
+            ),
+            (
+                "security.injection_guard",
+                self.security.injection_guard.clone(),
+            ),
+            ("redact", self.redact.clone()),
+            ("auto_max_tool_calls", self.auto_max_tool_calls.to_string()),
+            ("auto_max_tokens", self.auto_max_tokens.to_string()),
+            ("auto_max_seconds", self.auto_max_seconds.to_string()),
+            ("auto_checkpoint", self.auto_checkpoint.to_string()),
             ("save_session", format_option_value(&self.save_session)),
+            ("project_sessions", self.project_sessions.to_string()),
             ("compress_threshold", self.compress_threshold.to_string()),
+            ("compress_strategy", self.compress_strategy.clone()),
+            (
+                "compress_keep_recent",
+                self.compress_keep_recent.to_string(),
+            ),
             (
                 "rag_reranker_model",
                 format_option_value(&rag_reranker_model),
             ),
             ("rag_top_k", rag_top_k.to_string()),
+            ("history_rag", self.history_rag.to_string()),
+            (
+                "enforce_query_language",
+                self.enforce_query_language.to_string(),
+            ),
             ("highlight", self.highlight.to_string()),
+            ("code_style", self.code_style.clone()),
             ("light_theme", self.light_theme.to_string()),
+            ("theme", format_option_value(&self.theme)),
+            ("syntaxes_dir", format_option_value(&self.syntaxes_dir)),
+            ("log_requests", format_option_value(&self.log_requests)),
+            ("otel_endpoint", format_option_value(&self.otel_endpoint)),
+            ("clipboard", format_option_value(&self.clipboard)),
+            ("locale", format_option_value(&self.locale)),
+            ("on_complete", format_option_value(&self.on_complete)),
+            ("notify_webhook", format_option_value(&self.notify_webhook)),
             ("config_file", display_path(&Self::config_file())),
             ("env_file", display_path(&Self::env_file())),
             ("roles_dir", display_path(&Self::roles_dir())),
+            ("templates_dir", display_path(&Self::templates_dir())),
             ("sessions_dir", display_path(&self.sessions_dir())),
             ("rags_dir", display_path(&Self::rags_dir())),
             ("functions_dir", display_path(&Self::functions_dir())),
@@ -574,21 +1044,71 @@ impl Config {
         if let Ok((_, Some(log_path))) = Self::log_config(self.working_mode.is_serve()) {
             items.push(("log_path", display_path(&log_path)));
         }
-        let output = items
-            .iter()
-            .map(|(name, value)| format!("{name:<24}{value}\n"))
-            .collect::<Vec<String>>()
-            .join("");
-        Ok(output)
+        items
     }
 
-    pub fn update(config: &GlobalConfig, data: &str) -> Result<()> {
-        let parts: Vec<&str> = data.split_whitespace().collect();
-        if parts.len() != 2 {
-            bail!("Usage: .set <key> <value>. If value is null, unset key.");
+    /// Lists the `.set` keys with their current values and edits the selected one.
+    pub fn settings_browser(config: &GlobalConfig) -> Result<()> {
+        let mut cursor = 0;
+        loop {
+            let items = config.read().sysinfo_items();
+            let options: Vec<String> = SET_KEYS
+                .iter()
+                .map(|(key, description)| {
+                    let value = items
+                        .iter()
+                        .find(|(name, _)| name == key)
+                        .map(|(_, value)| value.as_str())
+                        .unwrap_or("-");
+                    format!("{key:<24}{value:<28}{}", dimmed_text(description))
+                })
+                .collect();
+            let Some(selected) = Select::new(&tr("Settings:"), options)
+                .with_page_size(SET_KEYS.len())
+                .with_starting_cursor(cursor)
+                .with_help_message("↑↓ to move, enter to edit, esc to quit")
+                .prompt_skippable()?
+            else {
+                break;
+            };
+            let Some(index) = SET_KEYS
+                .iter()
+                .position(|(key, _)| selected.split_whitespace().next() == Some(*key))
+            else {
+                break;
+            };
+            cursor = index;
+            let (key, description) = SET_KEYS[index];
+            let current = items
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.trim_end_matches(" (current model)"))
+                .filter(|v| *v != "-")
+                .unwrap_or_default()
+                .to_string();
+            let Some(value) = Text::new(&format!("{key}:"))
+                .with_initial_value(&current)
+                .with_help_message(&format!("{description}; use null to unset"))
+                .prompt_skippable()?
+            else {
+                continue;
+            };
+            let value = value.trim();
+            if value.is_empty() || value == current {
+                continue;
+            }
+            if let Err(err) = Self::update(config, &format!("{key} {value}")) {
+                eprintln!("{}", error_text(&format!("{err:#}")));
+            }
         }
-        let key = parts[0];
-        let value = parts[1];
+        Ok(())
+    }
+
+    pub fn update(config: &GlobalConfig, data: &str) -> Result<()> {
+        let (key, value) = match data.trim().split_once(char::is_whitespace) {
+            Some((key, value)) if !value.trim().is_empty() => (key, value.trim()),
+            _ => bail!("Usage: .set <key> <value>. If value is null, unset key."),
+        };
         match key {
             "max_output_tokens" => {
                 let value = parse_value(value)?;
@@ -608,12 +1128,50 @@ impl Config {
             }
             "stream" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
-                config.write().stream = value;
+                let mut config = config.write();
+                config.stream = value;
+                config.no_stream_models.clear();
+            }
+            "stream_render_interval" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().stream_render_interval = value;
+            }
+            "pager" => {
+                if !PAGER_MODES.contains(&value) {
+                    bail!("Invalid value, expected one of: {}", PAGER_MODES.join(", "));
+                }
+                config.write().pager = value.to_string();
+            }
+            "show_usage" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().show_usage = value;
+            }
+            "preview" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().preview = value;
+            }
+            "followups" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().followups = value;
+            }
+            "followup_model" => {
+                let value: Option<String> = parse_value(value)?;
+                if let Some(model_id) = &value {
+                    Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?;
+                }
+                config.write().followup_model = value;
             }
             "save" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
+                if value && config.read().read_only {
+                    bail!("Unable to save messages, the config dir is read-only");
+                }
                 config.write().save = value;
             }
+            "history_hints" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().history_hints = value;
+            }
             "function_calling" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 if value && config.write().functions.is_empty() {
@@ -625,6 +1183,75 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_use_tools(value);
             }
+            "tool_judge_model" => {
+                let value: Option<String> = parse_value(value)?;
+                if let Some(model_id) = &value {
+                    Model::retrieve_model(&config.read(), model_id, ModelType::Chat)?;
+                }
+                config.write().tool_judge_model = value;
+            }
+            "tool_judge_tools" => {
+                let value = parse_value(value)?;
+                config.write().tool_judge_tools = value;
+            }
+            "large_file_threshold" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().large_file_threshold = value;
+            }
+            "tool_timeout_secs" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().tool_timeout_secs = value;
+            }
+            "tool_max_output_bytes" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().tool_max_output_bytes = value;
+            }
+            "tool_allowed_paths" => {
+                let value: Option<String> = parse_value(value)?;
+                config.write().tool_allowed_paths = split_paths(value.as_deref());
+            }
+            "tool_result_max_tokens" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().tool_result_max_tokens = value;
+            }
+            "tool_result_summarize" => {
+                let value = parse_value(value)?;
+                config.write().tool_result_summarize = value;
+            }
+            "security.injection_guard" => {
+                if !INJECTION_GUARD_MODES.contains(&value) {
+                    bail!(
+                        "Invalid value, expected one of: {}",
+                        INJECTION_GUARD_MODES.join(", ")
+                    );
+                }
+                config.write().security.injection_guard = value.to_string();
+            }
+            "redact" => {
+                if !REDACT_MODES.contains(&value) {
+                    bail!(
+                        "Invalid value, expected one of: {}",
+                        REDACT_MODES.join(", ")
+                    );
+                }
+                config.write().redact = value.to_string();
+            }
+            "auto_max_tool_calls" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().auto_max_tool_calls = value;
+            }
+            "auto_max_tokens" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().auto_max_tokens = value;
+            }
+            "auto_max_seconds" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().auto_max_seconds = value;
+            }
+            "auto_checkpoint" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().auto_checkpoint = value;
+            }
             "save_session" => {
                 let value = parse_value(value)?;
                 config.write().set_save_session(value);
@@ -633,6 +1260,28 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_compress_threshold(value);
             }
+            "function_calling_mode" => {
+                if !FUNCTION_CALLING_MODES.contains(&value) {
+                    bail!(
+                        "Invalid value, expected one of: {}",
+                        FUNCTION_CALLING_MODES.join(", ")
+                    );
+                }
+                config.write().function_calling_mode = value.to_string();
+            }
+            "compress_strategy" => {
+                if !COMPRESS_STRATEGIES.contains(&value) {
+                    bail!(
+                        "Invalid value, expected one of: {}",
+                        COMPRESS_STRATEGIES.join(", ")
+                    );
+                }
+                config.write().compress_strategy = value.to_string();
+            }
+            "compress_keep_recent" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().compress_keep_recent = value;
+            }
             "rag_reranker_model" => {
                 let value = parse_value(value)?;
                 Self::set_rag_reranker_model(config, value)?;
@@ -641,10 +1290,56 @@ impl Config {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 Self::set_rag_top_k(config, value)?;
             }
+            "history_rag" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().history_rag = value;
+            }
+            "enforce_query_language" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().enforce_query_language = value;
+            }
+            "project_sessions" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                config.write().project_sessions = value;
+            }
+            "code_style" => {
+                if !CODE_STYLES.contains(&value) {
+                    bail!("Invalid value, expected one of: {}", CODE_STYLES.join(", "));
+                }
+                config.write().code_style = value.to_string();
+            }
             "highlight" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().highlight = value;
             }
+            "log_requests" => {
+                let value = parse_value(value)?;
+                config.write().log_requests = value;
+            }
+            "clipboard" => {
+                let value = parse_value(value)?;
+                set_clipboard_method(value.as_deref())?;
+                config.write().clipboard = value;
+            }
+            "locale" => {
+                let value = parse_value(value)?;
+                set_locale(value.as_deref())?;
+                config.write().locale = value;
+            }
+            "on_complete" => {
+                let value = parse_value(value)?;
+                if value.is_some() {
+                    Sink::parse(value.as_deref())?;
+                }
+                config.write().on_complete = value;
+            }
+            "notify_webhook" => {
+                let value = parse_value(value)?;
+                if let Some(url) = value.as_deref().filter(|v| !is_url(v)) {
+                    bail!("Invalid notify_webhook '{url}', expected an http(s) URL");
+                }
+                config.write().notify_webhook = value;
+            }
             _ => bail!("Unknown key '{key}'"),
         }
         Ok(())
@@ -805,6 +1500,9 @@ impl Config {
 
     pub fn set_model(&mut self, model_id: &str) -> Result<()> {
         let model = Model::retrieve_model(self, model_id, ModelType::Chat)?;
+        if let Some(agent) = &self.agent {
+            agent.check_model(&model)?;
+        }
         match self.role_like_mut() {
             Some(role_like) => role_like.set_model(&model),
             None => {
@@ -814,6 +1512,97 @@ impl Config {
         Ok(())
     }
 
+    /// Chat models with the favorites first, then the recently used ones, then the rest.
+    pub fn prioritized_models(&self) -> Vec<(&'static Model, Option<&'static str>)> {
+        let models = list_models(self, ModelType::Chat);
+        let recent_models = State::load().recent_models;
+        let mut output: Vec<(&'static Model, Option<&'static str>)> = vec![];
+        let ids = self
+            .favorite_models
+            .iter()
+            .map(|v| (v, "favorite"))
+            .chain(recent_models.iter().map(|v| (v, "recent")));
+        for (id, tag) in ids {
+            if output.iter().any(|(v, _)| &v.id() == id) {
+                continue;
+            }
+            if let Some(model) = models.iter().find(|v| &v.id() == id) {
+                output.push((model, Some(tag)));
+            }
+        }
+        for model in models {
+            if !output.iter().any(|(v, _)| v.id() == model.id()) {
+                output.push((model, None));
+            }
+        }
+        output
+    }
+
+    /// Lets the user pick a model with favorites and recently used ones listed first.
+    pub fn select_model(config: &GlobalConfig) -> Result<()> {
+        let models = config.read().prioritized_models();
+        if models.is_empty() {
+            bail!("No chat models available");
+        }
+        let current_model_id = config.read().current_model().id();
+        let items = models
+            .iter()
+            .map(|(model, tag)| {
+                let mark = match *tag {
+                    Some("favorite") => "★",
+                    Some(_) => "↺",
+                    None => " ",
+                };
+                (model.id(), format!("{mark} {}", model.description()))
+            })
+            .collect();
+        let Some(model_id) = select_name(
+            "Model:",
+            items,
+            Some(&current_model_id),
+            "★ favorite, ↺ recently used; tokens in/out | USD per 1M in/out; type to filter",
+        )?
+        else {
+            return Ok(());
+        };
+        config.write().set_model(&model_id)?;
+        State::record_model(config.read().current_model());
+        Ok(())
+    }
+
+    /// Picks one of the roles, `None` if the picker is dismissed.
+    pub fn select_role(&self) -> Result<Option<String>> {
+        let items = Self::all_roles()
+            .into_iter()
+            .map(|role| {
+                let hint = role
+                    .prompt()
+                    .lines()
+                    .map(|v| v.trim())
+                    .find(|v| !v.is_empty())
+                    .unwrap_or_default();
+                (role.name().to_string(), truncate_hint(hint))
+            })
+            .collect();
+        let current = self.role.as_ref().map(|v| v.name());
+        select_name("Role:", items, current, "type to filter")
+    }
+
+    /// Picks one of the installed agents, `None` if the picker is dismissed.
+    pub fn select_agent() -> Result<Option<String>> {
+        let items: Vec<(String, String)> = list_agents()
+            .into_iter()
+            .map(|name| {
+                let hint = agent_description(&name).unwrap_or_default();
+                (name, truncate_hint(&hint))
+            })
+            .collect();
+        if items.is_empty() {
+            bail!("No agents installed");
+        }
+        select_name("Agent:", items, None, "type to filter")
+    }
+
     pub fn use_prompt(&mut self, prompt: &str) -> Result<()> {
         let mut role = Role::new(TEMP_ROLE_NAME, prompt);
         role.set_model(&self.model);
@@ -864,14 +1653,8 @@ impl Config {
     }
 
     pub fn retrieve_role(&self, name: &str) -> Result<Role> {
-        let names = Self::list_roles(false);
-        let mut role = if let Some(role_name) = Role::match_name(&names, name) {
-            let path = Self::role_file(&role_name);
-            let content = read_to_string(&path)?;
-            Role::new(name, &content)
-        } else {
-            Role::builtin(name)?
-        };
+        let mut role = Self::load_role(name)?;
+        role.resolve_variables()?;
         match role.model_id() {
             Some(model_id) => {
                 if self.model.id() != model_id {
@@ -886,8 +1669,20 @@ impl Config {
         Ok(role)
     }
 
+    /// Reads the role as written, without resolving its variables or model.
+    fn load_role(name: &str) -> Result<Role> {
+        let names = Self::list_roles(false);
+        if let Some(role_name) = Role::match_name(&names, name) {
+            let path = Self::role_file(&role_name);
+            let content = read_to_string(&path)?;
+            Ok(Role::new(name, &content))
+        } else {
+            Role::builtin(name)
+        }
+    }
+
     pub fn new_role(&mut self, name: &str) -> Result<()> {
-        let ans = Confirm::new("Create a new role?")
+        let ans = Confirm::new(&tr("Create a new role?"))
             .with_default(true)
             .prompt()?;
         if ans {
@@ -939,7 +1734,7 @@ impl Config {
             None => bail!("No role"),
         };
         if role_name == TEMP_ROLE_NAME {
-            role_name = Text::new("Role name:")
+            role_name = Text::new(&tr("Role name:"))
                 .with_validator(|input: &str| {
                     let input = input.trim();
                     if input.is_empty() {
@@ -998,6 +1793,84 @@ impl Config {
         names
     }
 
+    /// Lists the roles with alike prompts, then offers to merge or delete each pair.
+    pub fn dedupe_roles(&self) -> Result<()> {
+        let roles: Vec<Role> = Self::list_roles(false)
+            .into_iter()
+            .filter_map(|name| {
+                let content = read_to_string(Self::role_file(&name)).ok()?;
+                Some(Role::new(&name, &content))
+            })
+            .collect();
+        let pairs = find_duplicate_roles(&roles, ROLE_DUPLICATE_RATIO);
+        if pairs.is_empty() {
+            println!("No duplicate roles found.");
+            return Ok(());
+        }
+        for (i, j, ratio) in &pairs {
+            println!(
+                "{} ~ {} ({:.0}% alike)",
+                roles[*i].name(),
+                roles[*j].name(),
+                ratio * 100.0
+            );
+        }
+        if !*IS_STDOUT_TERMINAL {
+            return Ok(());
+        }
+        let mut removed: HashSet<&str> = HashSet::new();
+        for (i, j, ratio) in pairs {
+            let (a, b) = (roles[i].name(), roles[j].name());
+            if removed.contains(a) || removed.contains(b) {
+                continue;
+            }
+            println!(
+                "\n{}",
+                render_diff(roles[i].prompt(), roles[j].prompt(), a, b, true)
+            );
+            let options = vec![
+                format!("Merge '{b}' into '{a}'"),
+                format!("Merge '{a}' into '{b}'"),
+                format!("Delete '{a}'"),
+                format!("Delete '{b}'"),
+                "Keep both".to_string(),
+            ];
+            let message = format!("'{a}' and '{b}' are {:.0}% alike:", ratio * 100.0);
+            let Some(selected) = Select::new(&message, options.clone()).prompt_skippable()? else {
+                break;
+            };
+            let index = options
+                .iter()
+                .position(|v| *v == selected)
+                .unwrap_or_default();
+            let (keep, drop) = match index {
+                0 | 3 => (a, b),
+                1 | 2 => (b, a),
+                _ => continue,
+            };
+            if index < 2 {
+                let path = Self::role_file(keep);
+                let content = read_to_string(&path)?;
+                let other = if keep == a {
+                    roles[j].prompt()
+                } else {
+                    roles[i].prompt()
+                };
+                std::fs::write(&path, merge_role_prompt(&content, other))
+                    .with_context(|| format!("Failed to write role to '{}'", path.display()))?;
+                if let Ok(editor) = self.editor() {
+                    edit_file(&editor, &path)?;
+                }
+            }
+            let path = Self::role_file(drop);
+            remove_file(&path)
+                .with_context(|| format!("Failed to delete role at '{}'", path.display()))?;
+            removed.insert(drop);
+            println!("✓ Removed role '{drop}'");
+        }
+        Ok(())
+    }
+
     pub fn has_role(name: &str) -> bool {
         let names = Self::list_roles(true);
         Role::match_name(&names, name).is_some()
@@ -1054,6 +1927,9 @@ impl Config {
                 }
             }
         }
+        if let (Some(agent), Some(session)) = (&self.agent, &session) {
+            agent.check_model(session.model())?;
+        }
         self.session = session;
         self.init_agent_session_variables(new_session)?;
         Ok(())
@@ -1061,27 +1937,43 @@ impl Config {
 
     pub fn session_info(&self) -> Result<String> {
         if let Some(session) = &self.session {
-            let render_options = self.render_options()?;
-            let mut markdown_render = MarkdownRender::init(render_options)?;
-            let agent_info: Option<(String, Vec<String>)> = self.agent.as_ref().map(|agent| {
-                let functions = agent
-                    .functions()
-                    .declarations()
-                    .iter()
-                    .filter_map(|v| if v.agent { Some(v.name.clone()) } else { None })
-                    .collect();
-                (agent.name().to_string(), functions)
-            });
-            session.render(&mut markdown_render, &agent_info)
+            self.render_session(session)
         } else {
             bail!("No session")
         }
     }
 
+    /// Renders a saved session without switching to it, the session file is left untouched.
+    pub fn view_session(&self, name: &str) -> Result<String> {
+        let session_path = self.session_file(name);
+        if !session_path.exists() {
+            bail!("Unknown session '{name}'");
+        }
+        let session = Session::load_readonly(name, &session_path)?;
+        self.render_session(&session)
+    }
+
+    fn render_session(&self, session: &Session) -> Result<String> {
+        let render_options = self.render_options()?;
+        let mut markdown_render = MarkdownRender::init(render_options)?;
+        let agent_info: Option<(String, Vec<String>)> = self.agent.as_ref().map(|agent| {
+            let functions = agent
+                .functions()
+                .declarations()
+                .iter()
+                .filter_map(|v| if v.agent { Some(v.name.clone()) } else { None })
+                .collect();
+            (agent.name().to_string(), functions)
+        });
+        session.render(&mut markdown_render, &agent_info)
+    }
+
     pub fn exit_session(&mut self) -> Result<()> {
         if let Some(mut session) = self.session.take() {
             let sessions_dir = self.sessions_dir();
-            session.exit(&sessions_dir, self.working_mode.is_repl())?;
+            if !self.read_only {
+                session.exit(&sessions_dir, self.working_mode.is_repl())?;
+            }
             self.discontinuous_last_message();
         }
         Ok(())
@@ -1112,6 +2004,11 @@ impl Config {
         };
         let session_path = self.session_file(&name);
         self.save_session(Some(&name))?;
+        let old_messages = self
+            .session
+            .as_ref()
+            .map(|v| message_texts(v.messages()))
+            .unwrap_or_default();
         let editor = self.editor()?;
         edit_file(&editor, &session_path).with_context(|| {
             format!(
@@ -1119,8 +2016,14 @@ impl Config {
                 session_path.display()
             )
         })?;
-        self.session = Some(Session::load(self, &name, &session_path)?);
+        let session = Session::load(self, &name, &session_path)?;
+        let new_messages = message_texts(session.messages());
+        self.session = Some(session);
         self.discontinuous_last_message();
+        match render_messages_diff(&old_messages, &new_messages, self.diff_theme()) {
+            Some(changes) => println!("Session changes:\n{changes}\n"),
+            None => println!("{}", dimmed_text("No messages changed.")),
+        }
         Ok(())
     }
 
@@ -1189,30 +2092,51 @@ impl Config {
     }
 
     pub async fn compress_session(config: &GlobalConfig) -> Result<()> {
-        match config.read().session.as_ref() {
-            Some(session) => {
-                if !session.has_user_messages() {
-                    bail!("No need to compress since there are no messages in the session")
+        let (windows, end, role) = {
+            let config = config.read();
+            let session = match config.session.as_ref() {
+                Some(session) => {
+                    if !session.has_user_messages() {
+                        bail!("No need to compress since there are no messages in the session")
+                    }
+                    session
                 }
-            }
-            None => bail!("No session"),
-        }
+                None => bail!("No session"),
+            };
+            let window_size = match config.compress_strategy.as_str() {
+                "single" => None,
+                _ => Some(COMPRESS_WINDOW_SIZE),
+            };
+            let (windows, end) =
+                session.compression_windows(config.compress_keep_recent, window_size)?;
+            let mut role = Role::new("", "");
+            role.sync(session);
+            (windows, end, role)
+        };
 
         let prompt = config
             .read()
             .summarize_prompt
             .clone()
             .unwrap_or_else(|| SUMMARIZE_PROMPT.into());
-        let input = Input::from_str(config, &prompt, None);
-        let client = input.create_client()?;
-        let summary = client.chat_completions(input).await?.text;
+        let mut summary = String::new();
+        for transcript in windows {
+            let text = if summary.is_empty() {
+                format!("<conversation>\n{transcript}\n</conversation>\n\n{prompt}")
+            } else {
+                format!("<summary>\n{summary}\n</summary>\n\n<conversation>\n{transcript}\n</conversation>\n\nThe summary covers the earlier part of the conversation. {prompt}")
+            };
+            let input = Input::from_str(config, &text, Some(role.clone()));
+            let client = input.create_client()?;
+            summary = client.chat_completions(input).await?.text;
+        }
         let summary_prompt = config
             .read()
             .summary_prompt
             .clone()
             .unwrap_or_else(|| SUMMARY_PROMPT.into());
         if let Some(session) = config.write().session.as_mut() {
-            session.compress(format!("{}{}", summary_prompt, summary));
+            session.compress(format!("{}{}", summary_prompt, summary), end);
         }
         config.write().discontinuous_last_message();
         Ok(())
@@ -1252,10 +2176,52 @@ impl Config {
         });
     }
 
-    pub async fn autoname_session(config: &GlobalConfig) -> Result<()> {
-        let text = match config
-            .read()
-            .session
+    /// With `followups` on, asks `followup_model` for questions that follow the last answer.
+    pub async fn suggest_followups(config: &GlobalConfig) -> Result<Vec<String>> {
+        let (question, answer, model_id) = {
+            let mut config = config.write();
+            config.last_followups.clear();
+            match &config.last_message {
+                Some(v) if config.followups && !v.output.is_empty() => (
+                    v.input.text(),
+                    v.output.clone(),
+                    config.followup_model.clone(),
+                ),
+                _ => return Ok(vec![]),
+            }
+        };
+        let model = match model_id {
+            Some(model_id) => Model::retrieve_model(&config.read(), &model_id, ModelType::Chat)?,
+            None => config.read().current_model().clone(),
+        };
+        let prompt = FOLLOWUPS_PROMPT
+            .replace("__QUESTION__", &question)
+            .replace("__ANSWER__", &answer);
+        let mut input = Input::from_str(config, &prompt, Some(Role::default()));
+        input.set_model(model);
+        let reply = input.create_client()?.chat_completions(input).await?;
+        let followups: Vec<String> = reply
+            .text
+            .lines()
+            .map(|line| {
+                line.trim()
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .trim_start_matches(['.', ')', '-', '*'])
+                    .trim()
+                    .trim_matches('"')
+                    .to_string()
+            })
+            .filter(|v| !v.is_empty())
+            .take(MAX_FOLLOWUPS)
+            .collect();
+        config.write().last_followups = followups.clone();
+        Ok(followups)
+    }
+
+    pub async fn autoname_session(config: &GlobalConfig) -> Result<()> {
+        let text = match config
+            .read()
+            .session
             .as_ref()
             .and_then(|v| v.chat_history_for_autonaming())
         {
@@ -1311,8 +2277,79 @@ impl Config {
             Some(v) => v.as_ref().clone(),
             None => bail!("No RAG"),
         };
+        let document_paths = rag.document_paths().to_vec();
+        let new_document_paths = Self::edit_document_paths(config, &rag, &document_paths).await?;
+        rag.refresh_document_paths(&new_document_paths, false, config, abort_signal)
+            .await?;
+        config.write().rag = Some(Arc::new(rag));
+        Ok(())
+    }
+
+    pub async fn rebuild_rag(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<()> {
+        let mut rag = match config.read().rag.clone() {
+            Some(v) => v.as_ref().clone(),
+            None => bail!("No RAG"),
+        };
+        let document_paths = rag.document_paths().to_vec();
+        rag.refresh_document_paths(&document_paths, true, config, abort_signal)
+            .await?;
+        config.write().rag = Some(Arc::new(rag));
+        Ok(())
+    }
+
+    pub async fn edit_agent_docs(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<()> {
+        let mut rag = config.read().agent_rag()?;
+        let document_paths = rag.document_paths().to_vec();
+        let new_document_paths = Self::edit_document_paths(config, &rag, &document_paths).await?;
+        if let Some(agent) = config.read().agent.as_ref() {
+            agent.guard_document_paths(&new_document_paths)?;
+        }
+        rag.refresh_document_paths(&new_document_paths, false, config, abort_signal)
+            .await?;
+        config.write().set_agent_rag(rag);
+        Ok(())
+    }
+
+    pub async fn rebuild_agent_rag(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<()> {
+        let mut rag = config.read().agent_rag()?;
+        let mut document_paths = rag.document_paths().to_vec();
+        if let Some(agent) = config.read().agent.as_ref() {
+            for path in agent.bundled_documents()? {
+                if !document_paths.contains(&path) {
+                    document_paths.push(path);
+                }
+            }
+            agent.guard_document_paths(&document_paths)?;
+        }
+        rag.refresh_document_paths(&document_paths, true, config, abort_signal)
+            .await?;
+        config.write().set_agent_rag(rag);
+        Ok(())
+    }
+
+    fn agent_rag(&self) -> Result<Rag> {
+        match self.agent.as_ref() {
+            Some(agent) => match agent.rag() {
+                Some(v) => Ok(v.as_ref().clone()),
+                None => bail!("The agent has no RAG"),
+            },
+            None => bail!("No agent"),
+        }
+    }
 
-        let document_paths = rag.document_paths();
+    fn set_agent_rag(&mut self, rag: Rag) {
+        let rag = Arc::new(rag);
+        if let Some(agent) = self.agent.as_mut() {
+            agent.set_rag(Some(rag.clone()));
+        }
+        self.rag = Some(rag);
+    }
+
+    async fn edit_document_paths(
+        config: &GlobalConfig,
+        rag: &Rag,
+        document_paths: &[String],
+    ) -> Result<Vec<String>> {
         let temp_file = temp_file(&format!("-rag-{}", rag.name()), ".txt");
         tokio::fs::write(&temp_file, &document_paths.join("\n"))
             .await
@@ -1347,22 +2384,7 @@ impl Config {
         if new_document_paths.is_empty() || new_document_paths == document_paths {
             bail!("No changes")
         }
-        rag.refresh_document_paths(&new_document_paths, false, config, abort_signal)
-            .await?;
-        config.write().rag = Some(Arc::new(rag));
-        Ok(())
-    }
-
-    pub async fn rebuild_rag(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<()> {
-        let mut rag = match config.read().rag.clone() {
-            Some(v) => v.as_ref().clone(),
-            None => bail!("No RAG"),
-        };
-        let document_paths = rag.document_paths().to_vec();
-        rag.refresh_document_paths(&document_paths, true, config, abort_signal)
-            .await?;
-        config.write().rag = Some(Arc::new(rag));
-        Ok(())
+        Ok(new_document_paths)
     }
 
     pub fn rag_sources(config: &GlobalConfig) -> Result<String> {
@@ -1395,10 +2417,17 @@ impl Config {
         abort_signal: AbortSignal,
     ) -> Result<String> {
         let (reranker_model, top_k) = rag.get_config();
-        let (embeddings, ids) = rag
+        let mode = config.read().security.injection_guard.clone();
+        let (ids, documents): (Vec<_>, Vec<_>) = rag
             .search(text, top_k, reranker_model.as_deref(), abort_signal)
-            .await?;
-        let text = config.read().rag_template(&embeddings, text);
+            .await?
+            .into_iter()
+            .filter(|(id, document)| {
+                let source = format!("the RAG document {}", rag.document_source(*id));
+                guard_injection(&mode, &source, document).is_none()
+            })
+            .unzip();
+        let text = config.read().rag_template(&documents.join("\n\n"), text);
         rag.set_last_sources(&ids);
         Ok(text)
     }
@@ -1454,9 +2483,19 @@ impl Config {
         } else {
             config.write().init_agent_shared_variables()?;
         }
+        config.read().run_agent_hook(AgentHook::Start);
         Ok(())
     }
 
+    pub fn run_agent_hook(&self, hook: AgentHook) {
+        if self.cli_info_flag {
+            return;
+        }
+        if let Some(agent) = &self.agent {
+            agent.run_hook(hook);
+        }
+    }
+
     pub fn agent_info(&self) -> Result<String> {
         if let Some(agent) = &self.agent {
             agent.export()
@@ -1465,11 +2504,48 @@ impl Config {
         }
     }
 
-    pub fn agent_banner(&self) -> Result<String> {
+    /// The agent banner, or the conversation starters of the active role.
+    pub fn starter_banner(&self) -> Result<String> {
+        if let Some(agent) = &self.agent {
+            return Ok(agent.banner());
+        }
+        let Some(role) = self.starter_role() else {
+            bail!("No agent or role")
+        };
+        let starters = role.conversation_starters();
+        if starters.is_empty() {
+            bail!("The role '{}' has no conversation starters", role.name());
+        }
+        let starters = starters
+            .iter()
+            .map(|v| format!("- {v}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(format!(
+            "# {}\n\n## Conversation Starters\n{starters}",
+            role.name()
+        ))
+    }
+
+    pub fn conversation_starters(&self) -> Vec<String> {
         if let Some(agent) = &self.agent {
-            Ok(agent.banner())
+            agent.conversation_staters().to_vec()
+        } else if let Some(role) = self.starter_role() {
+            role.conversation_starters().to_vec()
         } else {
-            bail!("No agent")
+            vec![]
+        }
+    }
+
+    /// The active role, reloaded from its file when it only lives in the session.
+    fn starter_role(&self) -> Option<Role> {
+        let name = match &self.session {
+            Some(session) => session.role_name()?,
+            None => self.role.as_ref()?.name(),
+        };
+        match &self.role {
+            Some(role) if role.name() == name => Some(role.clone()),
+            _ => Self::load_role(name).ok(),
         }
     }
 
@@ -1497,6 +2573,7 @@ impl Config {
 
     pub fn exit_agent(&mut self) -> Result<()> {
         self.exit_session()?;
+        self.run_agent_hook(AgentHook::Exit);
         if self.agent.take().is_some() {
             self.rag.take();
             self.discontinuous_last_message();
@@ -1505,6 +2582,12 @@ impl Config {
         Ok(())
     }
 
+    /// Runs the `on_exit` hook of the agent still in use when aichat ends.
+    pub fn finish_agent(&mut self) {
+        self.run_agent_hook(AgentHook::Exit);
+        self.agent = None;
+    }
+
     pub fn exit_agent_session(&mut self) -> Result<()> {
         self.exit_session()?;
         if let Some(agent) = self.agent.as_mut() {
@@ -1525,6 +2608,11 @@ impl Config {
             WorkingMode::Repl => self.repl_prelude.as_ref().or(self.prelude.as_ref()),
             WorkingMode::Serve => return Ok(()),
         };
+        if self.project_sessions {
+            if let Some(name) = project_name() {
+                return self.use_session(Some(&name));
+            }
+        }
         let prelude = match prelude {
             Some(v) => {
                 if v.is_empty() {
@@ -1556,6 +2644,19 @@ impl Config {
         Ok(())
     }
 
+    /// Starts a session unless the prelude or the arguments already did, the project session
+    /// when `project_sessions` is on and a temp one otherwise.
+    pub fn ensure_repl_session(&mut self) -> Result<()> {
+        if self.session.is_some() {
+            return Ok(());
+        }
+        let name = match self.project_sessions {
+            true => project_name(),
+            false => None,
+        };
+        self.use_session(name.as_deref())
+    }
+
     pub fn select_functions(&self, role: &Role) -> Option<Vec<FunctionDeclaration>> {
         let mut functions = vec![];
         if self.function_calling {
@@ -1568,7 +2669,13 @@ impl Config {
                     .map(|v| v.name.to_string())
                     .collect();
                 if use_tools == "all" {
-                    tool_names.extend(declaration_names);
+                    tool_names.extend(
+                        self.functions
+                            .declarations()
+                            .iter()
+                            .filter(|v| !v.is_explicit_only())
+                            .map(|v| v.name.to_string()),
+                    );
                 } else {
                     for item in use_tools.split(',') {
                         let item = item.trim();
@@ -1615,6 +2722,9 @@ impl Config {
                         .into_iter()
                         .filter(|v| !tool_names.contains(&v.name)),
                 );
+                if agent.memory().is_some() {
+                    agent_functions.extend(memory_declarations());
+                }
                 functions = agent_functions;
             }
         };
@@ -1653,9 +2763,16 @@ impl Config {
         if args.len() == 1 {
             values = match cmd {
                 ".role" => map_completion_values(Self::list_roles(true)),
-                ".model" => list_models(self, ModelType::Chat)
+                ".model" => self
+                    .prioritized_models()
                     .into_iter()
-                    .map(|v| (v.id(), Some(v.description())))
+                    .map(|(v, tag)| {
+                        let description = match tag {
+                            Some(tag) => format!("{} ({tag})", v.description()),
+                            None => v.description(),
+                        };
+                        (v.id(), Some(description))
+                    })
                     .collect(),
                 ".session" => {
                     if args[0].starts_with("_/") {
@@ -1672,10 +2789,7 @@ impl Config {
                 }
                 ".rag" => map_completion_values(Self::list_rags()),
                 ".agent" => map_completion_values(list_agents()),
-                ".starter" => match &self.agent {
-                    Some(agent) => map_completion_values(agent.conversation_staters().to_vec()),
-                    None => vec![],
-                },
+                ".starter" => map_completion_values(self.conversation_starters()),
                 ".variable" => match &self.agent {
                     Some(agent) => agent
                         .defined_variables()
@@ -1685,21 +2799,7 @@ impl Config {
                     None => vec![],
                 },
                 ".set" => {
-                    let mut values = vec![
-                        "max_output_tokens",
-                        "temperature",
-                        "top_p",
-                        "dry_run",
-                        "stream",
-                        "save",
-                        "function_calling",
-                        "use_tools",
-                        "save_session",
-                        "compress_threshold",
-                        "rag_reranker_model",
-                        "rag_top_k",
-                        "highlight",
-                    ];
+                    let mut values: Vec<&str> = SET_KEYS.iter().map(|(key, _)| *key).collect();
                     values.sort_unstable();
                     values
                         .into_iter()
@@ -1707,6 +2807,9 @@ impl Config {
                         .collect()
                 }
                 ".delete" => map_completion_values(vec!["role", "session", "rag", "agent-data"]),
+                ".context" => map_completion_values(vec!["create", "list", "delete"]),
+                ".copy" => map_completion_values(vec!["code"]),
+                ".buffer" => map_completion_values(vec!["dump", "restore"]),
                 _ => vec![],
             };
             filter = args[0]
@@ -1718,9 +2821,38 @@ impl Config {
                 },
                 "dry_run" => complete_bool(self.dry_run),
                 "stream" => complete_bool(self.stream),
+                "show_usage" => complete_bool(self.show_usage),
+                "preview" => complete_bool(self.preview),
+                "followups" => complete_bool(self.followups),
                 "save" => complete_bool(self.save),
+                "history_rag" => complete_bool(self.history_rag),
+                "history_hints" => complete_bool(self.history_hints),
+                "compress_strategy" => COMPRESS_STRATEGIES.iter().map(|v| v.to_string()).collect(),
+                "pager" => PAGER_MODES.iter().map(|v| v.to_string()).collect(),
+                "clipboard" => ["auto", "osc52", "command:"]
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect(),
+                "on_complete" => ["webhook:", "command:", "file:"]
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect(),
+                "locale" => ["auto"]
+                    .iter()
+                    .chain(LOCALES.iter())
+                    .map(|v| v.to_string())
+                    .collect(),
                 "function_calling" => complete_bool(self.function_calling),
-                "use_tools" => {
+                "function_calling_mode" => FUNCTION_CALLING_MODES
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect(),
+                "security.injection_guard" => INJECTION_GUARD_MODES
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect(),
+                "redact" => REDACT_MODES.iter().map(|v| v.to_string()).collect(),
+                "use_tools" | "tool_judge_tools" | "tool_result_summarize" => {
                     let mut prefix = String::new();
                     let mut ignores = HashSet::new();
                     if let Some((v, _)) = args[1].rsplit_once(',') {
@@ -1751,11 +2883,21 @@ impl Config {
                     .iter()
                     .map(|v| v.id())
                     .collect(),
+                "tool_judge_model" | "followup_model" => list_models(self, ModelType::Chat)
+                    .iter()
+                    .map(|v| v.id())
+                    .collect(),
+                "enforce_query_language" => complete_bool(self.enforce_query_language),
+                "project_sessions" => complete_bool(self.project_sessions),
                 "highlight" => complete_bool(self.highlight),
+                "code_style" => CODE_STYLES.iter().map(|v| v.to_string()).collect(),
                 _ => vec![],
             };
             values = candidates.into_iter().map(|v| (v, None)).collect();
             filter = args[1];
+        } else if cmd == ".view" && args.len() == 2 && args[0] == "session" {
+            values = map_completion_values(self.list_sessions());
+            filter = args[1];
         } else if cmd == ".agent" && args.len() >= 2 {
             let dir = Self::agent_data_dir(args[0]).join(SESSIONS_DIR_NAME);
             values = list_file_names(dir, ".yaml")
@@ -1763,13 +2905,26 @@ impl Config {
                 .map(|v| (v, None))
                 .collect();
         } else if cmd == ".starter" && args.len() >= 2 {
-            if let Some(agent) = &self.agent {
-                values = agent
-                    .conversation_staters()
-                    .iter()
-                    .filter_map(|v| v.strip_prefix(line).map(|x| (x.to_string(), None)))
-                    .collect()
+            values = self
+                .conversation_starters()
+                .iter()
+                .filter_map(|v| v.strip_prefix(line).map(|x| (x.to_string(), None)))
+                .collect()
+        } else if cmd == ".variable" && args.len() == 2 {
+            if let Some(agent_variable) = self
+                .agent
+                .as_ref()
+                .and_then(|agent| agent.defined_variables().iter().find(|v| v.name == args[0]))
+            {
+                values = match agent_variable.kind {
+                    Some(AgentVariableType::Enum) => {
+                        map_completion_values(agent_variable.values.clone())
+                    }
+                    Some(AgentVariableType::Bool) => map_completion_values(vec!["true", "false"]),
+                    _ => vec![],
+                };
             }
+            filter = args[1];
         };
         values
             .into_iter()
@@ -1778,7 +2933,12 @@ impl Config {
     }
 
     pub fn render_options(&self) -> Result<RenderOptions> {
-        let theme = if self.highlight {
+        let theme = if let (true, Some(theme)) = (self.highlight, &self.theme) {
+            let theme_path = Self::resolve_path(theme);
+            let theme = ThemeSet::get_theme(&theme_path)
+                .with_context(|| format!("Invalid theme at '{}'", theme_path.display()))?;
+            Some(theme)
+        } else if self.highlight {
             let theme_mode = if self.light_theme { "light" } else { "dark" };
             let theme_filename = format!("{theme_mode}.tmTheme");
             let theme_path = Self::local_path(&theme_filename);
@@ -1806,7 +2966,30 @@ impl Config {
             env::var("COLORTERM").as_ref().map(|v| v.as_str()),
             Ok("truecolor")
         );
-        Ok(RenderOptions::new(theme, wrap, self.wrap_code, truecolor))
+        Ok(
+            RenderOptions::new(theme, wrap, self.wrap_code, truecolor, *IS_STDOUT_TERMINAL)
+                .with_code_style(&self.code_style)
+                .with_syntaxes_dir(self.syntaxes_dir.as_deref().map(Self::resolve_path)),
+        )
+    }
+
+    /// Resolves a path from the config, relative paths are relative to the config dir.
+    fn resolve_path(path: &str) -> PathBuf {
+        match (path.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(path), Some(home)) => home.join(path),
+            _ => Self::config_dir().join(path),
+        }
+    }
+
+    /// Colors for diffs, plain when highlighting is off or not printing to a terminal.
+    pub fn diff_theme(&self) -> DiffTheme {
+        if !self.highlight || !*IS_STDOUT_TERMINAL {
+            DiffTheme::Plain
+        } else if self.light_theme {
+            DiffTheme::Light
+        } else {
+            DiffTheme::Dark
+        }
     }
 
     pub fn render_prompt_left(&self) -> String {
@@ -1821,6 +3004,48 @@ impl Config {
         render_prompt(right_prompt, &variables)
     }
 
+    /// Prints a completed answer, through the pager when `pager` asks for it.
+    pub fn print_answer(config: &GlobalConfig, text: &str) -> Result<()> {
+        if !*IS_STDOUT_TERMINAL {
+            println!("{text}");
+            return Ok(());
+        }
+        // The pager runs as long as the user reads, the config lock is released before it
+        let (output, paged) = {
+            let config = config.read();
+            let output = MarkdownRender::init(config.render_options()?)?.render(text);
+            let paged = need_pager(&config.pager, &output);
+            (output, paged)
+        };
+        if paged {
+            run_pager(&output)
+        } else {
+            println!("{output}");
+            Ok(())
+        }
+    }
+
+    /// Opens an answer that was already streamed in the pager when `pager` asks for it.
+    pub fn page_streamed_answer(config: &GlobalConfig, text: &str) -> Result<()> {
+        let output = {
+            let config = config.read();
+            if !*IS_STDOUT_TERMINAL || config.pager == "never" {
+                return Ok(());
+            }
+            let output = MarkdownRender::init(config.render_options()?)?.render(text);
+            if !need_pager(&config.pager, &output) {
+                return Ok(());
+            }
+            output
+        };
+        run_pager(&output)
+    }
+
+    pub fn page_markdown(config: &GlobalConfig, text: &str) -> Result<()> {
+        let output = MarkdownRender::init(config.read().render_options()?)?.render(text);
+        run_pager(&output)
+    }
+
     pub fn print_markdown(&self, text: &str) -> Result<()> {
         if *IS_STDOUT_TERMINAL {
             let render_options = self.render_options()?;
@@ -1889,6 +3114,9 @@ impl Config {
         if let Some(agent) = &self.agent {
             output.insert("agent", agent.name().to_string());
         }
+        if let Some(project) = project_name() {
+            output.insert("project", project);
+        }
 
         if self.highlight {
             output.insert("color.reset", "\u{1b}[0m".to_string());
@@ -1915,7 +3143,59 @@ impl Config {
         output
     }
 
+    /// With `preview` on, shows what is about to be sent and asks whether to send it.
+    pub fn confirm_preview(config: &GlobalConfig, input: &Input) -> Result<bool> {
+        if config.read().redact == "warn" && !Self::confirm_secrets(input)? {
+            return Ok(false);
+        }
+        if !config.read().preview || !*IS_STDOUT_TERMINAL {
+            return Ok(true);
+        }
+        println!("{}", dimmed_text(&input.preview()?));
+        let ans = Confirm::new(&tr("Send it?")).with_default(true).prompt()?;
+        if !ans {
+            println!("{}", tr("Not sent."));
+        }
+        Ok(ans)
+    }
+
+    /// Asks before sending secrets found in the input, with `redact: warn`.
+    fn confirm_secrets(input: &Input) -> Result<bool> {
+        let text = input
+            .build_messages()?
+            .iter()
+            .map(|v| v.content.to_text())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let secrets = unallowed_secrets(&text);
+        if secrets.is_empty() {
+            return Ok(true);
+        }
+        let list = list_secrets(&secrets);
+        if !*IS_STDOUT_TERMINAL {
+            bail!("The input contains secrets:\n{list}\nSet `redact` to 'mask' to send placeholders instead, or 'off' to send them as they are");
+        }
+        eprintln!(
+            "{}",
+            warning_text(&format!("The input contains secrets:\n{list}"))
+        );
+        let ans = Confirm::new(&tr("Send it anyway?"))
+            .with_default(false)
+            .prompt()?;
+        if ans {
+            allow_secrets(&secrets);
+        } else {
+            println!("{}", tr("Not sent."));
+        }
+        Ok(ans)
+    }
+
     pub fn before_chat_completion(&mut self, input: &Input) -> Result<()> {
+        if let Some(last_message) = self.last_message.take() {
+            if !last_message.output.is_empty() {
+                self.previous_output = Some(last_message.output);
+            }
+        }
         self.last_message = Some(LastMessage::new(input.clone(), String::new()));
         Ok(())
     }
@@ -1936,6 +3216,84 @@ impl Config {
         Ok(())
     }
 
+    /// Records a round with the model, folding the rounds that answer tool calls into one exchange.
+    pub fn record_exchange(&mut self, mut exchange: LastExchange, continued: bool) {
+        if continued {
+            if let Some(last) = self.last_exchange.take() {
+                let add = |a: Option<u64>, b: Option<u64>| Some(a? + b?);
+                exchange.rounds += last.rounds;
+                exchange.input_tokens = add(exchange.input_tokens, last.input_tokens);
+                exchange.output_tokens = add(exchange.output_tokens, last.output_tokens);
+                exchange.estimated |= last.estimated;
+                exchange.latency += last.latency;
+                let mut tools = last.tools;
+                tools.append(&mut exchange.tools);
+                exchange.tools = tools;
+                exchange.history_sources = last.history_sources;
+                if exchange.rag_sources.is_none() {
+                    exchange.rag_sources = last.rag_sources;
+                }
+            }
+        }
+        self.last_exchange = Some(exchange);
+    }
+
+    pub fn last_exchange_info(&self) -> Result<String> {
+        let Some(exchange) = &self.last_exchange else {
+            bail!("No exchange yet")
+        };
+        let format_tokens = |tokens: Option<u64>| match (tokens, exchange.estimated) {
+            (None, _) => "-".into(),
+            (Some(tokens), true) => format!("~{tokens}"),
+            (Some(tokens), false) => tokens.to_string(),
+        };
+        let mut items = vec![
+            ("model", exchange.model_id.clone()),
+            ("finish_reason", exchange.finish_reason.clone()),
+            ("input_tokens", format_tokens(exchange.input_tokens)),
+            ("output_tokens", format_tokens(exchange.output_tokens)),
+            ("latency", format!("{:.2}s", exchange.latency.as_secs_f64())),
+            ("rounds", exchange.rounds.to_string()),
+            (
+                "tools",
+                match exchange.tools.is_empty() {
+                    true => "-".into(),
+                    false => exchange.tools.join(", "),
+                },
+            ),
+            (
+                "rag_sources",
+                exchange
+                    .rag_sources
+                    .as_deref()
+                    .map(|v| v.replace('\n', ", "))
+                    .unwrap_or_else(|| "-".into()),
+            ),
+            (
+                "history_sources",
+                match exchange.history_sources.is_empty() {
+                    true => "-".into(),
+                    false => exchange.history_sources.join(", "),
+                },
+            ),
+        ];
+        if let Some(request_ids) = &exchange.request_ids {
+            items.push(("request_id", request_ids.id.clone()));
+            if let Some(provider_id) = &request_ids.provider_id {
+                items.push(("provider_request_id", provider_id.clone()));
+            }
+        }
+        if let Some(error) = &exchange.error {
+            items.push(("error", error.clone()));
+        }
+        let output = items
+            .iter()
+            .map(|(name, value)| format!("{name:<24}{value}\n"))
+            .collect::<Vec<String>>()
+            .join("");
+        Ok(output)
+    }
+
     fn discontinuous_last_message(&mut self) {
         if let Some(last_message) = self.last_message.as_mut() {
             last_message.continuous = false;
@@ -2045,6 +3403,7 @@ impl Config {
             agent.set_session_variables(session_variables);
             if !self.cli_info_flag {
                 agent.update_session_dynamic_instructions(None)?;
+                agent.run_hook(AgentHook::SessionNew);
             }
             session.sync_agent(agent);
         } else {
@@ -2067,9 +3426,18 @@ impl Config {
             .with_context(|| format!("Failed to create/append {}", path.display()))
     }
 
-    fn load_from_file(config_path: &Path) -> Result<Self> {
+    /// Loads the config file, migrating it in memory only when the config dir is read-only.
+    fn load_from_file(config_path: &Path, writable: bool) -> Result<Self> {
         let err = || format!("Failed to load config at '{}'", config_path.display());
         let content = read_to_string(config_path).with_context(err)?;
+        let content = migrate_file(
+            "config",
+            config_path,
+            content,
+            CONFIG_VERSION,
+            &CONFIG_MIGRATIONS,
+            writable,
+        )?;
         let config: Self = serde_yaml::from_str(&content)
             .map_err(|err| {
                 let err_msg = err.to_string();
@@ -2096,24 +3464,85 @@ impl Config {
             Some((v, _)) => v,
             _ => model_id,
         };
-        let is_openai_compatible = OPENAI_COMPATIBLE_PLATFORMS
-            .into_iter()
-            .any(|(name, _)| platform == name);
-        let client = if is_openai_compatible {
-            json!({ "type": "openai-compatible", "name": platform })
-        } else {
-            json!({ "type": platform })
-        };
         let config = json!({
             "model": model_id.to_string(),
             "save": false,
-            "clients": vec![client],
+            "clients": vec![dynamic_client_config(platform)],
         });
         let config =
             serde_json::from_value(config).with_context(|| "Failed to load config from env")?;
         Ok(config)
     }
 
+    /// Writes a config file for the platform without prompting, API keys are read from env vars.
+    pub fn init_config_file(
+        platform: Option<&str>,
+        model: Option<&str>,
+        overwrite: bool,
+    ) -> Result<()> {
+        let platform = match (platform, model.and_then(|v| v.split_once(':'))) {
+            (Some(v), _) => v,
+            (None, Some((v, _))) => v,
+            (None, None) => bail!("Please specify the platform with --platform"),
+        };
+        if !list_client_types().contains(&platform) {
+            bail!("Unknown platform '{platform}'");
+        }
+        let model_id = match model {
+            Some(v) if v.contains(':') => v.to_string(),
+            Some(v) => format!("{platform}:{v}"),
+            None => platform.to_string(),
+        };
+        let config_path = Self::config_file();
+        if config_path.exists() && !overwrite {
+            let ans = *IS_STDOUT_TERMINAL
+                && Confirm::new(&format!(
+                    "Config file '{}' already exists, overwrite it?",
+                    config_path.display()
+                ))
+                .with_default(false)
+                .prompt()?;
+            if !ans {
+                bail!(
+                    "Config file '{}' already exists, use --yes to overwrite it",
+                    config_path.display()
+                );
+            }
+        }
+        let config = json!({
+            "version": CONFIG_VERSION,
+            "model": model_id,
+            "clients": vec![dynamic_client_config(platform)],
+        });
+        write_config_file(&config_path, &config)
+    }
+
+    /// Checks the settings taking one of a few modes, which `.set` checks as well.
+    fn validate_modes(&self) -> Result<()> {
+        for (name, value, modes) in [
+            ("pager", &self.pager, &PAGER_MODES[..]),
+            ("redact", &self.redact, &REDACT_MODES[..]),
+            (
+                "compress_strategy",
+                &self.compress_strategy,
+                &COMPRESS_STRATEGIES[..],
+            ),
+            (
+                "security.injection_guard",
+                &self.security.injection_guard,
+                &INJECTION_GUARD_MODES[..],
+            ),
+        ] {
+            if !modes.contains(&value.as_str()) {
+                bail!(
+                    "Invalid {name} '{value}', expected one of: {}",
+                    modes.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
     fn load_envs(&mut self) {
         if let Ok(v) = env::var(get_env_name("model")) {
             self.model_id = v;
@@ -2131,6 +3560,21 @@ impl Config {
         if let Some(Some(v)) = read_env_bool(&get_env_name("stream")) {
             self.stream = v;
         }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("stream_render_interval")) {
+            self.stream_render_interval = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("show_usage")) {
+            self.show_usage = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("preview")) {
+            self.preview = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("followups")) {
+            self.followups = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("followup_model")) {
+            self.followup_model = v;
+        }
         if let Some(Some(v)) = read_env_bool(&get_env_name("save")) {
             self.save = v;
         }
@@ -2139,6 +3583,14 @@ impl Config {
                 self.keybindings = v;
             }
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("history_hints")) {
+            self.history_hints = v;
+        }
+        if let Ok(v) = env::var(get_env_name("keybindings_custom")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.keybindings_custom = v;
+            }
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("editor")) {
             self.editor = v;
         }
@@ -2148,10 +3600,19 @@ impl Config {
         if let Some(Some(v)) = read_env_bool(&get_env_name("wrap_code")) {
             self.wrap_code = v;
         }
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("code_style")) {
+            self.code_style = v;
+        }
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("pager")) {
+            self.pager = v;
+        }
 
         if let Some(Some(v)) = read_env_bool(&get_env_name("function_calling")) {
             self.function_calling = v;
         }
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("function_calling_mode")) {
+            self.function_calling_mode = v;
+        }
         if let Ok(v) = env::var(get_env_name("mapping_tools")) {
             if let Ok(v) = serde_json::from_str(&v) {
                 self.mapping_tools = v;
@@ -2160,6 +3621,48 @@ impl Config {
         if let Some(v) = read_env_value::<String>(&get_env_name("use_tools")) {
             self.use_tools = v;
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("tool_judge_model")) {
+            self.tool_judge_model = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("tool_judge_tools")) {
+            self.tool_judge_tools = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("large_file_threshold")) {
+            self.large_file_threshold = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("tool_timeout_secs")) {
+            self.tool_timeout_secs = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("tool_max_output_bytes")) {
+            self.tool_max_output_bytes = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("tool_allowed_paths")) {
+            self.tool_allowed_paths = split_paths(v.as_deref());
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("tool_result_max_tokens")) {
+            self.tool_result_max_tokens = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("tool_result_summarize")) {
+            self.tool_result_summarize = v;
+        }
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("security_injection_guard")) {
+            self.security.injection_guard = v;
+        }
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("redact")) {
+            self.redact = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("auto_max_tool_calls")) {
+            self.auto_max_tool_calls = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("auto_max_tokens")) {
+            self.auto_max_tokens = v;
+        }
+        if let Some(Some(v)) = read_env_value::<u64>(&get_env_name("auto_max_seconds")) {
+            self.auto_max_seconds = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("auto_checkpoint")) {
+            self.auto_checkpoint = v;
+        }
 
         if let Some(v) = read_env_value::<String>(&get_env_name("prelude")) {
             self.prelude = v;
@@ -2177,6 +3680,12 @@ impl Config {
         if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("compress_threshold")) {
             self.compress_threshold = v;
         }
+        if let Some(Some(v)) = read_env_value::<String>(&get_env_name("compress_strategy")) {
+            self.compress_strategy = v;
+        }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("compress_keep_recent")) {
+            self.compress_keep_recent = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("summarize_prompt")) {
             self.summarize_prompt = v;
         }
@@ -2193,6 +3702,9 @@ impl Config {
         if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_top_k")) {
             self.rag_top_k = v;
         }
+        if let Some(Some(v)) = read_env_value::<bool>(&get_env_name("history_rag")) {
+            self.history_rag = v;
+        }
         if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_size")) {
             self.rag_chunk_size = v;
         }
@@ -2202,12 +3714,23 @@ impl Config {
         if let Some(v) = read_env_value::<String>(&get_env_name("rag_template")) {
             self.rag_template = v;
         }
+        if let Some(Some(v)) = read_env_value::<bool>(&get_env_name("enforce_query_language")) {
+            self.enforce_query_language = v;
+        }
+        if let Some(Some(v)) = read_env_value::<bool>(&get_env_name("project_sessions")) {
+            self.project_sessions = v;
+        }
 
         if let Ok(v) = env::var(get_env_name("document_loaders")) {
             if let Ok(v) = serde_json::from_str(&v) {
                 self.document_loaders = v;
             }
         }
+        if let Ok(v) = env::var(get_env_name("code_interpreters")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.code_interpreters = v;
+            }
+        }
 
         if let Some(Some(v)) = read_env_bool(&get_env_name("highlight")) {
             self.highlight = v;
@@ -2224,6 +3747,12 @@ impl Config {
                 }
             }
         }
+        if let Some(v) = read_env_value::<String>(&get_env_name("theme")) {
+            self.theme = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("syntaxes_dir")) {
+            self.syntaxes_dir = v;
+        }
         if let Some(v) = read_env_value::<String>(&get_env_name("left_prompt")) {
             self.left_prompt = v;
         }
@@ -2240,10 +3769,57 @@ impl Config {
         if let Some(Some(v)) = read_env_bool(&get_env_name("save_shell_history")) {
             self.save_shell_history = v;
         }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("save_repl_history")) {
+            self.save_repl_history = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("timestamp_format")) {
+            self.timestamp_format = v;
+        }
+        if let Some(Some(v)) = read_env_bool(&get_env_name("timestamp_utc")) {
+            self.timestamp_utc = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("log_requests")) {
+            self.log_requests = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("otel_endpoint")) {
+            self.otel_endpoint = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("locale")) {
+            self.locale = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("clipboard")) {
+            self.clipboard = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("on_complete")) {
+            self.on_complete = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("notify_webhook")) {
+            self.notify_webhook = v;
+        }
+        if let Ok(v) = env::var(get_env_name("schedules")) {
+            if let Ok(v) = serde_json::from_str(&v) {
+                self.schedules = v;
+            }
+        }
+    }
+
+    /// Keeps chatting working when the config dir is read-only, e.g. in a container,
+    /// by keeping sessions, REPL history and messages in memory.
+    fn use_read_only_storage(&mut self) {
+        self.read_only = true;
+        self.save = false;
+        self.save_repl_history = false;
+        eprintln!(
+            "{}",
+            warning_text(&format!(
+                "The config dir '{}' is read-only, sessions, history and messages won't be saved",
+                Self::config_dir().display()
+            ))
+        );
     }
 
     fn load_functions(&mut self) -> Result<()> {
-        self.functions = Functions::init(&Self::functions_file())?;
+        self.functions = Functions::init(&Self::functions_file())?.with_builtins();
         Ok(())
     }
 
@@ -2270,6 +3846,15 @@ impl Config {
             });
     }
 
+    fn setup_code_interpreters(&mut self) {
+        [("python", "python3"), ("javascript", "node")]
+            .into_iter()
+            .for_each(|(k, v)| {
+                let (k, v) = (k.to_string(), v.to_string());
+                self.code_interpreters.entry(k).or_insert(v);
+            });
+    }
+
     fn setup_user_agent(&mut self) {
         if let Some("auto") = self.user_agent.as_deref() {
             self.user_agent = Some(format!(
@@ -2281,6 +3866,73 @@ impl Config {
     }
 }
 
+/// Name of the git repo around the current directory, usable as a session name.
+pub fn project_name() -> Option<String> {
+    PROJECT_NAME
+        .get_or_init(|| {
+            let cwd = env::current_dir().ok()?;
+            let root = cwd.ancestors().find(|v| v.join(".git").exists())?;
+            let name = root.file_name()?.to_string_lossy();
+            Some(
+                name.chars()
+                    .map(
+                        |c| match c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                            true => c,
+                            false => '-',
+                        },
+                    )
+                    .collect(),
+            )
+        })
+        .clone()
+}
+
+/// Picks a name from `(name, hint)` items listed as aligned columns, `None` if skipped.
+fn select_name(
+    message: &str,
+    items: Vec<(String, String)>,
+    current: Option<&str>,
+    help: &str,
+) -> Result<Option<String>> {
+    let width = items
+        .iter()
+        .map(|(name, _)| display_width(name))
+        .max()
+        .unwrap_or_default();
+    let options: Vec<String> = items
+        .iter()
+        .map(|(name, hint)| {
+            let padding = " ".repeat(width - display_width(name));
+            format!("{name}{padding}  {hint}").trim_end().to_string()
+        })
+        .collect();
+    let cursor = current
+        .and_then(|current| items.iter().position(|(name, _)| name == current))
+        .unwrap_or_default();
+    let Some(selected) = Select::new(message, options.clone())
+        .with_starting_cursor(cursor)
+        .with_help_message(help)
+        .prompt_skippable()?
+    else {
+        return Ok(None);
+    };
+    let index = options
+        .iter()
+        .position(|v| *v == selected)
+        .unwrap_or_default();
+    Ok(Some(items[index].0.clone()))
+}
+
+fn truncate_hint(hint: &str) -> String {
+    const MAX_HINT_CHARS: usize = 60;
+    if hint.chars().count() > MAX_HINT_CHARS {
+        let hint: String = hint.chars().take(MAX_HINT_CHARS - 1).collect();
+        format!("{hint}…")
+    } else {
+        hint.to_string()
+    }
+}
+
 pub fn load_env_file() -> Result<()> {
     let env_file_path = Config::env_file();
     let contents = match read_to_string(&env_file_path) {
@@ -2319,6 +3971,22 @@ impl WorkingMode {
     }
 }
 
+/// The `security` section of the config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SecurityConfig {
+    /// What to do with prompt injections found in tool calls, tool results and RAG content
+    pub injection_guard: String,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            injection_guard: "off".into(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LastMessage {
     pub input: Input,
@@ -2336,6 +4004,24 @@ impl LastMessage {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct LastExchange {
+    pub model_id: String,
+    pub finish_reason: String,
+    /// `None` when they couldn't be counted
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+    /// Some tokens were counted locally because the API didn't report them
+    pub estimated: bool,
+    pub latency: Duration,
+    pub rounds: usize,
+    pub tools: Vec<String>,
+    pub rag_sources: Option<String>,
+    pub history_sources: Vec<String>,
+    pub request_ids: Option<RequestIds>,
+    pub error: Option<String>,
+}
+
 bitflags::bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct StateFlags: u32 {
@@ -2377,6 +4063,14 @@ impl AssertState {
     }
 }
 
+/// The role and text of each message, as compared after `.edit session`.
+fn message_texts(messages: &[Message]) -> Vec<(String, String)> {
+    messages
+        .iter()
+        .map(|v| (format!("{:?}", v.role).to_lowercase(), v.content.to_text()))
+        .collect()
+}
+
 fn create_config_file(config_path: &Path) -> Result<()> {
     let ans = Confirm::new("No config file, create a new one?")
         .with_default(true)
@@ -2385,14 +4079,28 @@ fn create_config_file(config_path: &Path) -> Result<()> {
         process::exit(0);
     }
 
-    let client = Select::new("Platform:", list_client_types()).prompt()?;
-
     let mut config = serde_json::json!({});
-    let (model, clients_config) = create_client_config(client)?;
-    config["model"] = model.into();
-    config[CLIENTS_FIELD] = clients_config;
+    config["version"] = CONFIG_VERSION.into();
+    let (fallback_model, clients) = prompt_clients()?;
+    config[CLIENTS_FIELD] = clients.into();
+
+    let wizard_config: Config =
+        serde_json::from_value(config.clone()).with_context(|| "Failed to create config")?;
+    let models = list_client_models(&wizard_config.clients);
+    config["model"] = prompt_default_model(&wizard_config, &models, fallback_model)?.into();
+    if let Some(embedding_model) = prompt_embedding_model(&models)? {
+        config["rag_embedding_model"] = embedding_model.into();
+    }
+    prompt_functions_dir()?;
+    if prompt_light_theme()? {
+        config["light_theme"] = true.into();
+    }
+
+    write_config_file(config_path, &config)
+}
 
-    let config_data = serde_yaml::to_string(&config).with_context(|| "Failed to create config")?;
+fn write_config_file(config_path: &Path, config: &serde_json::Value) -> Result<()> {
+    let config_data = serde_yaml::to_string(config).with_context(|| "Failed to create config")?;
     let config_data = format!(
         "# see https://github.com/sigoden/aichat/blob/main/config.example.yaml\n\n{config_data}"
     );
@@ -2411,6 +4119,153 @@ fn create_config_file(config_path: &Path) -> Result<()> {
     Ok(())
 }
 
+fn dynamic_client_config(platform: &str) -> serde_json::Value {
+    let is_openai_compatible = OPENAI_COMPATIBLE_PLATFORMS
+        .into_iter()
+        .any(|(name, _)| platform == name);
+    if is_openai_compatible {
+        json!({ "type": "openai-compatible", "name": platform })
+    } else {
+        json!({ "type": platform })
+    }
+}
+
+/// Asks for one or more platforms, returns the model id of the first one and the client configs.
+fn prompt_clients() -> Result<(String, Vec<serde_json::Value>)> {
+    let mut client_types = list_client_types();
+    let mut first_model = None;
+    let mut clients = vec![];
+    loop {
+        let client = Select::new("Platform:", client_types.clone()).prompt()?;
+        client_types.retain(|v| *v != client);
+        let (model, clients_config) = create_client_config(client)?;
+        first_model.get_or_insert(model);
+        if let serde_json::Value::Array(list) = clients_config {
+            clients.extend(list);
+        }
+        if client_types.is_empty()
+            || !Confirm::new("Add another platform?")
+                .with_default(false)
+                .prompt()?
+        {
+            break;
+        }
+    }
+    Ok((first_model.unwrap_or_default(), clients))
+}
+
+fn prompt_default_model(config: &Config, models: &[Model], fallback: String) -> Result<String> {
+    let chat_models: Vec<&Model> = models
+        .iter()
+        .filter(|v| v.model_type() == ModelType::Chat)
+        .collect();
+    if chat_models.is_empty() {
+        return Ok(fallback);
+    }
+    let model_ids: Vec<String> = chat_models.iter().map(|v| v.id()).collect();
+    loop {
+        let model_id = Select::new("Default Model:", model_ids.clone()).prompt()?;
+        let model = chat_models[model_ids.iter().position(|v| *v == model_id).unwrap_or(0)];
+        match validate_model(config, model) {
+            Ok(()) => println!("✓ Model '{model_id}' is ready."),
+            Err(err) => {
+                println!(
+                    "{}",
+                    warning_text(&format!("Failed to validate model '{model_id}': {err:?}"))
+                );
+                if Confirm::new("Choose another model?")
+                    .with_default(true)
+                    .prompt()?
+                {
+                    continue;
+                }
+            }
+        }
+        return Ok(model_id);
+    }
+}
+
+/// Sends a tiny request to make sure the credentials and the model work.
+fn validate_model(config: &Config, model: &Model) -> Result<()> {
+    let mut config = config.clone();
+    config.model = model.clone();
+    config.function_calling = false;
+    let config: GlobalConfig = Arc::new(RwLock::new(config));
+    let client = init_client(&config, Some(model.clone()))?;
+    let input = Input::from_str(&config, "Hi", None);
+    tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(abortable_run_with_spinner(
+            client.chat_completions(input),
+            "Validating",
+            create_abort_signal(),
+        ))
+    })?;
+    Ok(())
+}
+
+fn prompt_embedding_model(models: &[Model]) -> Result<Option<String>> {
+    let model_ids: Vec<String> = models
+        .iter()
+        .filter(|v| v.model_type() == ModelType::Embedding)
+        .map(|v| v.id())
+        .collect();
+    if model_ids.is_empty()
+        || !Confirm::new("Set a default embedding model for RAG?")
+            .with_default(true)
+            .prompt()?
+    {
+        return Ok(None);
+    }
+    let model_id = Select::new("Embedding Model:", model_ids).prompt()?;
+    Ok(Some(model_id))
+}
+
+fn prompt_functions_dir() -> Result<()> {
+    let functions_file = Config::functions_file();
+    if functions_file.exists() {
+        return Ok(());
+    }
+    let functions_dir = Config::functions_dir();
+    let ans = Confirm::new(&format!(
+        "Set up the functions directory at '{}'?",
+        functions_dir.display()
+    ))
+    .with_default(false)
+    .with_help_message("Tools and agents are loaded from this directory")
+    .prompt()?;
+    if !ans {
+        return Ok(());
+    }
+    create_dir_all(Config::functions_bin_dir()).with_context(|| {
+        format!(
+            "Failed to create functions directory at '{}'",
+            functions_dir.display()
+        )
+    })?;
+    std::fs::write(&functions_file, "[]").with_context(|| {
+        format!(
+            "Failed to write functions file at '{}'",
+            functions_file.display()
+        )
+    })?;
+    println!(
+        "✓ Created functions directory at '{}', see https://github.com/sigoden/llm-functions to add tools.",
+        functions_dir.display()
+    );
+    Ok(())
+}
+
+fn prompt_light_theme() -> Result<bool> {
+    let detected = env::var("COLORFGBG")
+        .ok()
+        .and_then(|v| light_theme_from_colorfgbg(&v));
+    let mut prompt = Confirm::new("Use light theme?").with_default(detected.unwrap_or_default());
+    if detected.is_some() {
+        prompt = prompt.with_help_message("Detected from the terminal background");
+    }
+    Ok(prompt.prompt()?)
+}
+
 pub(crate) fn ensure_parent_exists(path: &Path) -> Result<()> {
     if path.exists() {
         return Ok(());
@@ -2454,6 +4309,17 @@ where
     Ok(value)
 }
 
+/// Splits a comma-separated list of paths, `null` or empty for none.
+fn split_paths(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .collect()
+}
+
 fn read_env_bool(key: &str) -> Option<Option<bool>> {
     let value = env::var(key).ok()?;
     Some(parse_bool(&value))
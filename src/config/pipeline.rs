@@ -0,0 +1,227 @@
+use super::*;
+
+use crate::function::eval_tool_calls;
+
+use anyhow::{anyhow, Context, Result};
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// One step of a pipeline agent, run by a role or another agent.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PipelineStage {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    /// The prompt of the stage, `{{input}}` by default; `{{previous}}` and `{{<stage>}}` hold earlier outputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    /// Runs the stage only when every output matches its case-insensitive regex, e.g. `classify: billing`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub when: IndexMap<String, String>,
+    /// Ends the pipeline after this stage, its output being the answer
+    #[serde(default)]
+    pub stop: bool,
+}
+
+impl PipelineStage {
+    fn matches(&self, variables: &IndexMap<String, String>) -> Result<bool> {
+        for (name, pattern) in &self.when {
+            let value = variables.get(name).map(|v| v.trim()).unwrap_or_default();
+            let re = Regex::new(&format!("(?i){pattern}")).with_context(|| {
+                format!(
+                    "Invalid `when` pattern '{pattern}' of stage '{}'",
+                    self.name
+                )
+            })?;
+            if !re.is_match(value).unwrap_or_default() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Checks the stages when the agent is loaded, so mistakes show up before the first message.
+pub fn validate_pipeline(stages: &[PipelineStage]) -> Result<()> {
+    let mut names: Vec<&str> = vec!["input", "previous"];
+    for stage in stages {
+        if stage.name.is_empty() || names.contains(&stage.name.as_str()) {
+            bail!("Pipeline stage names must be unique, not empty, input or previous");
+        }
+        if stage.role.is_some() == stage.agent.is_some() {
+            bail!(
+                "Pipeline stage '{}' needs either a role or an agent",
+                stage.name
+            );
+        }
+        if let Some(name) = stage.when.keys().find(|v| !names.contains(&v.as_str())) {
+            bail!(
+                "Pipeline stage '{}' depends on '{name}', which isn't an earlier stage",
+                stage.name
+            );
+        }
+        names.push(&stage.name);
+    }
+    Ok(())
+}
+
+/// Runs the pipeline of the active agent on the input and records the answer,
+/// `None` when the agent has no pipeline.
+pub async fn run_agent_pipeline(
+    config: &GlobalConfig,
+    input: &Input,
+    abort_signal: AbortSignal,
+) -> Result<Option<String>> {
+    let stages = match config.read().agent.as_ref() {
+        Some(agent) if !agent.pipeline().is_empty() => agent.pipeline().to_vec(),
+        _ => return Ok(None),
+    };
+    let output = run_pipeline(config, &stages, &input.text(), abort_signal).await?;
+    Config::print_answer(config, &output)?;
+    config.write().after_chat_completion(input, &output, &[])?;
+    Ok(Some(output))
+}
+
+async fn run_pipeline(
+    config: &GlobalConfig,
+    stages: &[PipelineStage],
+    text: &str,
+    abort_signal: AbortSignal,
+) -> Result<String> {
+    let mut variables = IndexMap::new();
+    variables.insert("input".to_string(), text.to_string());
+    variables.insert("previous".to_string(), text.to_string());
+    let mut output = None;
+    for stage in stages {
+        if !stage.matches(&variables)? {
+            variables.insert(stage.name.clone(), String::new());
+            continue;
+        }
+        let template = PromptTemplate {
+            body: stage.input.clone().unwrap_or_else(|| "{{input}}".into()),
+            ..Default::default()
+        };
+        let prompt = template.render(&variables)?;
+        if *IS_STDOUT_TERMINAL {
+            eprintln!("{}", dimmed_text(&format!("» {}", stage.name)));
+        }
+        let text = run_stage(config, stage, &prompt, abort_signal.clone())
+            .await
+            .with_context(|| format!("Failed at the pipeline stage '{}'", stage.name))?;
+        variables.insert(stage.name.clone(), text.clone());
+        variables.insert("previous".to_string(), text.clone());
+        output = Some(text);
+        if stage.stop {
+            break;
+        }
+    }
+    output.ok_or_else(|| anyhow!("No pipeline stage matched"))
+}
+
+/// Runs a stage on its own, with the agent of the stage, if any, standing in for the pipeline agent.
+async fn run_stage(
+    config: &GlobalConfig,
+    stage: &PipelineStage,
+    prompt: &str,
+    abort_signal: AbortSignal,
+) -> Result<String> {
+    let (role, agent) = match (&stage.role, &stage.agent) {
+        (Some(name), _) => {
+            let config = config.read();
+            let role = config.retrieve_role(name)?;
+            // The stage runs on behalf of the pipeline agent, so its model limits apply
+            if let Some(agent) = config.agent.as_ref() {
+                agent.check_model(role.model())?;
+            }
+            (role, None)
+        }
+        (None, Some(name)) => {
+            let mut agent = Agent::init(config, name, abort_signal.clone()).await?;
+            if !agent.pipeline().is_empty() {
+                bail!("The agent '{name}' is a pipeline, which can't be nested");
+            }
+            let variables = Agent::init_agent_variables(
+                agent.defined_variables(),
+                agent.config_variables(),
+                true,
+            )?;
+            agent.set_shared_variables(variables);
+            (agent.to_role(), Some(agent))
+        }
+        (None, None) => bail!("No role or agent"),
+    };
+    let rag = agent.as_ref().and_then(|v| v.rag());
+    let (outer_agent, outer_rag) = {
+        let mut config = config.write();
+        (
+            std::mem::replace(&mut config.agent, agent),
+            std::mem::replace(&mut config.rag, rag),
+        )
+    };
+    let ret = run_stage_prompt(config, role, prompt, &stage.name, abort_signal).await;
+    let mut config = config.write();
+    config.agent = outer_agent;
+    config.rag = outer_rag;
+    ret
+}
+
+async fn run_stage_prompt(
+    config: &GlobalConfig,
+    role: Role,
+    prompt: &str,
+    name: &str,
+    abort_signal: AbortSignal,
+) -> Result<String> {
+    let mut input = Input::from_str(config, prompt, Some(role));
+    input.use_embeddings(abort_signal.clone()).await?;
+    let client = input.create_client()?;
+    loop {
+        let output = abortable_run_with_spinner(
+            client.chat_completions(input.clone()),
+            &format!("Running {name}"),
+            abort_signal.clone(),
+        )
+        .await?;
+        let tool_results = eval_tool_calls(config, &input, output.tool_calls).await?;
+        if tool_results.is_empty() {
+            return Ok(output.text);
+        }
+        input = input.merge_tool_results(output.text, tool_results);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_stages() {
+        let stage = |name: &str, when: &[(&str, &str)]| PipelineStage {
+            name: name.into(),
+            role: Some("%code%".into()),
+            when: when
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        let stages = vec![
+            stage("classify", &[]),
+            stage("billing", &[("classify", "^(billing|refund)")]),
+        ];
+        assert!(validate_pipeline(&stages).is_ok());
+        assert!(validate_pipeline(&[stage("a", &[("b", "x")]), stage("b", &[])]).is_err());
+        assert!(validate_pipeline(&[stage("a", &[]), stage("a", &[])]).is_err());
+        let variables: IndexMap<String, String> =
+            [("classify".to_string(), " Refund\n".to_string())]
+                .into_iter()
+                .collect();
+        assert!(stages[1].matches(&variables).unwrap());
+        let variables: IndexMap<String, String> = [("classify".to_string(), "tech".to_string())]
+            .into_iter()
+            .collect();
+        assert!(!stages[1].matches(&variables).unwrap());
+    }
+}
@@ -7,6 +7,7 @@ use fancy_regex::Regex;
 use rust_embed::Embed;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use similar::TextDiff;
 
 pub const SHELL_ROLE: &str = "%shell%";
 pub const EXPLAIN_SHELL_ROLE: &str = "%explain-shell%";
@@ -15,6 +16,9 @@ pub const CREATE_TITLE_ROLE: &str = "%create-title%";
 
 pub const INPUT_PLACEHOLDER: &str = "__INPUT__";
 
+/// How alike two role prompts must be for `--dedupe-roles` to report them
+pub const ROLE_DUPLICATE_RATIO: f32 = 0.8;
+
 #[derive(Embed)]
 #[folder = "assets/roles/"]
 struct RolesAsset;
@@ -23,6 +27,13 @@ lazy_static::lazy_static! {
     static ref RE_METADATA: Regex = Regex::new(r"(?s)-{3,}\s*(.*?)\s*-{3,}\s*(.*)").unwrap();
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RoleVariable {
+    pub name: String,
+    pub from_cmd: Option<String>,
+    pub default: Option<String>,
+}
+
 pub trait RoleLike {
     fn to_role(&self) -> Role;
     fn model(&self) -> &Model;
@@ -52,6 +63,17 @@ pub struct Role {
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    native_tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    json_mode: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    conversation_starters: Vec<String>,
+    #[serde(skip)]
+    variables: Vec<RoleVariable>,
+    /// The prompt before `resolve_variables` filled it in, written back by `export`
+    #[serde(skip)]
+    prompt_template: Option<String>,
 
     #[serde(skip)]
     model: Model,
@@ -83,6 +105,19 @@ impl Role {
                             "temperature" => role.temperature = value.as_f64(),
                             "top_p" => role.top_p = value.as_f64(),
                             "use_tools" => role.use_tools = value.as_str().map(|v| v.to_string()),
+                            "native_tools" => {
+                                role.native_tools =
+                                    serde_json::from_value(value.clone()).unwrap_or_default()
+                            }
+                            "json_mode" => role.json_mode = value.as_bool().unwrap_or_default(),
+                            "variables" => {
+                                role.variables =
+                                    serde_json::from_value(value.clone()).unwrap_or_default()
+                            }
+                            "conversation_starters" => {
+                                role.conversation_starters =
+                                    serde_json::from_value(value.clone()).unwrap_or_default()
+                            }
                             _ => (),
                         }
                     }
@@ -92,6 +127,39 @@ impl Role {
         role
     }
 
+    /// Fills `{{name}}` in the prompt with the values of the role variables, once when the role is activated.
+    pub fn resolve_variables(&mut self) -> Result<()> {
+        if self.variables.is_empty() || self.prompt_template.is_some() {
+            return Ok(());
+        }
+        let template = self.prompt.clone();
+        for variable in &self.variables {
+            let value = match (&variable.from_cmd, &variable.default) {
+                (Some(cmd), default) => match run_variable_cmd(cmd) {
+                    Ok(value) => value,
+                    Err(err) => match default {
+                        Some(value) => {
+                            warn!("Failed to compute role variable '{}', {err}", variable.name);
+                            value.clone()
+                        }
+                        None => {
+                            return Err(err.context(format!(
+                                "Failed to compute role variable '{}'",
+                                variable.name
+                            )))
+                        }
+                    },
+                },
+                (None, default) => default.clone().unwrap_or_default(),
+            };
+            self.prompt = self
+                .prompt
+                .replace(&format!("{{{{{}}}}}", variable.name), &value);
+        }
+        self.prompt_template = Some(template);
+        Ok(())
+    }
+
     pub fn builtin(name: &str) -> Result<Self> {
         let content = RolesAsset::get(&format!("{name}.md"))
             .ok_or_else(|| anyhow!("Unknown role `{name}`"))?;
@@ -106,8 +174,9 @@ impl Role {
     }
 
     pub fn list_builtin_roles() -> Vec<Self> {
-        RolesAsset::iter()
-            .filter_map(|v| Role::builtin(&v).ok())
+        Self::list_builtin_role_names()
+            .iter()
+            .filter_map(|v| Role::builtin(v).ok())
             .collect()
     }
 
@@ -128,6 +197,29 @@ impl Role {
         }
     }
 
+    /// Server-side tools of the provider, such as Gemini's `google_search` and `code_execution`.
+    pub fn native_tools(&self) -> &[String] {
+        &self.native_tools
+    }
+
+    pub fn set_native_tools(&mut self, value: Vec<String>) {
+        self.native_tools = value;
+    }
+
+    /// Whether the answer must be a JSON object, sent as the provider's JSON mode when the model has one.
+    pub fn json_mode(&self) -> bool {
+        self.json_mode
+    }
+
+    pub fn set_json_mode(&mut self, value: bool) {
+        self.json_mode = value;
+    }
+
+    /// Example prompts offered by `.starter`, as with agents.
+    pub fn conversation_starters(&self) -> &[String] {
+        &self.conversation_starters
+    }
+
     pub fn has_args(&self) -> bool {
         self.name.contains('#')
     }
@@ -146,12 +238,41 @@ impl Role {
         if let Some(use_tools) = self.use_tools() {
             metadata.push(format!("use_tools: {}", use_tools));
         }
+        if !self.native_tools.is_empty() {
+            metadata.push(format!("native_tools: [{}]", self.native_tools.join(", ")));
+        }
+        if self.json_mode {
+            metadata.push("json_mode: true".into());
+        }
+        if !self.variables.is_empty() {
+            metadata.push("variables:".into());
+            for variable in &self.variables {
+                let name = serde_json::to_string(&variable.name).unwrap_or_default();
+                metadata.push(format!("- name: {name}"));
+                if let Some(from_cmd) = &variable.from_cmd {
+                    let from_cmd = serde_json::to_string(from_cmd).unwrap_or_default();
+                    metadata.push(format!("  from_cmd: {from_cmd}"));
+                }
+                if let Some(default) = &variable.default {
+                    let default = serde_json::to_string(default).unwrap_or_default();
+                    metadata.push(format!("  default: {default}"));
+                }
+            }
+        }
+        if !self.conversation_starters.is_empty() {
+            metadata.push("conversation_starters:".into());
+            for starter in &self.conversation_starters {
+                let starter = serde_json::to_string(starter).unwrap_or_default();
+                metadata.push(format!("- {starter}"));
+            }
+        }
+        let prompt = self.prompt_template.as_ref().unwrap_or(&self.prompt);
         if metadata.is_empty() {
-            format!("{}\n", self.prompt)
-        } else if self.prompt.is_empty() {
+            format!("{prompt}\n")
+        } else if prompt.is_empty() {
             format!("---\n{}\n---\n", metadata.join("\n"))
         } else {
-            format!("---\n{}\n---\n\n{}\n", metadata.join("\n"), self.prompt)
+            format!("---\n{}\n---\n\n{prompt}\n", metadata.join("\n"))
         }
     }
 
@@ -378,10 +499,70 @@ fn parse_structure_prompt(prompt: &str) -> (&str, Vec<(&str, &str)>) {
     (prompt, vec![])
 }
 
+/// Pairs of roles with alike prompts, as `(index, index, similarity)`, the most alike first.
+pub fn find_duplicate_roles(roles: &[Role], threshold: f32) -> Vec<(usize, usize, f32)> {
+    let mut pairs = vec![];
+    for (i, a) in roles.iter().enumerate() {
+        for (j, b) in roles.iter().enumerate().skip(i + 1) {
+            if a.is_empty_prompt() || b.is_empty_prompt() {
+                continue;
+            }
+            let ratio = TextDiff::from_words(a.prompt(), b.prompt()).ratio();
+            if ratio >= threshold {
+                pairs.push((i, j, ratio));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.2.total_cmp(&a.2));
+    pairs
+}
+
+/// Appends the lines of `other` prompt that the role file `content` lacks.
+pub fn merge_role_prompt(content: &str, other: &str) -> String {
+    let existing: HashSet<&str> = content.lines().map(|v| v.trim()).collect();
+    let missing: Vec<&str> = other
+        .lines()
+        .filter(|v| !v.trim().is_empty() && !existing.contains(v.trim()))
+        .collect();
+    if missing.is_empty() {
+        return content.to_string();
+    }
+    format!("{}\n\n{}\n", content.trim_end(), missing.join("\n"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_find_duplicate_roles() {
+        let roles = vec![
+            Role::new(
+                "a",
+                "You are a helpful translator. Translate the input into English.",
+            ),
+            Role::new(
+                "b",
+                "You are a helpful translator. Translate the input to English.",
+            ),
+            Role::new("c", "Write a haiku about the input."),
+            Role::new("d", ""),
+        ];
+        let pairs = find_duplicate_roles(&roles, ROLE_DUPLICATE_RATIO);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0, pairs[0].1), (0, 1));
+    }
+
+    #[test]
+    fn test_merge_role_prompt() {
+        let content = "---\nmodel: openai:gpt-4o\n---\nTranslate to English.\nBe concise.\n";
+        assert_eq!(
+            merge_role_prompt(content, "Translate to English.\n\nKeep the formatting."),
+            "---\nmodel: openai:gpt-4o\n---\nTranslate to English.\nBe concise.\n\nKeep the formatting.\n"
+        );
+        assert_eq!(merge_role_prompt(content, "Be concise."), content);
+    }
+
     #[test]
     fn test_merge_prompt_name() {
         assert_eq!(
@@ -394,6 +575,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve_variables() {
+        let mut role = Role::new(
+            "test",
+            r#"---
+variables:
+  - name: branch
+    from_cmd: echo main
+  - name: user
+    default: guest
+---
+On {{branch}} as {{user}}"#,
+        );
+        role.resolve_variables().unwrap();
+        assert_eq!(role.prompt(), "On main as guest");
+        let exported = Role::new("test", &role.export());
+        assert_eq!(exported.variables.len(), 2);
+        assert_eq!(exported.prompt(), "On {{branch}} as {{user}}");
+    }
+
+    #[test]
+    fn test_conversation_starters() {
+        let role = Role::new(
+            "test",
+            r#"---
+conversation_starters:
+  - "Translate: hello"
+  - Explain this error
+---
+You are a helper"#,
+        );
+        assert_eq!(
+            role.conversation_starters(),
+            ["Translate: hello", "Explain this error"]
+        );
+        let role = Role::new("test", &role.export());
+        assert_eq!(role.conversation_starters().len(), 2);
+        assert_eq!(role.prompt(), "You are a helper");
+    }
+
     #[test]
     fn test_match_name() {
         let names = vec![
@@ -1,8 +1,9 @@
 use super::input::*;
+use super::migration::{SESSION_MIGRATIONS, SESSION_VERSION};
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole};
-use crate::render::MarkdownRender;
+use crate::client::{Message, MessageContent, MessageContentPart, MessageRole};
+use crate::render::{image_protocol, render_image, MarkdownRender};
 
 use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
@@ -13,12 +14,16 @@ use std::collections::HashMap;
 use std::fs::{read_to_string, write};
 use std::path::Path;
 
+const TRANSCRIPT_TOOL_OUTPUT_LIMIT: usize = 2000;
+
 lazy_static::lazy_static! {
     static ref RE_AUTONAME_PREFIX: Regex = Regex::new(r"\d{8}T\d{6}-").unwrap();
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Session {
+    #[serde(default)]
+    version: u64,
     #[serde(rename(serialize = "model", deserialize = "model"))]
     model_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -27,6 +32,10 @@ pub struct Session {
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    native_tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    json_mode: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     save_session: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -44,6 +53,14 @@ pub struct Session {
     messages: Vec<Message>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     data_urls: HashMap<String, String>,
+    /// The attached files summarized for being too large, read back with `read_section`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    large_files: IndexMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<String>,
 
     #[serde(skip)]
     model: Model,
@@ -67,8 +84,10 @@ impl Session {
     pub fn new(config: &Config, name: &str) -> Self {
         let role = config.extract_role();
         let mut session = Self {
+            version: SESSION_VERSION,
             name: name.to_string(),
             save_session: config.save_session,
+            created_at: Some(now()),
             ..Default::default()
         };
         session.set_role(role);
@@ -79,21 +98,20 @@ impl Session {
     pub fn load(config: &Config, name: &str, path: &Path) -> Result<Self> {
         let content = read_to_string(path)
             .with_context(|| format!("Failed to load session {} at {}", name, path.display()))?;
+        let content = migrate_file(
+            "session",
+            path,
+            content,
+            SESSION_VERSION,
+            &SESSION_MIGRATIONS,
+            true,
+        )?;
         let mut session: Self =
             serde_yaml::from_str(&content).with_context(|| format!("Invalid session {}", name))?;
+        session.version = SESSION_VERSION;
 
         session.model = Model::retrieve_model(config, &session.model_id, ModelType::Chat)?;
-
-        if let Some(autoname) = name.strip_prefix("_/") {
-            session.name = TEMP_SESSION_NAME.to_string();
-            session.path = None;
-            if let Ok(true) = RE_AUTONAME_PREFIX.is_match(autoname) {
-                session.autoname = Some(AutoName::new(autoname[16..].to_string()));
-            }
-        } else {
-            session.name = name.to_string();
-            session.path = Some(path.display().to_string());
-        }
+        session.set_location(name, path);
 
         if let Some(role_name) = &session.role_name {
             if let Ok(role) = config.retrieve_role(role_name) {
@@ -104,6 +122,29 @@ impl Session {
         Ok(session)
     }
 
+    /// Reads a saved session only to show it, without migrating the file or resolving its model and role.
+    pub fn load_readonly(name: &str, path: &Path) -> Result<Self> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to load session {} at {}", name, path.display()))?;
+        let mut session: Self =
+            serde_yaml::from_str(&content).with_context(|| format!("Invalid session {}", name))?;
+        session.set_location(name, path);
+        Ok(session)
+    }
+
+    fn set_location(&mut self, name: &str, path: &Path) {
+        if let Some(autoname) = name.strip_prefix("_/") {
+            self.name = TEMP_SESSION_NAME.to_string();
+            self.path = None;
+            if let Ok(true) = RE_AUTONAME_PREFIX.is_match(autoname) {
+                self.autoname = Some(AutoName::new(autoname[16..].to_string()));
+            }
+        } else {
+            self.name = name.to_string();
+            self.path = Some(path.display().to_string());
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.messages.is_empty() && self.compressed_messages.is_empty()
     }
@@ -132,6 +173,10 @@ impl Session {
         self.messages.iter().any(|v| v.role.is_user())
     }
 
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
     pub fn user_messages_len(&self) -> usize {
         self.messages.iter().filter(|v| v.role.is_user()).count()
     }
@@ -161,6 +206,12 @@ impl Session {
         if percent != 0.0 {
             data["total/max"] = format!("{}%", percent).into();
         }
+        if let Some(created_at) = &self.created_at {
+            data["created_at"] = created_at.clone().into();
+        }
+        if let Some(updated_at) = &self.updated_at {
+            data["updated_at"] = updated_at.clone().into();
+        }
         data["messages"] = json!(self.messages);
 
         let output = serde_yaml::to_string(&data)
@@ -183,7 +234,7 @@ impl Session {
             items.push(("autoname", autoname.to_string()));
         }
 
-        items.push(("model", self.model().id()));
+        items.push(("model", self.model_id.clone()));
 
         if let Some(temperature) = self.temperature() {
             items.push(("temperature", temperature.to_string()));
@@ -208,6 +259,14 @@ impl Session {
             items.push(("max_input_tokens", max_input_tokens.to_string()));
         }
 
+        if let Some(created_at) = &self.created_at {
+            items.push(("created_at", created_at.clone()));
+        }
+
+        if let Some(updated_at) = &self.updated_at {
+            items.push(("updated_at", updated_at.clone()));
+        }
+
         let mut lines: Vec<String> = items
             .iter()
             .map(|(name, value)| format!("{name:<20}{value}"))
@@ -238,6 +297,15 @@ impl Session {
                             self.name,
                             message.content.render_input(resolve_url_fn, agent_info)
                         ));
+                        if let (MessageContent::Array(parts), Some(_)) =
+                            (&message.content, image_protocol())
+                        {
+                            for part in parts {
+                                if let MessageContentPart::ImageUrl { image_url } = part {
+                                    lines.extend(render_image(&resolve_url_fn(&image_url.url)));
+                                }
+                            }
+                        }
                     }
                     MessageRole::Tool => {
                         lines.push(message.content.render_input(resolve_url_fn, agent_info));
@@ -266,6 +334,8 @@ impl Session {
         self.temperature = role.temperature();
         self.top_p = role.top_p();
         self.use_tools = role.use_tools();
+        self.native_tools = role.native_tools().to_vec();
+        self.json_mode = role.json_mode();
         self.model = role.model().clone();
         self.role_name = convert_option_string(role.name());
         self.role_prompt = role.prompt().to_string();
@@ -275,6 +345,8 @@ impl Session {
     pub fn clear_role(&mut self) {
         self.role_name = None;
         self.role_prompt.clear();
+        self.native_tools.clear();
+        self.json_mode = false;
     }
 
     pub fn sync_agent(&mut self, agent: &Agent) {
@@ -282,6 +354,13 @@ impl Session {
         self.role_prompt = agent.interpolated_instructions();
         self.agent_variables = agent.variables().clone();
         self.agent_instructions = self.role_prompt.clone();
+        self.native_tools = agent.native_tools().to_vec();
+        self.json_mode = false;
+    }
+
+    /// The large files attached earlier in the session, which `read_section` can still serve.
+    pub fn large_files(&self) -> &IndexMap<String, String> {
+        &self.large_files
     }
 
     pub fn agent_variables(&self) -> &AgentVariables {
@@ -329,26 +408,79 @@ impl Session {
         self.compressing = compressing;
     }
 
-    pub fn compress(&mut self, mut prompt: String) {
-        if let Some(system_prompt) = self.messages.first().and_then(|v| {
-            if MessageRole::System == v.role {
-                let content = v.content.to_text();
-                if !content.is_empty() {
-                    return Some(content);
+    /// Splits the messages to be compressed into transcript windows.
+    ///
+    /// The last `keep_recent` turns are left out, and windows are only cut at turn
+    /// boundaries so a tool call always stays together with its result. Also returns
+    /// the index of the first message to keep, which should be passed to `compress`.
+    pub fn compression_windows(
+        &self,
+        keep_recent: usize,
+        window_size: Option<usize>,
+    ) -> Result<(Vec<String>, usize)> {
+        let (start, end) = self.compression_range(keep_recent);
+        if start >= end {
+            bail!("No need to compress since there are only {keep_recent} or fewer recent turns in the session")
+        }
+        let turns = split_turns(&self.messages[start..end]);
+        let mut windows = vec![];
+        let mut window: Vec<Message> = vec![];
+        for turn in turns {
+            if let Some(window_size) = window_size {
+                if !window.is_empty() && window.len() + turn.len() > window_size {
+                    windows.push(render_transcript(&window));
+                    window.clear();
                 }
             }
-            None
-        }) {
-            prompt = format!("{system_prompt}\n\n{prompt}",);
+            window.extend(turn.iter().cloned());
+        }
+        if !window.is_empty() {
+            windows.push(render_transcript(&window));
         }
+        Ok((windows, end))
+    }
+
+    pub fn compress(&mut self, mut prompt: String, end: usize) {
+        let end = end.min(self.messages.len());
+        if self.messages.first().map(|v| v.role.is_system()) == Some(true) {
+            let system_prompt = self.messages[0].content.to_text();
+            if !system_prompt.is_empty() {
+                prompt = format!("{system_prompt}\n\n{prompt}",);
+            }
+        }
+        let recent_messages = self.messages.split_off(end);
         self.compressed_messages.append(&mut self.messages);
         self.messages.push(Message::new(
             MessageRole::System,
             MessageContent::Text(prompt),
         ));
+        self.messages.extend(recent_messages);
         self.dirty = true;
     }
 
+    fn compression_range(&self, keep_recent: usize) -> (usize, usize) {
+        let start = match self.messages.first() {
+            Some(v) if v.role.is_system() => 1,
+            _ => 0,
+        };
+        let mut end = self.messages.len();
+        if keep_recent > 0 {
+            let turn_starts: Vec<usize> = self
+                .messages
+                .iter()
+                .enumerate()
+                .skip(start)
+                .filter(|(_, v)| v.role.is_user())
+                .map(|(i, _)| i)
+                .collect();
+            end = match turn_starts.len().checked_sub(keep_recent) {
+                Some(index) if index > 0 => turn_starts[index],
+                _ => start,
+            };
+        }
+        (start, end)
+    }
+
     pub fn need_autoname(&self) -> bool {
         self.autoname.as_ref().map(|v| v.need()).unwrap_or_default()
     }
@@ -387,12 +519,14 @@ impl Session {
                 if !is_repl {
                     return Ok(());
                 }
-                let ans = Confirm::new("Save session?").with_default(false).prompt()?;
+                let ans = Confirm::new(&tr("Save session?"))
+                    .with_default(false)
+                    .prompt()?;
                 if !ans {
                     return Ok(());
                 }
                 if session_name == TEMP_SESSION_NAME {
-                    session_name = Text::new("Session name:")
+                    session_name = Text::new(&tr("Session name:"))
                         .with_validator(|input: &str| {
                             let input = input.trim();
                             if input.is_empty() {
@@ -484,6 +618,7 @@ impl Session {
                     .push(Message::new(MessageRole::User, input.message_content()));
             }
             self.data_urls.extend(input.data_urls());
+            self.large_files.extend(input.own_large_files());
             if let Some(tool_calls) = input.tool_calls() {
                 self.messages.push(Message::new(
                     MessageRole::Tool,
@@ -495,6 +630,7 @@ impl Session {
                 MessageContent::Text(output.to_string()),
             ));
         }
+        self.updated_at = Some(now());
         self.dirty = true;
         Ok(())
     }
@@ -503,6 +639,7 @@ impl Session {
         self.messages.clear();
         self.compressed_messages.clear();
         self.data_urls.clear();
+        self.large_files.clear();
         self.autoname = None;
         self.dirty = true;
     }
@@ -546,6 +683,8 @@ impl RoleLike for Session {
         let role_name = self.role_name.as_deref().unwrap_or_default();
         let mut role = Role::new(role_name, &self.role_prompt);
         role.sync(self);
+        role.set_native_tools(self.native_tools.clone());
+        role.set_json_mode(self.json_mode);
         role
     }
 
@@ -599,6 +738,59 @@ impl RoleLike for Session {
     }
 }
 
+fn split_turns(messages: &[Message]) -> Vec<&[Message]> {
+    let mut turns = vec![];
+    let mut turn_start = 0;
+    for (i, message) in messages.iter().enumerate() {
+        if message.role.is_user() && i > turn_start {
+            turns.push(&messages[turn_start..i]);
+            turn_start = i;
+        }
+    }
+    if turn_start < messages.len() {
+        turns.push(&messages[turn_start..]);
+    }
+    turns
+}
+
+fn render_transcript(messages: &[Message]) -> String {
+    let mut lines = vec![];
+    for message in messages {
+        match &message.content {
+            MessageContent::ToolCalls(tool_calls) => {
+                if !tool_calls.text.is_empty() {
+                    lines.push(format!("ASSISTANT: {}", tool_calls.text));
+                }
+                for tool_result in &tool_calls.tool_results {
+                    let mut output = tool_result.output.to_string();
+                    if output.len() > TRANSCRIPT_TOOL_OUTPUT_LIMIT {
+                        let mut index = TRANSCRIPT_TOOL_OUTPUT_LIMIT;
+                        while !output.is_char_boundary(index) {
+                            index -= 1;
+                        }
+                        output.truncate(index);
+                        output.push_str("...");
+                    }
+                    lines.push(format!(
+                        "TOOL CALL: {} {}\nTOOL RESULT: {output}",
+                        tool_result.call.name, tool_result.call.arguments
+                    ));
+                }
+            }
+            content => {
+                let role = match message.role {
+                    MessageRole::System => "SYSTEM",
+                    MessageRole::Assistant => "ASSISTANT",
+                    MessageRole::User => "USER",
+                    MessageRole::Tool => "TOOL",
+                };
+                lines.push(format!("{role}: {}", content.to_text()));
+            }
+        }
+    }
+    lines.join("\n\n")
+}
+
 #[derive(Debug, Clone, Default)]
 struct AutoName {
     naming: bool,
@@ -623,3 +815,66 @@ impl AutoName {
         !self.naming && self.chat_history.is_some() && self.name.is_none()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::MessageContentToolCalls;
+    use crate::function::{ToolCall, ToolResult};
+
+    fn text_message(role: MessageRole, text: &str) -> Message {
+        Message::new(role, MessageContent::Text(text.into()))
+    }
+
+    fn create_session() -> Session {
+        let tool_call = ToolCall::new("get_weather".into(), json!({"city": "Paris"}), None);
+        let tool_calls = MessageContentToolCalls::new(
+            vec![ToolResult::new(tool_call, json!({"temperature": 20}))],
+            String::new(),
+        );
+        Session {
+            messages: vec![
+                text_message(MessageRole::System, "You are a helpful assistant."),
+                text_message(MessageRole::User, "hi"),
+                text_message(MessageRole::Assistant, "hello"),
+                text_message(MessageRole::User, "weather in paris?"),
+                Message::new(MessageRole::Tool, MessageContent::ToolCalls(tool_calls)),
+                text_message(MessageRole::Assistant, "It's 20 degrees."),
+                text_message(MessageRole::User, "thanks"),
+                text_message(MessageRole::Assistant, "You're welcome."),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compression_windows() {
+        let session = create_session();
+        let (windows, end) = session.compression_windows(1, Some(2)).unwrap();
+        assert_eq!(end, 6);
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0], "USER: hi\n\nASSISTANT: hello");
+        assert!(windows[1].contains("TOOL CALL: get_weather {\"city\":\"Paris\"}"));
+        assert!(windows[1].ends_with("ASSISTANT: It's 20 degrees."));
+
+        let (windows, end) = session.compression_windows(0, None).unwrap();
+        assert_eq!(end, 8);
+        assert_eq!(windows.len(), 1);
+
+        assert!(session.compression_windows(3, None).is_err());
+    }
+
+    #[test]
+    fn test_compress() {
+        let mut session = create_session();
+        let (_, end) = session.compression_windows(1, None).unwrap();
+        session.compress("summary".into(), end);
+        assert_eq!(session.messages.len(), 3);
+        assert_eq!(
+            session.messages[0].content.to_text(),
+            "You are a helpful assistant.\n\nsummary"
+        );
+        assert_eq!(session.messages[1].content.to_text(), "thanks");
+        assert_eq!(session.compressed_messages.len(), 6);
+    }
+}
@@ -0,0 +1,46 @@
+use super::Config;
+use crate::client::Model;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, write};
+
+const MAX_RECENT_MODELS: usize = 8;
+
+/// Data remembered between runs, kept apart from the user-edited config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub recent_models: Vec<String>,
+}
+
+impl State {
+    pub fn load() -> Self {
+        let path = Config::state_file();
+        read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_yaml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Config::state_file();
+        let content = serde_yaml::to_string(self)?;
+        write(&path, content)
+            .with_context(|| format!("Failed to write state to '{}'", path.display()))
+    }
+
+    /// Moves the model to the front of the recently used models and saves the state.
+    ///
+    /// Takes the resolved model so an alias or a bare client name is stored by its full id.
+    pub fn record_model(model: &Model) {
+        let model_id = model.id();
+        let mut state = Self::load();
+        state.recent_models.retain(|v| v != &model_id);
+        state.recent_models.insert(0, model_id);
+        state.recent_models.truncate(MAX_RECENT_MODELS);
+        if let Err(err) = state.save() {
+            warn!("{err}");
+        }
+    }
+}
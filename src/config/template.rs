@@ -0,0 +1,136 @@
+use super::Config;
+
+use anyhow::{anyhow, bail, Context, Result};
+use fancy_regex::{Captures, Regex};
+use indexmap::{IndexMap, IndexSet};
+use serde::Deserialize;
+use std::fs::read_to_string;
+
+lazy_static::lazy_static! {
+    static ref RE_PLACEHOLDER: Regex = Regex::new(r"\{\{(\w+)\}\}").unwrap();
+}
+
+/// A reusable user prompt from the templates directory, with `{{var}}` placeholders.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PromptTemplate {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(skip)]
+    pub body: String,
+}
+
+impl PromptTemplate {
+    pub fn load(name: &str) -> Result<Self> {
+        let path = Config::template_file(name);
+        let content = read_to_string(&path)
+            .with_context(|| format!("Unknown template '{name}' at '{}'", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid template at '{}'", path.display()))
+    }
+
+    /// Loads the template file when there is one, otherwise takes the value as the template body.
+    pub fn load_or_inline(value: &str) -> Result<Self> {
+        if Config::template_file(value).exists() {
+            Self::load(value)
+        } else {
+            Ok(Self {
+                body: value.to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let front_matter = content.strip_prefix("---").and_then(|rest| {
+            let end = rest.find("\n---")?;
+            let body = rest[end + 4..].trim_start_matches('-');
+            Some((&rest[..end], body))
+        });
+        let template = match front_matter {
+            Some((metadata, body)) => {
+                let mut template: Self = if metadata.trim().is_empty() {
+                    Self::default()
+                } else {
+                    serde_yaml::from_str(metadata)?
+                };
+                template.body = body.trim().to_string();
+                template
+            }
+            None => Self {
+                body: content.trim().to_string(),
+                ..Default::default()
+            },
+        };
+        Ok(template)
+    }
+
+    pub fn placeholders(&self) -> IndexSet<String> {
+        RE_PLACEHOLDER
+            .captures_iter(&self.body)
+            .flatten()
+            .filter_map(|caps| caps.get(1).map(|v| v.as_str().to_string()))
+            .collect()
+    }
+
+    /// Fills every placeholder, failing on the ones without a value.
+    pub fn render(&self, variables: &IndexMap<String, String>) -> Result<String> {
+        let missing: Vec<String> = self
+            .placeholders()
+            .into_iter()
+            .filter(|v| !variables.contains_key(v))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "Missing template variables: {}, set them with `--var <name>=<value>`",
+                missing.join(", ")
+            );
+        }
+        let output = RE_PLACEHOLDER.replace_all(&self.body, |caps: &Captures| {
+            variables[caps.get(1).map(|v| v.as_str()).unwrap_or_default()].clone()
+        });
+        Ok(output.to_string())
+    }
+}
+
+/// Parses `--var name=value` pairs.
+pub fn parse_template_variables(values: &[String]) -> Result<IndexMap<String, String>> {
+    values
+        .iter()
+        .map(|value| {
+            value
+                .split_once('=')
+                .map(|(k, v)| (k.trim().to_string(), v.to_string()))
+                .ok_or_else(|| anyhow!("Invalid variable '{value}', expected <name>=<value>"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let template = PromptTemplate::parse(
+            "---\nmodel: openai:gpt-4o\ntemperature: 0.2\n---\nWrite notes for {{version}}:\n{{log}}\n",
+        )
+        .unwrap();
+        assert_eq!(template.model.as_deref(), Some("openai:gpt-4o"));
+        assert_eq!(template.temperature, Some(0.2));
+        let variables =
+            parse_template_variables(&["version=1.2".into(), "log=fix {{version}} = 1".into()])
+                .unwrap();
+        assert_eq!(
+            template.render(&variables).unwrap(),
+            "Write notes for 1.2:\nfix {{version}} = 1"
+        );
+        assert!(template.render(&IndexMap::new()).is_err());
+
+        let template = PromptTemplate::parse("Summarize:\n---\n{{content}}").unwrap();
+        assert_eq!(template.role, None);
+        assert_eq!(template.body, "Summarize:\n---\n{{content}}");
+    }
+}
@@ -0,0 +1,389 @@
+use crate::client::{Model, ModelType};
+use crate::config::{Config, GlobalConfig, Input, Role};
+use crate::function::eval_tool_calls;
+use crate::utils::*;
+
+use anyhow::{bail, Context, Result};
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_MIN_SCORE: f64 = 7.0;
+const JUDGE_PROMPT: &str = r#"You are grading the response of an AI assistant.
+
+Criteria:
+__CRITERIA__
+
+Prompt:
+__PROMPT__
+
+Response:
+__RESPONSE__
+
+Rate how well the response meets the criteria on a scale from 0 to 10. Reply with the number only."#;
+
+#[derive(Debug, Deserialize)]
+struct EvalSuite {
+    #[serde(default)]
+    models: Vec<String>,
+    judge_model: Option<String>,
+    cases: Vec<EvalCase>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EvalCase {
+    name: String,
+    prompt: String,
+    role: Option<String>,
+    agent: Option<String>,
+    #[serde(default)]
+    assert: Vec<Assertion>,
+}
+
+/// One entry of `assert`, every key that is set gets checked.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Assertion {
+    /// The output contains the text
+    contains: Option<String>,
+    /// The output matches the regex
+    regex: Option<String>,
+    /// The output is JSON that contains the expected fields
+    json: Option<Value>,
+    /// Criteria for the judge model to score the output against
+    judge: Option<String>,
+    min_score: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct EvalResult {
+    case: String,
+    model: String,
+    passed: bool,
+    latency_ms: u128,
+    output: String,
+    checks: Vec<CheckResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct CheckResult {
+    kind: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+/// Runs every case of the suite against every model, prints a pass/fail table and
+/// writes a JSON report next to the suite file.
+pub async fn run(config: &GlobalConfig, suite_path: &str, abort_signal: AbortSignal) -> Result<()> {
+    let suite_path = Path::new(suite_path);
+    let content = fs::read_to_string(suite_path)
+        .with_context(|| format!("Failed to read eval suite at '{}'", suite_path.display()))?;
+    let suite: EvalSuite = serde_yaml::from_str(&content)
+        .with_context(|| format!("Invalid eval suite at '{}'", suite_path.display()))?;
+    if suite.cases.is_empty() {
+        bail!("No cases in eval suite at '{}'", suite_path.display());
+    }
+    let models = if suite.models.is_empty() {
+        vec![config.read().current_model().clone()]
+    } else {
+        suite
+            .models
+            .iter()
+            .map(|v| Model::retrieve_model(&config.read(), v, ModelType::Chat))
+            .collect::<Result<Vec<_>>>()?
+    };
+    let judge_model = match &suite.judge_model {
+        Some(v) => Model::retrieve_model(&config.read(), v, ModelType::Chat)?,
+        None => config.read().current_model().clone(),
+    };
+
+    let mut results = vec![];
+    for case in &suite.cases {
+        for model in &models {
+            let result = abortable_run_with_spinner(
+                run_case(config, case, model, &judge_model, abort_signal.clone()),
+                &format!("Evaluating '{}' with {}", case.name, model.id()),
+                abort_signal.clone(),
+            )
+            .await?;
+            println!("{}", render_result(&result));
+            results.push(result);
+        }
+    }
+
+    let failed = results.iter().filter(|v| !v.passed).count();
+    let report_path = suite_path.with_extension("report.json");
+    let report = json!({
+        "suite": suite_path.display().to_string(),
+        "passed": results.len() - failed,
+        "failed": failed,
+        "results": results,
+    });
+    fs::write(&report_path, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write eval report to '{}'", report_path.display()))?;
+    println!("\n✓ Saved eval report to '{}'.", report_path.display());
+    if failed > 0 {
+        bail!("{failed} of {} evals failed", results.len());
+    }
+    Ok(())
+}
+
+async fn run_case(
+    config: &GlobalConfig,
+    case: &EvalCase,
+    model: &Model,
+    judge_model: &Model,
+    abort_signal: AbortSignal,
+) -> Result<EvalResult> {
+    let mut input = match (&case.agent, &case.role) {
+        (Some(agent), _) => {
+            Config::use_agent(config, agent, None, abort_signal.clone()).await?;
+            Input::from_str(config, &case.prompt, None)
+        }
+        (None, Some(role)) => {
+            let role = config.read().retrieve_role(role)?;
+            Input::from_str(config, &case.prompt, Some(role))
+        }
+        (None, None) => Input::from_str(config, &case.prompt, Some(Role::default())),
+    };
+    input.set_model(model.clone());
+    let started_at = Instant::now();
+    let ret = complete(config, input).await;
+    let latency = started_at.elapsed();
+    if case.agent.is_some() {
+        config.write().exit_agent()?;
+    }
+    let output = match ret {
+        Ok(v) => v,
+        Err(err) => {
+            return Ok(EvalResult {
+                case: case.name.clone(),
+                model: model.id(),
+                passed: false,
+                latency_ms: latency.as_millis(),
+                output: String::new(),
+                checks: vec![CheckResult {
+                    kind: "request",
+                    passed: false,
+                    detail: format!("{err:?}"),
+                }],
+            });
+        }
+    };
+    let mut checks = vec![];
+    for assertion in &case.assert {
+        checks.extend(check_assertion(assertion, &output));
+        if let Some(criteria) = &assertion.judge {
+            checks.push(
+                judge(
+                    config,
+                    judge_model,
+                    case,
+                    &output,
+                    criteria,
+                    assertion.min_score,
+                )
+                .await,
+            );
+        }
+    }
+    Ok(EvalResult {
+        case: case.name.clone(),
+        model: model.id(),
+        passed: checks.iter().all(|v| v.passed),
+        latency_ms: latency.as_millis(),
+        output,
+        checks,
+    })
+}
+
+/// Sends the input and resolves tool calls until the model gives a final answer.
+///
+/// The tool calls are capped by `auto_max_tool_calls` so a looping model can't stall the run.
+async fn complete(config: &GlobalConfig, mut input: Input) -> Result<String> {
+    let max_tool_calls = config.read().auto_max_tool_calls;
+    let mut tool_calls = 0;
+    loop {
+        let client = input.create_client()?;
+        let output = client.chat_completions(input.clone()).await?;
+        tool_calls += output.tool_calls.len();
+        if tool_calls > max_tool_calls {
+            bail!("Stopped after {max_tool_calls} tool calls, raise `auto_max_tool_calls` to allow more");
+        }
+        let tool_results = eval_tool_calls(config, &input, output.tool_calls).await?;
+        if tool_results.is_empty() {
+            return Ok(output.text);
+        }
+        input = input.merge_tool_results(output.text, tool_results);
+    }
+}
+
+async fn judge(
+    config: &GlobalConfig,
+    judge_model: &Model,
+    case: &EvalCase,
+    output: &str,
+    criteria: &str,
+    min_score: Option<f64>,
+) -> CheckResult {
+    let min_score = min_score.unwrap_or(DEFAULT_MIN_SCORE);
+    let prompt = JUDGE_PROMPT
+        .replace("__CRITERIA__", criteria)
+        .replace("__PROMPT__", &case.prompt)
+        .replace("__RESPONSE__", output);
+    let mut input = Input::from_str(config, &prompt, Some(Role::default()));
+    input.set_model(judge_model.clone());
+    let (passed, detail) = match complete(config, input).await {
+        Ok(reply) => match parse_score(&reply) {
+            Some(score) => (
+                score >= min_score,
+                format!("score {score} (min {min_score})"),
+            ),
+            None => (
+                false,
+                format!(
+                    "unparsable score '{}'",
+                    reply.trim().lines().next().unwrap_or_default()
+                ),
+            ),
+        },
+        Err(err) => (false, format!("{err:?}")),
+    };
+    CheckResult {
+        kind: "judge",
+        passed,
+        detail,
+    }
+}
+
+fn check_assertion(assertion: &Assertion, output: &str) -> Vec<CheckResult> {
+    let mut checks = vec![];
+    if let Some(text) = &assertion.contains {
+        checks.push(CheckResult {
+            kind: "contains",
+            passed: output.contains(text.as_str()),
+            detail: format!("contains '{text}'"),
+        });
+    }
+    if let Some(pattern) = &assertion.regex {
+        let (passed, detail) = match Regex::new(pattern) {
+            Ok(re) => (
+                re.is_match(output).unwrap_or_default(),
+                format!("matches /{pattern}/"),
+            ),
+            Err(err) => (false, format!("invalid regex /{pattern}/, {err}")),
+        };
+        checks.push(CheckResult {
+            kind: "regex",
+            passed,
+            detail,
+        });
+    }
+    if let Some(expected) = &assertion.json {
+        let text = match CODE_BLOCK_RE.is_match(output) {
+            Ok(true) => extract_block(output),
+            _ => output.trim().to_string(),
+        };
+        let (passed, detail) = match serde_json::from_str::<Value>(&text) {
+            Ok(actual) => (
+                json_contains(&actual, expected),
+                format!("contains {expected}"),
+            ),
+            Err(err) => (false, format!("invalid JSON, {err}")),
+        };
+        checks.push(CheckResult {
+            kind: "json",
+            passed,
+            detail,
+        });
+    }
+    checks
+}
+
+/// Whether `actual` includes every field and item in `expected`.
+fn json_contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected
+            .iter()
+            .all(|(k, v)| actual.get(k).is_some_and(|a| json_contains(a, v))),
+        (Value::Array(actual), Value::Array(expected)) => expected
+            .iter()
+            .all(|v| actual.iter().any(|a| json_contains(a, v))),
+        _ => actual == expected,
+    }
+}
+
+fn parse_score(text: &str) -> Option<f64> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .find(|v| !v.is_empty())
+        .and_then(|v| v.trim_end_matches('.').parse().ok())
+}
+
+fn render_result(result: &EvalResult) -> String {
+    let status = if result.passed {
+        color_text("PASS", nu_ansi_term::Color::Green)
+    } else {
+        color_text("FAIL", nu_ansi_term::Color::Red)
+    };
+    let latency = Duration::from_millis(result.latency_ms as u64).as_secs_f64();
+    let mut line = format!(
+        "{status}  {:<24} {:<32} {latency:>6.2}s",
+        result.case, result.model
+    );
+    for check in result.checks.iter().filter(|v| !v.passed) {
+        line.push_str(&format!(
+            "\n      {}",
+            dimmed_text(&format!("✗ {}: {}", check.kind, check.detail))
+        ));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_assertion() {
+        let suite: EvalSuite = serde_yaml::from_str(
+            r#"
+cases:
+  - name: greeting
+    prompt: say hi in JSON
+    assert:
+      - contains: hello
+      - regex: "\"lang\":\\s*\"en\""
+      - json: { lang: en, tags: [a] }
+        contains: text
+"#,
+        )
+        .unwrap();
+        let output =
+            "```json\n{\"text\": \"hello\", \"lang\": \"en\", \"tags\": [\"b\", \"a\"]}\n```";
+        let assert = &suite.cases[0].assert;
+        let checks: Vec<_> = assert
+            .iter()
+            .flat_map(|v| check_assertion(v, output))
+            .collect();
+        assert_eq!(checks.len(), 4);
+        assert!(checks.iter().all(|v| v.passed));
+        let checks = check_assertion(&assert[2], "{\"lang\": \"fr\", \"text\": 1}");
+        assert_eq!(
+            checks.iter().map(|v| v.passed).collect::<Vec<_>>(),
+            [true, false]
+        );
+        assert!(!check_assertion(&assert[2], "not json")[1].passed);
+    }
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("8"), Some(8.0));
+        assert_eq!(parse_score("Score: 7.5."), Some(7.5));
+        assert_eq!(parse_score("none"), None);
+    }
+}
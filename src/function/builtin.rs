@@ -0,0 +1,302 @@
+use super::*;
+
+use crate::config::ensure_parent_exists;
+
+use inquire::Confirm;
+use reqwest::Method;
+
+const FS_LIST_LIMIT: usize = 1000;
+
+/// Builtins that write or reach the network, left out of `use_tools: all` unless named.
+pub const EXPLICIT_BUILTINS: [&str; 3] = ["fs_write", "http_get", "http_post"];
+
+/// Tools implemented in aichat itself, so they work without the llm-functions toolkit.
+pub fn builtin_declarations() -> Vec<FunctionDeclaration> {
+    let path_property = json!({
+        "type": "string",
+        "description": "Path of the file, absolute or relative to the current directory",
+    });
+    let url_property = json!({
+        "type": "string",
+        "description": "The http(s) URL",
+    });
+    let declarations = json!([
+        {
+            "name": "fs_read",
+            "description": "Read the contents of a text file.",
+            "parameters": {
+                "type": "object",
+                "properties": { "path": path_property },
+                "required": ["path"],
+            },
+        },
+        {
+            "name": "fs_write",
+            "description": "Write the contents to a file, creating it and its parent directories if needed. Replaces an existing file.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "path": path_property,
+                    "contents": {
+                        "type": "string",
+                        "description": "The full contents of the file",
+                    },
+                },
+                "required": ["path", "contents"],
+            },
+        },
+        {
+            "name": "fs_list",
+            "description": "List the files and directories in a directory.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path of the directory, absolute or relative to the current directory",
+                    },
+                },
+                "required": ["path"],
+            },
+        },
+        {
+            "name": "http_get",
+            "description": "Send a GET request and return the status and the response body.",
+            "parameters": {
+                "type": "object",
+                "properties": { "url": url_property },
+                "required": ["url"],
+            },
+        },
+        {
+            "name": "http_post",
+            "description": "Send a POST request and return the status and the response body.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "url": url_property,
+                    "body": {
+                        "type": "string",
+                        "description": "The request body",
+                    },
+                    "content_type": {
+                        "type": "string",
+                        "description": "The content type of the body, application/json by default",
+                    },
+                },
+                "required": ["url", "body"],
+            },
+        },
+    ]);
+    let mut declarations: Vec<FunctionDeclaration> =
+        serde_json::from_value(declarations).expect("valid declarations");
+    for declaration in declarations.iter_mut() {
+        declaration.builtin = true;
+    }
+    declarations
+}
+
+/// Runs a builtin tool, errors go back to the model so it can correct the call.
+pub fn eval_builtin(config: &GlobalConfig, name: &str, arguments: &Value) -> Result<Value> {
+    let (allowed_paths, max_output_bytes) = {
+        let config = config.read();
+        (
+            config.tool_allowed_paths.clone(),
+            config.tool_max_output_bytes,
+        )
+    };
+    let string_arg = |key: &str| -> Result<String> {
+        match arguments[key].as_str() {
+            Some(v) => Ok(v.to_string()),
+            None => bail!("Missing the argument '{key}'"),
+        }
+    };
+    let ret = match name {
+        "fs_read" => string_arg("path").and_then(|path| {
+            let path = allowed_path(&path, &allowed_paths)?;
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?;
+            let contents = truncate_output(&contents, max_output_bytes).unwrap_or(contents);
+            Ok(json!({ "contents": contents }))
+        }),
+        "fs_write" => string_arg("path").and_then(|path| {
+            let contents = string_arg("contents")?;
+            let path = allowed_path(&path, &allowed_paths)?;
+            confirm_builtin(
+                &format!(
+                    "fs_write: write {} bytes to '{}'",
+                    contents.len(),
+                    path.display()
+                ),
+                true,
+            )?;
+            ensure_parent_exists(&path)?;
+            fs::write(&path, &contents)
+                .with_context(|| format!("Failed to write '{}'", path.display()))?;
+            Ok(json!({ "written": contents.len() }))
+        }),
+        "fs_list" => string_arg("path").and_then(|path| {
+            let path = allowed_path(&path, &allowed_paths)?;
+            let mut entries = vec![];
+            for entry in fs::read_dir(&path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?
+            {
+                let entry = entry?;
+                let is_dir = entry.file_type()?.is_dir();
+                let name = entry.file_name().to_string_lossy().to_string();
+                entries.push(if is_dir { format!("{name}/") } else { name });
+            }
+            entries.sort();
+            let truncated = entries.len() > FS_LIST_LIMIT;
+            entries.truncate(FS_LIST_LIMIT);
+            Ok(json!({ "entries": entries, "truncated": truncated }))
+        }),
+        "http_get" => string_arg("url").and_then(|url| {
+            confirm_builtin(&format!("http_get: fetch '{url}'"), false)?;
+            run_http_request(Method::GET, &url, None, max_output_bytes)
+        }),
+        "http_post" => string_arg("url").and_then(|url| {
+            let body = string_arg("body")?;
+            let content_type = arguments["content_type"]
+                .as_str()
+                .unwrap_or("application/json")
+                .to_string();
+            confirm_builtin(
+                &format!("http_post: send {} bytes to '{url}'", body.len()),
+                true,
+            )?;
+            run_http_request(
+                Method::POST,
+                &url,
+                Some((content_type, body)),
+                max_output_bytes,
+            )
+        }),
+        _ => bail!("Unexpected builtin call: {name}"),
+    };
+    Ok(ret.unwrap_or_else(|err| json!({ "error": format!("{err:#}") })))
+}
+
+fn run_http_request(
+    method: Method,
+    url: &str,
+    body: Option<(String, String)>,
+    max_output_bytes: usize,
+) -> Result<Value> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        bail!("Only http(s) URLs are supported");
+    }
+    let (status, text) =
+        tokio::runtime::Handle::current().block_on(http_request(method, url, body))?;
+    let text = truncate_output(&text, max_output_bytes).unwrap_or(text);
+    Ok(json!({ "status": status, "body": text }))
+}
+
+/// Asks before a builtin tool acts, when there is someone to ask.
+/// Without a terminal, the calls that change something are refused.
+fn confirm_builtin(action: &str, mutating: bool) -> Result<()> {
+    if !*IS_STDOUT_TERMINAL {
+        if mutating {
+            bail!("Refused '{action}', there is no terminal to confirm it");
+        }
+        return Ok(());
+    }
+    println!("{}", dimmed_text(action));
+    let ans = Confirm::new(&tr("Allow it?")).with_default(true).prompt()?;
+    if !ans {
+        bail!("The user denied it");
+    }
+    Ok(())
+}
+
+/// Resolves `path` and checks it is inside one of `tool_allowed_paths`,
+/// the current directory when none are set.
+fn allowed_path(path: &str, allowed_paths: &[String]) -> Result<PathBuf> {
+    let current_dir = std::env::current_dir()?;
+    let resolve = |path: &str| match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(path), Some(home)) => home.join(path),
+        _ => current_dir.join(path),
+    };
+    let target = canonicalize_lenient(&resolve(path));
+    let roots: Vec<PathBuf> = if allowed_paths.is_empty() {
+        vec![canonicalize_lenient(&current_dir)]
+    } else {
+        allowed_paths
+            .iter()
+            .map(|v| canonicalize_lenient(&resolve(v)))
+            .collect()
+    };
+    if !roots.iter().any(|root| target.starts_with(root)) {
+        bail!(
+            "Access to '{}' is not allowed, it must be inside {}",
+            target.display(),
+            roots
+                .iter()
+                .map(|v| format!("'{}'", v.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(target)
+}
+
+/// Resolves `..` and canonicalizes the longest existing ancestor of `path`,
+/// so paths to be created are checked with symlinks resolved too.
+fn canonicalize_lenient(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            std::path::Component::CurDir => {}
+            _ => normalized.push(component),
+        }
+    }
+    let mut rest = vec![];
+    let mut current = normalized.clone();
+    loop {
+        if let Ok(real) = current.canonicalize() {
+            let mut output = real;
+            for part in rest.iter().rev() {
+                output.push(part);
+            }
+            return output;
+        }
+        match (current.file_name(), current.parent()) {
+            (Some(name), Some(parent)) => {
+                rest.push(name.to_os_string());
+                current = parent.to_path_buf();
+            }
+            _ => return normalized,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_path() {
+        let root = temp_file("-builtin-", "");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let allowed = vec![root.display().to_string()];
+        let inside = root.join("sub/new.txt").display().to_string();
+        assert!(allowed_path(&inside, &allowed).is_ok());
+        let escaped = root.join("sub/../../etc/passwd").display().to_string();
+        assert!(allowed_path(&escaped, &allowed).is_err());
+        assert!(allowed_path("/etc/passwd", &allowed).is_err());
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_explicit_builtins() {
+        let names: Vec<String> = builtin_declarations()
+            .into_iter()
+            .filter(|v| !v.is_explicit_only())
+            .map(|v| v.name)
+            .collect();
+        assert_eq!(names, vec!["fs_read", "fs_list"]);
+    }
+}
@@ -0,0 +1,149 @@
+use super::*;
+
+use crate::client::{Message, MessageContent, MessageRole};
+
+use fancy_regex::Regex;
+
+const TOOLS_PROMPT: &str = r#"# Tools
+
+You can call the following tools, given as JSON schemas:
+
+__TOOLS__
+
+To call tools, reply with one fenced block per call and stop there:
+
+```tool_call
+{"name": "<tool name>", "arguments": {<arguments>}}
+```
+
+The results come back in the next message. Only call a tool when you need it, otherwise answer directly."#;
+
+lazy_static::lazy_static! {
+    static ref PROMPT_TOOL_CALL_RE: Regex =
+        Regex::new(r"(?s)```tool_call\s*\n(.*?)```|<tool_call>(.*?)</tool_call>").unwrap();
+}
+
+/// Describes the tools in the system prompt, for models without native function calling.
+pub fn inject_tools_prompt(messages: &mut Vec<Message>, functions: &[FunctionDeclaration]) {
+    let tools: Vec<String> = functions
+        .iter()
+        .map(|v| {
+            json!({
+                "name": v.name,
+                "description": v.description,
+                "parameters": v.parameters,
+            })
+            .to_string()
+        })
+        .collect();
+    let prompt = TOOLS_PROMPT.replace("__TOOLS__", &tools.join("\n"));
+    match messages.first_mut() {
+        Some(Message {
+            role: MessageRole::System,
+            content: MessageContent::Text(text),
+        }) => {
+            text.push_str("\n\n");
+            text.push_str(&prompt);
+        }
+        _ => messages.insert(
+            0,
+            Message::new(MessageRole::System, MessageContent::Text(prompt)),
+        ),
+    }
+}
+
+/// Turns the tool call messages into plain text, the calls from the assistant and the results from the user.
+pub fn flatten_tool_call_messages(messages: Vec<Message>) -> Vec<Message> {
+    let mut output = vec![];
+    for message in messages {
+        let MessageContent::ToolCalls(tool_calls) = message.content else {
+            output.push(message);
+            continue;
+        };
+        let mut calls = vec![];
+        let text = PROMPT_TOOL_CALL_RE.replace_all(&tool_calls.text, "");
+        if !text.trim().is_empty() {
+            calls.push(text.trim().to_string());
+        }
+        let mut results = vec![];
+        for tool_result in &tool_calls.tool_results {
+            let call = json!({
+                "name": tool_result.call.name,
+                "arguments": tool_result.call.arguments,
+            });
+            calls.push(format!("```tool_call\n{call}\n```"));
+            let result = json!({
+                "name": tool_result.call.name,
+                "output": tool_result.output,
+            });
+            results.push(format!("```tool_result\n{result}\n```"));
+        }
+        output.push(Message::new(
+            MessageRole::Assistant,
+            MessageContent::Text(calls.join("\n\n")),
+        ));
+        output.push(Message::new(
+            MessageRole::User,
+            MessageContent::Text(format!("Tool results:\n\n{}", results.join("\n\n"))),
+        ));
+    }
+    output
+}
+
+/// The tool calls a model wrote in its reply as ```tool_call blocks or `<tool_call>` tags,
+/// keeping only the tools in `allowed_names`.
+pub fn parse_prompt_tool_calls(text: &str, allowed_names: &[String]) -> Vec<ToolCall> {
+    let mut calls = vec![];
+    for caps in PROMPT_TOOL_CALL_RE.captures_iter(text) {
+        let Ok(caps) = caps else {
+            continue;
+        };
+        let Some(body) = caps.get(1).or_else(|| caps.get(2)) else {
+            continue;
+        };
+        let Ok(value) = serde_json::from_str::<Value>(body.as_str().trim()) else {
+            warn!("Invalid tool call: {}", body.as_str());
+            continue;
+        };
+        let Some(name) = value["name"].as_str() else {
+            continue;
+        };
+        if !allowed_names.iter().any(|v| v == name) {
+            warn!("Ignored the call of '{name}', a tool that wasn't offered");
+            continue;
+        }
+        let arguments = match &value["arguments"] {
+            Value::Null => json!({}),
+            v => v.clone(),
+        };
+        calls.push(ToolCall::new(name.to_string(), arguments, None));
+    }
+    calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prompt_tool_calls() {
+        let text = r#"Let me check.
+
+```tool_call
+{"name": "get_weather", "arguments": {"city": "Paris"}}
+```
+
+<tool_call>{"name": "get_time"}</tool_call>"#;
+        let names = ["get_weather".to_string(), "get_time".to_string()];
+        let calls = parse_prompt_tool_calls(text, &names);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].name, "get_weather");
+        assert_eq!(calls[0].arguments, json!({"city": "Paris"}));
+        assert_eq!(calls[1].name, "get_time");
+        assert_eq!(calls[1].arguments, json!({}));
+        assert!(parse_prompt_tool_calls("```json\n{\"name\": \"x\"}\n```", &names).is_empty());
+        assert!(parse_prompt_tool_calls(text, &["get_time".to_string()])
+            .iter()
+            .all(|v| v.name == "get_time"));
+    }
+}
@@ -0,0 +1,134 @@
+use super::*;
+
+use crate::config::RoleLike;
+
+const TOOL_SUMMARY_PROMPT: &str = r#"Summarize the result of the tool `__TOOL__`, called with `__ARGUMENTS__`, for an AI assistant that will use it to answer the user.
+Keep every fact, number, name, path and URL that may matter, drop boilerplate and repetition. Reply with the summary only.
+
+<tool_result>
+__OUTPUT__
+</tool_result>"#;
+
+/// Shortens the results with more than `tool_result_max_tokens` tokens, summarizing the ones of
+/// the tools in `tool_result_summarize` and keeping the head and tail of the others.
+/// The full result is saved under `tool_results_dir` and referenced in its place.
+pub async fn shorten_tool_results(config: &GlobalConfig, results: &mut [ToolResult]) -> Result<()> {
+    let (max_tokens, summarize_tools, mapping_tools) = {
+        let config = config.read();
+        (
+            config.tool_result_max_tokens,
+            config.tool_result_summarize.clone(),
+            config.mapping_tools.clone(),
+        )
+    };
+    if max_tokens == 0 {
+        return Ok(());
+    }
+    for result in results.iter_mut() {
+        let text = match &result.output {
+            Value::String(v) => v.clone(),
+            v => v.to_string(),
+        };
+        let total_tokens = estimate_token_length(&text);
+        if total_tokens <= max_tokens {
+            continue;
+        }
+        let name = result.call.name.clone();
+        let full_result = match save_full_result(&name, &text) {
+            Ok(path) => Some(path.display().to_string()),
+            Err(err) => {
+                warn!("Failed to save the full result of '{name}', {err:#}");
+                None
+            }
+        };
+        let summarize = summarize_tools
+            .as_deref()
+            .is_some_and(|tools| is_tool_in(tools, &mapping_tools, &name));
+        let summary = if summarize {
+            match summarize_result(config, &result.call, &text).await {
+                Ok(v) => Some(v),
+                Err(err) => {
+                    warn!("Failed to summarize the result of '{name}', {err:#}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        result.output = match summary {
+            Some(summary) => json!({
+                "summary": summary,
+                "total_tokens": total_tokens,
+                "full_result": full_result,
+            }),
+            None => json!({
+                "content": head_tail(&text, max_tokens, total_tokens),
+                "total_tokens": total_tokens,
+                "full_result": full_result,
+            }),
+        };
+    }
+    Ok(())
+}
+
+fn save_full_result(name: &str, text: &str) -> Result<PathBuf> {
+    let dir = Config::tool_results_dir();
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let path = dir.join(format!(
+        "{}-{name}-{}.txt",
+        chrono::Local::now().format("%Y%m%dT%H%M%S"),
+        &id[..8]
+    ));
+    fs::write(&path, text).with_context(|| format!("Failed to write '{}'", path.display()))?;
+    Ok(path)
+}
+
+async fn summarize_result(config: &GlobalConfig, call: &ToolCall, text: &str) -> Result<String> {
+    let model = config.read().current_model().clone();
+    let prompt = TOOL_SUMMARY_PROMPT
+        .replace("__TOOL__", &call.name)
+        .replace("__ARGUMENTS__", &call.arguments.to_string())
+        .replace("__OUTPUT__", text);
+    let mut role = Role::default();
+    role.set_model(&model);
+    let input = Input::from_str(config, &prompt, Some(role));
+    let reply = input.create_client()?.chat_completions(input).await?;
+    Ok(reply.text)
+}
+
+/// Keeps about `max_tokens` worth of the text, half from the start and half from the end.
+fn head_tail(text: &str, max_tokens: usize, total_tokens: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let keep = chars.len() * max_tokens / total_tokens.max(1) / 2;
+    if keep * 2 >= chars.len() {
+        return text.to_string();
+    }
+    let head: String = chars[..keep].iter().collect();
+    let tail: String = chars[chars.len() - keep..].iter().collect();
+    format!(
+        "{head}\n[... {} of {} characters omitted ...]\n{tail}",
+        chars.len() - keep * 2,
+        chars.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_head_tail() {
+        let text = "a".repeat(50) + &"b".repeat(100) + &"c".repeat(50);
+        let output = head_tail(&text, 100, 200);
+        assert_eq!(
+            output,
+            format!(
+                "{}\n[... 100 of 200 characters omitted ...]\n{}",
+                "a".repeat(50),
+                "c".repeat(50)
+            )
+        );
+        assert_eq!(head_tail("short", 10, 5), "short");
+    }
+}
@@ -0,0 +1,312 @@
+use super::*;
+
+use crate::config::RoleLike;
+
+use fancy_regex::Regex;
+
+const LLM_FUNCTIONS_URL: &str = "https://github.com/sigoden/llm-functions";
+
+/// How long a tool may take to answer `--help` in `--check-tools`
+const CHECK_TOOL_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static::lazy_static! {
+    static ref TEXT_TOOL_CALL_RE: Regex =
+        Regex::new(r"(?s)<tool_call>(.*?)(?:</tool_call>|$)").unwrap();
+}
+
+/// Explains why a reply holds a `<tool_call>` block as text instead of a real tool call.
+pub fn diagnose_text_tool_calls(config: &Config, role: &Role, text: &str) -> Option<Vec<String>> {
+    let names = text_tool_call_names(text)?;
+    let mut hints = vec![];
+    if !config.function_calling {
+        hints.push("function_calling is off, turn it on with `.set function_calling true`".into());
+    }
+    let model = role.model();
+    if !model.capabilities().tools && config.function_calling_mode != "prompt" {
+        hints.push(format!(
+            "'{}' isn't configured with `supports_function_calling: true`, so no tools were sent and it wrote the call as text; use a model with native tool support or `.set function_calling_mode prompt`",
+            model.id()
+        ));
+    }
+    if !Config::functions_file().exists() {
+        hints.push(format!(
+            "No functions.json at '{}', only the builtin tools are available; see {LLM_FUNCTIONS_URL}",
+            Config::functions_file().display()
+        ));
+    }
+    let selected: Vec<String> = config
+        .select_functions(role)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| v.name)
+        .collect();
+    if selected.is_empty() {
+        hints.push("No tools are in use, set use_tools (e.g. `.set use_tools all`)".into());
+    }
+    for name in names {
+        if !selected.is_empty() && !selected.contains(&name) {
+            hints.push(unknown_tool_hint(config, &name));
+        }
+    }
+    hints.push("Run `aichat --check-tools` for details".into());
+    Some(hints)
+}
+
+/// Why a tool the model called isn't available.
+pub fn unknown_tool_hint(config: &Config, name: &str) -> String {
+    let declared = config.functions.contains(name)
+        || config
+            .agent
+            .as_ref()
+            .is_some_and(|v| v.functions().contains(name));
+    if declared {
+        format!("'{name}' is installed but not in use_tools")
+    } else {
+        format!("'{name}' isn't an installed tool")
+    }
+}
+
+/// The names in `<tool_call>` blocks, the block itself when it holds no JSON name.
+fn text_tool_call_names(text: &str) -> Option<Vec<String>> {
+    let mut names = vec![];
+    for caps in TEXT_TOOL_CALL_RE.captures_iter(text) {
+        let Ok(caps) = caps else {
+            continue;
+        };
+        let body = caps.get(1).map(|v| v.as_str().trim()).unwrap_or_default();
+        let name = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|v| v["name"].as_str().map(|v| v.to_string()));
+        if let Some(name) = name {
+            names.push(name);
+        }
+    }
+    if names.is_empty() && !text.contains("<tool_call>") {
+        return None;
+    }
+    Some(names)
+}
+
+/// Checks every piece function calling depends on, for `--check-tools`.
+pub fn check_tools(config: &Config, role: &Role) -> String {
+    let mut lines = vec![];
+    let mut check = |ok: bool, text: String| {
+        let mark = if ok { "✓" } else { "✗" };
+        lines.push(format!("{mark} {text}"));
+    };
+    check(
+        config.function_calling,
+        format!("function_calling: {}", config.function_calling),
+    );
+    let model = role.model();
+    let prompt_tools = config.function_calling_mode == "prompt";
+    check(
+        model.capabilities().tools || prompt_tools,
+        format!(
+            "model '{}' supports_function_calling: {}{}",
+            model.id(),
+            model.capabilities().tools,
+            if prompt_tools && !model.capabilities().tools {
+                ", the tools go in the prompt"
+            } else {
+                ""
+            }
+        ),
+    );
+    let functions_file = Config::functions_file();
+    check(
+        functions_file.exists(),
+        match functions_file.exists() {
+            true => format!("functions.json at '{}'", functions_file.display()),
+            false => format!(
+                "no functions.json at '{}', see {LLM_FUNCTIONS_URL}",
+                functions_file.display()
+            ),
+        },
+    );
+    let use_tools = role.use_tools();
+    let selected: Vec<String> = config
+        .select_functions(role)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| v.name)
+        .collect();
+    check(
+        !selected.is_empty(),
+        format!(
+            "use_tools: {} ({} selected)",
+            use_tools.as_deref().unwrap_or("-"),
+            selected.len()
+        ),
+    );
+    if let Some(use_tools) = use_tools.filter(|v| v != "all") {
+        for item in use_tools.split(',').map(|v| v.trim()) {
+            if !config.mapping_tools.contains_key(item) && !config.functions.contains(item) {
+                check(
+                    false,
+                    format!("use_tools item '{item}' is neither a tool nor in mapping_tools"),
+                );
+            }
+        }
+    }
+    let mut declarations: Vec<(Option<&str>, &FunctionDeclaration)> = config
+        .functions
+        .declarations()
+        .iter()
+        .map(|v| (None, v))
+        .collect();
+    if let Some(agent) = &config.agent {
+        declarations.extend(
+            agent
+                .functions()
+                .declarations()
+                .iter()
+                .map(|v| (Some(agent.name()), v)),
+        );
+    }
+    for (agent_name, declaration) in declarations {
+        let name = &declaration.name;
+        let scope = if selected.contains(name) {
+            ""
+        } else {
+            ", not in use"
+        };
+        if declaration.builtin {
+            check(true, format!("tool '{name}': builtin{scope}"));
+            continue;
+        }
+        let (bin_dir, cmd_name) = match agent_name {
+            Some(agent_name) => (
+                Config::agent_functions_dir(agent_name).join("bin"),
+                if declaration.agent { agent_name } else { name },
+            ),
+            None => (Config::functions_bin_dir(), name.as_str()),
+        };
+        match find_tool_bin(&bin_dir, cmd_name) {
+            Some(path) if !is_executable(&path) => check(
+                false,
+                format!("tool '{name}': '{}' isn't executable", path.display()),
+            ),
+            Some(path) => match missing_interpreter(&path) {
+                Some(interpreter) => check(
+                    false,
+                    format!(
+                        "tool '{name}': '{}' needs '{interpreter}', which isn't installed",
+                        path.display()
+                    ),
+                ),
+                None => match try_run_tool(&path) {
+                    Ok(()) => check(true, format!("tool '{name}': {}{scope}", path.display())),
+                    Err(err) => check(false, format!("tool '{name}': '{}' {err}", path.display())),
+                },
+            },
+            None => check(
+                false,
+                format!(
+                    "tool '{name}': no '{cmd_name}' in '{}', rebuild the tools",
+                    bin_dir.display()
+                ),
+            ),
+        }
+    }
+    lines.join("\n")
+}
+
+/// Runs the tool with `--help` and nothing on stdin, it passes if it exits in time whatever its code.
+fn try_run_tool(path: &Path) -> Result<(), String> {
+    let mut child = Command::new(path)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("can't run, {err}"))?;
+    let started_at = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => return Ok(()),
+            Ok(None) if started_at.elapsed() < CHECK_TOOL_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(50))
+            }
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "didn't exit within {}s of running with --help",
+                    CHECK_TOOL_TIMEOUT.as_secs()
+                ));
+            }
+            Err(err) => return Err(format!("can't run, {err}")),
+        }
+    }
+}
+
+fn find_tool_bin(bin_dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = bin_dir.join(name);
+    if path.is_file() {
+        return Some(path);
+    }
+    #[cfg(windows)]
+    if let Ok(exts) = std::env::var("PATHEXT") {
+        for ext in exts.split(';') {
+            let path = bin_dir.join(format!("{name}{ext}"));
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// The interpreter named in the script's shebang when it can't be found.
+fn missing_interpreter(path: &Path) -> Option<String> {
+    let mut line = String::new();
+    let file = std::fs::File::open(path).ok()?;
+    std::io::BufRead::read_line(&mut std::io::BufReader::new(file), &mut line).ok()?;
+    let mut parts = line.strip_prefix("#!")?.split_whitespace();
+    let mut interpreter = parts.next()?;
+    if interpreter.ends_with("/env") {
+        interpreter = parts.find(|v| !v.starts_with('-'))?;
+    }
+    let found = if interpreter.contains('/') {
+        Path::new(interpreter).exists()
+    } else {
+        std::env::var_os("PATH")
+            .is_some_and(|v| std::env::split_paths(&v).any(|dir| dir.join(interpreter).is_file()))
+    };
+    (!found).then(|| interpreter.to_string())
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .map(|v| v.permissions().mode() & 0o111 != 0)
+            .unwrap_or_default()
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_tool_call_names() {
+        assert_eq!(text_tool_call_names("no calls here"), None);
+        assert_eq!(
+            text_tool_call_names(
+                "Let me check.\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}\n</tool_call>"
+            ),
+            Some(vec!["get_weather".into()])
+        );
+        assert_eq!(
+            text_tool_call_names("<tool_call>get_weather(city=Paris)"),
+            Some(vec![])
+        );
+    }
+}
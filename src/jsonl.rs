@@ -0,0 +1,128 @@
+use crate::client::ChatCompletionsOutput;
+use crate::config::{GlobalConfig, Input, PromptTemplate};
+use crate::function::eval_tool_calls;
+use crate::utils::*;
+
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{stdin, stdout, BufRead, Write};
+use tokio::sync::mpsc::unbounded_channel;
+
+/// One line of `--jsonl` input.
+#[derive(Debug, Deserialize)]
+struct JsonlRequest {
+    #[serde(default)]
+    id: Option<Value>,
+    text: String,
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    vars: IndexMap<String, String>,
+}
+
+/// Reads JSON requests line by line from stdin and prints one JSON result per line, in order.
+///
+/// The process stays up until stdin is closed, so the HTTP connections are reused across requests.
+pub async fn run(
+    config: &GlobalConfig,
+    template: Option<&PromptTemplate>,
+    variables: &IndexMap<String, String>,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    let (tx, mut rx) = unbounded_channel();
+    std::thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let stop = line.is_err();
+            if tx.send(line).is_err() || stop {
+                break;
+            }
+        }
+    });
+    while let Some(line) = rx.recv().await {
+        let line = line.context("Failed to read stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, ret) = match serde_json::from_str::<JsonlRequest>(&line) {
+            Ok(request) => {
+                let id = request.id.clone();
+                tokio::select! {
+                    ret = process(config, request, template, variables, abort_signal.clone()) => (id, ret),
+                    _ = wait_abort_signal(&abort_signal) => break,
+                }
+            }
+            Err(err) => (None, Err(err).context("Invalid request")),
+        };
+        let mut output = json!({});
+        if let Some(id) = id {
+            output["id"] = id;
+        }
+        match ret {
+            Ok(ret) => {
+                output["text"] = ret.text.into();
+                output["input_tokens"] = ret.input_tokens.into();
+                output["output_tokens"] = ret.output_tokens.into();
+            }
+            Err(err) => output["error"] = format!("{err:#}").into(),
+        }
+        let mut stdout = stdout().lock();
+        writeln!(stdout, "{output}")?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+async fn process(
+    config: &GlobalConfig,
+    request: JsonlRequest,
+    template: Option<&PromptTemplate>,
+    variables: &IndexMap<String, String>,
+    abort_signal: AbortSignal,
+) -> Result<ChatCompletionsOutput> {
+    let JsonlRequest {
+        text, role, vars, ..
+    } = request;
+    let mut variables = variables.clone();
+    variables.extend(vars);
+    let text = match template {
+        Some(template) => {
+            let prompt = template.render(&variables)?;
+            if text.is_empty() {
+                prompt
+            } else {
+                format!("{prompt}\n\n{text}")
+            }
+        }
+        None if !variables.is_empty() => PromptTemplate {
+            body: text,
+            ..Default::default()
+        }
+        .render(&variables)?,
+        None => text,
+    };
+    let role = match role {
+        Some(name) => Some(config.read().retrieve_role(&name)?),
+        None => None,
+    };
+    let mut input = Input::from_str(config, &text, role);
+    input.use_embeddings(abort_signal).await?;
+    let client = input.create_client()?;
+    let mut input_tokens = 0;
+    let mut output_tokens = 0;
+    loop {
+        let output = client.chat_completions(input.clone()).await?;
+        input_tokens += output.input_tokens.unwrap_or_default();
+        output_tokens += output.output_tokens.unwrap_or_default();
+        let tool_results = eval_tool_calls(config, &input, output.tool_calls).await?;
+        if tool_results.is_empty() {
+            return Ok(ChatCompletionsOutput {
+                input_tokens: Some(input_tokens).filter(|v| *v > 0),
+                output_tokens: Some(output_tokens).filter(|v| *v > 0),
+                ..ChatCompletionsOutput::new(&output.text)
+            });
+        }
+        input = input.merge_tool_results(output.text, tool_results);
+    }
+}
@@ -1,11 +1,17 @@
+mod batch;
 mod cli;
 mod client;
 mod config;
+mod eval;
 mod function;
+mod jsonl;
+mod map;
 mod rag;
 mod render;
 mod repl;
+mod schedule;
 mod serve;
+mod telemetry;
 #[macro_use]
 mod utils;
 
@@ -14,53 +20,124 @@ extern crate log;
 
 use crate::cli::Cli;
 use crate::client::{
-    call_chat_completions, call_chat_completions_streaming, list_models, ModelType,
+    call_chat_completions, call_chat_completions_streaming, compare_models, list_models,
+    models_json, render_models_table, sync_models, ModelType,
 };
 use crate::config::{
-    ensure_parent_exists, list_agents, load_env_file, Config, GlobalConfig, Input, WorkingMode,
-    CODE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
+    bug_report, ensure_parent_exists, export_profile, import_profile, install_agent, list_agents,
+    load_env_file, parse_template_variables, remove_agent, run_agent_pipeline, run_cleanup,
+    update_agent, Config, GlobalConfig, Input, PromptTemplate, State, WorkingMode, CODE_ROLE,
+    EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
 };
+use crate::function::{check_tools, eval_tool_calls};
 use crate::render::render_error;
-use crate::repl::Repl;
+use crate::repl::{run_repl_stdin, Repl};
+use crate::schedule::Schedule;
 use crate::utils::*;
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
 use inquire::validator::Validation;
 use inquire::Text;
 use is_terminal::IsTerminal;
 use parking_lot::RwLock;
+use serde_json::{json, Value};
 use simplelog::{format_description, ConfigBuilder, LevelFilter, SimpleLogger, WriteLogger};
 use std::{
     env,
     io::{stdin, Read},
+    path::Path,
     process,
     sync::Arc,
+    time::Instant,
 };
 
 #[tokio::main]
 async fn main() -> Result<()> {
     load_env_file()?;
     let cli = Cli::parse();
+    set_offline(cli.offline);
+    if cli.init {
+        return Config::init_config_file(cli.platform.as_deref(), cli.model.as_deref(), cli.yes);
+    }
+    if cli.sync_models {
+        return sync_models(cli.merge_local).await;
+    }
+    if let Some(path) = &cli.export_profile {
+        return export_profile(path, cli.exclude_keys);
+    }
+    if let Some(path) = &cli.import_profile {
+        return import_profile(path);
+    }
+    if let Some(source) = &cli.install_agent {
+        return install_agent(source);
+    }
+    if let Some(name) = &cli.update_agent {
+        return update_agent(name);
+    }
+    if let Some(name) = &cli.remove_agent {
+        return remove_agent(name);
+    }
+    if cli.bug_report {
+        print!("{}", bug_report());
+        return Ok(());
+    }
     let text = cli.text();
-    let text = aggregate_text(text)?;
+    let (text, stdin_text) = if cli.jsonl || cli.repl_stdin {
+        (text, None)
+    } else {
+        aggregate_text(text)?
+    };
     let working_mode = if cli.serve.is_some() {
         WorkingMode::Serve
-    } else if text.is_none() && cli.file.is_empty() {
+    } else if cli.repl
+        || (text.is_none()
+            && cli.file.is_empty()
+            && cli.template.is_none()
+            && !cli.jsonl
+            && !cli.repl_stdin)
+    {
         WorkingMode::Repl
     } else {
         WorkingMode::Cmd
     };
     setup_logger(working_mode.is_serve())?;
     let config = Arc::new(RwLock::new(Config::init(working_mode)?));
-    if let Err(err) = run(config, cli, text).await {
-        render_error(err);
+    setup_panic_hook(&config)?;
+    if !cli.cleanup && !config.read().cleanup.is_empty() {
+        let config = config.read();
+        match run_cleanup(&config, &config.cleanup) {
+            Ok(report) => debug!("{report}"),
+            Err(err) => warn!("Failed to clean up, {err:#}"),
+        }
+    }
+    let otel_endpoint = config.read().otel_endpoint.clone();
+    if let Some(endpoint) = otel_endpoint {
+        telemetry::init(&endpoint)?;
+    }
+    let json_format = cli.is_json_format();
+    let ret = run(config.clone(), cli, text, stdin_text).await;
+    config.write().finish_agent();
+    schedule::wait_webhooks().await;
+    telemetry::shutdown().await;
+    if let Err(err) = ret {
+        log::error!("{err:#}");
+        if json_format {
+            eprintln!("{}", json!({ "error": format!("{err:#}") }));
+        } else {
+            render_error(err);
+        }
         std::process::exit(1);
     }
     Ok(())
 }
 
-async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()> {
+async fn run(
+    config: GlobalConfig,
+    cli: Cli,
+    mut text: Option<String>,
+    stdin_text: Option<String>,
+) -> Result<()> {
     let abort_signal = create_abort_signal();
 
     if let Some(addr) = cli.serve {
@@ -69,10 +146,31 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
     if cli.info {
         config.write().cli_info_flag = true;
     }
+    if cli.cleanup {
+        let config = config.read();
+        let report = run_cleanup(&config, &config.cleanup.or_defaults())?;
+        println!("{report}");
+        return Ok(());
+    }
 
-    if cli.list_models {
-        for model in list_models(&config.read(), ModelType::Chat) {
-            println!("{}", model.id());
+    if let Some(pattern) = &cli.list_models {
+        let config = config.read();
+        let models: Vec<_> = list_models(&config, ModelType::Chat)
+            .into_iter()
+            .filter(|v| match pattern {
+                Some(pattern) if pattern.contains('*') => wildcard_match(&v.id(), pattern),
+                Some(pattern) => v.id().contains(pattern.as_str()),
+                None => true,
+            })
+            .collect();
+        if cli.is_json_format() {
+            println!("{}", serde_json::to_string_pretty(&models_json(&models))?);
+        } else if cli.detail {
+            println!("{}", render_models_table(&models));
+        } else {
+            for model in models {
+                println!("{}", model.id());
+            }
         }
         return Ok(());
     }
@@ -81,6 +179,9 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         println!("{roles}");
         return Ok(());
     }
+    if cli.dedupe_roles {
+        return config.read().dedupe_roles();
+    }
     if cli.list_agents {
         let agents = list_agents().join("\n");
         println!("{agents}");
@@ -91,10 +192,27 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         println!("{rags}");
         return Ok(());
     }
-    if cli.dry_run {
-        config.write().dry_run = true;
+    if let Some(name) = &cli.show_session {
+        let output = config.read().view_session(name)?;
+        print!("{output}");
+        return Ok(());
+    }
+    if let Some(mode) = &cli.dry_run {
+        let mut config = config.write();
+        config.dry_run = true;
+        config.cli_dry_run_request = mode.is_some();
+    }
+    if let Some(suite) = &cli.eval {
+        return eval::run(&config, suite, abort_signal).await;
     }
 
+    let template = match &cli.template {
+        Some(value) if cli.map.is_some() => Some(PromptTemplate::load_or_inline(value)?),
+        Some(name) => Some(PromptTemplate::load(name)?),
+        None => None,
+    };
+    let template_variables = parse_template_variables(&cli.var)?;
+
     if let Some(agent) = &cli.agent {
         let session = cli.session.as_ref().map(|v| match v {
             Some(v) => v.as_str(),
@@ -108,12 +226,20 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
                     .collect(),
             );
         }
+        if let Some(model_id) = &cli.model {
+            // Lets the agent start on it when the default model isn't in its allowed_models
+            config.write().set_model(model_id)?;
+        }
 
         Config::use_agent(&config, agent, session, abort_signal.clone()).await?
     } else {
         if let Some(prompt) = &cli.prompt {
             config.write().use_prompt(prompt)?;
-        } else if let Some(name) = &cli.role {
+        } else if let Some(name) = cli
+            .role
+            .as_ref()
+            .or_else(|| template.as_ref().and_then(|v| v.role.as_ref()))
+        {
             config.write().use_role(name)?;
         } else if cli.execute {
             config.write().use_role(SHELL_ROLE)?;
@@ -136,10 +262,21 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
     }
     if let Some(model_id) = &cli.model {
         config.write().set_model(model_id)?;
+        State::record_model(config.read().current_model());
+    } else if let Some(model_id) = template.as_ref().and_then(|v| v.model.as_ref()) {
+        config.write().set_model(model_id)?;
+    }
+    if let Some(temperature) = template.as_ref().and_then(|v| v.temperature) {
+        config.write().set_temperature(Some(temperature));
     }
     if cli.no_stream {
         config.write().stream = false;
     }
+    if let Some(format) = &cli.stream_raw {
+        let mut config = config.write();
+        config.stream = true;
+        config.cli_stream_raw = Some(format.clone().unwrap_or_else(|| "text".into()));
+    }
     if cli.empty_session {
         config.write().empty_session()?;
     }
@@ -151,6 +288,60 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         println!("{}", info);
         return Ok(());
     }
+    if cli.check_tools {
+        let config = config.read();
+        println!("{}", check_tools(&config, &config.extract_role()));
+        return Ok(());
+    }
+    if let Some(jobs) = &cli.batch {
+        return batch::run(&config, jobs, abort_signal).await;
+    }
+    if cli.jsonl {
+        return jsonl::run(
+            &config,
+            template.as_ref(),
+            &template_variables,
+            abort_signal,
+        )
+        .await;
+    }
+    if cli.repl_stdin {
+        config.write().apply_prelude()?;
+        return run_repl_stdin(&config, abort_signal).await;
+    }
+    if let (Some(pattern), Some(template), Some(out_dir)) = (&cli.map, &template, &cli.out_dir) {
+        return map::run(
+            &config,
+            pattern,
+            template,
+            template_variables,
+            out_dir,
+            cli.concurrency,
+            abort_signal,
+        )
+        .await;
+    }
+    if let Some(template) = &template {
+        let prompt = template.render(&template_variables)?;
+        text = Some(match text {
+            Some(text) => format!("{prompt}\n\n{text}"),
+            None => prompt,
+        });
+    }
+    if let Some(cron) = cli.cron {
+        let Some(prompt) = text else {
+            bail!("No prompt to run with --cron");
+        };
+        let schedule = Schedule {
+            name: cli.agent.or(cli.role).unwrap_or_else(|| "cron".into()),
+            cron,
+            prompt,
+            sink: cli.sink,
+            ..Default::default()
+        };
+        let daemonize = cli.daemonize.then_some(stdin_text);
+        return schedule::run(&config, schedule, daemonize).await;
+    }
     let is_repl = config.read().working_mode.is_repl();
     if cli.rebuild_rag {
         Config::rebuild_rag(&config, abort_signal.clone()).await?;
@@ -171,32 +362,127 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         false => {
             let mut input = create_input(&config, text, &cli.file, abort_signal.clone()).await?;
             input.use_embeddings(abort_signal.clone()).await?;
-            start_directive(&config, input, cli.code, abort_signal).await
+            if !cli.models.is_empty() {
+                return compare_models(&config, &input, &cli.models, abort_signal).await;
+            }
+            if cli.is_json_format() {
+                let output = json_directive(&config, input, abort_signal).await?;
+                println!("{}", serde_json::to_string_pretty(&output)?);
+                return Ok(());
+            }
+            let output_file = cli.output.map(|path| OutputFile {
+                path,
+                extract_code: cli.extract_code.or(cli.code.then_some(None)),
+            });
+            start_directive(&config, input, cli.code, output_file.as_ref(), abort_signal).await
         }
         true => {
+            if !cli.models.is_empty() {
+                bail!("No input to compare models with")
+            }
+            if cli.is_json_format() {
+                bail!("No input to answer with --format json")
+            }
             if !*IS_STDOUT_TERMINAL {
-                bail!("No TTY for REPL")
+                bail!("No TTY for REPL, use --repl-stdin to drive it through a pipe")
+            }
+            let seed = if text.is_some() || !cli.file.is_empty() {
+                // Follow-ups to the first message need its context
+                config.write().ensure_repl_session()?;
+                Some(create_input(&config, text, &cli.file, abort_signal.clone()).await?)
+            } else {
+                None
+            };
+            start_interactive(&config, seed).await
+        }
+    }
+}
+
+/// Where `-o/--output` writes the final answer.
+struct OutputFile {
+    path: String,
+    /// Keeps only the first code block, of the given language if any.
+    extract_code: Option<Option<String>>,
+}
+
+impl OutputFile {
+    fn write(&self, text: &str) -> Result<()> {
+        let (language, mut content) = match &self.extract_code {
+            Some(Some(language)) => extract_code_block(text, Some(language))
+                .ok_or_else(|| anyhow!("No {language} code block in the answer"))?,
+            Some(None) => extract_code_block(text, None)
+                .unwrap_or_else(|| (String::new(), strip_think_tag(text).trim().to_string())),
+            None => (String::new(), text.to_string()),
+        };
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        let path = Path::new(&self.path);
+        ensure_parent_exists(path)?;
+        std::fs::write(path, &content)
+            .with_context(|| format!("Failed to write '{}'", path.display()))?;
+        if is_shell_script(&language, path, &content) {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
             }
-            start_interactive(&config).await
         }
+        eprintln!("{}", dimmed_text(&format!("Saved to '{}'", path.display())));
+        Ok(())
     }
 }
 
+fn is_shell_script(language: &str, path: &Path, content: &str) -> bool {
+    let is_shell = |v: &str| matches!(v, "sh" | "bash" | "zsh" | "shell" | "fish");
+    is_shell(language)
+        || path
+            .extension()
+            .is_some_and(|v| is_shell(&v.to_string_lossy()))
+        || content.starts_with("#!")
+}
+
 #[async_recursion::async_recursion]
 async fn start_directive(
     config: &GlobalConfig,
     input: Input,
     code_mode: bool,
+    output_file: Option<&'async_recursion OutputFile>,
     abort_signal: AbortSignal,
 ) -> Result<()> {
+    if !Config::confirm_preview(config, &input)? {
+        return Ok(());
+    }
+    if let Some(output) = run_agent_pipeline(config, &input, abort_signal.clone()).await? {
+        if let Some(output_file) = output_file {
+            output_file.write(&output)?;
+        }
+        schedule::notify_complete(config, &input, &output).await;
+        config.write().exit_session()?;
+        return Ok(());
+    }
     let client = input.create_client()?;
-    let extract_code = !*IS_STDOUT_TERMINAL && code_mode;
+    let extract_code = !*IS_STDOUT_TERMINAL && code_mode && output_file.is_none();
     config.write().before_chat_completion(&input)?;
     let (output, tool_results) = if !input.stream() || extract_code {
         call_chat_completions(&input, extract_code, client.as_ref(), abort_signal.clone()).await?
     } else {
         call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
     };
+    // The raw stream has already been written to stdout and can't be replaced
+    let stream_raw = config.read().cli_stream_raw.is_some();
+    if let Some(language) = input
+        .mismatched_language(&output)
+        .filter(|_| tool_results.is_empty() && !stream_raw)
+    {
+        eprintln!(
+            "{}",
+            warning_text(&format!("The answer isn't in {language}, asking again"))
+        );
+        let mut input = input;
+        input.enforce_language(language);
+        return start_directive(config, input, code_mode, output_file, abort_signal).await;
+    }
     config
         .write()
         .after_chat_completion(&input, &output, &tool_results)?;
@@ -206,18 +492,76 @@ async fn start_directive(
             config,
             input.merge_tool_results(output, tool_results),
             code_mode,
+            output_file,
             abort_signal,
         )
         .await?;
+    } else {
+        if let Some(output_file) = output_file {
+            output_file.write(&output)?;
+        }
+        schedule::notify_complete(config, &input, &output).await;
     }
 
     config.write().exit_session()?;
     Ok(())
 }
 
-async fn start_interactive(config: &GlobalConfig) -> Result<()> {
+/// Runs the prompt and its tool calls without rendering, collecting the result for `--format json`.
+async fn json_directive(
+    config: &GlobalConfig,
+    mut input: Input,
+    abort_signal: AbortSignal,
+) -> Result<Value> {
+    let started_at = Instant::now();
+    let client = input.create_client()?;
+    let mut tool_calls = vec![];
+    let mut usage: Option<(u64, u64)> = Some((0, 0));
+    let mut requests = vec![];
+    let text = loop {
+        config.write().before_chat_completion(&input)?;
+        let output = tokio::select! {
+            ret = client.chat_completions(input.clone()) => ret?,
+            _ = wait_abort_signal(&abort_signal) => bail!("Aborted"),
+        };
+        usage =
+            usage.and_then(|(i, o)| Some((i + output.input_tokens?, o + output.output_tokens?)));
+        requests.extend(output.request_ids.as_ref().map(|v| v.to_json()));
+        let tool_results = eval_tool_calls(config, &input, output.tool_calls).await?;
+        config
+            .write()
+            .after_chat_completion(&input, &output.text, &tool_results)?;
+        if tool_results.is_empty() {
+            break output.text;
+        }
+        tool_calls.extend(tool_results.iter().map(|v| {
+            json!({
+                "id": v.call.id,
+                "name": v.call.name,
+                "arguments": v.call.arguments,
+                "output": v.output,
+            })
+        }));
+        input = input.merge_tool_results(output.text, tool_results);
+    };
+    schedule::notify_complete(config, &input, &text).await;
+    config.write().exit_session()?;
+    Ok(json!({
+        "model": client.model().id(),
+        "text": text,
+        "tool_calls": tool_calls,
+        "usage": usage.map(|(input_tokens, output_tokens)| json!({
+            "input_tokens": input_tokens,
+            "output_tokens": output_tokens,
+        })),
+        "requests": requests,
+        "elapsed_ms": started_at.elapsed().as_millis() as u64,
+    }))
+}
+
+async fn start_interactive(config: &GlobalConfig, seed: Option<Input>) -> Result<()> {
     let mut repl: Repl = Repl::init(config)?;
-    repl.run().await
+    repl.run(seed).await
 }
 
 #[async_recursion::async_recursion]
@@ -235,7 +579,7 @@ async fn shell_execute(
         abort_signal.clone(),
     )
     .await;
-    let mut eval_str = ret?.text;
+    let mut eval_str = strip_think_tag(&ret?.text).to_string();
     if let Ok(true) = CODE_BLOCK_RE.is_match(&eval_str) {
         eval_str = extract_block(&eval_str);
     }
@@ -282,7 +626,7 @@ async fn shell_execute(
                     process::exit(code);
                 }
                 "r" => {
-                    let revision = Text::new("Enter your revision:").prompt()?;
+                    let revision = Text::new(&tr("Enter your revision:")).prompt()?;
                     let text = format!("{}\n{revision}", input.text());
                     input.set_text(text);
                     return shell_execute(config, shell, input, abort_signal.clone()).await;
@@ -318,19 +662,18 @@ async fn shell_execute(
     Ok(())
 }
 
-fn aggregate_text(text: Option<String>) -> Result<Option<String>> {
-    let text = if stdin().is_terminal() {
-        text
-    } else {
-        let mut stdin_text = String::new();
-        stdin().read_to_string(&mut stdin_text)?;
-        if let Some(text) = text {
-            Some(format!("{text}\n{stdin_text}"))
-        } else {
-            Some(stdin_text)
-        }
+/// Appends the piped stdin to the text, also returning the piped stdin on its own.
+fn aggregate_text(text: Option<String>) -> Result<(Option<String>, Option<String>)> {
+    if stdin().is_terminal() {
+        return Ok((text, None));
+    }
+    let mut stdin_text = String::new();
+    stdin().read_to_string(&mut stdin_text)?;
+    let text = match text {
+        Some(text) => format!("{text}\n{stdin_text}"),
+        None => stdin_text.clone(),
     };
-    Ok(text)
+    Ok((Some(text), Some(stdin_text)))
 }
 
 async fn create_input(
@@ -357,6 +700,21 @@ async fn create_input(
     Ok(input)
 }
 
+fn setup_panic_hook(config: &GlobalConfig) -> Result<()> {
+    let (_, log_path) = Config::log_config(config.read().working_mode.is_serve())?;
+    let config = config.clone();
+    install_panic_hook(
+        move || match config.try_read() {
+            Some(config) => config
+                .sysinfo()
+                .unwrap_or_else(|err| format!("Failed to get config info, {err}")),
+            None => "The config is locked".into(),
+        },
+        log_path,
+    );
+    Ok(())
+}
+
 fn setup_logger(is_serve: bool) -> Result<()> {
     let (log_level, log_path) = Config::log_config(is_serve)?;
     if log_level == LevelFilter::Off {
@@ -377,15 +735,27 @@ fn setup_logger(is_serve: bool) -> Result<()> {
         ))
         .set_thread_level(LevelFilter::Off)
         .build();
-    match log_path {
-        None => {
-            SimpleLogger::init(log_level, config)?;
-        }
+    let logger: Box<dyn log::Log> = match log_path {
+        None => SimpleLogger::new(log_level, config),
         Some(log_path) => {
-            ensure_parent_exists(&log_path)?;
-            let log_file = std::fs::File::create(log_path)?;
-            WriteLogger::init(log_level, config, log_file)?;
+            let log_file =
+                ensure_parent_exists(&log_path).and_then(|_| Ok(std::fs::File::create(&log_path)?));
+            match log_file {
+                Ok(log_file) => WriteLogger::new(log_level, config, log_file),
+                Err(err) => {
+                    eprintln!(
+                        "{}",
+                        warning_text(&format!(
+                            "Unable to write the log to '{}', logging to stderr instead, {err}",
+                            log_path.display()
+                        ))
+                    );
+                    WriteLogger::new(log_level, config, std::io::stderr())
+                }
+            }
         }
-    }
+    };
+    log::set_boxed_logger(Box::new(RedactLogger(logger)))?;
+    log::set_max_level(log_level);
     Ok(())
 }
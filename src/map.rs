@@ -0,0 +1,170 @@
+use crate::client::is_transient_error;
+use crate::config::{ensure_parent_exists, GlobalConfig, Input, PromptTemplate};
+use crate::utils::*;
+
+use anyhow::{bail, Context, Result};
+use futures_util::{stream, StreamExt};
+use indexmap::IndexMap;
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Applies the prompt template to every file matching the glob and writes each answer
+/// to the same relative path under `out_dir`.
+///
+/// Besides the given variables, the template takes `{{content}}`, `{{path}}` and `{{filename}}`.
+pub async fn run(
+    config: &GlobalConfig,
+    pattern: &str,
+    template: &PromptTemplate,
+    variables: IndexMap<String, String>,
+    out_dir: &str,
+    concurrency: Option<usize>,
+    abort_signal: AbortSignal,
+) -> Result<()> {
+    if !template.placeholders().contains("content") {
+        bail!("The template has no {{{{content}}}} placeholder");
+    }
+    let paths = expand_glob_paths(&[pattern], true).await?;
+    if paths.is_empty() {
+        bail!("No files match '{pattern}'");
+    }
+    let out_dir = Path::new(out_dir);
+    let concurrency = concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1);
+    let total = paths.len();
+
+    let (spinner, spinner_rx) = Spinner::create(&format!("Processing [0/{total}]"));
+    let variables = &variables;
+    let tasks = paths.iter().map(|path| async move {
+        let ret = process_file(config, path, template, variables, out_dir).await;
+        (path, ret)
+    });
+    let results = abortable_run_with_spinner_rx(
+        async {
+            let mut results = vec![];
+            let mut tasks = stream::iter(tasks).buffer_unordered(concurrency);
+            while let Some(result) = tasks.next().await {
+                results.push(result);
+                spinner.set_message(format!("Processing [{}/{total}]", results.len()))?;
+            }
+            Ok(results)
+        },
+        spinner_rx,
+        abort_signal,
+    )
+    .await;
+    spinner.stop();
+
+    let mut failed = 0;
+    for (path, ret) in results? {
+        match ret {
+            Ok(output_path) => println!("✓ {path} → {}", output_path.display()),
+            Err(err) => {
+                failed += 1;
+                println!("{}", error_text(&format!("✗ {path}: {err:#}")));
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {total} files failed");
+    }
+    Ok(())
+}
+
+async fn process_file(
+    config: &GlobalConfig,
+    path: &str,
+    template: &PromptTemplate,
+    variables: &IndexMap<String, String>,
+    out_dir: &Path,
+) -> Result<PathBuf> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read '{path}'"))?;
+    let filename = Path::new(path)
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut variables = variables.clone();
+    variables.insert("path".into(), path.to_string());
+    variables.insert("filename".into(), filename);
+    variables.insert("content".into(), content);
+    let prompt = template.render(&variables)?;
+    let input = Input::from_str(config, &prompt, None);
+    let client = input.create_client()?;
+
+    let mut attempt = 0;
+    let output = loop {
+        attempt += 1;
+        match client.chat_completions(input.clone()).await {
+            Ok(output) => break output,
+            // Mostly rate limits when many files are in flight, so back off and try again.
+            Err(err) if attempt < MAX_ATTEMPTS && is_transient_error(&err) => {
+                debug!("attempt {attempt} for '{path}' failed: {err}");
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+            Err(err) => return Err(err),
+        }
+    };
+    if !output.tool_calls.is_empty() {
+        warn!(
+            "Skipped {} tool call(s) for '{path}'",
+            output.tool_calls.len()
+        );
+    }
+    let mut text = strip_think_tag(&output.text).to_string();
+    if text.trim_start().starts_with("```") {
+        text = extract_block(&text);
+    }
+
+    let output_path = out_dir.join(relative_path(Path::new(path)));
+    ensure_parent_exists(&output_path)?;
+    fs::write(&output_path, text)
+        .with_context(|| format!("Failed to write '{}'", output_path.display()))?;
+    Ok(output_path)
+}
+
+/// Keeps the path below the current directory so the results mirror the source tree.
+/// Parent components become `__` so files from outside can't escape or collide.
+fn relative_path(path: &Path) -> PathBuf {
+    let path = env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .unwrap_or(path);
+    path.components()
+        .filter_map(|v| match v {
+            Component::Normal(v) => Some(v),
+            Component::ParentDir => Some(OsStr::new("__")),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("./src/main.rs")),
+            PathBuf::from("src/main.rs")
+        );
+        assert_eq!(
+            relative_path(Path::new("../lib/../a.rs")),
+            PathBuf::from("__/lib/__/a.rs")
+        );
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(
+            relative_path(&cwd.join("src/a.rs")),
+            PathBuf::from("src/a.rs")
+        );
+    }
+}
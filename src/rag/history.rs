@@ -0,0 +1,424 @@
+use super::*;
+
+use crate::client::{Message, MessageRole};
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub const HISTORY_RAG_NAME: &str = "history";
+
+const MESSAGES_SOURCE: &str = "messages.md";
+const CHAT_HEADER: &str = "# CHAT: ";
+const CHAT_SEPARATOR: &str = "\n--------\n";
+
+impl Rag {
+    /// Opens the store behind `history_rag`, built from the `rag_*` settings without prompting.
+    pub fn load_history(config: &GlobalConfig, path: &Path) -> Result<Self> {
+        let (embedding_model_id, chunk_size, chunk_overlap, reranker_model, top_k) = {
+            let config = config.read();
+            (
+                config.rag_embedding_model.clone(),
+                config.rag_chunk_size,
+                config.rag_chunk_overlap,
+                config.rag_reranker_model.clone(),
+                config.rag_top_k,
+            )
+        };
+        let Some(embedding_model_id) = embedding_model_id else {
+            bail!("history_rag needs `rag_embedding_model` to be set");
+        };
+        if path.exists() {
+            let rag = Self::load(config, HISTORY_RAG_NAME, path)?;
+            if rag.data.embedding_model == embedding_model_id {
+                return Ok(rag);
+            }
+        }
+        let embedding_model =
+            Model::retrieve_model(&config.read(), &embedding_model_id, ModelType::Embedding)?;
+        let chunk_size = chunk_size.unwrap_or_else(|| embedding_model.default_chunk_size());
+        let data = RagData::new(
+            embedding_model.id(),
+            chunk_size,
+            chunk_overlap.unwrap_or(chunk_size / 20),
+            reranker_model,
+            top_k,
+            embedding_model.max_batch_size(),
+        );
+        Self::create(config, HISTORY_RAG_NAME, path, data)
+    }
+
+    /// Indexes texts keyed by where they came from, embedding only the ones that are new or changed.
+    /// The texts of the `replaced` sources that are no longer there are dropped.
+    /// Every chunk starts with where it came from.
+    pub async fn sync_texts(
+        &mut self,
+        replaced: &[String],
+        texts: IndexMap<String, String>,
+    ) -> Result<bool> {
+        let mut to_deleted = vec![];
+        let mut texts = texts;
+        for (file_id, file) in &self.data.files {
+            match texts.get(&file.path) {
+                Some(text) if sha256(text) == file.hash => {
+                    texts.swap_remove(&file.path);
+                }
+                Some(_) => to_deleted.push(*file_id),
+                None if replaced.iter().any(|v| is_entry_of(&file.path, v)) => {
+                    to_deleted.push(*file_id)
+                }
+                None => {}
+            }
+        }
+        if texts.is_empty() && to_deleted.is_empty() {
+            return Ok(false);
+        }
+
+        let max_chunk_tokens = self.max_chunk_tokens();
+        let tokenizer = self.embedding_model.tokenizer();
+        let splitter = RecursiveCharacterTextSplitter::new(
+            self.data.chunk_size,
+            self.data.chunk_overlap,
+            &get_separators("md"),
+        );
+        let mut next_file_id = self.data.next_file_id;
+        let mut files = vec![];
+        let mut document_ids = vec![];
+        let mut chunks = vec![];
+        for (path, text) in texts {
+            let split_options =
+                SplitterChunkHeaderOptions::default().with_chunk_header(&format!("From {path}:\n"));
+            let mut documents =
+                splitter.split_documents(&[RagDocument::new(&text)], &split_options);
+            if let Some(max_tokens) = max_chunk_tokens {
+                (documents, _) = split_oversized_documents(documents, max_tokens, &tokenizer);
+            }
+            for (document_index, document) in documents.iter().enumerate() {
+                document_ids.push(DocumentId::new(next_file_id, document_index));
+                chunks.push(document.page_content.clone());
+            }
+            let file = RagFile {
+                hash: sha256(&text),
+                path,
+                documents,
+            };
+            files.push((next_file_id, file));
+            next_file_id += 1;
+        }
+        let embeddings = match chunks.is_empty() {
+            true => vec![],
+            false => {
+                self.create_embeddings(EmbeddingsData::new(chunks, false), None)
+                    .await?
+            }
+        };
+        self.data.del(to_deleted);
+        self.data.add(next_file_id, files, document_ids, embeddings);
+        self.hnsw = self.data.build_hnsw();
+        self.bm25 = self.data.build_bm25();
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.files.is_empty()
+    }
+
+    fn count_entries(&self, source: &str) -> usize {
+        self.data
+            .files
+            .values()
+            .filter(|v| is_entry_of(&v.path, source))
+            .count()
+    }
+}
+
+/// A history file as it was when its Q&As were last indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedSource {
+    /// Modification time in milliseconds since the epoch
+    pub modified: u64,
+    pub size: u64,
+}
+
+impl SyncedSource {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = path.metadata().ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_millis() as u64;
+        Some(Self {
+            modified,
+            size: metadata.len(),
+        })
+    }
+}
+
+/// What has to be indexed to bring the store up to date with the history files.
+#[derive(Debug, Default)]
+struct HistoryChanges {
+    /// The sources whose old Q&As are dropped unless they are still there
+    replaced: Vec<String>,
+    texts: IndexMap<String, String>,
+    sources: IndexMap<String, SyncedSource>,
+}
+
+/// The history files, `messages.md` and the saved sessions, as `source => path`.
+fn history_sources(config: &Config) -> IndexMap<String, PathBuf> {
+    let mut sources = IndexMap::new();
+    sources.insert(MESSAGES_SOURCE.to_string(), config.messages_file());
+    let sessions = config.list_sessions().into_iter().chain(
+        config
+            .list_autoname_sessions()
+            .into_iter()
+            .map(|v| format!("_/{v}")),
+    );
+    for name in sessions {
+        let path = config.session_file(&name);
+        sources.insert(session_source(&name), path);
+    }
+    sources
+}
+
+/// Reads only the history files changed since the last sync.
+/// New exchanges appended to `messages.md` are read from where the last sync stopped.
+fn collect_changes(rag: &Rag, sources: IndexMap<String, PathBuf>) -> HistoryChanges {
+    let mut changes = HistoryChanges::default();
+    for source in rag.data.synced_sources.keys() {
+        if !sources.contains_key(source) {
+            changes.replaced.push(source.clone());
+        }
+    }
+    for (source, path) in sources {
+        let Some(current) = SyncedSource::of(&path) else {
+            continue;
+        };
+        let synced = rag.data.synced_sources.get(&source).copied();
+        if synced == Some(current) {
+            changes.sources.insert(source, current);
+            continue;
+        }
+        if source == MESSAGES_SOURCE {
+            let (offset, index) = match synced {
+                Some(synced) if synced.size <= current.size => {
+                    (synced.size, rag.count_entries(&source))
+                }
+                _ => {
+                    changes.replaced.push(source.clone());
+                    (0, 0)
+                }
+            };
+            let Ok(content) = read_from(&path, offset) else {
+                continue;
+            };
+            for (i, (time, text)) in parse_messages_file(&content).into_iter().enumerate() {
+                changes
+                    .texts
+                    .insert(format!("{source} #{} [{time}]", index + i + 1), text);
+            }
+        } else {
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            changes.replaced.push(source.clone());
+            for (i, text) in parse_session_file(&content).into_iter().enumerate() {
+                changes.texts.insert(format!("{source} #{}", i + 1), text);
+            }
+        }
+        changes.sources.insert(source, current);
+    }
+    changes
+}
+
+/// Searches the past conversations for the text, indexing what changed since the last search first.
+/// Returns the relevant Q&As with where they came from, leaving out the current session.
+pub async fn search_history(
+    config: &GlobalConfig,
+    text: &str,
+    abort_signal: AbortSignal,
+) -> Result<Vec<(String, String)>> {
+    let (path, sources, current_session) = {
+        let config = config.read();
+        (
+            config.history_rag_file(),
+            history_sources(&config),
+            config.session.as_ref().map(|v| session_source(v.name())),
+        )
+    };
+    let mut rag = match take_cached_history(config, &path) {
+        Some(rag) => rag,
+        None => Rag::load_history(config, &path)?,
+    };
+    let changes = collect_changes(&rag, sources);
+    if rag.is_empty() && !changes.texts.is_empty() {
+        eprintln!(
+            "{}",
+            dimmed_text(&format!(
+                "history_rag: embedding {} past exchange(s) once, later only new ones are embedded",
+                changes.texts.len()
+            ))
+        );
+    }
+    let documents = abortable_run_with_spinner(
+        async {
+            let synced = rag.sync_texts(&changes.replaced, changes.texts).await?;
+            if synced || rag.data.synced_sources != changes.sources {
+                rag.data.synced_sources = changes.sources;
+                rag.save()?;
+            }
+            if rag.is_empty() {
+                return Ok(vec![]);
+            }
+            let (reranker_model, top_k) = rag.get_config();
+            rag.hybird_search(text, top_k, reranker_model.as_deref())
+                .await
+        },
+        "Searching history",
+        abort_signal,
+    )
+    .await?;
+    let documents = documents
+        .into_iter()
+        .filter_map(|(id, document)| {
+            let (file_index, _) = id.split();
+            let file = rag.data.files.get(&file_index)?;
+            if current_session
+                .as_deref()
+                .is_some_and(|v| is_entry_of(&file.path, v))
+            {
+                return None;
+            }
+            Some((file.path.clone(), document))
+        })
+        .collect();
+    config.write().history_rag_cache = Some((path.clone(), modified_time(&path), Arc::new(rag)));
+    Ok(documents)
+}
+
+/// The store left by the previous search, unless the file or `rag_embedding_model` changed since.
+fn take_cached_history(config: &GlobalConfig, path: &Path) -> Option<Rag> {
+    let mut config = config.write();
+    let (cached_path, modified, rag) = config.history_rag_cache.take()?;
+    let rag = Arc::try_unwrap(rag).ok()?;
+    let unchanged = cached_path == path
+        && modified == modified_time(path)
+        && config.rag_embedding_model.as_deref() == Some(rag.data.embedding_model.as_str());
+    unchanged.then_some(rag)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|v| v.modified()).ok()
+}
+
+fn session_source(name: &str) -> String {
+    format!("session '{name}'")
+}
+
+fn is_entry_of(entry: &str, source: &str) -> bool {
+    entry
+        .strip_prefix(source)
+        .is_some_and(|v| v.starts_with(" #"))
+}
+
+fn read_from(path: &Path, offset: u64) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// The (time, Q&A) entries of `messages.md`, without their tool calls.
+fn parse_messages_file(content: &str) -> Vec<(String, String)> {
+    let mut entries = vec![];
+    for block in content.split(CHAT_HEADER).skip(1) {
+        let Some((header, body)) = block.split_once('\n') else {
+            continue;
+        };
+        let Some((question, answer)) = body.split_once(CHAT_SEPARATOR) else {
+            continue;
+        };
+        let answer = answer
+            .rsplit_once(CHAT_SEPARATOR)
+            .map(|(v, _)| v)
+            .unwrap_or(answer);
+        let answer = match answer.rsplit_once("</tool_calls>\n") {
+            Some((_, v)) => v,
+            None => answer,
+        };
+        if question.trim().is_empty() || answer.trim().is_empty() {
+            continue;
+        }
+        let time = header
+            .rsplit_once('[')
+            .and_then(|(_, v)| v.split_once(']'))
+            .map(|(v, _)| v.to_string())
+            .unwrap_or_default();
+        entries.push((time, format_qa(question, answer)));
+    }
+    entries
+}
+
+/// The Q&As of a session file, a user message paired with the assistant reply after it.
+fn parse_session_file(content: &str) -> Vec<String> {
+    #[derive(Deserialize)]
+    struct SessionMessages {
+        #[serde(default)]
+        messages: Vec<Message>,
+    }
+    let Ok(session) = serde_yaml::from_str::<SessionMessages>(content) else {
+        return vec![];
+    };
+    let mut entries = vec![];
+    let mut question: Option<String> = None;
+    for message in session.messages {
+        match message.role {
+            MessageRole::User => question = Some(message.content.to_text()),
+            MessageRole::Assistant => {
+                let answer = message.content.to_text();
+                if let Some(question) = question.take().filter(|_| !answer.is_empty()) {
+                    entries.push(format_qa(&question, &answer));
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+fn format_qa(question: &str, answer: &str) -> String {
+    format!("Q: {}\nA: {}", question.trim(), answer.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_history() {
+        let content = "# CHAT: hi [2024-05-01T10:00:00+00:00]\nhello\n--------\nHi there\n--------\n\n# CHAT: ls [2024-05-02T10:00:00+00:00] (coder)\nlist files\n--------\n<tool_calls>\n[]\n</tool_calls>\nTwo files\n--------\n\n";
+        assert_eq!(
+            parse_messages_file(content),
+            vec![
+                (
+                    "2024-05-01T10:00:00+00:00".to_string(),
+                    "Q: hello\nA: Hi there".to_string()
+                ),
+                (
+                    "2024-05-02T10:00:00+00:00".to_string(),
+                    "Q: list files\nA: Two files".to_string()
+                ),
+            ]
+        );
+        let content = "model: openai:gpt-4o\nmessages:\n- role: system\n  content: be brief\n- role: user\n  content: what is rust\n- role: assistant\n  content: A language\n- role: user\n  content: unanswered\n";
+        assert_eq!(
+            parse_session_file(content),
+            vec!["Q: what is rust\nA: A language".to_string()]
+        );
+        assert!(is_entry_of("session 'a' #2", "session 'a'"));
+        assert!(!is_entry_of("session 'ab' #1", "session 'a'"));
+    }
+}
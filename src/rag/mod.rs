@@ -4,9 +4,13 @@ use crate::client::*;
 use crate::config::*;
 use crate::utils::*;
 
+mod history;
 mod serde_vectors;
 mod splitter;
 
+pub use self::history::search_history;
+use self::history::SyncedSource;
+
 use anyhow::{anyhow, bail, Context, Result};
 use bm25::{Language, SearchEngine, SearchEngineBuilder};
 use hnsw_rs::prelude::*;
@@ -103,7 +107,9 @@ impl Rag {
     pub fn load(config: &GlobalConfig, name: &str, path: &Path) -> Result<Self> {
         let err = || format!("Failed to load rag '{name}' at '{}'", path.display());
         let content = fs::read_to_string(path).with_context(err)?;
-        let data: RagData = serde_yaml::from_str(&content).with_context(err)?;
+        let content = migrate_file("RAG", path, content, RAG_VERSION, &RAG_MIGRATIONS, true)?;
+        let mut data: RagData = serde_yaml::from_str(&content).with_context(err)?;
+        data.version = RAG_VERSION;
         Self::create(config, name, path, data)
     }
 
@@ -204,6 +210,15 @@ impl Rag {
         self.last_sources.read().clone()
     }
 
+    /// The file a document was split from, with the document id.
+    pub fn document_source(&self, id: DocumentId) -> String {
+        let (file_index, _) = id.split();
+        match self.data.files.get(&file_index) {
+            Some(file) => format!("{} ({id:?})", file.path),
+            None => format!("{id:?}"),
+        }
+    }
+
     pub fn set_last_sources(&self, ids: &[DocumentId]) {
         let mut sources: IndexMap<String, Vec<String>> = IndexMap::new();
         for id in ids {
@@ -299,18 +314,20 @@ impl Rag {
         top_k: usize,
         rerank_model: Option<&str>,
         abort_signal: AbortSignal,
-    ) -> Result<(String, Vec<DocumentId>)> {
-        let ret = abortable_run_with_spinner(
+    ) -> Result<Vec<(DocumentId, String)>> {
+        abortable_run_with_spinner(
             self.hybird_search(text, top_k, rerank_model),
             "Searching",
             abort_signal,
         )
-        .await;
-        let (ids, documents): (Vec<_>, Vec<_>) = ret?.into_iter().unzip();
-        let embeddings = documents.join("\n\n");
-        Ok((embeddings, ids))
+        .await
     }
 
+    #[tracing::instrument(
+        name = "rag.sync_documents",
+        skip_all,
+        fields(rag = self.name(), refresh = refresh)
+    )]
     pub async fn sync_documents(
         &mut self,
         paths: &[String],
@@ -395,7 +412,7 @@ impl Rag {
         if has_error {
             let mut aborted = true;
             if *IS_STDOUT_TERMINAL && total > 0 {
-                let ans = Confirm::new("Some documents failed to load. Continue?")
+                let ans = Confirm::new(&tr("Some documents failed to load. Continue?"))
                     .with_default(false)
                     .prompt()?;
                 aborted = !ans;
@@ -405,6 +422,8 @@ impl Rag {
             }
         }
 
+        let max_chunk_tokens = self.max_chunk_tokens();
+        let tokenizer = self.embedding_model.tokenizer();
         let mut rag_files = vec![];
         for LoadedDocument {
             path,
@@ -439,7 +458,18 @@ impl Rag {
 
             let split_options = SplitterChunkHeaderOptions::default();
             let document = RagDocument::new(contents);
-            let split_documents = splitter.split_documents(&[document], &split_options);
+            let mut split_documents = splitter.split_documents(&[document], &split_options);
+            if let Some(max_tokens) = max_chunk_tokens {
+                let oversized;
+                (split_documents, oversized) =
+                    split_oversized_documents(split_documents, max_tokens, &tokenizer);
+                if oversized > 0 {
+                    warn!(
+                        "'{path}' produced {oversized} chunk(s) over the {max_tokens} tokens of '{}', split them further",
+                        self.embedding_model.id()
+                    );
+                }
+            }
             rag_files.push(RagFile {
                 hash: hash.clone(),
                 path,
@@ -485,6 +515,11 @@ impl Rag {
         Ok(())
     }
 
+    #[tracing::instrument(
+        name = "rag.search",
+        skip_all,
+        fields(rag = self.name(), top_k = top_k, rerank_model = rerank_model)
+    )]
     async fn hybird_search(
         &self,
         query: &str,
@@ -553,6 +588,7 @@ impl Rag {
         Ok(output)
     }
 
+    #[tracing::instrument(name = "rag.vector_search", skip_all)]
     async fn vector_search(
         &self,
         query: &str,
@@ -587,6 +623,7 @@ impl Rag {
         Ok(output)
     }
 
+    #[tracing::instrument(name = "rag.keyword_search", skip_all)]
     async fn keyword_search(
         &self,
         query: &str,
@@ -608,35 +645,50 @@ impl Rag {
         Ok(output)
     }
 
+    /// The most tokens one text may have for the embedding model.
+    fn max_chunk_tokens(&self) -> Option<usize> {
+        match (
+            self.embedding_model.max_tokens_per_chunk(),
+            self.embedding_model.max_input_tokens(),
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    #[tracing::instrument(name = "rag.create_embeddings", skip_all)]
     async fn create_embeddings(
         &self,
         data: EmbeddingsData,
         spinner: Option<Spinner>,
     ) -> Result<EmbeddingsOutput> {
         let embedding_client = init_client(&self.config, Some(self.embedding_model.clone()))?;
-        let EmbeddingsData { texts, query } = data;
+        let EmbeddingsData { mut texts, query } = data;
+        // The embeddings API sees documents and past conversations, which `confirm_preview` never
+        // shows, so their secrets are masked for `warn` as well.
+        if self.config.read().redact != "off" {
+            texts = texts.iter().map(|v| mask_secrets(v)).collect();
+        }
         let batch_size = self
             .data
             .batch_size
             .or_else(|| self.embedding_model.max_batch_size());
-        let batch_size = match self.embedding_model.max_input_tokens() {
-            Some(max_input_tokens) => {
-                let x = max_input_tokens / self.data.chunk_size;
-                match batch_size {
-                    Some(y) => x.min(y),
-                    None => x,
-                }
-            }
-            None => batch_size.unwrap_or(1),
+        let max_input_tokens = self.embedding_model.max_input_tokens();
+        let batch_size = match (batch_size, max_input_tokens) {
+            (Some(v), _) => v,
+            (None, Some(_)) => usize::MAX,
+            (None, None) => 1,
         };
+        let tokenizer = self.embedding_model.tokenizer();
         let mut output = vec![];
-        let batch_chunks = texts.chunks(batch_size.max(1));
+        let batch_chunks =
+            split_batches(&texts, batch_size, max_input_tokens, |v| tokenizer.count(v));
         let batch_chunks_len = batch_chunks.len();
         let retry_limit = env::var(get_env_name("embeddings_retry_limit"))
             .ok()
             .and_then(|v| v.parse::<u32>().ok())
             .unwrap_or(2);
-        for (index, texts) in batch_chunks.enumerate() {
+        for (index, texts) in batch_chunks.into_iter().enumerate() {
             progress(
                 &spinner,
                 format!("Creating embeddings [{}/{batch_chunks_len}]", index + 1),
@@ -670,6 +722,8 @@ impl Rag {
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RagData {
+    #[serde(default)]
+    pub version: u64,
     pub embedding_model: String,
     pub chunk_size: usize,
     pub chunk_overlap: usize,
@@ -681,11 +735,15 @@ pub struct RagData {
     pub files: IndexMap<FileId, RagFile>,
     #[serde(with = "serde_vectors")]
     pub vectors: IndexMap<DocumentId, Vec<f32>>,
+    /// The history files indexed by `history_rag` as they were when last synced
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub synced_sources: IndexMap<String, SyncedSource>,
 }
 
 impl Debug for RagData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RagData")
+            .field("version", &self.version)
             .field("embedding_model", &self.embedding_model)
             .field("chunk_size", &self.chunk_size)
             .field("chunk_overlap", &self.chunk_overlap)
@@ -709,6 +767,7 @@ impl RagData {
         batch_size: Option<usize>,
     ) -> Self {
         Self {
+            version: RAG_VERSION,
             embedding_model,
             chunk_size,
             chunk_overlap,
@@ -719,6 +778,7 @@ impl RagData {
             document_paths: Default::default(),
             files: Default::default(),
             vectors: Default::default(),
+            synced_sources: Default::default(),
         }
     }
 
@@ -838,17 +898,78 @@ fn select_embedding_model(models: &[&Model]) -> Result<String> {
         .iter()
         .map(|v| SelectOption::new(v.id(), v.description()))
         .collect();
-    let result = Select::new("Select embedding model:", models).prompt()?;
+    let result = Select::new(&tr("Select embedding model:"), models).prompt()?;
     Ok(result.value)
 }
 
+/// Groups texts into batches limited by both the number of texts and the total tokens.
+fn split_batches<F>(
+    texts: &[String],
+    batch_size: usize,
+    max_input_tokens: Option<usize>,
+    count_tokens: F,
+) -> Vec<&[String]>
+where
+    F: Fn(&str) -> usize,
+{
+    let batch_size = batch_size.max(1);
+    let mut batches = vec![];
+    let mut start = 0;
+    let mut tokens = 0;
+    for (i, text) in texts.iter().enumerate() {
+        let text_tokens = count_tokens(text);
+        let exceed_tokens = max_input_tokens
+            .map(|v| tokens + text_tokens > v)
+            .unwrap_or_default();
+        if i > start && (i - start >= batch_size || exceed_tokens) {
+            batches.push(&texts[start..i]);
+            start = i;
+            tokens = 0;
+        }
+        tokens += text_tokens;
+    }
+    if start < texts.len() {
+        batches.push(&texts[start..]);
+    }
+    batches
+}
+
+/// Splits the documents with more than `max_tokens` tokens by tokens rather than characters,
+/// returning how many there were.
+fn split_oversized_documents(
+    documents: Vec<RagDocument>,
+    max_tokens: usize,
+    tokenizer: &Tokenizer,
+) -> (Vec<RagDocument>, usize) {
+    let mut output = vec![];
+    let mut oversized = 0;
+    for document in documents {
+        if tokenizer.count(&document.page_content) <= max_tokens {
+            output.push(document);
+            continue;
+        }
+        oversized += 1;
+        let tokenizer = tokenizer.clone();
+        let splitter = RecursiveCharacterTextSplitter {
+            chunk_size: max_tokens,
+            chunk_overlap: 0,
+            length_function: Box::new(move |v| tokenizer.count(v)),
+            ..Default::default()
+        };
+        output
+            .extend(splitter.split_documents(&[document], &SplitterChunkHeaderOptions::default()));
+    }
+    (output, oversized)
+}
+
 fn set_chunk_size(model: &Model) -> Result<usize> {
     let default_value = model.default_chunk_size().to_string();
     let help_message = model
         .max_tokens_per_chunk()
         .map(|v| format!("The model's max_tokens is {v}"));
+    let message = tr("Set chunk size:");
 
-    let mut text = Text::new("Set chunk size:")
+    let mut text = Text::new(&message)
         .with_default(&default_value)
         .with_validator(move |text: &str| {
             let out = match text.parse::<usize>() {
@@ -865,7 +986,7 @@ fn set_chunk_size(model: &Model) -> Result<usize> {
 }
 
 fn set_chunk_overlay(default_value: usize) -> Result<usize> {
-    let value = Text::new("Set chunk overlay:")
+    let value = Text::new(&tr("Set chunk overlay:"))
         .with_default(&default_value.to_string())
         .with_validator(move |text: &str| {
             let out = match text.parse::<usize>() {
@@ -879,7 +1000,7 @@ fn set_chunk_overlay(default_value: usize) -> Result<usize> {
 }
 
 fn add_documents() -> Result<Vec<String>> {
-    let text = Text::new("Add documents:")
+    let text = Text::new(&tr("Add documents:"))
         .with_validator(required!("This field is required"))
         .with_help_message("e.g. file;dir/;dir/**/*.{md,mdx};solo-url;site-url/**")
         .prompt()?;
@@ -945,10 +1066,7 @@ fn reciprocal_rank_fusion(
 ) -> Vec<DocumentId> {
     let rrf_k = top_k * 2;
     let mut map: IndexMap<DocumentId, f32> = IndexMap::new();
-    for (document_ids, weight) in list_of_document_ids
-        .into_iter()
-        .zip(list_of_weights.into_iter())
-    {
+    for (document_ids, weight) in list_of_document_ids.into_iter().zip(list_of_weights) {
         for (index, &item) in document_ids.iter().enumerate() {
             *map.entry(item).or_default() += (1.0 / ((rrf_k + index + 1) as f32)) * weight;
         }
@@ -962,3 +1080,46 @@ fn reciprocal_rank_fusion(
         .map(|(v, _)| v)
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_batches() {
+        let texts: Vec<String> = ["a b", "c", "d e f", "g", "h"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let count_tokens = |v: &str| v.split(' ').count();
+        let lens = |batches: Vec<&[String]>| batches.iter().map(|v| v.len()).collect::<Vec<_>>();
+        assert_eq!(
+            lens(split_batches(&texts, 2, None, count_tokens)),
+            [2, 2, 1]
+        );
+        assert_eq!(
+            lens(split_batches(&texts, usize::MAX, Some(4), count_tokens)),
+            [2, 2, 1]
+        );
+        assert_eq!(
+            lens(split_batches(&texts, 10, Some(2), count_tokens)),
+            [1, 1, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_split_oversized_documents() {
+        let documents = vec![
+            RagDocument::new("short text"),
+            RagDocument::new("word ".repeat(100)),
+        ];
+        let tokenizer = Tokenizer::Estimate;
+        let (output, oversized) = split_oversized_documents(documents, 30, &tokenizer);
+        assert_eq!(oversized, 1);
+        assert!(output.len() > 2);
+        assert_eq!(output[0].page_content, "short text");
+        assert!(output
+            .iter()
+            .all(|v| tokenizer.count(&v.page_content) <= 30));
+    }
+}
@@ -264,7 +264,6 @@ impl Default for SplitterChunkHeaderOptions {
 
 impl SplitterChunkHeaderOptions {
     // Set the value of chunk_header
-    #[allow(unused)]
     pub fn with_chunk_header(mut self, header: &str) -> Self {
         self.chunk_header = header.to_string();
         self
@@ -0,0 +1,251 @@
+use crossterm::style::{Color, Stylize};
+use similar::{capture_diff_slices, Algorithm, ChangeTag, DiffOp, TextDiff};
+
+const PREVIEW_WIDTH: usize = 80;
+const CONTEXT_WORDS: usize = 6;
+/// How similar two messages must be to count as an edit rather than a removal and an addition
+const EDIT_RATIO: f32 = 0.3;
+
+/// How changes are colored, following `highlight` and `light_theme`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffTheme {
+    Plain,
+    Dark,
+    Light,
+}
+
+impl DiffTheme {
+    fn paint(&self, text: &str, tag: ChangeTag) -> String {
+        let color = match (self, tag) {
+            (DiffTheme::Plain, _) | (_, ChangeTag::Equal) => return text.to_string(),
+            (DiffTheme::Dark, ChangeTag::Delete) => Color::Red,
+            (DiffTheme::Dark, ChangeTag::Insert) => Color::Green,
+            (DiffTheme::Light, ChangeTag::Delete) => Color::DarkRed,
+            (DiffTheme::Light, ChangeTag::Insert) => Color::DarkGreen,
+        };
+        match tag {
+            ChangeTag::Delete => text.with(color).crossed_out().to_string(),
+            _ => text.with(color).to_string(),
+        }
+    }
+}
+
+/// Colors a line of unified diff output by its marker.
+pub fn colorize_diff_line(line: &str) -> String {
+    if line.starts_with("+++") || line.starts_with("---") {
+        line.bold().to_string()
+    } else if line.starts_with('+') {
+        line.green().to_string()
+    } else if line.starts_with('-') {
+        line.red().to_string()
+    } else if line.starts_with("@@") {
+        line.cyan().to_string()
+    } else {
+        line.to_string()
+    }
+}
+
+/// Computes the unified diff between two texts, `None` if they are identical.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> Option<String> {
+    // Only the content matters here, not whether the texts end with a newline
+    let (old, new) = (ensure_newline(old), ensure_newline(new));
+    let diff = TextDiff::from_lines(&old, &new);
+    if diff.ratio() == 1.0 {
+        return None;
+    }
+    let output = diff
+        .unified_diff()
+        .context_radius(3)
+        .header(old_label, new_label)
+        .to_string();
+    Some(output.trim_end().to_string())
+}
+
+/// Renders the unified diff between two texts, colored if `color` is true.
+pub fn render_diff(old: &str, new: &str, old_label: &str, new_label: &str, color: bool) -> String {
+    match unified_diff(old, new, old_label, new_label) {
+        Some(output) if color => output
+            .lines()
+            .map(colorize_diff_line)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(output) => output,
+        None => "No differences".to_string(),
+    }
+}
+
+/// Summarizes which messages were added, removed or edited, `None` if there are no changes.
+///
+/// Messages are given as `(role, text)` pairs; edits are shown as an inline word diff.
+pub fn render_messages_diff(
+    old: &[(String, String)],
+    new: &[(String, String)],
+    theme: DiffTheme,
+) -> Option<String> {
+    let ops = capture_diff_slices(Algorithm::Myers, old, new);
+    let mut lines = vec![];
+    let removed = |lines: &mut Vec<String>, index: usize| {
+        let (role, text) = &old[index];
+        let text = theme.paint(&preview(text), ChangeTag::Delete);
+        lines.push(format!("- #{} {role}: {text}", index + 1));
+    };
+    let added = |lines: &mut Vec<String>, index: usize| {
+        let (role, text) = &new[index];
+        let text = theme.paint(&preview(text), ChangeTag::Insert);
+        lines.push(format!("+ #{} {role}: {text}", index + 1));
+    };
+    for op in ops {
+        match op {
+            DiffOp::Equal { .. } => {}
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => (old_index..old_index + old_len).for_each(|i| removed(&mut lines, i)),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => (new_index..new_index + new_len).for_each(|i| added(&mut lines, i)),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => {
+                // Pair each new message with the next similar old one, the rest are removed or added
+                let mut next_old = old_index;
+                for (j, (new_role, new_text)) in
+                    new.iter().enumerate().skip(new_index).take(new_len)
+                {
+                    let matched = (next_old..old_index + old_len).find(|&i| {
+                        let (old_role, old_text) = &old[i];
+                        old_role == new_role
+                            && TextDiff::from_words(old_text, new_text).ratio() >= EDIT_RATIO
+                    });
+                    match matched {
+                        Some(i) => {
+                            (next_old..i).for_each(|i| removed(&mut lines, i));
+                            let text = inline_diff(&old[i].1, new_text, theme);
+                            lines.push(format!("~ #{} {new_role}: {text}", j + 1));
+                            next_old = i + 1;
+                        }
+                        None => added(&mut lines, j),
+                    }
+                }
+                (next_old..old_index + old_len).for_each(|i| removed(&mut lines, i));
+            }
+        }
+    }
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// Word diff of two texts on one line, long unchanged stretches are elided.
+fn inline_diff(old: &str, new: &str, theme: DiffTheme) -> String {
+    let (old, new) = (old.replace('\n', " "), new.replace('\n', " "));
+    let diff = TextDiff::from_words(&old, &new);
+    let changes: Vec<(ChangeTag, &str)> = diff
+        .iter_all_changes()
+        .map(|change| (change.tag(), change.value()))
+        .collect();
+    let mut output = String::new();
+    let mut index = 0;
+    while index < changes.len() {
+        let (tag, _) = changes[index];
+        let end = changes[index..]
+            .iter()
+            .position(|(v, _)| *v != tag)
+            .map(|v| index + v)
+            .unwrap_or(changes.len());
+        let text: Vec<&str> = changes[index..end].iter().map(|(_, v)| *v).collect();
+        if tag == ChangeTag::Equal {
+            // Words and the spaces between them are separate tokens
+            let keep = CONTEXT_WORDS * 2;
+            let head = if index == 0 { 0 } else { keep };
+            let tail = if end == changes.len() { 0 } else { keep };
+            if text.len() > head + tail + 1 {
+                output.push_str(&text[..head].concat());
+                output.push('…');
+                output.push_str(&text[text.len() - tail..].concat());
+            } else {
+                output.push_str(&text.concat());
+            }
+        } else if let DiffTheme::Plain = theme {
+            // Without colors, wdiff-style markers tell the removed words from the added ones
+            match tag {
+                ChangeTag::Delete => output.push_str(&format!("[-{}-]", text.concat())),
+                _ => output.push_str(&format!("{{+{}+}}", text.concat())),
+            }
+        } else {
+            output.push_str(&theme.paint(&text.concat(), tag));
+        }
+        index = end;
+    }
+    output
+}
+
+fn preview(text: &str) -> String {
+    let text = text.replace('\n', " ");
+    if text.chars().count() > PREVIEW_WIDTH {
+        let text: String = text.chars().take(PREVIEW_WIDTH).collect();
+        format!("{text}…")
+    } else {
+        text
+    }
+}
+
+fn ensure_newline(text: &str) -> String {
+    if text.ends_with('\n') {
+        text.to_string()
+    } else {
+        format!("{text}\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "fn main() {\n    println!(\"hi\");\n}\n";
+        let new = "fn main() {\n    println!(\"hello\");\n}\n";
+        let output = unified_diff(old, new, "a", "b").unwrap();
+        assert_eq!(
+            output,
+            r#"--- a
++++ b
+@@ -1,3 +1,3 @@
+ fn main() {
+-    println!("hi");
++    println!("hello");
+ }"#
+        );
+        assert_eq!(unified_diff(old, old, "a", "b"), None);
+    }
+
+    #[test]
+    fn test_render_messages_diff() {
+        let message = |role: &str, text: &str| (role.to_string(), text.to_string());
+        let old = vec![
+            message("user", "hi"),
+            message("assistant", "hello"),
+            message("user", "what is the capital of france"),
+            message("assistant", "paris"),
+        ];
+        let new = vec![
+            message("user", "hi"),
+            message("user", "what is the capital of spain"),
+            message("assistant", "paris"),
+            message("user", "thanks"),
+        ];
+        let output = render_messages_diff(&old, &new, DiffTheme::Plain).unwrap();
+        assert_eq!(
+            output,
+            "- #2 assistant: hello
+~ #2 user: what is the capital of [-france-]{+spain+}
++ #4 user: thanks"
+        );
+        assert_eq!(render_messages_diff(&old, &old, DiffTheme::Plain), None);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::utils::{
+    base64_decode, base64_encode, dimmed_text, run_command_with_output, temp_file,
+    IS_STDOUT_TERMINAL,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use fancy_regex::Regex;
+use std::{env, fs, path::Path, sync::OnceLock};
+
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+static IMAGE_PROTOCOL: OnceLock<Option<ImageProtocol>> = OnceLock::new();
+
+lazy_static::lazy_static! {
+    static ref MARKDOWN_IMAGE_RE: Regex = Regex::new(r"!\[[^\]]*\]\(([^)\s]+)\)").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageProtocol {
+    Kitty,
+    Iterm2,
+    Sixel,
+}
+
+/// The inline image protocol of the terminal, if any.
+pub fn image_protocol() -> Option<ImageProtocol> {
+    *IMAGE_PROTOCOL.get_or_init(|| {
+        if !*IS_STDOUT_TERMINAL || env::var("TMUX").is_ok() {
+            return None;
+        }
+        let term = env::var("TERM").unwrap_or_default();
+        let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+        if term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || env::var("KITTY_WINDOW_ID").is_ok()
+            || term_program == "ghostty"
+        {
+            Some(ImageProtocol::Kitty)
+        } else if term_program == "iTerm.app"
+            || term_program == "WezTerm"
+            || env::var("LC_TERMINAL").is_ok_and(|v| v == "iTerm2")
+        {
+            Some(ImageProtocol::Iterm2)
+        } else if (term.contains("sixel") || term == "foot" || term.starts_with("mlterm"))
+            && run_command_with_output("img2sixel", &["--version"], None).is_ok()
+        {
+            Some(ImageProtocol::Sixel)
+        } else {
+            None
+        }
+    })
+}
+
+/// Writes image bytes (e.g. generated by the model) to a temporary file and returns its path.
+pub fn save_temp_image(data: &[u8], mime_type: &str) -> Result<String> {
+    let extension = match mime_type {
+        "image/jpeg" => "jpg",
+        "image/webp" => "webp",
+        "image/gif" => "gif",
+        _ => "png",
+    };
+    let path = temp_file("-image-", &format!(".{extension}"));
+    fs::write(&path, data)
+        .with_context(|| format!("Failed to write image to '{}'", path.display()))?;
+    Ok(path.display().to_string())
+}
+
+/// Prints the images referenced by a reply (`![..](<path or data url>)`) below it.
+pub fn print_reply_images(text: &str) {
+    if !*IS_STDOUT_TERMINAL || !text.contains("![") {
+        return;
+    }
+    for captures in MARKDOWN_IMAGE_RE.captures_iter(text).flatten() {
+        let url = &captures[1];
+        if url.starts_with("data:image/") || Path::new(url).is_file() {
+            if let Some(output) = render_image(url) {
+                println!("{output}");
+            }
+        }
+    }
+}
+
+/// Renders an image given as a local path or a data URL for the terminal.
+///
+/// Without an inline image protocol, data URLs are saved to a temporary file and its path is shown.
+pub fn render_image(url: &str) -> Option<String> {
+    match render_image_inner(url) {
+        Ok(output) => output,
+        Err(err) => {
+            debug!("Failed to render image: {err:#}");
+            None
+        }
+    }
+}
+
+fn render_image_inner(url: &str) -> Result<Option<String>> {
+    let (data, mime_type) = match url.strip_prefix("data:") {
+        Some(data_url) => {
+            let (mime_type, data) = data_url
+                .split_once(";base64,")
+                .ok_or_else(|| anyhow!("Invalid data url"))?;
+            (base64_decode(data)?, Some(mime_type))
+        }
+        None => (fs::read(url)?, None),
+    };
+    let local_path = || match mime_type {
+        Some(mime_type) => save_temp_image(&data, mime_type),
+        None => Ok(url.to_string()),
+    };
+    let output = match image_protocol() {
+        Some(ImageProtocol::Kitty) if data.starts_with(PNG_MAGIC) => kitty_sequence(&data),
+        Some(ImageProtocol::Iterm2) => format!(
+            "\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07",
+            data.len(),
+            base64_encode(&data)
+        ),
+        Some(ImageProtocol::Sixel) => {
+            let (success, stdout, stderr) =
+                run_command_with_output("img2sixel", &[local_path()?], None)?;
+            if !success {
+                bail!("img2sixel failed: {stderr}");
+            }
+            stdout
+        }
+        _ if mime_type.is_some() => dimmed_text(&format!("Image saved to '{}'", local_path()?)),
+        _ => return Ok(None),
+    };
+    Ok(Some(output))
+}
+
+/// Transmits a PNG with the kitty graphics protocol, in chunks as the protocol requires.
+fn kitty_sequence(data: &[u8]) -> String {
+    let encoded = base64_encode(data);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    let mut output = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        let chunk = String::from_utf8_lossy(chunk);
+        if i == 0 {
+            output.push_str(&format!("\x1b_Gf=100,a=T,m={more};{chunk}\x1b\\"));
+        } else {
+            output.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\"));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kitty_sequence() {
+        let data = vec![0u8; KITTY_CHUNK_SIZE];
+        let output = kitty_sequence(&data);
+        assert!(output.starts_with("\x1b_Gf=100,a=T,m=1;"));
+        assert_eq!(output.matches("\x1b_Gm=").count(), 1);
+        assert!(output.contains("\x1b_Gm=0;"));
+    }
+}
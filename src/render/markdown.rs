@@ -1,15 +1,31 @@
+use super::diff::colorize_diff_line;
+
+use crate::utils::parse_code_fence;
+
 use ansi_colours::AsRGB;
 use anyhow::{anyhow, Context, Result};
 use crossterm::style::{Color, Stylize};
 use crossterm::terminal;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 use syntect::highlighting::{Color as SyntectColor, FontStyle, Style, Theme};
-use syntect::parsing::SyntaxSet;
+use syntect::parsing::{SyntaxSet, SyntaxSetBuilder};
 use syntect::{easy::HighlightLines, parsing::SyntaxReference};
+use textwrap::core::display_width;
 
 /// Comes from https://github.com/sharkdp/bat/raw/5e77ca37e89c873e4490b42ff556370dc5c6ba4f/assets/syntaxes.bin
 const SYNTAXES: &[u8] = include_bytes!("../../assets/syntaxes.bin");
 
+/// Turns off the terminal's line wrapping, so long code lines overflow instead of wrapping.
+pub const DISABLE_AUTOWRAP: &str = "\x1b[?7l";
+pub const ENABLE_AUTOWRAP: &str = "\x1b[?7h";
+const MIN_TABLE_COLUMN_WIDTH: usize = 3;
+
+static EXTRA_SYNTAX_SET: OnceLock<Option<SyntaxSet>> = OnceLock::new();
+
 lazy_static::lazy_static! {
     static ref LANG_MAPS: HashMap<String, String> = {
         let mut m = HashMap::new();
@@ -22,17 +38,23 @@ lazy_static::lazy_static! {
 pub struct MarkdownRender {
     options: RenderOptions,
     syntax_set: SyntaxSet,
+    extra_syntax_set: Option<SyntaxSet>,
     code_color: Option<Color>,
     md_syntax: SyntaxReference,
     code_syntax: Option<SyntaxReference>,
     prev_line_type: LineType,
     wrap_width: Option<u16>,
+    code_overflow_width: Option<u16>,
+    max_width: Option<u16>,
+    table_lines: Vec<String>,
+    code_line_number: usize,
 }
 
 impl MarkdownRender {
     pub fn init(options: RenderOptions) -> Result<Self> {
         let syntax_set: SyntaxSet = bincode::deserialize_from(SYNTAXES)
             .with_context(|| "MarkdownRender: invalid syntaxes binary")?;
+        let extra_syntax_set = load_extra_syntax_set(options.syntaxes_dir.as_deref())?;
 
         let code_color = options
             .theme
@@ -56,45 +78,289 @@ impl MarkdownRender {
                 Err(_) => None,
             },
         };
+        let code_overflow_width = match options.code_overflow && !options.wrap_code {
+            true => terminal::size().ok().map(|(columns, _)| columns),
+            false => None,
+        };
+        let max_width = wrap_width.or_else(|| terminal::size().ok().map(|(columns, _)| columns));
         Ok(Self {
             syntax_set,
+            extra_syntax_set,
             code_color,
             md_syntax,
             code_syntax: None,
             prev_line_type: line_type,
             wrap_width,
+            code_overflow_width,
+            max_width,
+            table_lines: vec![],
+            code_line_number: 0,
             options,
         })
     }
 
     pub fn render(&mut self, text: &str) -> String {
-        text.split('\n')
-            .map(|line| self.render_line_mut(line))
-            .collect::<Vec<String>>()
-            .join("\n")
+        let mut output = self.render_lines(text);
+        output.extend(self.flush_table());
+        output.join("\n")
     }
 
-    pub fn render_line(&self, line: &str) -> String {
-        let (_, code_syntax, is_code) = self.check_line(line);
-        if is_code {
-            self.highlight_code_line(line, &code_syntax)
-        } else {
-            self.highlight_line(line, &self.md_syntax, false)
+    /// Renders complete lines of a streamed answer.
+    ///
+    /// Table rows are held back until the table ends, `None` means nothing is ready to print.
+    pub fn render_stream(&mut self, text: &str) -> Option<String> {
+        let output = self.render_lines(text);
+        (!output.is_empty()).then(|| output.join("\n"))
+    }
+
+    /// Renders the unfinished last line of a streamed answer along with the table held back, if any.
+    pub fn finish_stream(&mut self, buffer: &str) -> Option<String> {
+        if self.table_lines.is_empty() {
+            return None;
+        }
+        let mut output = vec![];
+        if !buffer.is_empty() {
+            output = self.render_lines(buffer);
+        }
+        output.extend(self.flush_table());
+        Some(output.join("\n"))
+    }
+
+    fn render_lines(&mut self, text: &str) -> Vec<String> {
+        let mut output = vec![];
+        for line in text.split('\n') {
+            let in_code = matches!(
+                self.prev_line_type,
+                LineType::CodeBegin | LineType::CodeInner
+            );
+            if !in_code && line.trim_start().starts_with('|') {
+                self.table_lines.push(line.to_string());
+                continue;
+            }
+            output.extend(self.flush_table());
+            output.push(self.render_line_mut(line));
+        }
+        output
+    }
+
+    fn flush_table(&mut self) -> Vec<String> {
+        let lines = std::mem::take(&mut self.table_lines);
+        if lines.is_empty() {
+            return vec![];
+        }
+        match self.render_table(&lines) {
+            Some(output) => output,
+            None => lines
+                .iter()
+                .map(|line| self.render_line_mut(line))
+                .collect(),
         }
     }
 
+    /// Draws a GFM table with box-drawing characters, wrapping cells to fit the width.
+    fn render_table(&self, lines: &[String]) -> Option<Vec<String>> {
+        if lines.len() < 2 {
+            return None;
+        }
+        let header = split_table_row(&lines[0]);
+        let alignments = parse_table_alignments(&lines[1])?;
+        if header.len() != alignments.len() {
+            return None;
+        }
+        let columns = header.len();
+        let mut rows = vec![header];
+        for line in &lines[2..] {
+            let mut row = split_table_row(line);
+            row.resize(columns, String::new());
+            rows.push(row);
+        }
+        for cell in rows.iter_mut().flatten() {
+            *cell = self.style_table_cell(cell);
+        }
+
+        let mut widths = vec![1; columns];
+        for row in &rows {
+            for (i, cell) in row.iter().enumerate() {
+                let width = cell.lines().map(display_width).max().unwrap_or_default();
+                widths[i] = widths[i].max(width);
+            }
+        }
+        if let Some(max_width) = self.max_width {
+            let available = (max_width as usize)
+                .saturating_sub(3 * columns + 1)
+                .max(MIN_TABLE_COLUMN_WIDTH * columns);
+            while widths.iter().sum::<usize>() > available {
+                let (i, width) = widths
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .max_by_key(|(_, width)| *width)
+                    .unwrap_or_default();
+                if width <= MIN_TABLE_COLUMN_WIDTH {
+                    break;
+                }
+                widths[i] -= 1;
+            }
+        }
+
+        let border = |left: &str, middle: &str, right: &str| {
+            let segments: Vec<String> = widths.iter().map(|v| "─".repeat(v + 2)).collect();
+            format!("{left}{}{right}", segments.join(middle))
+        };
+        let mut output = vec![border("┌", "┬", "┐")];
+        for (index, row) in rows.iter().enumerate() {
+            let cells: Vec<Vec<String>> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| {
+                    cell.lines()
+                        .flat_map(|line| textwrap::wrap(line, *width))
+                        .map(|line| line.to_string())
+                        .collect()
+                })
+                .collect();
+            let height = cells
+                .iter()
+                .map(|v| v.len())
+                .max()
+                .unwrap_or_default()
+                .max(1);
+            for line_index in 0..height {
+                let mut line = String::from("│");
+                for ((cell, width), alignment) in cells.iter().zip(&widths).zip(&alignments) {
+                    let text = cell.get(line_index).map(|v| v.as_str()).unwrap_or_default();
+                    let padding = width.saturating_sub(display_width(text));
+                    let (left, right) = match alignment {
+                        TableAlignment::Left => (0, padding),
+                        TableAlignment::Right => (padding, 0),
+                        TableAlignment::Center => (padding / 2, padding - padding / 2),
+                    };
+                    let text = if index == 0 && self.options.theme.is_some() {
+                        text.bold().to_string()
+                    } else {
+                        text.to_string()
+                    };
+                    line.push_str(&format!(
+                        " {}{text}{} │",
+                        " ".repeat(left),
+                        " ".repeat(right)
+                    ));
+                }
+                output.push(line);
+            }
+            if index == 0 {
+                output.push(border("├", "┼", "┤"));
+            }
+        }
+        output.push(border("└", "┴", "┘"));
+        Some(output)
+    }
+
+    /// Shows `**bold**` and `` `code` `` spans styled, without their markers, when there is a theme.
+    ///
+    /// The widths are measured on the result, markers that don't pair up are kept as they are.
+    fn style_table_cell(&self, cell: &str) -> String {
+        if self.options.theme.is_none() {
+            return cell.to_string();
+        }
+        let mut output = String::new();
+        let mut rest = cell;
+        while !rest.is_empty() {
+            let span = [("`", "`"), ("**", "**")]
+                .into_iter()
+                .find_map(|(open, close)| {
+                    let inner = rest.strip_prefix(open)?;
+                    let end = inner.find(close).filter(|v| *v > 0)?;
+                    Some((open, &inner[..end], &inner[end + close.len()..]))
+                });
+            match span {
+                Some((open, text, next)) => {
+                    match (open, self.code_color) {
+                        ("`", Some(color)) => output.push_str(&text.with(color).to_string()),
+                        ("`", None) => output.push_str(text),
+                        _ => output.push_str(&text.bold().to_string()),
+                    }
+                    rest = next;
+                }
+                None => {
+                    let len = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+                    output.push_str(&rest[..len]);
+                    rest = &rest[len..];
+                }
+            }
+        }
+        output
+    }
+
+    pub fn render_line(&self, line: &str) -> String {
+        let (line_type, code_syntax, is_code) = self.check_line(line);
+        self.render_checked_line(line, line_type, &code_syntax, is_code)
+    }
+
     fn render_line_mut(&mut self, line: &str) -> String {
         let (line_type, code_syntax, is_code) = self.check_line(line);
-        let output = if is_code {
-            self.highlight_code_line(line, &code_syntax)
-        } else {
-            self.highlight_line(line, &self.md_syntax, false)
-        };
+        let output = self.render_checked_line(line, line_type, &code_syntax, is_code);
+        match line_type {
+            LineType::CodeBegin => self.code_line_number = 0,
+            _ if is_code => self.code_line_number += 1,
+            _ => {}
+        }
         self.prev_line_type = line_type;
         self.code_syntax = code_syntax;
         output
     }
 
+    fn render_checked_line(
+        &self,
+        line: &str,
+        line_type: LineType,
+        code_syntax: &Option<SyntaxReference>,
+        is_code: bool,
+    ) -> String {
+        if is_code {
+            return self.highlight_code_line(line, code_syntax);
+        }
+        if self.options.code_header {
+            match line_type {
+                LineType::CodeBegin => return self.code_header(line),
+                LineType::CodeEnd => return self.style_gutter(&self.rule("─")),
+                _ => {}
+            }
+        }
+        self.highlight_line(line, &self.md_syntax, false)
+    }
+
+    /// Replaces the opening fence with a bar showing the language and the file name, if any.
+    fn code_header(&self, line: &str) -> String {
+        let (lang, filename) = parse_code_fence(line).unwrap_or_default();
+        let mut title: Vec<&str> = vec![];
+        if !lang.is_empty() {
+            title.push(&lang);
+        }
+        if let Some(filename) = &filename {
+            title.push(filename);
+        }
+        let head = match title.is_empty() {
+            true => String::new(),
+            false => format!("─── {} ", title.join(" · ")),
+        };
+        let rule = self.rule("─");
+        let fill: String = rule.chars().skip(display_width(&head)).collect();
+        self.style_gutter(&format!("{head}{fill}"))
+    }
+
+    fn rule(&self, c: &str) -> String {
+        let width = self.max_width.unwrap_or(80).min(80) as usize;
+        c.repeat(width)
+    }
+
+    fn style_gutter(&self, text: &str) -> String {
+        match self.options.theme.is_some() {
+            true => text.dark_grey().to_string(),
+            false => text.to_string(),
+        }
+    }
+
     fn check_line(&self, line: &str) -> (LineType, Option<SyntaxReference>, bool) {
         let mut line_type = self.prev_line_type;
         let mut code_syntax = self.code_syntax.clone();
@@ -138,35 +404,57 @@ impl MarkdownRender {
     }
 
     fn highlight_line(&self, line: &str, syntax: &SyntaxReference, is_code: bool) -> String {
+        let line = self.highlight_text(line, syntax);
+        self.wrap_line(line, is_code)
+    }
+
+    fn highlight_text(&self, line: &str, syntax: &SyntaxReference) -> String {
         let ws: String = line.chars().take_while(|c| c.is_whitespace()).collect();
         let trimmed_line: &str = &line[ws.len()..];
         let mut line_highlighted = None;
         if let Some(theme) = &self.options.theme {
+            let syntax_set = match &self.extra_syntax_set {
+                Some(set) if set.syntaxes().iter().any(|v| v.scope == syntax.scope) => set,
+                _ => &self.syntax_set,
+            };
             let mut highlighter = HighlightLines::new(syntax, theme);
-            if let Ok(ranges) = highlighter.highlight_line(trimmed_line, &self.syntax_set) {
+            if let Ok(ranges) = highlighter.highlight_line(trimmed_line, syntax_set) {
                 line_highlighted = Some(format!(
                     "{ws}{}",
                     as_terminal_escaped(&ranges, self.options.truecolor)
                 ))
             }
         }
-        let line = line_highlighted.unwrap_or_else(|| line.into());
-        self.wrap_line(line, is_code)
+        line_highlighted.unwrap_or_else(|| line.into())
     }
 
     fn highlight_code_line(&self, line: &str, code_syntax: &Option<SyntaxReference>) -> String {
-        if let Some(syntax) = code_syntax {
-            self.highlight_line(line, syntax, true)
-        } else {
-            let line = match self.code_color {
+        let mut output = match code_syntax {
+            Some(syntax) if syntax.name == "Diff" && self.options.theme.is_some() => {
+                colorize_diff_line(line)
+            }
+            Some(syntax) => self.highlight_text(line, syntax),
+            None => match self.code_color {
                 Some(color) => line.with(color).to_string(),
                 None => line.to_string(),
-            };
-            self.wrap_line(line, true)
+            },
+        };
+        if self.options.code_line_numbers {
+            let gutter = format!("{:>4} │ ", self.code_line_number + 1);
+            output = format!("{}{output}", self.style_gutter(&gutter));
         }
+        self.wrap_line(output, true)
     }
 
     fn wrap_line(&self, line: String, is_code: bool) -> String {
+        if is_code && !self.options.wrap_code {
+            return match self.code_overflow_width {
+                Some(width) if display_width(&line) > width as usize => {
+                    format!("{DISABLE_AUTOWRAP}{line}{ENABLE_AUTOWRAP}")
+                }
+                _ => line,
+            };
+        }
         if let Some(width) = self.wrap_width {
             if is_code && !self.options.wrap_code {
                 return line;
@@ -178,7 +466,13 @@ impl MarkdownRender {
     }
 
     fn find_syntax(&self, lang: &str) -> Option<&SyntaxReference> {
-        if let Some(new_lang) = LANG_MAPS.get(&lang.to_ascii_lowercase()) {
+        let extra_syntax = self.extra_syntax_set.as_ref().and_then(|set| {
+            set.find_syntax_by_token(lang)
+                .or_else(|| set.find_syntax_by_extension(lang))
+        });
+        if extra_syntax.is_some() {
+            extra_syntax
+        } else if let Some(new_lang) = LANG_MAPS.get(&lang.to_ascii_lowercase()) {
             self.syntax_set.find_syntax_by_name(new_lang)
         } else {
             self.syntax_set
@@ -202,6 +496,11 @@ pub struct RenderOptions {
     pub wrap: Option<String>,
     pub wrap_code: bool,
     pub truecolor: bool,
+    /// Let code lines wider than the terminal overflow instead of being soft-wrapped by it.
+    pub code_overflow: bool,
+    pub code_line_numbers: bool,
+    pub code_header: bool,
+    pub syntaxes_dir: Option<PathBuf>,
 }
 
 impl RenderOptions {
@@ -210,14 +509,80 @@ impl RenderOptions {
         wrap: Option<String>,
         wrap_code: bool,
         truecolor: bool,
+        code_overflow: bool,
     ) -> Self {
         Self {
             theme,
             wrap,
             wrap_code,
             truecolor,
+            code_overflow,
+            ..Default::default()
         }
     }
+
+    pub(crate) fn with_syntaxes_dir(mut self, syntaxes_dir: Option<PathBuf>) -> Self {
+        self.syntaxes_dir = syntaxes_dir;
+        self
+    }
+
+    /// Applies the `code_style` setting (plain, numbers, header, full).
+    pub(crate) fn with_code_style(mut self, code_style: &str) -> Self {
+        self.code_line_numbers = matches!(code_style, "numbers" | "full");
+        self.code_header = matches!(code_style, "header" | "full");
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableAlignment {
+    Left,
+    Right,
+    Center,
+}
+
+/// Splits a table row into its cells, turning `<br>` into line breaks.
+fn split_table_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell.push('|');
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells
+        .into_iter()
+        .map(|cell| cell.trim().replace("<br>", "\n").replace("<br/>", "\n"))
+        .collect()
+}
+
+/// Parses the delimiter row (e.g. `| :--- | ---: |`), `None` if it isn't one.
+fn parse_table_alignments(line: &str) -> Option<Vec<TableAlignment>> {
+    split_table_row(line)
+        .iter()
+        .map(|cell| {
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            let alignment = match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => TableAlignment::Center,
+                (false, true) => TableAlignment::Right,
+                _ => TableAlignment::Left,
+            };
+            Some(alignment)
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -278,17 +643,28 @@ fn blend_fg_color(fg: SyntectColor, bg: SyntectColor) -> SyntectColor {
     }
 }
 
+/// Loads the `.sublime-syntax` files in `syntaxes_dir`, once per run.
+///
+/// They are kept apart from the builtin syntaxes, since relinking those is slow.
+fn load_extra_syntax_set(syntaxes_dir: Option<&Path>) -> Result<Option<SyntaxSet>> {
+    if let Some(syntax_set) = EXTRA_SYNTAX_SET.get() {
+        return Ok(syntax_set.clone());
+    }
+    let syntax_set = match syntaxes_dir.filter(|v| v.is_dir()) {
+        Some(dir) => {
+            let mut builder = SyntaxSetBuilder::new();
+            builder
+                .add_from_folder(dir, true)
+                .with_context(|| format!("Failed to load syntaxes from '{}'", dir.display()))?;
+            Some(builder.build())
+        }
+        None => None,
+    };
+    Ok(EXTRA_SYNTAX_SET.get_or_init(|| syntax_set).clone())
+}
+
 fn detect_code_block(line: &str) -> Option<String> {
-    let line = line.trim_start();
-    if !line.starts_with("```") {
-        return None;
-    }
-    let lang = line
-        .chars()
-        .skip(3)
-        .take_while(|v| !v.is_whitespace())
-        .collect();
-    Some(lang)
+    parse_code_fence(line).map(|(lang, _)| lang)
 }
 
 fn get_code_color(theme: &Theme, truecolor: bool) -> Color {
@@ -381,6 +757,100 @@ std::error::Error>> {
         assert_eq!(TEXT_WRAP_ALL, output);
     }
 
+    #[test]
+    fn overflow_code() {
+        let options = RenderOptions::default();
+        let mut render = MarkdownRender::init(options).unwrap();
+        render.wrap_width = Some(80);
+        render.code_overflow_width = Some(80);
+        let output = render.render(TEXT);
+        let long_line = "fn unzip_file(path: &str, output_dir: &str) -> Result<(), Box<dyn std::error::Error>> {";
+        assert!(output.contains(&format!("{DISABLE_AUTOWRAP}{long_line}{ENABLE_AUTOWRAP}")));
+        assert!(output.contains("\nuse std::fs::File;\n"));
+        assert!(output.contains("example code\nthat shows"));
+    }
+
+    #[test]
+    fn render_diff() {
+        let options = RenderOptions {
+            theme: Some(Theme::default()),
+            ..Default::default()
+        };
+        let mut render = MarkdownRender::init(options).unwrap();
+        let output = render.render("```diff\n-old\n+new\n```");
+        assert!(output.contains(&"-old".red().to_string()));
+        assert!(output.contains(&"+new".green().to_string()));
+    }
+
+    #[test]
+    fn render_table() {
+        let options = RenderOptions::default();
+        let mut render = MarkdownRender::init(options).unwrap();
+        render.max_width = Some(25);
+        let text = "Sizes:\n| Name | Size |\n| :--- | ---: |\n| small | 1 |\n| a rather long name | 1024 |\nDone.";
+        let expected = r#"Sizes:
+┌────────────────┬──────┐
+│ Name           │ Size │
+├────────────────┼──────┤
+│ small          │    1 │
+│ a rather long  │ 1024 │
+│ name           │      │
+└────────────────┴──────┘
+Done."#;
+        assert_eq!(render.render(text), expected);
+
+        let mut render = MarkdownRender::init(RenderOptions::default()).unwrap();
+        render.max_width = Some(25);
+        let (head, tail) = text.rsplit_once('\n').unwrap();
+        let (head, row) = head.rsplit_once('\n').unwrap();
+        let mut output = vec![render.render_stream(head).unwrap()];
+        assert_eq!(output[0], "Sizes:");
+        assert!(render.render_stream(row).is_none());
+        output.extend(render.finish_stream(tail));
+        assert_eq!(output.join("\n"), expected);
+
+        let text = "| not | a table |\nplain";
+        assert_eq!(render.render(text), text);
+    }
+
+    #[test]
+    fn table_cell_markup() {
+        let render = MarkdownRender::init(RenderOptions::default()).unwrap();
+        assert_eq!(render.style_table_cell("**a** `b`"), "**a** `b`");
+
+        let options = RenderOptions {
+            theme: Some(Theme::default()),
+            ..Default::default()
+        };
+        let render = MarkdownRender::init(options).unwrap();
+        let output = render.style_table_cell("**a** and 2**3");
+        assert!(output.contains(&"a".bold().to_string()));
+        assert!(output.ends_with(" and 2**3"));
+        assert_eq!(display_width(&output), "a and 2**3".len());
+        assert_eq!(
+            display_width(&render.style_table_cell("`x` | ``")),
+            "x | ``".len()
+        );
+    }
+
+    #[test]
+    fn code_style() {
+        let options = RenderOptions::default().with_code_style("full");
+        let mut render = MarkdownRender::init(options).unwrap();
+        render.max_width = Some(30);
+        let text = "```rust:src/main.rs\nfn main() {\n}\n```\nDone.";
+        let expected = r#"─── rust · src/main.rs ───────
+   1 │ fn main() {
+   2 │ }
+──────────────────────────────
+Done."#;
+        assert_eq!(render.render(text), expected);
+        assert_eq!(
+            parse_code_fence(r#"```py title="app.py""#),
+            Some(("py".into(), Some("app.py".into())))
+        );
+    }
+
     #[test]
     fn test_detect_code_block() {
         assert_eq!(detect_code_block("```rust"), Some("rust".into()));
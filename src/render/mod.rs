@@ -1,13 +1,24 @@
+mod diff;
+mod image;
 mod markdown;
+mod pager;
 mod stream;
 
-pub use self::markdown::{MarkdownRender, RenderOptions};
+pub use self::diff::{render_diff, render_messages_diff, DiffTheme};
+pub use self::image::{image_protocol, print_reply_images, render_image, save_temp_image};
+pub use self::markdown::{MarkdownRender, RenderOptions, DISABLE_AUTOWRAP, ENABLE_AUTOWRAP};
+pub use self::pager::{need_pager, run_pager, PAGER_MODES};
 use self::stream::{markdown_stream, raw_stream};
+pub use self::stream::{write_ndjson_frame, write_raw_text};
 
 use crate::utils::{error_text, pretty_error, AbortSignal, IS_STDOUT_TERMINAL};
-use crate::{client::SseEvent, config::GlobalConfig};
+use crate::{
+    client::{SseEvent, Tokenizer},
+    config::GlobalConfig,
+};
 
 use anyhow::Result;
+use std::time::Duration;
 use tokio::sync::mpsc::UnboundedReceiver;
 
 pub async fn render_stream(
@@ -15,12 +26,29 @@ pub async fn render_stream(
     config: &GlobalConfig,
     abort_signal: AbortSignal,
 ) -> Result<()> {
-    let ret = if *IS_STDOUT_TERMINAL {
-        let render_options = config.read().render_options()?;
+    let stream_raw = config.read().cli_stream_raw.clone();
+    let ret = if let Some(format) = stream_raw {
+        raw_stream(rx, &abort_signal, format == "ndjson").await
+    } else if *IS_STDOUT_TERMINAL {
+        let (render_options, usage_tokenizer, render_interval) = {
+            let config = config.read();
+            let usage_tokenizer: Option<Tokenizer> = config
+                .show_usage
+                .then(|| config.current_model().tokenizer());
+            let render_interval = Duration::from_millis(config.stream_render_interval);
+            (config.render_options()?, usage_tokenizer, render_interval)
+        };
         let mut render = MarkdownRender::init(render_options)?;
-        markdown_stream(rx, &mut render, &abort_signal).await
+        markdown_stream(
+            rx,
+            &mut render,
+            &abort_signal,
+            usage_tokenizer,
+            render_interval,
+        )
+        .await
     } else {
-        raw_stream(rx, &abort_signal).await
+        raw_stream(rx, &abort_signal, false).await
     };
     ret.map_err(|err| err.context("Failed to reader stream"))
 }
@@ -0,0 +1,67 @@
+use super::{DISABLE_AUTOWRAP, ENABLE_AUTOWRAP};
+
+use anyhow::{bail, Context, Result};
+use crossterm::terminal;
+use std::{
+    env,
+    io::{ErrorKind, Write},
+    process::{Command, Stdio},
+};
+use textwrap::core::display_width;
+
+pub const PAGER_MODES: [&str; 3] = ["auto", "always", "never"];
+
+/// Whether `pager` asks for the rendered output to be paged.
+pub fn need_pager(mode: &str, rendered: &str) -> bool {
+    match mode {
+        "always" => true,
+        "auto" => match terminal::size() {
+            Ok((columns, rows)) => {
+                let columns = columns.max(1) as usize;
+                let lines: usize = rendered
+                    .lines()
+                    .map(|line| display_width(line).max(1).div_ceil(columns))
+                    .sum();
+                lines >= rows as usize
+            }
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Pipes the text through `$PAGER` (`less -R` by default), keeping the ANSI colors.
+pub fn run_pager(text: &str) -> Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| "less".into());
+    let mut args =
+        shell_words::split(&pager).with_context(|| format!("Invalid pager `{pager}`"))?;
+    if args.is_empty() {
+        bail!("Invalid pager `{pager}`");
+    }
+    let cmd = args.remove(0);
+    if cmd == "less" && args.is_empty() && env::var("LESS").is_err() {
+        args.push("-R".into());
+    }
+    let mut child = Command::new(&cmd)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run pager `{pager}`"))?;
+    // Pagers show the autowrap switches literally and handle long lines themselves
+    let text = text
+        .replace(DISABLE_AUTOWRAP, "")
+        .replace(ENABLE_AUTOWRAP, "");
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(err) = stdin.write_all(text.as_bytes()) {
+            // The pager was closed before reading everything
+            if err.kind() != ErrorKind::BrokenPipe {
+                return Err(err.into());
+            }
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -1,28 +1,45 @@
-use super::{MarkdownRender, SseEvent};
+use super::{MarkdownRender, SseEvent, DISABLE_AUTOWRAP};
 
-use crate::utils::{poll_abort_signal, spawn_spinner, AbortSignal};
+use crate::client::Tokenizer;
+use crate::utils::{dimmed_text, poll_abort_signal, spawn_spinner, AbortSignal};
 
 use anyhow::Result;
 use crossterm::{
     cursor, queue, style,
     terminal::{self, disable_raw_mode, enable_raw_mode},
 };
+use serde_json::{json, Value};
 use std::{
     io::{self, stdout, Stdout, Write},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use textwrap::core::display_width;
 use tokio::sync::mpsc::UnboundedReceiver;
 
+/// How long to wait for events before polling the abort signal again.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Upper bound of the repaint interval when it is widened because repaints are slow.
+const MAX_RENDER_INTERVAL: Duration = Duration::from_millis(250);
+
 pub async fn markdown_stream(
     rx: UnboundedReceiver<SseEvent>,
     render: &mut MarkdownRender,
     abort_signal: &AbortSignal,
+    usage_tokenizer: Option<Tokenizer>,
+    render_interval: Duration,
 ) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
 
-    let ret = markdown_stream_inner(rx, render, abort_signal, &mut stdout).await;
+    let ret = markdown_stream_inner(
+        rx,
+        render,
+        abort_signal,
+        usage_tokenizer,
+        render_interval,
+        &mut stdout,
+    )
+    .await;
 
     disable_raw_mode()?;
 
@@ -35,6 +52,7 @@ pub async fn markdown_stream(
 pub async fn raw_stream(
     mut rx: UnboundedReceiver<SseEvent>,
     abort_signal: &AbortSignal,
+    ndjson: bool,
 ) -> Result<()> {
     loop {
         if abort_signal.aborted() {
@@ -42,10 +60,7 @@ pub async fn raw_stream(
         }
         if let Some(evt) = rx.recv().await {
             match evt {
-                SseEvent::Text(text) => {
-                    print!("{}", text);
-                    stdout().flush()?;
-                }
+                SseEvent::Text(text) => write_raw_text(&text, ndjson)?,
                 SseEvent::Done => {
                     break;
                 }
@@ -55,14 +70,38 @@ pub async fn raw_stream(
     Ok(())
 }
 
+/// Writes text as is, or as a `delta` frame for `--stream-raw=ndjson`, and flushes it.
+pub fn write_raw_text(text: &str, ndjson: bool) -> Result<()> {
+    if ndjson {
+        return write_ndjson_frame(&json!({ "type": "delta", "text": text }));
+    }
+    let mut stdout = stdout();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Writes one line of `--stream-raw=ndjson` and flushes it.
+pub fn write_ndjson_frame(frame: &Value) -> Result<()> {
+    let mut stdout = stdout();
+    writeln!(stdout, "{frame}")?;
+    stdout.flush()?;
+    Ok(())
+}
+
 async fn markdown_stream_inner(
     mut rx: UnboundedReceiver<SseEvent>,
     render: &mut MarkdownRender,
     abort_signal: &AbortSignal,
+    usage_tokenizer: Option<Tokenizer>,
+    min_render_interval: Duration,
     writer: &mut Stdout,
 ) -> Result<()> {
     let mut buffer = String::new();
     let mut buffer_rows = 1;
+    let mut output_tokens = 0;
+    let started_at = Instant::now();
+    let mut render_at = started_at;
 
     let columns = terminal::size()?.0;
 
@@ -70,15 +109,22 @@ async fn markdown_stream_inner(
 
     'outer: loop {
         if abort_signal.aborted() {
-            return Ok(());
+            break;
         }
-        for reply_event in gather_events(&mut rx).await {
+        for reply_event in gather_events(&mut rx, render_at).await {
             if let Some(spinner) = spinner.take() {
                 spinner.stop();
             }
 
             match reply_event {
                 SseEvent::Text(mut text) => {
+                    let render_started_at = Instant::now();
+
+                    // Counting each delta rather than the whole output keeps repaints cheap
+                    if let Some(tokenizer) = &usage_tokenizer {
+                        output_tokens += tokenizer.count(&text);
+                    }
+
                     // tab width hacking
                     text = text.replace('\t', "    ");
 
@@ -113,8 +159,9 @@ async fn markdown_stream_inner(
                     if text.contains('\n') {
                         let text = format!("{buffer}{text}");
                         let (head, tail) = split_line_tail(&text);
-                        let output = render.render(head);
-                        print_block(writer, &output, columns)?;
+                        if let Some(output) = render.render_stream(head) {
+                            print_block(writer, &output, columns)?;
+                        }
                         buffer = tail.to_string();
                     } else {
                         buffer = format!("{buffer}{text}");
@@ -135,6 +182,28 @@ async fn markdown_stream_inner(
                     }
 
                     writer.flush()?;
+
+                    if usage_tokenizer.is_some() {
+                        // The status line is cleared along with the buffer on the next render.
+                        let (col, _) = cursor::position()?;
+                        let status = usage_status(output_tokens, started_at.elapsed());
+                        queue!(
+                            writer,
+                            style::Print("\r\n"),
+                            style::Print(dimmed_text(&status)),
+                            cursor::MoveUp(1),
+                            cursor::MoveToColumn(col),
+                        )?;
+                        writer.flush()?;
+                    }
+
+                    // Repaints that take long (e.g. slow terminals) widen the interval to avoid flicker.
+                    let mut render_interval = min_render_interval;
+                    if !min_render_interval.is_zero() {
+                        let cost = render_started_at.elapsed() * 4;
+                        render_interval = render_interval.max(cost.min(MAX_RENDER_INTERVAL));
+                    }
+                    render_at = Instant::now() + render_interval;
                 }
                 SseEvent::Done => {
                     break 'outer;
@@ -150,14 +219,52 @@ async fn markdown_stream_inner(
     if let Some(spinner) = spinner.take() {
         spinner.stop();
     }
+    // A table at the end of the answer, or where it was aborted, is still held back
+    if let Some(output) = render.finish_stream(&buffer) {
+        let (_, row) = cursor::position()?;
+        queue!(
+            writer,
+            cursor::MoveTo(0, (row + 1).saturating_sub(buffer_rows)),
+            terminal::Clear(terminal::ClearType::FromCursorDown),
+        )?;
+        let (head, tail) = split_line_tail(&output);
+        if output.contains('\n') {
+            print_block(writer, head, columns)?;
+        }
+        queue!(writer, style::Print(tail))?;
+        writer.flush()?;
+    }
+    if usage_tokenizer.is_some() {
+        queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+        writer.flush()?;
+    }
     Ok(())
 }
 
-async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
+fn usage_status(tokens: usize, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let speed = if secs > 0.0 {
+        tokens as f64 / secs
+    } else {
+        0.0
+    };
+    format!("{tokens} tokens · {speed:.1} tokens/s · {secs:.1}s")
+}
+
+/// Waits for the next events, then keeps gathering them until `render_at`.
+///
+/// Text arriving after a pause is returned at once, while fast streams are coalesced into fewer repaints.
+async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>, render_at: Instant) -> Vec<SseEvent> {
     let mut texts = vec![];
     let mut done = false;
-    tokio::select! {
-        _ = async {
+    match tokio::time::timeout(POLL_INTERVAL, rx.recv()).await {
+        Ok(Some(SseEvent::Text(v))) => texts.push(v),
+        Ok(Some(SseEvent::Done)) | Ok(None) => done = true,
+        Err(_) => return vec![],
+    }
+    if !done {
+        let render_at = tokio::time::Instant::from_std(render_at);
+        let _ = tokio::time::timeout_at(render_at, async {
             while let Some(reply_event) = rx.recv().await {
                 match reply_event {
                     SseEvent::Text(v) => texts.push(v),
@@ -167,9 +274,9 @@ async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
                     }
                 }
             }
-        } => {}
-        _ = tokio::time::sleep(Duration::from_millis(50)) => {}
-    };
+        })
+        .await;
+    }
     let mut events = vec![];
     if !texts.is_empty() {
         events.push(SseEvent::Text(texts.join("")))
@@ -203,6 +310,9 @@ fn split_line_tail(text: &str) -> (&str, &str) {
 }
 
 fn need_rows(text: &str, columns: u16) -> u16 {
+    if text.contains(DISABLE_AUTOWRAP) {
+        return 1;
+    }
     let buffer_width = display_width(text).max(1) as u16;
     buffer_width.div_ceil(columns)
 }
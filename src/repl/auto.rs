@@ -0,0 +1,127 @@
+use crate::client::{call_chat_completions, call_chat_completions_streaming};
+use crate::config::{GlobalConfig, Input};
+use crate::utils::{dimmed_text, tr, warning_text, AbortSignal};
+
+use anyhow::Result;
+use inquire::Confirm;
+use std::time::{Duration, Instant};
+
+const AUTO_PROMPT: &str = r#"Work toward the goal below on your own, using the tools available to you.
+Start every reply with the current plan as a markdown checklist, checking off the finished steps.
+Keep calling tools until the goal is reached. Then, or if it can't be reached, reply with a short summary and no tool calls.
+
+Goal: "#;
+
+/// Limits of one `.auto` run, the run stops once any of them is used up.
+#[derive(Debug, Clone, Copy)]
+struct Budget {
+    max_tool_calls: usize,
+    max_tokens: usize,
+    max_time: Duration,
+    checkpoint: usize,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    steps: usize,
+    tool_calls: usize,
+    tokens: usize,
+}
+
+impl Usage {
+    fn status(&self, budget: &Budget, elapsed: Duration) -> String {
+        format!(
+            "Step {}: {}/{} tool calls, ~{}/{} tokens, {}s/{}s",
+            self.steps,
+            self.tool_calls,
+            budget.max_tool_calls,
+            self.tokens,
+            budget.max_tokens,
+            elapsed.as_secs(),
+            budget.max_time.as_secs()
+        )
+    }
+
+    /// The budget used up, as its name and its setting.
+    fn exhausted(
+        &self,
+        budget: &Budget,
+        elapsed: Duration,
+    ) -> Option<(&'static str, &'static str)> {
+        if self.tool_calls >= budget.max_tool_calls {
+            Some(("tool-call", "auto_max_tool_calls"))
+        } else if self.tokens >= budget.max_tokens {
+            Some(("token", "auto_max_tokens"))
+        } else if elapsed >= budget.max_time {
+            Some(("time", "auto_max_seconds"))
+        } else {
+            None
+        }
+    }
+}
+
+/// Lets the agent call tools toward `goal` until it answers without tool calls or a budget runs out.
+///
+/// The budgets are checked between steps, so a step with several tool calls may go slightly over.
+pub async fn run_auto(config: &GlobalConfig, abort_signal: AbortSignal, goal: &str) -> Result<()> {
+    let budget = {
+        let config = config.read();
+        Budget {
+            max_tool_calls: config.auto_max_tool_calls,
+            max_tokens: config.auto_max_tokens,
+            max_time: Duration::from_secs(config.auto_max_seconds),
+            checkpoint: config.auto_checkpoint,
+        }
+    };
+    let started = Instant::now();
+    let mut usage = Usage::default();
+    let mut input = Input::from_str(config, &format!("{AUTO_PROMPT}{goal}"), None);
+    loop {
+        let client = input.create_client()?;
+        let model = client.model().clone();
+        config.write().before_chat_completion(&input)?;
+        let (output, tool_results) = if input.stream() {
+            call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
+        } else {
+            call_chat_completions(&input, false, client.as_ref(), abort_signal.clone()).await?
+        };
+        config
+            .write()
+            .after_chat_completion(&input, &output, &tool_results)?;
+        usage.steps += 1;
+        usage.tool_calls += tool_results.len();
+        usage.tokens += model.total_tokens(&input.build_messages()?);
+        usage.tokens += model.tokenizer().count(&output);
+        let elapsed = started.elapsed();
+        println!(
+            "{}",
+            dimmed_text(&format!("⚙ {}", usage.status(&budget, elapsed)))
+        );
+        if tool_results.is_empty() {
+            println!("✓ Finished in {} steps.", usage.steps);
+            return Ok(());
+        }
+        if abort_signal.aborted() {
+            println!("{}", warning_text("Stopped, aborted."));
+            return Ok(());
+        }
+        if let Some((name, key)) = usage.exhausted(&budget, elapsed) {
+            let message = format!("Stopped, the {name} budget is used up (see `.set {key}`).");
+            println!("{}", warning_text(&message));
+            return Ok(());
+        }
+        let checkpoint_reached = budget.checkpoint > 0
+            && usage.tool_calls / budget.checkpoint
+                > (usage.tool_calls - tool_results.len()) / budget.checkpoint;
+        if checkpoint_reached {
+            let ans = Confirm::new(&tr("Continue toward the goal?"))
+                .with_default(true)
+                .prompt()?;
+            if !ans {
+                println!("Stopped.");
+                return Ok(());
+            }
+        }
+        input = input.merge_tool_results(output, tool_results);
+    }
+}
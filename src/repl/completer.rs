@@ -1,9 +1,13 @@
 use super::{ReplCommand, REPL_COMMANDS};
 
-use crate::{config::GlobalConfig, utils::fuzzy_match};
+use crate::{
+    client::{list_models, ModelType},
+    config::{Config, GlobalConfig},
+    utils::{fuzzy_match, tr},
+};
 
 use reedline::{Completer, Span, Suggestion};
-use std::collections::HashMap;
+use std::{collections::HashMap, fs::read_dir};
 
 impl Completer for ReplCompleter {
     fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
@@ -24,6 +28,35 @@ impl Completer for ReplCompleter {
         let (cmd, cmd_start) = parts[0];
 
         if !cmd.starts_with('.') {
+            if line.ends_with(char::is_whitespace) {
+                return suggestions;
+            }
+            let (word, word_start) = parts[parts_len - 1];
+            let span = Span::new(word_start, pos);
+            if let Some(prefix) = word.strip_prefix('@') {
+                // A leading mention may also pick the model for this message
+                if parts_len == 1 {
+                    suggestions.extend(
+                        list_models(&self.config.read(), ModelType::Chat)
+                            .iter()
+                            .map(|v| v.id())
+                            .filter(|v| v.starts_with(prefix))
+                            .map(|v| create_suggestion(&format!("@{v} "), "", span)),
+                    );
+                }
+                suggestions.extend(
+                    complete_path(prefix)
+                        .iter()
+                        .map(|v| create_suggestion(&format!("@{v}"), "", span)),
+                );
+            } else if let Some(prefix) = word.strip_prefix('#') {
+                suggestions.extend(
+                    Config::list_roles(true)
+                        .iter()
+                        .filter(|v| v.starts_with(prefix))
+                        .map(|v| create_suggestion(&format!("#{v} "), "", span)),
+                );
+            }
             return suggestions;
         }
 
@@ -49,6 +82,32 @@ impl Completer for ReplCompleter {
             })
             .collect();
 
+        if cmd == ".help" && parts_len == 2 {
+            let span = Span::new(parts[1].1, pos);
+            let mut names: Vec<&str> = vec![];
+            for cmd in self.commands.iter() {
+                let name = cmd.name.split(' ').next().unwrap_or(cmd.name);
+                if name != ".help"
+                    && name[1..].starts_with(parts[1].0.trim_start_matches('.'))
+                    && !names.contains(&name)
+                {
+                    names.push(name);
+                }
+            }
+            return names
+                .into_iter()
+                .map(|name| create_suggestion(name, "", span))
+                .collect();
+        }
+
+        if cmd == ".diff" && parts_len == 2 {
+            let span = Span::new(parts[1].1, pos);
+            return complete_path(parts[1].0)
+                .iter()
+                .map(|v| create_suggestion(v, "", span))
+                .collect();
+        }
+
         if parts_len > 1 {
             let span = Span::new(parts[parts_len - 1].1, pos);
             let args_line = &line[parts[1].1..];
@@ -69,14 +128,14 @@ impl Completer for ReplCompleter {
             let span = Span::new(cmd_start, pos);
             suggestions.extend(commands.iter().map(|cmd| {
                 let name = cmd.name;
-                let description = cmd.description;
+                let description = tr(cmd.description);
                 let has_group = self.groups.get(name).map(|v| *v > 1).unwrap_or_default();
                 let name = if has_group {
                     name.to_string()
                 } else {
                     format!("{name} ")
                 };
-                create_suggestion(&name, description, span)
+                create_suggestion(&name, &description, span)
             }))
         }
         suggestions
@@ -128,6 +187,30 @@ fn create_suggestion(value: &str, description: &str, span: Span) -> Suggestion {
     }
 }
 
+/// Lists the paths starting with `prefix` for `@file` mentions and `.diff`, directories end with `/`.
+fn complete_path(prefix: &str) -> Vec<String> {
+    let (dir, name_prefix) = match prefix.rfind('/') {
+        Some(index) => (&prefix[..=index], &prefix[index + 1..]),
+        None => ("", prefix),
+    };
+    let Ok(entries) = read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return vec![];
+    };
+    let mut values: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(name_prefix) || (name.starts_with('.') && name_prefix.is_empty()) {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{name}{suffix}"))
+        })
+        .collect();
+    values.sort_unstable();
+    values
+}
+
 fn split_line(line: &str) -> Vec<(&str, usize)> {
     let mut parts = vec![];
     let mut part_start = None;
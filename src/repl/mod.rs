@@ -1,45 +1,71 @@
+mod auto;
 mod completer;
 mod highlighter;
 mod prompt;
+mod stdin;
 
+use self::auto::run_auto;
 use self::completer::ReplCompleter;
 use self::highlighter::ReplHighlighter;
 use self::prompt::ReplPrompt;
+pub use self::stdin::run_repl_stdin;
 
-use crate::client::{call_chat_completions, call_chat_completions_streaming};
-use crate::config::{AssertState, Config, GlobalConfig, Input, LastMessage, StateFlags};
-use crate::render::render_error;
+use crate::client::{
+    call_chat_completions, call_chat_completions_streaming, compare_models,
+    is_stream_unsupported_error, Model, ModelType,
+};
+use crate::config::{
+    run_agent_pipeline, AssertState, Config, ContextSets, GlobalConfig, Input, LastMessage, State,
+    StateFlags, CONTEXT_SET_PREFIX,
+};
+use crate::render::{render_diff, render_error, DiffTheme};
 use crate::utils::{
-    abortable_run_with_spinner, create_abort_signal, set_text, temp_file, AbortSignal,
+    abortable_run_with_spinner, create_abort_signal, dimmed_text, extract_code_block,
+    extract_code_blocks, is_url, run_command_streaming, set_text, temp_file, tr, warning_text,
+    AbortSignal, SHELL,
 };
 
 use anyhow::{bail, Context, Result};
 use fancy_regex::Regex;
+use inquire::Confirm;
+use nu_ansi_term::{Color, Style};
 use reedline::{
     default_emacs_keybindings, default_vi_insert_keybindings, default_vi_normal_keybindings,
-    ColumnarMenu, EditCommand, EditMode, Emacs, KeyCode, KeyModifiers, Keybindings, Reedline,
-    ReedlineEvent, ReedlineMenu, ValidationResult, Validator, Vi,
+    ColumnarMenu, DefaultHinter, EditCommand, EditMode, Emacs, FileBackedHistory, KeyCode,
+    KeyModifiers, Keybindings, Reedline, ReedlineEvent, ReedlineMenu, ValidationResult, Validator,
+    Vi, HISTORY_SIZE,
 };
 use reedline::{MenuBuilder, Signal};
-use std::{env, process};
+use std::{
+    env, fs, mem,
+    path::{Path, PathBuf},
+    process,
+};
 
 const MENU_NAME: &str = "completion_menu";
 
 lazy_static::lazy_static! {
-    static ref REPL_COMMANDS: [ReplCommand; 34] = [
-        ReplCommand::new(".help", "Show this help message", AssertState::pass()),
-        ReplCommand::new(".info", "View system info", AssertState::pass()),
-        ReplCommand::new(".model", "Change the current LLM", AssertState::pass()),
+    static ref REPL_COMMANDS: [ReplCommand; 46] = [
+        ReplCommand::new(".help", "Show this help message", AssertState::pass())
+        .examples(&[".help", ".help .file", ".help session"]),
+        ReplCommand::new(".info", "View system info", AssertState::pass())
+        .examples(&[".info", ".info role", ".info session", ".info last                 # the model, tokens, latency, tools and rag sources of the last reply"]),
+        ReplCommand::new(".model", "Change the current LLM", AssertState::pass())
+        .examples(&[".model                     # pick from all chat models, favorites and recently used first", ".model openai:gpt-4o", ".model ollama:llama3.1"])
+        .settings(&["favorite_models"]),
         ReplCommand::new(
             ".prompt",
             "Create a temporary role using a prompt",
             AssertState::False(StateFlags::SESSION | StateFlags::AGENT)
-        ),
+        )
+        .examples(&[".prompt you are a translator, translate the input to english"]),
         ReplCommand::new(
             ".role",
             "Create or switch to a specific role",
             AssertState::False(StateFlags::SESSION | StateFlags::AGENT)
-        ),
+        )
+        .examples(&[".role                      # pick from all roles", ".role coder", ".role coder write a quicksort in rust"])
+        .settings(&["temperature", "top_p", "use_tools"]),
         ReplCommand::new(
             ".info role",
             "View role info",
@@ -64,7 +90,9 @@ lazy_static::lazy_static! {
             ".session",
             "Begin a session",
             AssertState::False(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
-        ),
+        )
+        .examples(&[".session", ".session work", ".session temp"])
+        .settings(&["save_session", "compress_threshold", "compress_strategy", "compress_keep_recent"]),
         ReplCommand::new(
             ".empty session",
             "Erase messages in the current session",
@@ -80,6 +108,12 @@ lazy_static::lazy_static! {
             "View session info",
             AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION),
         ),
+        ReplCommand::new(
+            ".view session",
+            "View a saved session without joining it",
+            AssertState::pass()
+        )
+        .examples(&[".view session work"]),
         ReplCommand::new(
             ".edit session",
             "Edit the current session",
@@ -95,22 +129,43 @@ lazy_static::lazy_static! {
             "End the session",
             AssertState::True(StateFlags::SESSION_EMPTY | StateFlags::SESSION)
         ),
-        ReplCommand::new(".agent", "Use a agent", AssertState::bare()),
+        ReplCommand::new(".agent", "Use a agent", AssertState::bare())
+        .examples(&[".agent                     # pick from the installed agents", ".agent todo", ".agent todo work"])
+        .settings(&["function_calling", "use_tools"]),
         ReplCommand::new(
             ".starter",
             "Use the conversation starter",
-            AssertState::True(StateFlags::AGENT)
-        ),
+            AssertState::True(StateFlags::AGENT | StateFlags::ROLE)
+        )
+        .examples(&[".starter", ".starter What can you do?"]),
         ReplCommand::new(
             ".variable",
             "Set agent variable",
             AssertState::TrueFalse(StateFlags::AGENT, StateFlags::SESSION)
-        ),
+        )
+        .examples(&[".variable username alice"]),
+        ReplCommand::new(
+            ".auto",
+            "Let the agent work toward a goal on its own, within budgets",
+            AssertState::True(StateFlags::AGENT)
+        )
+        .examples(&[".auto find and fix the failing test"])
+        .settings(&["auto_max_tool_calls", "auto_max_tokens", "auto_max_seconds", "auto_checkpoint"]),
         ReplCommand::new(
             ".info agent",
             "View agent info",
             AssertState::True(StateFlags::AGENT),
         ),
+        ReplCommand::new(
+            ".edit agent-docs",
+            "Edit the agent RAG documents",
+            AssertState::True(StateFlags::AGENT),
+        ),
+        ReplCommand::new(
+            ".rebuild agent-rag",
+            "Rebuild the agent RAG to sync document changes",
+            AssertState::True(StateFlags::AGENT),
+        ),
         ReplCommand::new(
             ".exit agent",
             "Leave the agent",
@@ -120,7 +175,9 @@ lazy_static::lazy_static! {
             ".rag",
             "Init or use the RAG",
             AssertState::False(StateFlags::AGENT)
-        ),
+        )
+        .examples(&[".rag", ".rag docs"])
+        .settings(&["rag_reranker_model", "rag_top_k"]),
         ReplCommand::new(
             ".edit rag-docs",
             "Edit the RAG documents",
@@ -150,16 +207,46 @@ lazy_static::lazy_static! {
             ".file",
             "Include files, directories, URLs or commands",
             AssertState::pass()
-        ),
+        )
+        .examples(&[".file /tmp/file.txt", ".file src/ Cargo.toml -- analyze", ".file https://example.com/file.txt -- summarize", ".file https://example.com/image.png -- recognize text", ".file %% -- translate last reply to english", ".file %clip% -- explain this screenshot", ".file `git diff` -- Generate git commit message"])
+        .settings(&["document_loaders"]),
+        ReplCommand::new(
+            ".context",
+            "Manage named sets of files, attached with use:<name>",
+            AssertState::pass()
+        )
+        .examples(&[".context create api-docs docs/api.md src/api/ https://example.com/spec.json", ".context list", ".context delete api-docs", ".file use:api-docs -- how do I paginate?"]),
+        ReplCommand::new(".buffer", "Restore the input buffer saved by Alt+S or a failed message", AssertState::pass())
+        .examples(&[".buffer restore"]),
         ReplCommand::new(".continue", "Continue the response", AssertState::pass()),
         ReplCommand::new(
             ".regenerate",
             "Regenerate the response",
             AssertState::pass()
         ),
-        ReplCommand::new(".copy", "Copy the last chat response", AssertState::pass()),
-        ReplCommand::new(".set", "Adjust runtime configuration", AssertState::pass()),
-        ReplCommand::new(".delete", "Delete roles/sessions/RAGs/agents", AssertState::pass()),
+        ReplCommand::new(".f", "Ask a suggested follow-up question", AssertState::pass())
+        .examples(&[".f", ".f 2"])
+        .settings(&["followups", "followup_model"]),
+        ReplCommand::new(".copy", "Copy the last chat response, or one of its code blocks", AssertState::pass())
+        .examples(&[".copy", ".copy code", ".copy code 2"]),
+        ReplCommand::new(".run", "Run a code block from the last chat response", AssertState::pass())
+        .examples(&[".run", ".run 2"])
+        .settings(&["code_interpreters"]),
+        ReplCommand::new(".last", "Open the last chat response in the pager", AssertState::pass())
+        .settings(&["pager"]),
+        ReplCommand::new(".diff", "Diff the last two chat responses, or a file against the last one", AssertState::pass())
+        .examples(&[".diff", ".diff src/main.rs"]),
+        ReplCommand::new(
+            ".compare",
+            "Compare responses from multiple models",
+            AssertState::pass()
+        )
+        .examples(&[".compare openai:gpt-4o,claude:claude-3-5-sonnet-20241022 explain monads", ".compare openai:gpt-4o,ollama:llama3"]),
+        ReplCommand::new(".set", "Adjust runtime configuration", AssertState::pass())
+        .examples(&[".set temperature 0.2", ".set stream false", ".set use_tools fs,web_search", ".set max_output_tokens null"]),
+        ReplCommand::new(".settings", "Browse and edit runtime configuration", AssertState::pass()),
+        ReplCommand::new(".delete", "Delete roles/sessions/RAGs/agents", AssertState::pass())
+        .examples(&[".delete role", ".delete session"]),
         ReplCommand::new(".exit", "Exit the REPL", AssertState::pass()),
     ];
     static ref COMMAND_RE: Regex = Regex::new(r"^\s*(\.\S*)\s*").unwrap();
@@ -169,6 +256,8 @@ lazy_static::lazy_static! {
 pub struct Repl {
     config: GlobalConfig,
     editor: Reedline,
+    editor_settings: (Option<PathBuf>, bool),
+    buffer_file: PathBuf,
     prompt: ReplPrompt,
     abort_signal: AbortSignal,
 }
@@ -176,6 +265,7 @@ pub struct Repl {
 impl Repl {
     pub fn init(config: &GlobalConfig) -> Result<Self> {
         let editor = Self::create_editor(config)?;
+        let editor_settings = Self::editor_settings(config);
 
         let prompt = ReplPrompt::new(config);
         let abort_signal = create_abort_signal();
@@ -183,22 +273,41 @@ impl Repl {
         Ok(Self {
             config: config.clone(),
             editor,
+            editor_settings,
+            buffer_file: temp_file("-buffer-", ".txt"),
             prompt,
             abort_signal,
         })
     }
 
-    pub async fn run(&mut self) -> Result<()> {
+    /// Runs the REPL, sending `seed` (from `--repl <text>`) as the first message if given.
+    pub async fn run(&mut self, seed: Option<Input>) -> Result<()> {
         if AssertState::False(StateFlags::AGENT | StateFlags::RAG)
             .assert(self.config.read().state())
         {
             self.banner();
         }
 
+        if let Some(input) = seed {
+            let prompt = self.config.read().render_prompt_left();
+            println!("{prompt}{}", input.raw());
+            if let Err(err) = ask(&self.config, self.abort_signal.clone(), input, true).await {
+                render_error(err);
+            }
+            println!();
+        }
+
         loop {
             if self.abort_signal.aborted_ctrld() {
                 break;
             }
+            // Switch to the history of the agent or session now in use
+            let editor_settings = Self::editor_settings(&self.config);
+            if editor_settings != self.editor_settings {
+                let editor = mem::replace(&mut self.editor, Reedline::create());
+                self.editor = Self::with_history(editor, &self.config)?;
+                self.editor_settings = editor_settings;
+            }
             let sig = self.editor.read_line(&self.prompt);
             match sig {
                 Ok(Signal::Success(line)) => {
@@ -211,6 +320,10 @@ impl Repl {
                         }
                         Err(err) => {
                             render_error(err);
+                            // Keep a failed message around instead of making the user retype it
+                            if parse_command(&line).is_none() && self.dump_buffer(&line).is_ok() {
+                                println!("(To get the input back, enter \".buffer restore\")");
+                            }
                             println!()
                         }
                     }
@@ -230,7 +343,7 @@ impl Repl {
         Ok(())
     }
 
-    async fn handle(&self, mut line: &str) -> Result<bool> {
+    async fn handle(&mut self, mut line: &str) -> Result<bool> {
         if let Ok(Some(captures)) = MULTILINE_RE.captures(line) {
             if let Some(text_match) = captures.get(1) {
                 line = text_match.as_str();
@@ -238,9 +351,10 @@ impl Repl {
         }
         match parse_command(line) {
             Some((cmd, args)) => match cmd {
-                ".help" => {
-                    dump_repl_help();
-                }
+                ".help" => match args {
+                    Some(name) => println!("{}", render_command_help(name)?),
+                    None => dump_repl_help(),
+                },
                 ".info" => match args {
                     Some("role") => {
                         let info = self.config.read().role_info()?;
@@ -258,17 +372,29 @@ impl Repl {
                         let info = self.config.read().agent_info()?;
                         print!("{}", info);
                     }
+                    Some("last") => {
+                        let info = self.config.read().last_exchange_info()?;
+                        print!("{}", info);
+                    }
                     Some(_) => unknown_command()?,
                     None => {
                         let output = self.config.read().sysinfo()?;
                         print!("{}", output);
                     }
                 },
+                ".view" => match split_args(args) {
+                    Some(("session", Some(name))) => {
+                        let output = self.config.read().view_session(name)?;
+                        print!("{}", output);
+                    }
+                    _ => println!("Usage: .view session <name>"),
+                },
                 ".model" => match args {
                     Some(name) => {
                         self.config.write().set_model(name)?;
+                        State::record_model(self.config.read().current_model());
                     }
-                    None => println!("Usage: .model <name>"),
+                    None => Config::select_model(&self.config)?,
                 },
                 ".prompt" => match args {
                     Some(text) => {
@@ -292,11 +418,12 @@ impl Repl {
                             }
                         }
                     },
-                    None => println!(
-                        r#"Usage:
-    .role <name>                    # If the role exists, switch to it; otherwise, create a new role
-    .role <name> [text]...          # Temporarily switch to the role, send the text, and switch back"#
-                    ),
+                    None => {
+                        let name = self.config.read().select_role()?;
+                        if let Some(name) = name {
+                            self.config.write().use_role(&name)?;
+                        }
+                    }
                 },
                 ".session" => {
                     self.config.write().use_session(args)?;
@@ -305,28 +432,48 @@ impl Repl {
                 ".rag" => {
                     Config::use_rag(&self.config, args, self.abort_signal.clone()).await?;
                 }
-                ".agent" => match split_args(args) {
-                    Some((agent_name, session_name)) => {
-                        Config::use_agent(
-                            &self.config,
-                            agent_name,
-                            session_name,
-                            self.abort_signal.clone(),
-                        )
-                        .await?;
+                ".agent" => {
+                    let (words, _) = split_args_text(args.unwrap_or_default(), cfg!(windows));
+                    match words.as_slice() {
+                        [] => {
+                            if let Some(agent_name) = Config::select_agent()? {
+                                Config::use_agent(
+                                    &self.config,
+                                    &agent_name,
+                                    None,
+                                    self.abort_signal.clone(),
+                                )
+                                .await?;
+                            }
+                        }
+                        [agent_name] | [agent_name, _] => {
+                            Config::use_agent(
+                                &self.config,
+                                agent_name,
+                                words.get(1).map(|v| v.as_str()),
+                                self.abort_signal.clone(),
+                            )
+                            .await?;
+                        }
+                        _ => println!(r#"Usage: .agent <agent-name> [session-name]"#),
                     }
-                    None => println!(r#"Usage: .agent <agent-name> [session-name]"#),
-                },
+                }
                 ".starter" => match args {
                     Some(value) => {
                         let input = Input::from_str(&self.config, value, None);
                         ask(&self.config, self.abort_signal.clone(), input, true).await?;
                     }
                     None => {
-                        let banner = self.config.read().agent_banner()?;
+                        let banner = self.config.read().starter_banner()?;
                         self.config.read().print_markdown(&banner)?;
                     }
                 },
+                ".auto" => match args {
+                    Some(goal) => {
+                        run_auto(&self.config, self.abort_signal.clone(), goal).await?;
+                    }
+                    None => println!("Usage: .auto <goal>"),
+                },
                 ".variable" => match args {
                     Some(args) => {
                         self.config.write().set_agent_variable(args)?;
@@ -356,8 +503,11 @@ impl Repl {
                     Some("rag-docs") => {
                         Config::edit_rag_docs(&self.config, self.abort_signal.clone()).await?;
                     }
+                    Some("agent-docs") => {
+                        Config::edit_agent_docs(&self.config, self.abort_signal.clone()).await?;
+                    }
                     _ => {
-                        println!(r#"Usage: .edit <role|session|rag-docs>"#)
+                        println!(r#"Usage: .edit <role|session|rag-docs|agent-docs>"#)
                     }
                 },
                 ".compress" => match args {
@@ -386,8 +536,11 @@ impl Repl {
                     Some("rag") => {
                         Config::rebuild_rag(&self.config, self.abort_signal.clone()).await?;
                     }
+                    Some("agent-rag") => {
+                        Config::rebuild_agent_rag(&self.config, self.abort_signal.clone()).await?;
+                    }
                     _ => {
-                        println!(r#"Usage: .rebuild rag"#)
+                        println!(r#"Usage: .rebuild <rag|agent-rag>"#)
                     }
                 },
                 ".sources" => match args {
@@ -401,7 +554,7 @@ impl Repl {
                 },
                 ".file" => match args {
                     Some(args) => {
-                        let (files, text) = split_files_text(args, cfg!(windows));
+                        let (files, text) = split_args_text(args, cfg!(windows));
                         let input = Input::from_files_with_spinner(
                             &self.config,
                             text,
@@ -413,16 +566,50 @@ impl Repl {
                         ask(&self.config, self.abort_signal.clone(), input, true).await?;
                     }
                     None => println!(
-                        r#"Usage: .file <file|dir|url|%%|cmd>... [-- <text>...]
+                        r#"Usage: .file <file|dir|url|%%|%clip%|cmd>... [-- <text>...]
 
 .file /tmp/file.txt
 .file src/ Cargo.toml -- analyze
 .file https://example.com/file.txt -- summarize
 .file https://example.com/image.png -- recognize text
 .file %% -- translate last reply to english
+.file %clip% -- explain this screenshot
 .file `git diff` -- Generate git commit message"#
                     ),
                 },
+                ".context" => {
+                    let words = args
+                        .map(|v| split_args_text(v, cfg!(windows)).0)
+                        .unwrap_or_default();
+                    let mut sets = ContextSets::load()?;
+                    match words
+                        .iter()
+                        .map(|v| v.as_str())
+                        .collect::<Vec<_>>()
+                        .as_slice()
+                    {
+                        ["create", name, paths @ ..] if !paths.is_empty() => {
+                            sets.create(name, &words[2..])?;
+                            sets.save()?;
+                            println!("✓ Saved the context set, attach it with '{CONTEXT_SET_PREFIX}{name}'.");
+                        }
+                        ["delete", name] => {
+                            sets.delete(name)?;
+                            sets.save()?;
+                            println!("✓ Successfully deleted the context set.");
+                        }
+                        [] | ["list"] => {
+                            for (name, paths) in sets.list() {
+                                println!("{name}: {}", paths.join(" "));
+                            }
+                        }
+                        _ => println!(
+                            r#"Usage: .context create <name> <file|dir|url|cmd>...
+       .context list
+       .context delete <name>"#
+                        ),
+                    }
+                }
                 ".continue" => {
                     let LastMessage {
                         mut input, output, ..
@@ -455,14 +642,73 @@ impl Repl {
                     input.set_regenerate();
                     ask(&self.config, self.abort_signal.clone(), input, true).await?;
                 }
+                ".f" => {
+                    let followups = self.config.read().last_followups.clone();
+                    if followups.is_empty() {
+                        bail!("No follow-up questions, turn them on with `.set followups true`");
+                    }
+                    match args {
+                        Some(n) => {
+                            let text = match n.parse::<usize>() {
+                                Ok(n) if (1..=followups.len()).contains(&n) => &followups[n - 1],
+                                _ => bail!(
+                                    "Invalid follow-up '{n}', there are {} suggestions",
+                                    followups.len()
+                                ),
+                            };
+                            println!("{}", dimmed_text(text));
+                            let input = Input::from_str(&self.config, text, None);
+                            ask(&self.config, self.abort_signal.clone(), input, true).await?;
+                        }
+                        None => print_followups(&followups),
+                    }
+                }
+                ".compare" => {
+                    let (models, text) = match args {
+                        Some(args) => match args.split_once(char::is_whitespace) {
+                            Some((models, text)) => (models, text.trim()),
+                            None => (args, ""),
+                        },
+                        None => ("", ""),
+                    };
+                    let model_ids: Vec<String> = models
+                        .split(',')
+                        .map(|v| v.trim())
+                        .filter(|v| !v.is_empty())
+                        .map(|v| v.to_string())
+                        .collect();
+                    if model_ids.is_empty() {
+                        println!(
+                            r#"Usage: .compare <model>,<model>... [text]
+
+.compare openai:gpt-4o,claude:claude-3-5-sonnet-20241022 explain monads
+.compare openai:gpt-4o,ollama:llama3     # reuse the last input"#
+                        );
+                    } else {
+                        let mut input = if text.is_empty() {
+                            match self.config.read().last_message.as_ref() {
+                                Some(v) => v.input.clone(),
+                                None => bail!("No input to compare models with"),
+                            }
+                        } else {
+                            Input::from_str(&self.config, text, None)
+                        };
+                        input.use_embeddings(self.abort_signal.clone()).await?;
+                        compare_models(&self.config, &input, &model_ids, self.abort_signal.clone())
+                            .await?;
+                    }
+                }
                 ".set" => match args {
                     Some(args) => {
                         Config::update(&self.config, args)?;
                     }
                     _ => {
-                        println!("Usage: .set <key> <value>...")
+                        println!("Usage: .set <key> <value>...\n\nType \".settings\" to browse all keys.")
                     }
                 },
+                ".settings" => {
+                    Config::settings_browser(&self.config)?;
+                }
                 ".delete" => match args {
                     Some(args) => {
                         Config::delete(&self.config, args)?;
@@ -483,8 +729,139 @@ impl Repl {
                         Some(v) => v,
                         None => bail!("No chat response to copy"),
                     };
-                    self.copy(&output)
-                        .with_context(|| "Failed to copy the last chat response")?;
+                    let args: Vec<&str> = args.unwrap_or_default().split_whitespace().collect();
+                    let text = match args[..] {
+                        [] => Some(output),
+                        ["code", ref rest @ ..] if rest.len() <= 1 => {
+                            let mut blocks = extract_code_blocks(&output);
+                            if blocks.is_empty() {
+                                bail!("No code block in the last chat response");
+                            }
+                            // Defaults to the last block, numbers start from 1
+                            let index = match rest.first() {
+                                Some(n) => match n.parse::<usize>() {
+                                    Ok(n) if (1..=blocks.len()).contains(&n) => n - 1,
+                                    _ => bail!(
+                                        "Invalid code block '{n}', the last chat response has {} code block(s)",
+                                        blocks.len()
+                                    ),
+                                },
+                                None => blocks.len() - 1,
+                            };
+                            Some(blocks.swap_remove(index).1)
+                        }
+                        _ => {
+                            println!("Usage: .copy [code [n]]");
+                            None
+                        }
+                    };
+                    if let Some(text) = text {
+                        self.copy(&text)
+                            .with_context(|| "Failed to copy the last chat response")?;
+                    }
+                }
+                ".buffer" => match args {
+                    // Only sent by Alt+S, a typed command has already cleared the buffer
+                    Some("dump") => {
+                        let text = self.editor.current_buffer_contents().to_string();
+                        if text.trim().is_empty() {
+                            bail!("Nothing to save, press Alt+S while typing a message");
+                        }
+                        self.dump_buffer(&text)?;
+                        println!(
+                            "\nSaved the input buffer to '{}'",
+                            self.buffer_file.display()
+                        );
+                    }
+                    Some("restore") => {
+                        let text = fs::read_to_string(&self.buffer_file)
+                            .with_context(|| "No saved input buffer")?;
+                        self.editor.run_edit_commands(&[
+                            EditCommand::Clear,
+                            EditCommand::InsertString(text),
+                        ]);
+                    }
+                    _ => println!("Usage: .buffer restore"),
+                },
+                ".run" => {
+                    let output = match self
+                        .config
+                        .read()
+                        .last_message
+                        .as_ref()
+                        .filter(|v| !v.output.is_empty())
+                        .map(|v| v.output.clone())
+                    {
+                        Some(v) => v,
+                        None => bail!("No chat response to run code from"),
+                    };
+                    let mut blocks = extract_code_blocks(&output);
+                    if blocks.is_empty() {
+                        bail!("No code block in the last chat response");
+                    }
+                    // Defaults to the last block, numbers start from 1
+                    let index = match args {
+                        Some(n) => match n.parse::<usize>() {
+                            Ok(n) if (1..=blocks.len()).contains(&n) => n - 1,
+                            _ => bail!(
+                                "Invalid code block '{n}', the last chat response has {} code block(s)",
+                                blocks.len()
+                            ),
+                        },
+                        None => blocks.len() - 1,
+                    };
+                    let (lang, code) = blocks.swap_remove(index);
+                    self.run_code_block(&lang, &code).await?;
+                }
+                ".last" => {
+                    let output = match self
+                        .config
+                        .read()
+                        .last_message
+                        .as_ref()
+                        .filter(|v| !v.output.is_empty())
+                        .map(|v| v.output.clone())
+                    {
+                        Some(v) => v,
+                        None => bail!("No chat response to show"),
+                    };
+                    Config::page_markdown(&self.config, &output)?;
+                }
+                ".diff" => {
+                    let (last_output, previous_output) = {
+                        let config = self.config.read();
+                        let last_output = config
+                            .last_message
+                            .as_ref()
+                            .filter(|v| !v.output.is_empty())
+                            .map(|v| v.output.clone());
+                        (last_output, config.previous_output.clone())
+                    };
+                    let Some(last_output) = last_output else {
+                        bail!("No chat response to diff");
+                    };
+                    let (old, new, old_label, new_label) = match args {
+                        Some(path) => {
+                            let contents = fs::read_to_string(path)
+                                .with_context(|| format!("Failed to read '{path}'"))?;
+                            // Compare against the code in the response if there is any
+                            let new = extract_code_block(&last_output, None)
+                                .map(|(_, code)| code)
+                                .unwrap_or(last_output);
+                            (contents, new, path.to_string(), "last response".to_string())
+                        }
+                        None => match previous_output {
+                            Some(previous_output) => (
+                                previous_output,
+                                last_output,
+                                "previous response".to_string(),
+                                "last response".to_string(),
+                            ),
+                            None => bail!("Need two chat responses to diff"),
+                        },
+                    };
+                    let color = self.config.read().diff_theme() != DiffTheme::Plain;
+                    println!("{}", render_diff(&old, &new, &old_label, &new_label, color));
                 }
                 ".exit" => match args {
                     Some("role") => {
@@ -517,7 +894,42 @@ impl Repl {
                 _ => unknown_command()?,
             },
             None => {
-                let input = Input::from_str(&self.config, line, None);
+                // `@<model-id> text` sends just this message to another model
+                let (line, model) = match split_model_override(line, |v| Path::new(v).exists()) {
+                    Some((model_id, text)) => {
+                        let config = self.config.read();
+                        let model = Model::retrieve_model(&config, model_id, ModelType::Chat)?;
+                        if let Some(agent) = &config.agent {
+                            agent.check_model(&model)?;
+                        }
+                        (text, Some(model))
+                    }
+                    None => (line, None),
+                };
+                let (text, files, role) = parse_mentions(
+                    line,
+                    |v| is_url(v) || v.starts_with(CONTEXT_SET_PREFIX) || Path::new(v).exists(),
+                    Config::has_role,
+                );
+                let role = match role {
+                    Some(name) => Some(self.config.read().retrieve_role(&name)?),
+                    None => None,
+                };
+                let mut input = if files.is_empty() {
+                    Input::from_str(&self.config, &text, role)
+                } else {
+                    Input::from_files_with_spinner(
+                        &self.config,
+                        &text,
+                        files,
+                        role,
+                        self.abort_signal.clone(),
+                    )
+                    .await?
+                };
+                if let Some(model) = model {
+                    input.set_model(model);
+                }
                 ask(&self.config, self.abort_signal.clone(), input, true).await?;
             }
         }
@@ -530,11 +942,10 @@ impl Repl {
     fn banner(&self) {
         let name = env!("CARGO_CRATE_NAME");
         let version = env!("CARGO_PKG_VERSION");
-        print!(
-            r#"Welcome to {name} {version}
-Type ".help" for additional help.
-"#
-        )
+        let welcome = tr("Welcome to {name} {version}")
+            .replace("{name}", name)
+            .replace("{version}", version);
+        println!("{welcome}\n{}", tr(r#"Type ".help" for additional help."#));
     }
 
     fn create_editor(config: &GlobalConfig) -> Result<Reedline> {
@@ -559,7 +970,33 @@ Type ".help" for additional help.
             editor = editor.with_buffer_editor(command, temp_file);
         }
 
-        Ok(editor)
+        Self::with_history(editor, config)
+    }
+
+    /// Sets up the input history and the hints drawn from it.
+    fn with_history(editor: Reedline, config: &GlobalConfig) -> Result<Reedline> {
+        let config = config.read();
+        let history = match config.repl_history_file() {
+            Some(path) => FileBackedHistory::with_file(HISTORY_SIZE, path.clone())
+                .with_context(|| format!("Failed to load history from '{}'", path.display()))?,
+            None => FileBackedHistory::default(),
+        };
+        let editor = editor.with_history(Box::new(history));
+        if !config.history_hints {
+            return Ok(editor.disable_hints());
+        }
+        let color = if config.light_theme {
+            Color::LightGray
+        } else {
+            Color::DarkGray
+        };
+        let hinter = DefaultHinter::default().with_style(Style::new().fg(color));
+        Ok(editor.with_hinter(Box::new(hinter)))
+    }
+
+    fn editor_settings(config: &GlobalConfig) -> (Option<PathBuf>, bool) {
+        let config = config.read();
+        (config.repl_history_file(), config.history_hints)
     }
 
     fn extra_keybindings(keybindings: &mut Keybindings) {
@@ -581,6 +1018,50 @@ Type ".help" for additional help.
             KeyCode::Enter,
             ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
         );
+        keybindings.add_binding(
+            KeyModifiers::ALT,
+            KeyCode::Char('s'),
+            ReedlineEvent::ExecuteHostCommand(".buffer dump".into()),
+        );
+    }
+
+    /// Adds the `keybindings_custom` bindings, warning about the ones that can't be parsed.
+    fn custom_keybindings(config: &GlobalConfig, keybindings: &mut Keybindings) {
+        for (chord, action) in &config.read().keybindings_custom {
+            let binding = parse_key_chord(chord)
+                .and_then(|(modifiers, code)| Ok((modifiers, code, parse_key_action(action)?)));
+            match binding {
+                Ok((modifiers, code, event)) => keybindings.add_binding(modifiers, code, event),
+                Err(err) => eprintln!(
+                    "{}",
+                    warning_text(&format!("Skipped keybinding '{chord}': {err}"))
+                ),
+            }
+        }
+    }
+
+    /// The emacs kill/yank and undo/redo keys, which the vi insert mode lacks.
+    fn readline_keybindings(keybindings: &mut Keybindings) {
+        let bindings = [
+            (KeyModifiers::CONTROL, 'z', EditCommand::Undo),
+            (KeyModifiers::CONTROL, 'g', EditCommand::Redo),
+            (KeyModifiers::CONTROL, 'k', EditCommand::CutToLineEnd),
+            (KeyModifiers::CONTROL, 'u', EditCommand::CutFromStart),
+            (KeyModifiers::CONTROL, 'w', EditCommand::CutWordLeft),
+            (KeyModifiers::ALT, 'd', EditCommand::CutWordRight),
+            (
+                KeyModifiers::CONTROL,
+                'y',
+                EditCommand::PasteCutBufferBefore,
+            ),
+        ];
+        for (modifiers, c, command) in bindings {
+            keybindings.add_binding(
+                modifiers,
+                KeyCode::Char(c),
+                ReedlineEvent::Edit(vec![command]),
+            );
+        }
     }
 
     fn create_edit_mode(config: &GlobalConfig) -> Box<dyn EditMode> {
@@ -589,10 +1070,13 @@ Type ".help" for additional help.
             let mut insert_keybindings = default_vi_insert_keybindings();
             Self::extra_keybindings(&mut normal_keybindings);
             Self::extra_keybindings(&mut insert_keybindings);
+            Self::readline_keybindings(&mut insert_keybindings);
+            Self::custom_keybindings(config, &mut insert_keybindings);
             Box::new(Vi::new(insert_keybindings, normal_keybindings))
         } else {
             let mut keybindings = default_emacs_keybindings();
             Self::extra_keybindings(&mut keybindings);
+            Self::custom_keybindings(config, &mut keybindings);
             Box::new(Emacs::new(keybindings))
         };
         edit_mode
@@ -603,6 +1087,94 @@ Type ".help" for additional help.
         ReedlineMenu::EngineCompleter(Box::new(completion_menu))
     }
 
+    /// Shows a code block, runs it once confirmed and offers to send its output to the model.
+    async fn run_code_block(&self, lang: &str, code: &str) -> Result<()> {
+        let command = |cmd: &str, arg: &str| (cmd.to_string(), vec![arg.to_string(), code.into()]);
+        let mut _code_file = None;
+        let (cmd, args) = match lang {
+            "" => bail!("The code block names no language, so there is no telling how to run it"),
+            "sh" | "bash" | "zsh" | "fish" => command(lang, "-c"),
+            "shell" | "console" => command(&SHELL.cmd, &SHELL.arg),
+            "powershell" | "pwsh" => command(lang, "-Command"),
+            "cmd" => command("cmd", "/C"),
+            _ => {
+                let lang = match lang {
+                    "py" | "python3" => "python",
+                    "js" | "node" => "javascript",
+                    _ => lang,
+                };
+                let interpreter = match self.config.read().code_interpreters.get(lang) {
+                    Some(v) => v.clone(),
+                    None => bail!(
+                        "No interpreter for {lang} code, please add it to `code_interpreters`"
+                    ),
+                };
+                let mut args = shell_words::split(&interpreter)
+                    .with_context(|| format!("Invalid interpreter for {lang}: `{interpreter}`"))?;
+                if args.is_empty() {
+                    bail!("Invalid interpreter for {lang}: `{interpreter}`");
+                }
+                let path = temp_file("-code-", "");
+                fs::write(&path, code)
+                    .with_context(|| format!("Failed to write code to '{}'", path.display()))?;
+                args.push(path.display().to_string());
+                _code_file = Some(TempFile(path));
+                (args.remove(0), args)
+            }
+        };
+        self.config
+            .read()
+            .print_markdown(&format!("```{lang}\n{code}\n```"))?;
+        let ans = Confirm::new(&tr("Run this code?"))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            return Ok(());
+        }
+        // Ctrl+C reaches the code through the terminal, the watcher keeps it from ending the REPL.
+        let interrupted = create_abort_signal();
+        let ctrlc_watcher = {
+            let interrupted = interrupted.clone();
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    interrupted.set_ctrlc();
+                }
+            })
+        };
+        let ret = tokio::task::block_in_place(|| run_command_streaming(&cmd, &args));
+        ctrlc_watcher.abort();
+        let (success, output) = ret.with_context(|| format!("Failed to run `{cmd}`"))?;
+        let output = output.trim_end().to_string();
+        if interrupted.aborted() {
+            println!("{}", warning_text("The code was interrupted"));
+        } else if !success {
+            println!("{}", warning_text("The code exited with an error"));
+        }
+        let ans = Confirm::new(&tr("Send the output to the model?"))
+            .with_default(false)
+            .prompt()?;
+        if ans {
+            let status = if success {
+                ""
+            } else {
+                " (it exited with an error)"
+            };
+            let text = format!("I ran the code{status}, the output is:\n```\n{output}\n```");
+            let input = Input::from_str(&self.config, &text, None);
+            ask(&self.config, self.abort_signal.clone(), input, false).await?;
+        }
+        Ok(())
+    }
+
+    fn dump_buffer(&self, text: &str) -> Result<()> {
+        fs::write(&self.buffer_file, text).with_context(|| {
+            format!(
+                "Failed to save the input buffer to '{}'",
+                self.buffer_file.display()
+            )
+        })
+    }
+
     fn copy(&self, text: &str) -> Result<()> {
         if text.is_empty() {
             bail!("No text to copy")
@@ -617,6 +1189,8 @@ pub struct ReplCommand {
     name: &'static str,
     description: &'static str,
     state: AssertState,
+    examples: &'static [&'static str],
+    settings: &'static [&'static str],
 }
 
 impl ReplCommand {
@@ -625,9 +1199,21 @@ impl ReplCommand {
             name,
             description: desc,
             state,
+            examples: &[],
+            settings: &[],
         }
     }
 
+    fn examples(mut self, examples: &'static [&'static str]) -> Self {
+        self.examples = examples;
+        self
+    }
+
+    fn settings(mut self, settings: &'static [&'static str]) -> Self {
+        self.settings = settings;
+        self
+    }
+
     fn is_valid(&self, flags: StateFlags) -> bool {
         self.state.assert(flags)
     }
@@ -647,6 +1233,15 @@ impl Validator for ReplValidator {
     }
 }
 
+/// Removes the file when dropped, whichever way the function holding it returns.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
 #[async_recursion::async_recursion]
 async fn ask(
     config: &GlobalConfig,
@@ -663,14 +1258,47 @@ async fn ask(
     while config.read().is_compressing_session() {
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     }
+    if !Config::confirm_preview(config, &input)? {
+        return Ok(());
+    }
+    if run_agent_pipeline(config, &input, abort_signal.clone())
+        .await?
+        .is_some()
+    {
+        return Ok(());
+    }
 
     let client = input.create_client()?;
     config.write().before_chat_completion(&input)?;
     let (output, tool_results) = if input.stream() {
-        call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
+        match call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await {
+            Err(err) if is_stream_unsupported_error(&err) => {
+                let model_id = client.model().id();
+                println!(
+                    "{}",
+                    warning_text(&format!(
+                        "⚠️ '{model_id}' can't stream the response, streaming is turned off for it"
+                    ))
+                );
+                config.write().no_stream_models.insert(model_id);
+                call_chat_completions(&input, false, client.as_ref(), abort_signal.clone()).await?
+            }
+            ret => ret?,
+        }
     } else {
         call_chat_completions(&input, false, client.as_ref(), abort_signal.clone()).await?
     };
+    if let Some(language) = input
+        .mismatched_language(&output)
+        .filter(|_| tool_results.is_empty())
+    {
+        println!(
+            "{}",
+            warning_text(&format!("⚠️ The answer isn't in {language}, asking again"))
+        );
+        input.enforce_language(language);
+        return ask(config, abort_signal, input, false).await;
+    }
     config
         .write()
         .after_chat_completion(&input, &output, &tool_results)?;
@@ -685,27 +1313,104 @@ async fn ask(
     } else {
         Config::maybe_autoname_session(config.clone());
         Config::maybe_compress_session(config.clone());
+        if config.read().followups {
+            let ret = abortable_run_with_spinner(
+                Config::suggest_followups(config),
+                "Suggesting follow-ups",
+                abort_signal,
+            )
+            .await;
+            match ret {
+                Ok(followups) => print_followups(&followups),
+                Err(err) => println!(
+                    "{}",
+                    warning_text(&format!("Failed to suggest follow-ups: {err}"))
+                ),
+            }
+        }
         Ok(())
     }
 }
 
+fn print_followups(followups: &[String]) {
+    for (i, text) in followups.iter().enumerate() {
+        println!("{}", dimmed_text(&format!("{}. {text}", i + 1)));
+    }
+}
+
 fn unknown_command() -> Result<()> {
-    bail!(r#"Unknown command. Type ".help" for additional help."#);
+    bail!(tr(r#"Unknown command. Type ".help" for additional help."#));
 }
 
 fn dump_repl_help() {
     let head = REPL_COMMANDS
         .iter()
-        .map(|cmd| format!("{:<24} {}", cmd.name, cmd.description))
+        .map(|cmd| format!("{:<24} {}", cmd.name, tr(cmd.description)))
         .collect::<Vec<String>>()
         .join("\n");
-    println!(
-        r###"{head}
+    let footer = [
+        r#"Type ".help <command>" for examples and related settings of a command."#,
+        "Type @<path> in a message to attach a file, #<role> to use a role for that message.",
+        "Start a message with @<model-id> to send just that message to another model.",
+        "Type ::: to start multi-line editing, type ::: to finish it.",
+        "Press Ctrl+O to open an editor for editing the input buffer.",
+        "Press Ctrl+C to cancel the response, Ctrl+D to exit the REPL.",
+    ]
+    .map(tr)
+    .join("\n");
+    println!("{head}\n\n{footer}");
+}
 
-Type ::: to start multi-line editing, type ::: to finish it.
-Press Ctrl+O to open an editor for editing the input buffer.
-Press Ctrl+C to cancel the response, Ctrl+D to exit the REPL."###,
-    );
+/// Renders the variants, examples and related settings of a command, e.g. `.help .file`.
+/// `.help .session` also covers subcommands such as `.exit session`.
+fn render_command_help(name: &str) -> Result<String> {
+    let name = name.trim();
+    let name = format!(".{}", name.trim_start_matches('.'));
+    let commands: Vec<&ReplCommand> = REPL_COMMANDS
+        .iter()
+        .filter(|cmd| {
+            let mut words = cmd.name.split(' ');
+            cmd.name == name
+                || words.next() == Some(name.as_str())
+                || words.next() == Some(&name[1..])
+        })
+        .collect();
+    if commands.is_empty() {
+        bail!(
+            tr(r#"Unknown command '{name}'. Type ".help" for additional help."#)
+                .replace("{name}", &name)
+        );
+    }
+    let mut output = commands
+        .iter()
+        .map(|cmd| format!("{:<24} {}", cmd.name, tr(cmd.description)))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let examples: Vec<&str> = commands
+        .iter()
+        .flat_map(|cmd| cmd.examples)
+        .copied()
+        .collect();
+    if !examples.is_empty() {
+        output.push_str(&format!("\n\n{}\n", tr("Examples:")));
+        output.push_str(
+            &examples
+                .iter()
+                .map(|v| format!("  {v}"))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        );
+    }
+    let settings: Vec<&str> = commands
+        .iter()
+        .flat_map(|cmd| cmd.settings)
+        .copied()
+        .collect();
+    if !settings.is_empty() {
+        output.push_str(&format!("\n\n{}\n  ", tr("Related settings:")));
+        output.push_str(&settings.join(", "));
+    }
+    Ok(output)
 }
 
 fn parse_command(line: &str) -> Option<(&str, Option<&str>)> {
@@ -727,76 +1432,203 @@ fn split_args(args: Option<&str>) -> Option<(&str, Option<&str>)> {
     })
 }
 
-fn split_files_text(line: &str, is_win: bool) -> (Vec<String>, &str) {
-    let mut words = Vec::new();
-    let mut word = String::new();
-    let mut unbalance: Option<char> = None;
-    let mut prev_char: Option<char> = None;
-    let mut text_starts_at = None;
-    let unquote_word = |word: &str| {
-        if ((word.starts_with('"') && word.ends_with('"'))
-            || (word.starts_with('\'') && word.ends_with('\'')))
-            && word.len() >= 2
+/// Splits a leading `@<client>:<model>` off the line, unless it names a URL, a context set
+/// or an existing file.
+fn split_model_override(line: &str, is_file: impl Fn(&str) -> bool) -> Option<(&str, &str)> {
+    let (word, text) = line
+        .trim_start()
+        .strip_prefix('@')?
+        .split_once(char::is_whitespace)?;
+    if word.contains(':')
+        && !is_url(word)
+        && !word.starts_with(CONTEXT_SET_PREFIX)
+        && !is_file(word)
+    {
+        Some((word, text.trim_start()))
+    } else {
+        None
+    }
+}
+
+/// Extracts `@path` mentions of files or URLs and the first `#name` mention of a role from
+/// a message, the sigils are dropped from the returned text.
+fn parse_mentions(
+    line: &str,
+    is_file: impl Fn(&str) -> bool,
+    is_role: impl Fn(&str) -> bool,
+) -> (String, Vec<String>, Option<String>) {
+    let mut text = String::new();
+    let mut files = vec![];
+    let mut role = None;
+    for piece in line.split_inclusive(char::is_whitespace) {
+        let word = piece.trim_end();
+        let name = word.trim_end_matches([',', '.', ';', ':', '!', '?', ')']);
+        if let Some(path) = name
+            .strip_prefix('@')
+            .filter(|v| !v.is_empty() && is_file(v))
         {
-            word[1..word.len() - 1].to_string()
+            files.push(path.to_string());
+            text.push_str(&piece[1..]);
+        } else if let Some(name) = name
+            .strip_prefix('#')
+            .filter(|v| role.is_none() && !v.is_empty() && is_role(v))
+        {
+            role = Some(name.to_string());
+            text.push_str(&piece[1..]);
         } else {
-            word.to_string()
+            text.push_str(piece);
         }
-    };
-    let chars: Vec<char> = line.chars().collect();
+    }
+    (text, files, role)
+}
 
-    for (i, char) in chars.iter().cloned().enumerate() {
-        match unbalance {
-            Some(ub_char) if ub_char == char => {
-                word.push(char);
-                unbalance = None;
-            }
-            Some(_) => {
-                word.push(char);
+/// Splits command arguments into words, and returns the text after a standalone `--` as is.
+///
+/// Single and double quotes group words and may appear mid-word, backticks are kept so shell
+/// commands pass through. Outside Windows a backslash escapes the next character, except in
+/// single quotes; on Windows it is a path separator and always literal.
+fn split_args_text(line: &str, is_win: bool) -> (Vec<String>, &str) {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some(q) if c == q => {
+                if q == '`' {
+                    word.push(c);
+                }
+                quote = None;
             }
-            None => match char {
-                ' ' | '\t' | '\r' | '\n' => {
-                    if char == '\r' && chars.get(i + 1) == Some(&'\n') {
+            Some('"') if c == '\\' && !is_win => match chars.peek() {
+                Some(&(_, next)) if next == '"' || next == '\\' => {
+                    word.push(next);
+                    chars.next();
+                }
+                _ => word.push(c),
+            },
+            Some(_) => word.push(c),
+            None => match c {
+                c if c.is_whitespace() => {
+                    if !in_word {
                         continue;
                     }
-                    if let Some('\\') = prev_char.filter(|_| !is_win) {
-                        word.push(char);
-                    } else if !word.is_empty() {
-                        if word == "--" {
-                            word.clear();
-                            text_starts_at = Some(i + 1);
-                            break;
+                    if word == "--" && !quoted {
+                        let mut start = i + c.len_utf8();
+                        if c == '\r' && line[start..].starts_with('\n') {
+                            start += 1;
                         }
-                        words.push(unquote_word(&word));
-                        word.clear();
+                        return (words, &line[start..]);
                     }
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                    quoted = false;
+                }
+                '"' | '\'' => {
+                    quote = Some(c);
+                    in_word = true;
+                    quoted = true;
                 }
-                '\'' | '"' | '`' => {
-                    word.push(char);
-                    unbalance = Some(char);
+                '`' => {
+                    word.push(c);
+                    quote = Some(c);
+                    in_word = true;
                 }
-                '\\' => {
-                    if is_win || prev_char.map(|c| c == '\\').unwrap_or_default() {
-                        word.push(char);
+                '\\' if !is_win => {
+                    if let Some((_, next)) = chars.next() {
+                        word.push(next);
                     }
+                    in_word = true;
                 }
                 _ => {
-                    word.push(char);
+                    word.push(c);
+                    in_word = true;
                 }
             },
         }
-        prev_char = Some(char);
     }
+    if in_word && (word != "--" || quoted) {
+        words.push(word);
+    }
+    (words, "")
+}
 
-    if !word.is_empty() && word != "--" {
-        words.push(unquote_word(&word));
+/// Parses a key chord such as `ctrl-r`, `alt+m`, `ctrl-alt-x` or `f5`.
+fn parse_key_chord(chord: &str) -> Result<(KeyModifiers, KeyCode)> {
+    let parts: Vec<&str> = chord.trim().split(['-', '+']).collect();
+    let (key, modifier_names) = match parts.split_last() {
+        Some((key, modifier_names)) if !key.is_empty() => (*key, modifier_names),
+        _ => bail!("Missing key"),
+    };
+    let mut modifiers = KeyModifiers::NONE;
+    for name in modifier_names {
+        modifiers |= match name.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => bail!("Unknown modifier '{name}'"),
+        };
     }
-    let text = match text_starts_at {
-        Some(start) => &line[start..],
-        None => "",
+    let mut chars = key.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c.to_ascii_lowercase()),
+        _ => match key.to_lowercase().as_str() {
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "esc" | "escape" => KeyCode::Esc,
+            "space" => KeyCode::Char(' '),
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            name => match name.strip_prefix('f').and_then(|v| v.parse::<u8>().ok()) {
+                Some(n @ 1..=12) => KeyCode::F(n),
+                _ => bail!("Unknown key '{key}'"),
+            },
+        },
     };
+    Ok((modifiers, code))
+}
 
-    (words, text)
+/// Maps a `keybindings_custom` action to an editor event, a REPL command is submitted as if typed.
+fn parse_key_action(action: &str) -> Result<ReedlineEvent> {
+    let action = action.trim();
+    if action.starts_with('.') {
+        return Ok(ReedlineEvent::Multiple(vec![
+            ReedlineEvent::Edit(vec![
+                EditCommand::Clear,
+                EditCommand::InsertString(action.to_string()),
+            ]),
+            ReedlineEvent::Submit,
+        ]));
+    }
+    let event = match action {
+        "menu" => ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(MENU_NAME.to_string()),
+            ReedlineEvent::MenuNext,
+        ]),
+        "newline" => ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
+        "submit" => ReedlineEvent::Submit,
+        "clear" => ReedlineEvent::Edit(vec![EditCommand::Clear]),
+        "clear_screen" => ReedlineEvent::ClearScreen,
+        "history_search" => ReedlineEvent::SearchHistory,
+        "open_editor" => ReedlineEvent::OpenEditor,
+        "undo" => ReedlineEvent::Edit(vec![EditCommand::Undo]),
+        "redo" => ReedlineEvent::Edit(vec![EditCommand::Redo]),
+        "none" => ReedlineEvent::None,
+        _ => bail!("Unknown action '{action}', expected a REPL command or an editor action"),
+    };
+    Ok(event)
 }
 
 #[cfg(test)]
@@ -823,44 +1655,134 @@ mod tests {
     }
 
     #[test]
-    fn test_split_files_text() {
+    fn test_split_model_override() {
+        let no_file = |_: &str| false;
+        assert_eq!(
+            split_model_override("@claude:claude-3-5-haiku-latest summarize this", no_file),
+            Some(("claude:claude-3-5-haiku-latest", "summarize this"))
+        );
+        assert_eq!(split_model_override("@notes.md summarize", no_file), None);
+        assert_eq!(split_model_override("@openai:gpt-4o", no_file), None);
+        assert_eq!(
+            split_model_override("@c:notes.md summarize", |v| v == "c:notes.md"),
+            None
+        );
+        assert_eq!(split_model_override("ask @openai:gpt-4o", no_file), None);
+        assert_eq!(
+            split_model_override("@https://example.com/a.md summarize", no_file),
+            None
+        );
+        assert_eq!(split_model_override("@use:docs summarize", no_file), None);
+    }
+
+    #[test]
+    fn test_parse_key_chord() {
         assert_eq!(
-            split_files_text("file.txt", false),
+            parse_key_chord("ctrl-r").unwrap(),
+            (KeyModifiers::CONTROL, KeyCode::Char('r'))
+        );
+        assert_eq!(
+            parse_key_chord("Ctrl+Alt+X").unwrap(),
+            (
+                KeyModifiers::CONTROL | KeyModifiers::ALT,
+                KeyCode::Char('x')
+            )
+        );
+        assert_eq!(
+            parse_key_chord("f5").unwrap(),
+            (KeyModifiers::NONE, KeyCode::F(5))
+        );
+        assert_eq!(
+            parse_key_chord("shift-pageup").unwrap(),
+            (KeyModifiers::SHIFT, KeyCode::PageUp)
+        );
+        assert!(parse_key_chord("ctrl-").is_err());
+        assert!(parse_key_chord("hyper-a").is_err());
+        assert!(parse_key_chord("f13").is_err());
+    }
+
+    #[test]
+    fn test_render_command_help() {
+        let help = render_command_help("file").unwrap();
+        assert!(help.starts_with(".file "));
+        assert!(help.contains("Examples:\n  .file /tmp/file.txt\n"));
+        assert!(help.ends_with("Related settings:\n  document_loaders"));
+        let help = render_command_help(".session").unwrap();
+        assert!(help.contains(".exit session"));
+        assert!(help.contains(".info session"));
+        assert!(!help.contains(".info role"));
+        assert!(render_command_help(".unknown").is_err());
+    }
+
+    #[test]
+    fn test_parse_mentions() {
+        let is_file = |v: &str| ["src/main.rs", "README.md"].contains(&v);
+        let is_role = |v: &str| v == "teacher";
+        assert_eq!(
+            parse_mentions("explain @src/main.rs using #teacher", is_file, is_role),
+            (
+                "explain src/main.rs using teacher".into(),
+                vec!["src/main.rs".into()],
+                Some("teacher".into())
+            )
+        );
+        assert_eq!(
+            parse_mentions(
+                "compare @src/main.rs, @README.md.\nthanks",
+                is_file,
+                is_role
+            ),
+            (
+                "compare src/main.rs, README.md.\nthanks".into(),
+                vec!["src/main.rs".into(), "README.md".into()],
+                None
+            )
+        );
+        assert_eq!(
+            parse_mentions("ping @john about #123 and a@b.c", is_file, is_role),
+            ("ping @john about #123 and a@b.c".into(), vec![], None)
+        );
+    }
+
+    #[test]
+    fn test_split_args_text() {
+        assert_eq!(
+            split_args_text("file.txt", false),
             (vec!["file.txt".into()], "")
         );
         assert_eq!(
-            split_files_text("file.txt --", false),
+            split_args_text("file.txt --", false),
             (vec!["file.txt".into()], "")
         );
         assert_eq!(
-            split_files_text("file.txt -- hello", false),
+            split_args_text("file.txt -- hello", false),
             (vec!["file.txt".into()], "hello")
         );
         assert_eq!(
-            split_files_text("file.txt -- \thello", false),
+            split_args_text("file.txt -- \thello", false),
             (vec!["file.txt".into()], "\thello")
         );
         assert_eq!(
-            split_files_text("file.txt --\nhello", false),
+            split_args_text("file.txt --\nhello", false),
             (vec!["file.txt".into()], "hello")
         );
         assert_eq!(
-            split_files_text("file.txt --\r\nhello", false),
+            split_args_text("file.txt --\r\nhello", false),
             (vec!["file.txt".into()], "hello")
         );
         assert_eq!(
-            split_files_text("file.txt --\rhello", false),
+            split_args_text("file.txt --\rhello", false),
             (vec!["file.txt".into()], "hello")
         );
         assert_eq!(
-            split_files_text(r#"file1.txt 'file2.txt' "file3.txt""#, false),
+            split_args_text(r#"file1.txt 'file2.txt' "file3.txt""#, false),
             (
                 vec!["file1.txt".into(), "file2.txt".into(), "file3.txt".into()],
                 ""
             )
         );
         assert_eq!(
-            split_files_text(r#"./file1.txt 'file1 - Copy.txt' file\ 2.txt"#, false),
+            split_args_text(r#"./file1.txt 'file1 - Copy.txt' file\ 2.txt"#, false),
             (
                 vec![
                     "./file1.txt".into(),
@@ -871,8 +1793,50 @@ mod tests {
             )
         );
         assert_eq!(
-            split_files_text(r#".\file.txt C:\dir\file.txt"#, true),
+            split_args_text(r#".\file.txt C:\dir\file.txt"#, true),
             (vec![".\\file.txt".into(), "C:\\dir\\file.txt".into()], "")
         );
+        assert_eq!(
+            split_args_text(
+                r#""C:\Program Files\app\a b.txt" \\server\share\c.txt"#,
+                true
+            ),
+            (
+                vec![
+                    "C:\\Program Files\\app\\a b.txt".into(),
+                    "\\\\server\\share\\c.txt".into()
+                ],
+                ""
+            )
+        );
+        assert_eq!(
+            split_args_text(r#"'\\server\My Share\*.md' C:\"My Docs"\x.txt"#, true),
+            (
+                vec![
+                    "\\\\server\\My Share\\*.md".into(),
+                    "C:\\My Docs\\x.txt".into()
+                ],
+                ""
+            )
+        );
+        assert_eq!(
+            split_args_text(
+                r#""src/**/*.rs" 'a \b' "c \"d\"" `git diff` -- 总结"#,
+                false
+            ),
+            (
+                vec![
+                    "src/**/*.rs".into(),
+                    "a \\b".into(),
+                    "c \"d\"".into(),
+                    "`git diff`".into()
+                ],
+                "总结"
+            )
+        );
+        assert_eq!(
+            split_args_text(r#"é.txt "--" -- hi"#, false),
+            (vec!["é.txt".into(), "--".into()], "hi")
+        );
     }
 }
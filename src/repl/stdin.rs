@@ -0,0 +1,180 @@
+use super::parse_command;
+
+use crate::client::{record_exchange, ChatCompletionsOutput};
+use crate::config::{GlobalConfig, Input};
+use crate::function::eval_tool_calls;
+use crate::utils::{wait_abort_signal, AbortSignal};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{stdin, stdout, BufRead, Write};
+use std::time::Instant;
+use tokio::sync::mpsc::unbounded_channel;
+
+/// One line of `--repl-stdin` input.
+#[derive(Debug, Deserialize)]
+struct StdinRequest {
+    #[serde(default)]
+    id: Option<Value>,
+    text: String,
+}
+
+enum Reply {
+    Chat(ChatCompletionsOutput),
+    Command(String),
+    Exit,
+}
+
+/// A REPL driven by JSON lines on stdin, for when stdin is not a terminal.
+///
+/// Each `{"id", "text"}` request gets one JSON reply line. Messages continue one conversation,
+/// and a few REPL commands (`.model`, `.role`, `.session`, ...) are understood.
+pub async fn run_repl_stdin(config: &GlobalConfig, abort_signal: AbortSignal) -> Result<()> {
+    if config.read().session.is_none() {
+        config.write().use_session(None)?;
+    }
+    let (tx, mut rx) = unbounded_channel();
+    std::thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let stop = line.is_err();
+            if tx.send(line).is_err() || stop {
+                break;
+            }
+        }
+    });
+    while let Some(line) = rx.recv().await {
+        let line = line.context("Failed to read stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, ret) = match serde_json::from_str::<StdinRequest>(&line) {
+            Ok(request) => {
+                let id = request.id.clone();
+                tokio::select! {
+                    ret = handle(config, &request.text, abort_signal.clone()) => (id, ret),
+                    _ = wait_abort_signal(&abort_signal) => break,
+                }
+            }
+            Err(err) => (None, Err(err).context("Invalid request")),
+        };
+        let mut output = json!({});
+        if let Some(id) = id {
+            output["id"] = id;
+        }
+        let exit = matches!(ret, Ok(Reply::Exit));
+        match ret {
+            Ok(Reply::Chat(ret)) => {
+                output["text"] = ret.text.into();
+                output["input_tokens"] = ret.input_tokens.into();
+                output["output_tokens"] = ret.output_tokens.into();
+                if let Some(request_ids) = ret.request_ids {
+                    output["request_id"] = request_ids.id.into();
+                    output["provider_request_id"] = request_ids.provider_id.into();
+                }
+            }
+            Ok(Reply::Command(text)) => output["text"] = text.into(),
+            Ok(Reply::Exit) => output["text"] = "".into(),
+            Err(err) => output["error"] = format!("{err:#}").into(),
+        }
+        let mut stdout = stdout().lock();
+        writeln!(stdout, "{output}")?;
+        stdout.flush()?;
+        if exit {
+            break;
+        }
+    }
+    config.write().exit_session()?;
+    Ok(())
+}
+
+async fn handle(config: &GlobalConfig, text: &str, abort_signal: AbortSignal) -> Result<Reply> {
+    let text = text.trim();
+    if let Some((cmd, args)) = parse_command(text) {
+        let output = match (cmd, args) {
+            (".info", None) => config.read().info()?,
+            (".info", Some("role")) => config.read().role_info()?,
+            (".info", Some("session")) => config.read().session_info()?,
+            (".info", Some("last")) => config.read().last_exchange_info()?,
+            (".model", Some(name)) => {
+                config.write().set_model(name)?;
+                String::new()
+            }
+            (".role", Some(name)) => {
+                config.write().use_role(name)?;
+                String::new()
+            }
+            (".session", args) => {
+                config.write().exit_session()?;
+                config.write().use_session(args)?;
+                String::new()
+            }
+            (".save", Some(args)) if args.starts_with("session") => {
+                let name = args["session".len()..].trim();
+                let name = if name.is_empty() { None } else { Some(name) };
+                config.write().save_session(name)?;
+                String::new()
+            }
+            (".empty", Some("session")) => {
+                config.write().empty_session()?;
+                String::new()
+            }
+            (".exit", Some("role")) => {
+                config.write().exit_role()?;
+                String::new()
+            }
+            (".exit", None) => return Ok(Reply::Exit),
+            _ => bail!("Unsupported command '{text}' in --repl-stdin"),
+        };
+        return Ok(Reply::Command(output));
+    }
+    let mut input = Input::from_str(config, text, None);
+    input.use_embeddings(abort_signal).await?;
+    let client = input.create_client()?;
+    let mut input_tokens = 0;
+    let mut output_tokens = 0;
+    loop {
+        config.write().before_chat_completion(&input)?;
+        let started_at = Instant::now();
+        let output = match client.chat_completions(input.clone()).await {
+            Ok(output) => output,
+            Err(err) => {
+                let latency = started_at.elapsed();
+                record_exchange(
+                    client.as_ref(),
+                    &input,
+                    "",
+                    &[],
+                    (None, None),
+                    latency,
+                    Err(&err),
+                );
+                return Err(err);
+            }
+        };
+        record_exchange(
+            client.as_ref(),
+            &input,
+            &output.text,
+            &output.tool_calls,
+            (output.input_tokens, output.output_tokens),
+            started_at.elapsed(),
+            Ok(output.request_ids.as_ref()),
+        );
+        input_tokens += output.input_tokens.unwrap_or_default();
+        output_tokens += output.output_tokens.unwrap_or_default();
+        let tool_results = eval_tool_calls(config, &input, output.tool_calls).await?;
+        config
+            .write()
+            .after_chat_completion(&input, &output.text, &tool_results)?;
+        if tool_results.is_empty() {
+            return Ok(Reply::Chat(ChatCompletionsOutput {
+                input_tokens: Some(input_tokens).filter(|v| *v > 0),
+                output_tokens: Some(output_tokens).filter(|v| *v > 0),
+                request_ids: output.request_ids,
+                ..ChatCompletionsOutput::new(&output.text)
+            }));
+        }
+        input = input.merge_tool_results(output.text, tool_results);
+    }
+}
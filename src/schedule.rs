@@ -0,0 +1,350 @@
+use crate::client::ChatCompletionsOutput;
+use crate::config::{ensure_parent_exists, Config, GlobalConfig, Input, RoleLike};
+use crate::function::eval_tool_calls;
+use crate::utils::*;
+
+use anyhow::{bail, Context, Result};
+use parking_lot::RwLock;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    env,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+use tokio::task::JoinHandle;
+
+lazy_static::lazy_static! {
+    static ref PENDING_WEBHOOKS: parking_lot::Mutex<Vec<JoinHandle<()>>> = Default::default();
+}
+
+/// A prompt run on a cron schedule, from `--cron` or the `schedules` config section.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub name: String,
+    pub cron: String,
+    #[serde(default)]
+    pub agent: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub rag: Option<String>,
+    pub prompt: String,
+    /// `file:<path>`, `webhook:<url>` or `command:<cmd>`, stdout by default
+    #[serde(default)]
+    pub sink: Option<String>,
+}
+
+/// Where the answers of a schedule go.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sink {
+    Stdout,
+    File(PathBuf),
+    Webhook(String),
+    Command(String),
+}
+
+impl Sink {
+    pub fn parse(value: Option<&str>) -> Result<Self> {
+        let Some(value) = value.map(|v| v.trim()).filter(|v| !v.is_empty()) else {
+            return Ok(Self::Stdout);
+        };
+        let sink = match value.split_once(':') {
+            _ if value == "stdout" => Self::Stdout,
+            Some(("file", path)) if !path.is_empty() => {
+                let path = match (path.strip_prefix("~/"), dirs::home_dir()) {
+                    (Some(path), Some(home)) => home.join(path),
+                    _ => PathBuf::from(path),
+                };
+                Self::File(path)
+            }
+            Some(("webhook", url)) if is_url(url) => Self::Webhook(url.to_string()),
+            Some(("command", cmd)) if !cmd.trim().is_empty() => Self::Command(cmd.to_string()),
+            _ => bail!(
+                "Invalid sink '{value}', expected stdout, file:<path>, webhook:<url> or command:<cmd>"
+            ),
+        };
+        Ok(sink)
+    }
+
+    pub async fn deliver(&self, name: &str, prompt: &str, text: &str) -> Result<()> {
+        let time = now();
+        match self {
+            Self::Stdout => {
+                println!("{text}");
+            }
+            Self::File(path) => {
+                ensure_parent_exists(path)?;
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open '{}'", path.display()))?;
+                writeln!(file, "## {name} ({time})\n\n{text}\n")?;
+            }
+            Self::Webhook(url) => {
+                let body = json!({
+                    "name": name,
+                    "time": time,
+                    "prompt": prompt,
+                    "text": text,
+                });
+                post_json(url, &body)
+                    .await
+                    .with_context(|| format!("Failed to post to '{url}'"))?;
+            }
+            Self::Command(cmd) => run_shell_with_stdin(cmd, text)?,
+        }
+        Ok(())
+    }
+}
+
+/// Sends the answer of a non-interactive run to the `on_complete` hook, warning on failure.
+pub async fn notify_complete(config: &GlobalConfig, input: &Input, text: &str) {
+    let Some(hook) = config.read().on_complete.clone() else {
+        return;
+    };
+    let name = match input.role().name() {
+        "" => "aichat",
+        name => name,
+    };
+    let ret = match Sink::parse(Some(&hook)) {
+        Ok(Sink::Stdout) => return,
+        Ok(sink) => sink.deliver(name, &input.text(), text).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = ret {
+        eprintln!(
+            "{}",
+            warning_text(&format!("Failed to run the on_complete hook: {err:#}"))
+        );
+    }
+}
+
+/// Posts a finished reply, or the error that ended it, to `notify_webhook` in the background, warning on failure.
+pub fn notify_webhook(config: &GlobalConfig, input: &Input, ret: Result<&str, &anyhow::Error>) {
+    let (url, body) = {
+        let config = config.read();
+        let Some(url) = config.notify_webhook.clone() else {
+            return;
+        };
+        let role = input.role();
+        let agent = match (input.with_agent(), &config.agent) {
+            (true, Some(agent)) => Some(agent.name().to_string()),
+            _ => None,
+        };
+        let mut body = json!({
+            "time": now(),
+            "model": role.model().id(),
+            "role": Some(role.name()).filter(|v| !v.is_empty()),
+            "session": input.session(&config.session).map(|v| v.name()),
+            "agent": agent,
+            "prompt": input.raw(),
+        });
+        match ret {
+            Ok(text) => body["text"] = text.into(),
+            Err(err) => body["error"] = format!("{err:#}").into(),
+        }
+        (url, body)
+    };
+    let handle = tokio::spawn(async move {
+        if let Err(err) = post_json(&url, &body).await {
+            eprintln!(
+                "{}",
+                warning_text(&format!(
+                    "Failed to post to notify_webhook '{url}': {err:#}"
+                ))
+            );
+        }
+    });
+    let mut pending = PENDING_WEBHOOKS.lock();
+    pending.retain(|v| !v.is_finished());
+    pending.push(handle);
+}
+
+/// Waits for the `notify_webhook` posts still in flight, call it before the process exits.
+pub async fn wait_webhooks() {
+    let pending = std::mem::take(&mut *PENDING_WEBHOOKS.lock());
+    for handle in pending {
+        let _ = handle.await;
+    }
+}
+
+/// Runs the prompt of `--cron` with the current agent/role until Ctrl+C.
+///
+/// With `daemonize`, it starts in the background instead, handing over the stdin piped to this process.
+pub async fn run(
+    config: &GlobalConfig,
+    schedule: Schedule,
+    daemonize: Option<Option<String>>,
+) -> Result<()> {
+    let cron = Cron::parse(&schedule.cron)?;
+    let sink = Sink::parse(schedule.sink.as_deref())?;
+    if let Some(stdin_text) = daemonize {
+        return spawn_daemon(stdin_text);
+    }
+    tokio::select! {
+        ret = run_schedule(config, &schedule, &cron, &sink) => ret,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+/// Starts the `schedules` of the config in the background, for serve mode.
+pub fn spawn_schedules(config: &GlobalConfig) -> Result<Vec<String>> {
+    let schedules = config.read().schedules.clone();
+    let mut names = vec![];
+    for (index, mut schedule) in schedules.into_iter().enumerate() {
+        if schedule.name.is_empty() {
+            schedule.name = format!("schedule-{}", index + 1);
+        }
+        let err_msg = || format!("Invalid schedule '{}'", schedule.name);
+        let cron = Cron::parse(&schedule.cron).with_context(err_msg)?;
+        let sink = Sink::parse(schedule.sink.as_deref()).with_context(err_msg)?;
+        names.push(format!("{} ({})", schedule.name, schedule.cron));
+        let config = Arc::new(RwLock::new(config.read().clone()));
+        tokio::spawn(async move {
+            let ret = match setup_schedule(&config, &schedule).await {
+                Ok(()) => run_schedule(&config, &schedule, &cron, &sink).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = ret {
+                eprintln!(
+                    "{}",
+                    warning_text(&format!("Stopped schedule '{}': {err:#}", schedule.name))
+                );
+            }
+        });
+    }
+    Ok(names)
+}
+
+async fn setup_schedule(config: &GlobalConfig, schedule: &Schedule) -> Result<()> {
+    let abort_signal = create_abort_signal();
+    if let Some(agent) = &schedule.agent {
+        Config::use_agent(config, agent, None, abort_signal.clone()).await?;
+    } else if let Some(role) = &schedule.role {
+        config.write().use_role(role)?;
+    }
+    if let Some(rag) = &schedule.rag {
+        Config::use_rag(config, Some(rag), abort_signal).await?;
+    }
+    Ok(())
+}
+
+async fn run_schedule(
+    config: &GlobalConfig,
+    schedule: &Schedule,
+    cron: &Cron,
+    sink: &Sink,
+) -> Result<()> {
+    let name = &schedule.name;
+    loop {
+        let Some(next) = cron.next_after(chrono::Local::now()) else {
+            bail!("The cron expression '{}' never matches", schedule.cron);
+        };
+        println!(
+            "{}",
+            dimmed_text(&format!("Next run of '{name}' at {}", next.to_rfc3339()))
+        );
+        let wait = (next - chrono::Local::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        let ret = match run_prompt(config, &schedule.prompt).await {
+            Ok(output) => sink.deliver(name, &schedule.prompt, &output.text).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = ret {
+            eprintln!(
+                "{}",
+                warning_text(&format!("Failed to run '{name}' at {}: {err:#}", now()))
+            );
+        }
+    }
+}
+
+async fn run_prompt(config: &GlobalConfig, prompt: &str) -> Result<ChatCompletionsOutput> {
+    let mut input = Input::from_str(config, prompt, None);
+    input.use_embeddings(create_abort_signal()).await?;
+    let client = input.create_client()?;
+    loop {
+        config.write().before_chat_completion(&input)?;
+        let output = client.chat_completions(input.clone()).await?;
+        let tool_results = eval_tool_calls(config, &input, output.tool_calls.clone()).await?;
+        config
+            .write()
+            .after_chat_completion(&input, &output.text, &tool_results)?;
+        if tool_results.is_empty() {
+            return Ok(output);
+        }
+        input = input.merge_tool_results(output.text, tool_results);
+    }
+}
+
+/// Starts this command again without `--daemonize`, detached and logging to a file.
+///
+/// The stdin was already read here, so it's written to the daemon's stdin to build the same prompt.
+fn spawn_daemon(stdin_text: Option<String>) -> Result<()> {
+    let log_path = Config::local_path("schedule.log");
+    ensure_parent_exists(&log_path)?;
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open '{}'", log_path.display()))?;
+    let args: Vec<String> = env::args().skip(1).filter(|v| v != "--daemonize").collect();
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(args)
+        .stdin(match stdin_text {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| "Failed to start the daemon")?;
+    if let (Some(mut stdin), Some(text)) = (child.stdin.take(), stdin_text) {
+        stdin
+            .write_all(text.as_bytes())
+            .with_context(|| "Failed to pass stdin to the daemon")?;
+    }
+    println!(
+        "✓ Running in the background (pid {}), logging to '{}'",
+        child.id(),
+        log_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sink() {
+        assert_eq!(Sink::parse(None).unwrap(), Sink::Stdout);
+        assert_eq!(
+            Sink::parse(Some("file:digest.md")).unwrap(),
+            Sink::File(PathBuf::from("digest.md"))
+        );
+        assert_eq!(
+            Sink::parse(Some("webhook:https://ntfy.sh/digest")).unwrap(),
+            Sink::Webhook("https://ntfy.sh/digest".into())
+        );
+        assert_eq!(
+            Sink::parse(Some("command:mail -s digest me@example.com")).unwrap(),
+            Sink::Command("mail -s digest me@example.com".into())
+        );
+        assert!(Sink::parse(Some("webhook:digest")).is_err());
+        assert!(Sink::parse(Some("email:me@example.com")).is_err());
+    }
+}
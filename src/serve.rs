@@ -1,4 +1,4 @@
-use crate::{client::*, config::*, function::*, rag::*, utils::*};
+use crate::{client::*, config::*, function::*, rag::*, schedule::spawn_schedules, utils::*};
 
 use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
@@ -59,6 +59,9 @@ pub async fn run(config: GlobalConfig, addr: Option<String>) -> Result<()> {
     println!("Rerank API:           http://{addr}/v1/rerank");
     println!("LLM Playground:       http://{addr}/playground");
     println!("LLM Arena:            http://{addr}/arena?num=2");
+    for name in spawn_schedules(&config)? {
+        println!("Schedule:             {name}");
+    }
     shutdown_signal().await;
     let _ = stop_server.send(());
     Ok(())
@@ -139,6 +142,16 @@ impl Server {
         Ok(tx)
     }
 
+    #[tracing::instrument(
+        name = "http_request",
+        skip_all,
+        fields(
+            otel.name = format!("{} {}", req.method(), req.uri().path()),
+            http.request.method = %req.method(),
+            url.path = req.uri().path(),
+            http.response.status_code = tracing::field::Empty,
+        )
+    )]
     async fn handle(
         self: Arc<Self>,
         req: hyper::Request<Incoming>,
@@ -192,6 +205,7 @@ impl Server {
         };
         *res.status_mut() = status;
         set_cors_header(&mut res);
+        tracing::Span::current().record("http.response.status_code", status.as_u16());
         Ok(res)
     }
 
@@ -312,11 +326,14 @@ impl Server {
         if client.model().no_system_message() {
             patch_system_message(&mut messages);
         }
+        let (temperature, top_p) = client.model().apply_param_policies(temperature, top_p);
         let data: ChatCompletionsData = ChatCompletionsData {
             messages,
             temperature,
             top_p,
             functions,
+            native_tools: vec![],
+            json_mode: false,
             stream,
         };
 
@@ -882,7 +899,7 @@ fn parse_messages(message: Vec<Value>) -> Result<Vec<Message>> {
                     if tool_calls.len() == tool_values.len() {
                         let mut list = vec![];
                         for ((id, name, arguments), (value, tool_call_id)) in
-                            tool_calls.into_iter().zip(tool_values.into_iter())
+                            tool_calls.into_iter().zip(tool_values)
                         {
                             if id != tool_call_id {
                                 return Err(err());
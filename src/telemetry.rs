@@ -0,0 +1,62 @@
+use crate::client::Model;
+
+use anyhow::{Context, Result};
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use std::sync::OnceLock;
+use tracing::{field::Empty, Span};
+use tracing_subscriber::layer::SubscriberExt;
+
+static PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+
+/// Exports `tracing` spans to the OTLP/HTTP traces endpoint set by `otel_endpoint`.
+pub fn init(endpoint: &str) -> Result<()> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .with_context(|| format!("Failed to create OTLP exporter for '{endpoint}'"))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([
+            KeyValue::new("service.name", env!("CARGO_CRATE_NAME")),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ]))
+        .build();
+    let layer =
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_CRATE_NAME")));
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .context("Failed to install the tracing subscriber")?;
+    let _ = PROVIDER.set(provider);
+    Ok(())
+}
+
+/// Flushes the pending spans, call it before the process exits.
+pub async fn shutdown() {
+    if let Some(provider) = PROVIDER.get().cloned() {
+        let _ = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+    }
+}
+
+/// Span of a call to the LLM provider, named after the GenAI semantic conventions.
+pub fn provider_span(model: &Model, request_id: &str, operation: &str, stream: bool) -> Span {
+    tracing::info_span!(
+        "provider_call",
+        otel.name = format!("{operation} {}", model.id()),
+        otel.status_code = Empty,
+        otel.status_message = Empty,
+        gen_ai.operation.name = operation,
+        gen_ai.system = model.client_name(),
+        gen_ai.request.model = model.name(),
+        gen_ai.usage.input_tokens = Empty,
+        gen_ai.usage.output_tokens = Empty,
+        aichat.request_id = request_id,
+        stream,
+    )
+}
+
+pub fn record_error(span: &Span, err: &anyhow::Error) {
+    span.record("otel.status_code", "ERROR");
+    span.record("otel.status_message", format!("{err:#}"));
+}
@@ -1,11 +1,172 @@
+use super::{base64_encode, SHELL};
+
+#[cfg(target_os = "windows")]
+use super::base64_decode;
+
+use anyhow::{anyhow, bail, Context, Result};
+use is_terminal::IsTerminal;
+use parking_lot::RwLock;
+use std::{
+    env,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ClipboardMethod {
+    Auto,
+    Osc52,
+    Command(String),
+}
+
+lazy_static::lazy_static! {
+    static ref CLIPBOARD_METHOD: RwLock<ClipboardMethod> = RwLock::new(ClipboardMethod::Auto);
+}
+
 #[cfg(not(any(target_os = "android", target_os = "emscripten")))]
 lazy_static::lazy_static! {
     static ref CLIPBOARD: std::sync::Arc<std::sync::Mutex<Option<arboard::Clipboard>>> =
         std::sync::Arc::new(std::sync::Mutex::new(arboard::Clipboard::new().ok()));
 }
 
+/// Sets how `set_text` copies: `auto`, `osc52` or `command:<cmd>`, defaults to `auto`.
+pub fn set_clipboard_method(value: Option<&str>) -> Result<()> {
+    *CLIPBOARD_METHOD.write() = parse_method(value.unwrap_or("auto"))?;
+    Ok(())
+}
+
+pub fn set_text(text: &str) -> Result<()> {
+    let method = CLIPBOARD_METHOD.read().clone();
+    match method {
+        ClipboardMethod::Auto => set_text_auto(text),
+        ClipboardMethod::Osc52 => set_text_osc52(text),
+        ClipboardMethod::Command(cmd) => set_text_command(&cmd, text),
+    }
+    .context("Failed to copy")
+}
+
+/// What the `%clip%` pseudo-path reads.
+pub enum ClipboardContent {
+    Text(String),
+    /// PNG bytes
+    Image(Vec<u8>),
+}
+
+/// Reads the image in the clipboard if there is one, otherwise its text.
+pub fn get_clipboard_content() -> Result<ClipboardContent> {
+    if let Some(image) = get_image().filter(|v| v.starts_with(b"\x89PNG")) {
+        return Ok(ClipboardContent::Image(image));
+    }
+    let text = get_text().context("Failed to read the clipboard")?;
+    if text.trim().is_empty() {
+        bail!("The clipboard is empty");
+    }
+    Ok(ClipboardContent::Text(text))
+}
+
+fn get_text() -> Result<String> {
+    let err = match get_text_native() {
+        Ok(text) => return Ok(text),
+        Err(err) => err,
+    };
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        if let Some(output) = command_output("wl-paste", &["--no-newline"]) {
+            return Ok(String::from_utf8(output)?);
+        }
+    }
+    Err(err)
+}
+
 #[cfg(not(any(target_os = "android", target_os = "emscripten")))]
-pub fn set_text(text: &str) -> anyhow::Result<()> {
+fn get_text_native() -> Result<String> {
+    let mut clipboard = CLIPBOARD.lock().unwrap();
+    match clipboard.as_mut() {
+        Some(clipboard) => Ok(clipboard.get_text()?),
+        None => bail!("No clipboard available"),
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "emscripten"))]
+fn get_text_native() -> Result<String> {
+    bail!("No clipboard available")
+}
+
+// arboard is built without image support, so images come from the platform tools.
+#[cfg(target_os = "macos")]
+fn get_image() -> Option<Vec<u8>> {
+    let output = command_output("osascript", &["-e", "the clipboard as «class PNGf»"])?;
+    let output = String::from_utf8(output).ok()?;
+    let hex = output
+        .trim()
+        .strip_prefix("«data PNGf")?
+        .strip_suffix('»')?;
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn get_image() -> Option<Vec<u8>> {
+    let script = "Add-Type -AssemblyName System.Windows.Forms; $i = [Windows.Forms.Clipboard]::GetImage(); if ($i) { $m = New-Object IO.MemoryStream; $i.Save($m, [Drawing.Imaging.ImageFormat]::Png); [Convert]::ToBase64String($m.ToArray()) }";
+    let output = command_output("powershell", &["-NoProfile", "-Command", script])?;
+    base64_decode(String::from_utf8_lossy(&output).trim()).ok()
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn get_image() -> Option<Vec<u8>> {
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        command_output("wl-paste", &["--type", "image/png"])
+    } else {
+        command_output(
+            "xclip",
+            &["-selection", "clipboard", "-t", "image/png", "-o"],
+        )
+    }
+}
+
+fn command_output(cmd: &str, args: &[&str]) -> Option<Vec<u8>> {
+    let output = Command::new(cmd)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    (output.status.success() && !output.stdout.is_empty()).then_some(output.stdout)
+}
+
+fn parse_method(value: &str) -> Result<ClipboardMethod> {
+    let method = match value {
+        "auto" => ClipboardMethod::Auto,
+        "osc52" => ClipboardMethod::Osc52,
+        _ => match value.strip_prefix("command:").map(|v| v.trim()) {
+            Some(cmd) if !cmd.is_empty() => ClipboardMethod::Command(cmd.to_string()),
+            _ => bail!("Invalid clipboard '{value}', expected auto, osc52 or command:<cmd>"),
+        },
+    };
+    Ok(method)
+}
+
+/// Over SSH the local clipboard belongs to the remote host, so go straight to OSC52.
+fn set_text_auto(text: &str) -> Result<()> {
+    if env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some() {
+        return set_text_osc52(text);
+    }
+    let err = match set_text_native(text) {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+    if env::var_os("WAYLAND_DISPLAY").is_some() && set_text_command("wl-copy", text).is_ok() {
+        return Ok(());
+    }
+    if std::io::stdout().is_terminal() {
+        return set_text_osc52(text);
+    }
+    Err(err)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "emscripten")))]
+fn set_text_native(text: &str) -> Result<()> {
     let mut clipboard = CLIPBOARD.lock().unwrap();
     match clipboard.as_mut() {
         Some(clipboard) => {
@@ -13,12 +174,78 @@ pub fn set_text(text: &str) -> anyhow::Result<()> {
             #[cfg(target_os = "linux")]
             std::thread::sleep(std::time::Duration::from_millis(50));
         }
-        None => return Err(anyhow::anyhow!("No clipboard available").context("Failed to copy")),
+        None => bail!("No clipboard available"),
     }
     Ok(())
 }
 
 #[cfg(any(target_os = "android", target_os = "emscripten"))]
-pub fn set_text(_text: &str) -> anyhow::Result<()> {
-    Err(anyhow::anyhow!("No clipboard available").context("Failed to copy"))
+fn set_text_native(_text: &str) -> Result<()> {
+    bail!("No clipboard available")
+}
+
+/// Asks the terminal to set the clipboard, which works across SSH and inside tmux.
+fn set_text_osc52(text: &str) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    if !stdout.is_terminal() {
+        bail!("OSC52 needs a terminal on stdout");
+    }
+    stdout.write_all(osc52_sequence(text, env::var_os("TMUX").is_some()).as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn osc52_sequence(text: &str, tmux: bool) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", base64_encode(text));
+    if tmux {
+        format!("\x1bPtmux;\x1b{sequence}\x1b\\")
+    } else {
+        sequence
+    }
+}
+
+/// Pipes the text into a shell command such as `wl-copy` or `xclip -selection clipboard`.
+fn set_text_command(cmd: &str, text: &str) -> Result<()> {
+    let mut child = Command::new(&SHELL.cmd)
+        .arg(&SHELL.arg)
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run '{cmd}'"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("'{cmd}' exited with {status}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_method() {
+        assert_eq!(parse_method("auto").unwrap(), ClipboardMethod::Auto);
+        assert_eq!(parse_method("osc52").unwrap(), ClipboardMethod::Osc52);
+        assert_eq!(
+            parse_method("command: xclip -sel clip").unwrap(),
+            ClipboardMethod::Command("xclip -sel clip".into())
+        );
+        assert!(parse_method("command:").is_err());
+        assert!(parse_method("xclip").is_err());
+    }
+
+    #[test]
+    fn test_osc52_sequence() {
+        assert_eq!(osc52_sequence("hi", false), "\x1b]52;c;aGk=\x07");
+        assert_eq!(
+            osc52_sequence("hi", true),
+            "\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\"
+        );
+    }
 }
@@ -5,13 +5,15 @@ use std::{
     env,
     ffi::OsStr,
     fs::OpenOptions,
-    io::{self, Write},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
+    sync::Arc,
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use dirs::home_dir;
+use parking_lot::Mutex;
 
 lazy_static::lazy_static! {
     pub static ref SHELL: Shell = detect_shell();
@@ -106,6 +108,72 @@ pub fn run_command_with_output<T: AsRef<OsStr>>(
     Ok((status.success(), stdout.to_string(), stderr.to_string()))
 }
 
+/// Runs a command on the terminal's stdin, printing its stdout and stderr as they come,
+/// and returns whether it succeeded along with everything it printed.
+pub fn run_command_streaming<T: AsRef<OsStr>>(cmd: &str, args: &[T]) -> Result<(bool, String)> {
+    let mut child = Command::new(cmd)
+        .args(args.iter())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let output = Arc::new(Mutex::new(vec![]));
+    let mut readers = vec![];
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(tee_output(stdout, false, output.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(tee_output(stderr, true, output.clone()));
+    }
+    let status = child.wait()?;
+    for reader in readers {
+        let _ = reader.join();
+    }
+    let output = String::from_utf8_lossy(&output.lock()).to_string();
+    Ok((status.success(), output))
+}
+
+fn tee_output(
+    mut reader: impl Read + Send + 'static,
+    to_stderr: bool,
+    output: Arc<Mutex<Vec<u8>>>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = if to_stderr {
+                io::stderr().write_all(&buf[..n])
+            } else {
+                let mut stdout = io::stdout();
+                stdout.write_all(&buf[..n]).and_then(|_| stdout.flush())
+            };
+            output.lock().extend_from_slice(&buf[..n]);
+        }
+    })
+}
+
+/// Runs a shell command line with `input` on its stdin.
+///
+/// What it prints goes to stderr, stdout may carry the answer or JSON for another program.
+pub fn run_shell_with_stdin(command: &str, input: &str) -> Result<()> {
+    let mut child = Command::new(&SHELL.cmd)
+        .args([&SHELL.arg, command])
+        .stdin(Stdio::piped())
+        .stdout(io::stderr())
+        .spawn()
+        .with_context(|| format!("Failed to run `{command}`"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("`{command}` exited with {status}");
+    }
+    Ok(())
+}
+
 pub fn run_loader_command(path: &str, extension: &str, loader_command: &str) -> Result<String> {
     let cmd_args = shell_words::split(loader_command).with_context(|| {
         anyhow!("Invalid rag document loader '{extension}': `{loader_command}`")
@@ -0,0 +1,192 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A five-field cron expression (minute hour day-of-month month day-of-week), in local time.
+///
+/// Supports `*`, lists, ranges, steps, month/weekday names and aliases such as `@daily`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = expr.trim();
+        let expanded = match expr {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            _ => expr,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields.as_slice() else {
+            bail!("Invalid cron expression '{expr}', expected 5 fields");
+        };
+        let parse = |field: &str, min: u32, max: u32, names: &[&str]| {
+            parse_field(field, min, max, names)
+                .with_context(|| format!("Invalid cron expression '{expr}'"))
+        };
+        let mut weekdays = parse(weekday, 0, 7, &WEEKDAY_NAMES)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse(minute, 0, 59, &[])?,
+            hours: parse(hour, 0, 23, &[])?,
+            days: parse(day, 1, 31, &[])?,
+            months: parse(month, 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+
+    /// The first matching minute after `time`, or None if it never comes within a few years.
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = time.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut next = start;
+        while next.year() <= start.year() + 4 {
+            if !has(self.months, next.month()) {
+                let (year, month) = match next.month() {
+                    12 => (next.year() + 1, 1),
+                    month => (next.year(), month + 1),
+                };
+                next = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(&next) {
+                next = (next.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if !has(self.hours, next.hour()) {
+                next = next.with_minute(0)? + Duration::hours(1);
+            } else if !has(self.minutes, next.minute()) {
+                next += Duration::minutes(1);
+            } else if let Some(time) = Local.from_local_datetime(&next).earliest() {
+                return Some(time);
+            } else {
+                // Skipped by a daylight saving change
+                next += Duration::minutes(1);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            // Like cron, a restricted day and weekday match either of them
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+fn has(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| anyhow!("Invalid step '{item}'"))?;
+                if step == 0 {
+                    bail!("Invalid step '{item}'");
+                }
+                (range, step)
+            }
+            None => (item, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    parse_value(start, min, names)?,
+                    parse_value(end, min, names)?,
+                ),
+                None => {
+                    let value = parse_value(range, min, names)?;
+                    // `5/15` means from 5 to the end
+                    (value, if step > 1 { max } else { value })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            bail!("Out of range '{item}', expected {min}-{max}");
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_value(value: &str, min: u32, names: &[&str]) -> Result<u32> {
+    if let Some(index) = names.iter().position(|v| v.eq_ignore_ascii_case(value)) {
+        return Ok(index as u32 + min);
+    }
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid value '{value}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(value: &str) -> DateTime<Local> {
+        let time = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap();
+        Local.from_local_datetime(&time).earliest().unwrap()
+    }
+
+    fn next(expr: &str, time: &str) -> String {
+        Cron::parse(expr)
+            .unwrap()
+            .next_after(local(time))
+            .unwrap()
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        assert_eq!(next("0 8 * * *", "2024-05-01 07:59"), "2024-05-01 08:00");
+        assert_eq!(next("0 8 * * *", "2024-05-01 08:00"), "2024-05-02 08:00");
+        assert_eq!(next("*/15 * * * *", "2024-05-01 10:07"), "2024-05-01 10:15");
+        assert_eq!(
+            next("30 9 * * mon-fri", "2024-05-03 10:00"),
+            "2024-05-06 09:30"
+        );
+        assert_eq!(next("0 0 29 feb *", "2024-03-01 00:00"), "2028-02-29 00:00");
+        assert_eq!(next("@monthly", "2024-12-15 12:00"), "2025-01-01 00:00");
+        assert_eq!(next("0 12 1 * 0", "2024-05-02 00:00"), "2024-05-05 12:00");
+        assert_eq!(next("0 0 * * 7", "2024-05-01 00:00"), "2024-05-05 00:00");
+    }
+
+    #[test]
+    fn test_cron_parse_errors() {
+        assert!(Cron::parse("0 8 * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("0 5-2 * * *").is_err());
+        assert!(Cron::parse("0 0 30 feb *")
+            .unwrap()
+            .next_after(Local::now())
+            .is_none());
+    }
+}
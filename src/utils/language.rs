@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+const MIN_LATIN_WORDS: usize = 4;
+
+const STOPWORDS: [(&str, &[&str]); 7] = [
+    (
+        "English",
+        &[
+            "the", "and", "is", "are", "of", "to", "in", "that", "it", "with", "for", "this",
+            "what", "how", "you", "was", "be", "not",
+        ],
+    ),
+    (
+        "German",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ich", "sie", "es", "mit", "ein", "eine",
+            "zu", "auf", "für", "wie", "was", "den", "sind", "auch",
+        ],
+    ),
+    (
+        "French",
+        &[
+            "le", "la", "les", "et", "est", "un", "une", "des", "du", "que", "qui", "pour", "dans",
+            "pas", "sur", "avec", "ce", "sont", "comment",
+        ],
+    ),
+    (
+        "Spanish",
+        &[
+            "el", "la", "los", "las", "y", "es", "un", "una", "que", "de", "en", "por", "para",
+            "con", "no", "se", "del", "cómo", "qué", "está",
+        ],
+    ),
+    (
+        "Italian",
+        &[
+            "il", "lo", "la", "gli", "e", "è", "un", "una", "che", "di", "per", "non", "con",
+            "sono", "come", "della", "nel", "questo",
+        ],
+    ),
+    (
+        "Portuguese",
+        &[
+            "o", "a", "os", "as", "e", "é", "um", "uma", "que", "de", "para", "não", "com", "em",
+            "do", "da", "como", "são", "está",
+        ],
+    ),
+    (
+        "Dutch",
+        &[
+            "de", "het", "een", "en", "is", "van", "niet", "dat", "ik", "je", "met", "op", "voor",
+            "zijn", "wat", "hoe", "ook",
+        ],
+    ),
+];
+
+/// Guesses the natural language of the text, code blocks excluded.
+///
+/// Non-Latin scripts are told apart by their characters; Latin languages by common words,
+/// so short Latin texts return `None`.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let text = strip_code_blocks(text);
+    let mut scripts: HashMap<&str, usize> = HashMap::new();
+    let mut latin = 0;
+    for c in text.chars() {
+        let script = match c as u32 {
+            0x3040..=0x30FF => "Japanese",
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => "Korean",
+            0x4E00..=0x9FFF | 0x3400..=0x4DBF => "Chinese",
+            0x0400..=0x04FF => "Russian",
+            0x0600..=0x06FF => "Arabic",
+            0x0590..=0x05FF => "Hebrew",
+            0x0370..=0x03FF => "Greek",
+            0x0E00..=0x0E7F => "Thai",
+            0x0900..=0x097F => "Hindi",
+            _ => {
+                if c.is_alphabetic() {
+                    latin += 1;
+                }
+                continue;
+            }
+        };
+        *scripts.entry(script).or_default() += 1;
+    }
+    // Japanese mixes kana with Han characters.
+    if scripts.contains_key("Japanese") {
+        if let Some(count) = scripts.remove("Chinese") {
+            *scripts.entry("Japanese").or_default() += count;
+        }
+    }
+    if let Some((script, count)) = scripts.into_iter().max_by_key(|(_, v)| *v) {
+        if count >= latin {
+            return Some(script);
+        }
+    }
+
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_lowercase())
+        .collect();
+    if words.len() < MIN_LATIN_WORDS {
+        return None;
+    }
+    let mut scores: Vec<(&str, usize)> = STOPWORDS
+        .iter()
+        .map(|(language, stopwords)| {
+            let score = words
+                .iter()
+                .filter(|v| stopwords.contains(&v.as_str()))
+                .count();
+            (*language, score)
+        })
+        .collect();
+    scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    match scores.as_slice() {
+        [(language, top), (_, second), ..] if *top >= 2 && top > second => Some(language),
+        _ => None,
+    }
+}
+
+fn strip_code_blocks(text: &str) -> String {
+    let mut output = String::new();
+    let mut in_block = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_block = !in_block;
+            continue;
+        }
+        if !in_block {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(
+            detect_language("Wie funktioniert die Suche in der Datenbank?"),
+            Some("German")
+        );
+        assert_eq!(
+            detect_language("The search works by ranking the documents.\n```sh\ndie und der\n```"),
+            Some("English")
+        );
+        assert_eq!(
+            detect_language("Comment est-ce que la recherche fonctionne dans les documents ?"),
+            Some("French")
+        );
+        assert_eq!(detect_language("这个函数是做什么的？"), Some("Chinese"));
+        assert_eq!(detect_language("この関数は何をしますか"), Some("Japanese"));
+        assert_eq!(detect_language("Как это работает?"), Some("Russian"));
+        assert_eq!(detect_language("hello world"), None);
+    }
+}
@@ -29,6 +29,7 @@ pub async fn load_recursive_url(
     loaders: &HashMap<String, String>,
     path: &str,
 ) -> Result<Vec<LoadedDocument>> {
+    ensure_reachable(path)?;
     let extension = RECURSIVE_URL_LOADER;
     let pages: Vec<Page> = match loaders.get(extension) {
         Some(loader_command) => {
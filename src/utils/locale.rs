@@ -0,0 +1,110 @@
+use anyhow::{bail, Result};
+use parking_lot::RwLock;
+use rust_embed::Embed;
+use std::{collections::HashMap, env};
+
+/// Locales with a message catalog, English is built in.
+pub const LOCALES: [&str; 4] = ["en", "de", "ja", "zh-CN"];
+
+#[derive(Embed)]
+#[folder = "assets/locales/"]
+struct LocalesAsset;
+
+lazy_static::lazy_static! {
+    static ref CATALOG: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+/// Switches the UI strings to `locale`, `auto` follows `LC_ALL`/`LC_MESSAGES`/`LANG`.
+pub fn set_locale(locale: Option<&str>) -> Result<()> {
+    let name = match locale {
+        None => None,
+        Some("auto") => system_locale().as_deref().and_then(match_locale),
+        Some(value) => match match_locale(value) {
+            Some(name) => Some(name),
+            None if is_english(value) => None,
+            None => bail!(
+                "Unknown locale '{value}', expected one of: auto, {}",
+                LOCALES.join(", ")
+            ),
+        },
+    };
+    let catalog = match name {
+        Some(name) => load_catalog(name)?,
+        None => HashMap::new(),
+    };
+    *CATALOG.write() = catalog;
+    Ok(())
+}
+
+/// The text in the current locale, or the text itself when there is no translation.
+pub fn tr(text: &str) -> String {
+    CATALOG
+        .read()
+        .get(text)
+        .cloned()
+        .unwrap_or_else(|| text.to_string())
+}
+
+/// Maps values such as `de_DE.UTF-8`, `ja` or `zh_CN` to a locale with a catalog.
+fn match_locale(value: &str) -> Option<&'static str> {
+    let value = value.split(['.', '@']).next()?.replace('_', "-");
+    let language = value.split('-').next()?;
+    LOCALES[1..]
+        .iter()
+        .find(|v| v.eq_ignore_ascii_case(&value))
+        .or_else(|| {
+            LOCALES[1..].iter().find(|v| {
+                v.split('-')
+                    .next()
+                    .is_some_and(|v| v.eq_ignore_ascii_case(language))
+            })
+        })
+        .copied()
+}
+
+fn is_english(value: &str) -> bool {
+    value.eq_ignore_ascii_case("en") || value.starts_with("en_") || value.starts_with("en-")
+}
+
+fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|v| !v.is_empty())
+}
+
+fn load_catalog(name: &str) -> Result<HashMap<String, String>> {
+    let Some(content) = LocalesAsset::get(&format!("{name}.yaml")) else {
+        bail!("No message catalog for '{name}'");
+    };
+    let catalog = serde_yaml::from_slice(&content.data)?;
+    Ok(catalog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_locale() {
+        assert_eq!(match_locale("de_DE.UTF-8"), Some("de"));
+        assert_eq!(match_locale("ja"), Some("ja"));
+        assert_eq!(match_locale("zh_CN.UTF-8"), Some("zh-CN"));
+        assert_eq!(match_locale("zh-TW"), Some("zh-CN"));
+        assert_eq!(match_locale("en_US.UTF-8"), None);
+        assert_eq!(match_locale("C"), None);
+    }
+
+    #[test]
+    fn test_catalogs() {
+        for name in &LOCALES[1..] {
+            let catalog = load_catalog(name).unwrap();
+            assert!(!catalog.is_empty());
+            for (key, value) in catalog {
+                for placeholder in ["{name}", "{version}"] {
+                    assert_eq!(key.contains(placeholder), value.contains(placeholder));
+                }
+            }
+        }
+    }
+}
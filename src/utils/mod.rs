@@ -1,43 +1,80 @@
 mod abort_signal;
 mod clipboard;
 mod command;
+mod cron;
 mod crypto;
 mod html_to_md;
+mod language;
 mod loader;
+mod locale;
+mod panic;
 mod path;
 mod prompt_input;
+mod redact;
 mod render_prompt;
 mod request;
 mod spinner;
 mod variables;
 
 pub use self::abort_signal::*;
-pub use self::clipboard::set_text;
+pub use self::clipboard::{
+    get_clipboard_content, set_clipboard_method, set_text, ClipboardContent,
+};
 pub use self::command::*;
+pub use self::cron::Cron;
 pub use self::crypto::*;
 pub use self::html_to_md::*;
+pub use self::language::detect_language;
 pub use self::loader::*;
+pub use self::locale::{set_locale, tr, LOCALES};
+pub use self::panic::install_panic_hook;
 pub use self::path::*;
 pub use self::prompt_input::*;
+pub use self::redact::{
+    allow_secrets, has_masked_secrets, list_secrets, map_json_strings, mask_secrets,
+    redact_secrets, restore_secrets, take_restorable, unallowed_secrets, RedactLogger,
+};
 pub use self::render_prompt::render_prompt;
 pub use self::request::*;
 pub use self::spinner::*;
 pub use self::variables::*;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::format::{Item, StrftimeItems};
 use fancy_regex::Regex;
 use is_terminal::IsTerminal;
+use parking_lot::RwLock;
 use std::{env, path::PathBuf, process};
 use unicode_segmentation::UnicodeSegmentation;
 
 lazy_static::lazy_static! {
     pub static ref CODE_BLOCK_RE: Regex = Regex::new(r"(?ms)```\w*(.*)```").unwrap();
+    static ref THINK_TAG_RE: Regex = Regex::new(r"(?s)^\s*<(think|thinking)>.*?(</\1>|$)").unwrap();
     pub static ref IS_STDOUT_TERMINAL: bool = std::io::stdout().is_terminal();
     pub static ref NO_COLOR: bool = env::var("NO_COLOR").ok().and_then(|v| parse_bool(&v)).unwrap_or_default() || !*IS_STDOUT_TERMINAL;
+    static ref TIMESTAMP_OPTIONS: RwLock<(Option<String>, bool)> = RwLock::new((None, false));
 }
 
 pub fn now() -> String {
-    chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+    let options = TIMESTAMP_OPTIONS.read();
+    let (format, utc) = (options.0.as_deref(), options.1);
+    match (format, utc) {
+        (None, false) => chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        (None, true) => chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        (Some(format), false) => chrono::Local::now().format(format).to_string(),
+        (Some(format), true) => chrono::Utc::now().format(format).to_string(),
+    }
+}
+
+/// Sets the strftime format and timezone used by `now()`, defaults to RFC3339 in local time.
+pub fn set_timestamp_options(format: Option<String>, utc: bool) -> Result<()> {
+    if let Some(format) = &format {
+        if StrftimeItems::new(format).any(|v| matches!(v, Item::Error)) {
+            bail!("Invalid timestamp format '{format}'");
+        }
+    }
+    *TIMESTAMP_OPTIONS.write() = (format, utc);
+    Ok(())
 }
 
 pub fn now_timestamp() -> i64 {
@@ -96,7 +133,16 @@ pub fn light_theme_from_colorfgbg(colorfgbg: &str) -> Option<bool> {
     Some(light)
 }
 
+/// Drops the leading `<think>` section that reasoning models put before the answer.
+pub fn strip_think_tag(input: &str) -> &str {
+    match THINK_TAG_RE.find(input) {
+        Ok(Some(m)) => input[m.end()..].trim_start(),
+        _ => input,
+    }
+}
+
 pub fn extract_block(input: &str) -> String {
+    let input = strip_think_tag(input);
     let output: String = CODE_BLOCK_RE
         .captures_iter(input)
         .filter_map(|m| {
@@ -112,6 +158,57 @@ pub fn extract_block(input: &str) -> String {
     }
 }
 
+/// Returns the language and code of the first fenced block, limited to the given language if any.
+pub fn extract_code_block(input: &str, language: Option<&str>) -> Option<(String, String)> {
+    extract_code_blocks(input)
+        .into_iter()
+        .find(|(lang, _)| language.is_none_or(|v| v.eq_ignore_ascii_case(lang)))
+}
+
+/// Returns the language and code of every closed fenced block, in order.
+pub fn extract_code_blocks(input: &str) -> Vec<(String, String)> {
+    let mut blocks = vec![];
+    let mut block: Option<(String, Vec<&str>)> = None;
+    for line in strip_think_tag(input).lines() {
+        let trimmed = line.trim_start();
+        match block.take() {
+            None => {
+                if let Some((lang, _)) = parse_code_fence(trimmed) {
+                    block = Some((lang.to_lowercase(), vec![]));
+                }
+            }
+            Some((lang, mut lines)) => {
+                if trimmed.starts_with("```") {
+                    blocks.push((lang, lines.join("\n")));
+                } else {
+                    lines.push(line);
+                    block = Some((lang, lines));
+                }
+            }
+        }
+    }
+    blocks
+}
+
+/// Parses a fence like ```` ```rust ````, ```` ```rust:src/main.rs ```` or ```` ```py title="app.py" ````
+/// into the language and the file name.
+pub fn parse_code_fence(line: &str) -> Option<(String, Option<String>)> {
+    let info = line.trim_start().strip_prefix("```")?;
+    let (token, attrs) = info.split_once(char::is_whitespace).unwrap_or((info, ""));
+    let (lang, filename) = match token.split_once(':') {
+        Some((lang, filename)) if !filename.is_empty() => (lang, Some(filename.to_string())),
+        _ => (token, None),
+    };
+    let filename = filename.or_else(|| {
+        attrs.split_whitespace().find_map(|attr| {
+            let (key, value) = attr.split_once('=')?;
+            matches!(key, "title" | "file" | "filename")
+                .then(|| value.trim_matches(|c| c == '"' || c == '\'').to_string())
+        })
+    });
+    Some((lang.to_string(), filename))
+}
+
 pub fn format_option_value<T>(value: &Option<T>) -> String
 where
     T: std::fmt::Display,
@@ -147,6 +244,45 @@ pub fn fuzzy_match(text: &str, pattern: &str) -> bool {
     pattern_index == pattern_chars.len()
 }
 
+/// The Levenshtein distance between two strings, counted in chars.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Matches `text` against a pattern in which `*` stands for any sequence of characters.
+pub fn wildcard_match(text: &str, pattern: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = text.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
 pub fn pretty_error(err: &anyhow::Error) -> String {
     let mut output = vec![];
     output.push(format!("Error: {err}"));
@@ -162,7 +298,7 @@ pub fn pretty_error(err: &anyhow::Error) -> String {
             }
         }
     }
-    output.join("\n")
+    redact_secrets(&output.join("\n"))
 }
 
 pub fn indent_text<T: ToString>(s: T, size: usize) -> String {
@@ -231,6 +367,67 @@ pub fn set_proxy(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_extract_code_block() {
+        let text = "Run this:\n```sh\necho hi\n```\nand then:\n```python\nprint(1)\nprint(2)\n```";
+        assert_eq!(
+            extract_code_block(text, None),
+            Some(("sh".into(), "echo hi".into()))
+        );
+        assert_eq!(
+            extract_code_block(text, Some("Python")),
+            Some(("python".into(), "print(1)\nprint(2)".into()))
+        );
+        assert_eq!(extract_code_block(text, Some("rust")), None);
+        assert_eq!(extract_code_block("no code", None), None);
+        assert_eq!(extract_code_blocks(text).len(), 2);
+        let text = "```rust:src/main.rs\nfn main() {}\n```\n```py title=\"app.py\"\npass\n```";
+        assert_eq!(
+            extract_code_block(text, Some("rust")),
+            Some(("rust".into(), "fn main() {}".into()))
+        );
+        assert_eq!(extract_code_blocks(text)[1].0, "py");
+    }
+
+    #[test]
+    fn test_extract_code_with_thinking() {
+        let cases = [
+            // closed think section holding a draft
+            (
+                "<think>\nMaybe:\n```sh\nrm -rf /\n```\nNo.\n</think>\n\n```sh\nls -la\n```",
+                Some("ls -la"),
+                "```sh\nls -la\n```",
+            ),
+            // `<thinking>` variant, answer without a fence
+            ("<thinking>use ls</thinking>ls -la", None, "ls -la"),
+            // cut off while thinking
+            ("<think>\n```sh\nls\n```\nstill going", None, ""),
+            // a tag later in the answer is kept
+            (
+                "```html\n<think></think>\n```",
+                Some("<think></think>"),
+                "```html\n<think></think>\n```",
+            ),
+        ];
+        for (input, code, stripped) in cases {
+            assert_eq!(strip_think_tag(input), stripped, "{input}");
+            assert_eq!(
+                extract_code_block(input, None).map(|(_, v)| v).as_deref(),
+                code,
+                "{input}"
+            );
+            assert_eq!(extract_block(input), extract_block(stripped), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("gpt-4o", "gpt-4o"), 0);
+        assert_eq!(edit_distance("gpt4o", "gpt-4o"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
     #[test]
     fn test_fuzzy_match() {
         assert!(fuzzy_match("openai:gpt-4-turbo", "gpt4"));
@@ -238,6 +435,31 @@ mod tests {
         assert!(!fuzzy_match("openai:gpt-4-turbo", "4gpt"));
     }
 
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("ollama:llama3.1", "ollama:*"));
+        assert!(wildcard_match("openai:gpt-4o-mini", "*gpt-4o*"));
+        assert!(wildcard_match("openai:gpt-4o", "openai:gpt-4o"));
+        assert!(!wildcard_match("openai:gpt-4o-mini", "openai:gpt-4o"));
+        assert!(!wildcard_match("claude:claude-3", "ollama:*"));
+        assert!(!wildcard_match("a", "a*a"));
+    }
+
+    #[test]
+    fn test_pretty_error_redacts_secrets() {
+        let err = anyhow::anyhow!("Authorization: Bearer abc.def rejected")
+            .context("Failed to call https://example.com/v1?key=sk-abcdefghijklmnop1234");
+        let output = pretty_error(&err);
+        assert!(!output.contains("abc.def"));
+        assert!(!output.contains("sk-abcdefghijklmnop1234"));
+    }
+
+    #[test]
+    fn test_set_timestamp_options() {
+        assert!(set_timestamp_options(Some("%Y-%m-%d %Q".into()), false).is_err());
+        assert!(set_timestamp_options(Some("%Y-%m-%d %".into()), false).is_err());
+    }
+
     #[test]
     #[cfg(not(target_os = "windows"))]
     fn test_safe_join_path() {
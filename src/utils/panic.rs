@@ -0,0 +1,89 @@
+use super::*;
+
+use crossterm::{cursor, execute, terminal};
+use std::{
+    backtrace::Backtrace,
+    fs,
+    io::stdout,
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+};
+
+const LOG_TAIL_LINES: usize = 50;
+
+/// Installs a panic hook that restores the terminal and writes a redacted diagnostics
+/// report to a temp file, so crashes can be reported with actionable data.
+pub fn install_panic_hook<F>(summary_fn: F, log_path: Option<PathBuf>)
+where
+    F: Fn() -> String + Send + Sync + 'static,
+{
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(stdout(), cursor::Show);
+        default_hook(info);
+        let report = build_report(info, &summary_fn(), log_path.as_ref());
+        let report_path = temp_file("-crash-", ".txt");
+        match fs::write(&report_path, redact_secrets(&report)) {
+            Ok(_) => eprintln!(
+                "\n{} crashed unexpectedly. A diagnostics report was written to '{}'.\nPlease attach it when reporting the issue at {}/issues",
+                env!("CARGO_CRATE_NAME"),
+                report_path.display(),
+                env!("CARGO_PKG_REPOSITORY"),
+            ),
+            Err(err) => eprintln!("Failed to write the diagnostics report, {err}"),
+        }
+    }));
+}
+
+fn build_report(info: &PanicHookInfo<'_>, summary: &str, log_path: Option<&PathBuf>) -> String {
+    let message = if let Some(v) = info.payload().downcast_ref::<&str>() {
+        v.to_string()
+    } else if let Some(v) = info.payload().downcast_ref::<String>() {
+        v.clone()
+    } else {
+        "unknown".into()
+    };
+    let location = info
+        .location()
+        .map(|v| format!("{}:{}:{}", v.file(), v.line(), v.column()))
+        .unwrap_or_else(|| "unknown".into());
+    let thread = std::thread::current();
+    let logs = match log_path {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(contents) => {
+                let lines: Vec<&str> = contents.lines().collect();
+                lines[lines.len().saturating_sub(LOG_TAIL_LINES)..].join("\n")
+            }
+            Err(err) => format!("Failed to read '{}', {err}", path.display()),
+        },
+        None => "No log file".into(),
+    };
+    format!(
+        r#"# Crash Report
+
+version: {} {}
+os: {} ({})
+time: {}
+thread: {}
+panic: {message}
+location: {location}
+
+## Backtrace
+{}
+
+## Config
+{summary}
+
+## Last Log Lines
+{logs}
+"#,
+        env!("CARGO_CRATE_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        os_info::get(),
+        env::consts::ARCH,
+        now(),
+        thread.name().unwrap_or("unnamed"),
+        Backtrace::force_capture(),
+    )
+}
@@ -29,6 +29,24 @@ pub fn safe_join_path<T1: AsRef<Path>, T2: AsRef<Path>>(
     }
 }
 
+/// Whether files can be created in `dir`, or in its nearest existing ancestor when it's missing.
+pub fn is_writable_dir(dir: &Path) -> bool {
+    let Some(dir) = dir.ancestors().find(|v| v.exists()) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let Ok(path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+            return false;
+        };
+        // SAFETY: `path` is a NUL-terminated string that lives until the call returns.
+        unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+    }
+    #[cfg(not(unix))]
+    std::fs::metadata(dir).is_ok_and(|v| !v.permissions().readonly())
+}
+
 pub async fn expand_glob_paths<T: AsRef<str>>(
     paths: &[T],
     bail_non_exist: bool,
@@ -10,7 +10,11 @@ use serde::Deserialize;
 use serde_json::Value;
 use std::{
     collections::{HashMap, HashSet},
-    sync::Arc,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 use tokio::io::AsyncWriteExt;
@@ -26,6 +30,8 @@ const MAX_CRAWLS: usize = 5;
 const BREAK_ON_ERROR: bool = false;
 const USER_AGENT: &str = "curl/8.6.0";
 
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
 lazy_static::lazy_static! {
     static ref CLIENT: Result<reqwest::Client> = {
         let builder = reqwest::ClientBuilder::new().timeout(Duration::from_secs(30));
@@ -50,11 +56,45 @@ lazy_static::lazy_static! {
     static ref GITHUB_REPO_RE: Regex = Regex::new(r"^https://github\.com/([^/]+)/([^/]+)/tree/([^/]+)").unwrap();
 }
 
+pub fn set_offline(value: bool) {
+    OFFLINE.store(value, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Fails in offline mode unless the url points to this machine or the local network.
+pub fn ensure_reachable(url: &str) -> Result<()> {
+    if is_offline() && !is_local_url(url) {
+        bail!("Unable to load '{url}' in offline mode");
+    }
+    Ok(())
+}
+
+pub fn is_local_url(url: &str) -> bool {
+    let Some(host) = Url::parse(url)
+        .ok()
+        .and_then(|v| v.host_str().map(|v| v.to_string()))
+    else {
+        return false;
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+        }
+        Ok(IpAddr::V6(ip)) => ip.is_loopback() || ip.is_unspecified(),
+        Err(_) => host == "localhost" || host.ends_with(".localhost") || host.ends_with(".local"),
+    }
+}
+
 pub async fn fetch(
     loaders: &HashMap<String, String>,
     path: &str,
     allow_media: bool,
 ) -> Result<(String, String)> {
+    ensure_reachable(path)?;
     if let Some(loader_command) = loaders.get(URL_LOADER) {
         let contents = run_loader_command(path, URL_LOADER, loader_command)?;
         return Ok((contents, DEFAULT_EXTENSION.into()));
@@ -259,6 +299,40 @@ pub struct Page {
     pub text: String,
 }
 
+/// Posts `body` as JSON to `url`, failing on a non-success status.
+pub async fn post_json(url: &str, body: &Value) -> Result<()> {
+    ensure_reachable(url)?;
+    let client = match *CLIENT {
+        Ok(ref client) => client,
+        Err(ref err) => bail!("{err}"),
+    };
+    let res = client.post(url).json(body).send().await?;
+    if !res.status().is_success() {
+        bail!("Invalid status: {}", res.status());
+    }
+    Ok(())
+}
+
+/// Sends a request with an optional `(content_type, body)`, returning the status and the body as text.
+pub async fn http_request(
+    method: reqwest::Method,
+    url: &str,
+    body: Option<(String, String)>,
+) -> Result<(u16, String)> {
+    ensure_reachable(url)?;
+    let client = match *CLIENT {
+        Ok(ref client) => client,
+        Err(ref err) => bail!("{err}"),
+    };
+    let mut builder = client.request(method, url);
+    if let Some((content_type, body)) = body {
+        builder = builder.header(CONTENT_TYPE, content_type).body(body);
+    }
+    let res = builder.send().await?;
+    let status = res.status().as_u16();
+    Ok((status, res.text().await?))
+}
+
 async fn crawl_gh_tree(start_url: &Url, exclude: &[String]) -> Result<Vec<String>> {
     let path_segs: Vec<&str> = start_url.path().split('/').collect();
     if path_segs.len() < 4 {
@@ -425,3 +499,20 @@ fn match_link(path: &str, link: &str) -> bool {
                 .trim_end_matches("/index.html")
                 .trim_end_matches("/index.htm")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_local_url() {
+        assert!(is_local_url("http://localhost:11434"));
+        assert!(is_local_url("http://127.0.0.1:8080/v1"));
+        assert!(is_local_url("http://[::1]:8080/v1"));
+        assert!(is_local_url("http://192.168.1.20:11434"));
+        assert!(is_local_url("http://gpu-box.local/v1"));
+        assert!(!is_local_url("https://api.openai.com/v1"));
+        assert!(!is_local_url("https://8.8.8.8"));
+        assert!(!is_local_url("not a url"));
+    }
+}
@@ -4,6 +4,15 @@ use fancy_regex::{Captures, Regex};
 lazy_static::lazy_static! {
     pub static ref RE_VARIABLE: Regex = Regex::new(r"\{\{(\w+)\}\}").unwrap();
 }
+/// Runs the command of a `from_cmd` variable, returns its trimmed stdout.
+pub fn run_variable_cmd(cmd: &str) -> anyhow::Result<String> {
+    let (success, stdout, stderr) = run_command_with_output(&SHELL.cmd, &[&SHELL.arg, cmd], None)?;
+    if !success {
+        anyhow::bail!("Command `{cmd}` failed: {}", stderr.trim());
+    }
+    Ok(stdout.trim().to_string())
+}
+
 pub fn interpolate_variables(text: &mut String) {
     *text = RE_VARIABLE
         .replace_all(text, |caps: &Captures<'_>| {